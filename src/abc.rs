@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::key_signature::KeySignature;
+use crate::lmms_model::LMMS_TICKS_PER_BAR;
+use crate::notation::{key_signature_alteration, Chord, Pitch, Score, LETTER_NAMES};
+
+// Unit note length of the tune body, an eighth note
+const ABC_UNIT_NOTE_LENGTH: usize = LMMS_TICKS_PER_BAR / 8;
+
+const ABC_BARS_PER_LINE: usize = 4;
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Text of a header field or voice name, on a single line and without quotes
+fn abc_text(text: &str) -> String {
+    text.replace(['\r', '\n'], " ").replace('"', "'")
+}
+
+/// Length of a note as a multiple of the unit note length, e.g. "3/2"
+fn abc_length(duration: usize) -> String {
+    let divisor = gcd(duration, ABC_UNIT_NOTE_LENGTH);
+    let (numerator, denominator) = (duration / divisor, ABC_UNIT_NOTE_LENGTH / divisor);
+
+    match (numerator, denominator) {
+        (1, 1) => String::new(),
+        (_, 1) => numerator.to_string(),
+        (1, 2) => "/".to_string(),
+        (1, _) => format!("/{denominator}"),
+        _ => format!("{numerator}/{denominator}"),
+    }
+}
+
+/// Pitch in ABC notation, middle C being "C". Accidentals last until the end
+/// of the bar, so they are only written when the alteration in effect on the
+/// letter and octave differs.
+fn abc_pitch(
+    key: u8,
+    key_signature: KeySignature,
+    alterations: &mut HashMap<(usize, i32), i8>,
+) -> String {
+    let pitch = Pitch::spell(key, key_signature);
+    let mut abc_pitch = String::new();
+
+    let alteration = alterations
+        .get(&(pitch.letter, pitch.octave))
+        .copied()
+        .unwrap_or_else(|| key_signature_alteration(pitch.letter, key_signature));
+
+    if alteration != pitch.alteration {
+        abc_pitch.push(match pitch.alteration {
+            1 => '^',
+            -1 => '_',
+            _ => '=',
+        });
+        alterations.insert((pitch.letter, pitch.octave), pitch.alteration);
+    }
+
+    let letter = LETTER_NAMES[pitch.letter];
+
+    if pitch.octave >= 5 {
+        abc_pitch.push(letter.to_ascii_lowercase());
+        abc_pitch.extend(std::iter::repeat_n('\'', (pitch.octave - 5) as usize));
+    } else {
+        abc_pitch.push(letter);
+        abc_pitch.extend(std::iter::repeat_n(',', (4 - pitch.octave) as usize));
+    }
+
+    abc_pitch
+}
+
+/// A note, a chord in brackets or a rest
+fn abc_chord(
+    chord: &Chord,
+    key_signature: KeySignature,
+    alterations: &mut HashMap<(usize, i32), i8>,
+) -> String {
+    let length = abc_length(chord.duration);
+
+    let pitches = chord
+        .keys
+        .iter()
+        .map(|chord_key| {
+            let tie = if chord_key.tied { "-" } else { "" };
+            format!(
+                "{}{tie}",
+                abc_pitch(chord_key.key, key_signature, alterations)
+            )
+        })
+        .collect::<Vec<_>>();
+
+    match pitches.as_slice() {
+        [] => format!("z{length}"),
+        [pitch] => match pitch.strip_suffix('-') {
+            Some(pitch) => format!("{pitch}{length}-"),
+            None => format!("{pitch}{length}"),
+        },
+        _ => format!("[{}]{length}", pitches.concat()),
+    }
+}
+
+/// The score as an ABC tune, one voice per staff with its General MIDI
+/// program for abc2midi. Notes are beamed within beats.
+pub fn abc(score: &Score) -> String {
+    let (numerator, denominator) = score.time_signature;
+    let ticks_per_beat = (LMMS_TICKS_PER_BAR / denominator.max(1)).max(1);
+
+    let mut abc = String::from("X:1\n");
+
+    if let Some(ref title) = score.title {
+        writeln!(abc, "T:{}", abc_text(title)).unwrap();
+    }
+
+    writeln!(abc, "M:{numerator}/{denominator}").unwrap();
+    writeln!(abc, "L:1/8").unwrap();
+    writeln!(abc, "Q:1/4={}", score.bpm.round()).unwrap();
+    writeln!(abc, "K:{}", score.key_signature).unwrap();
+
+    for (voice_index, voice) in score.voices.iter().enumerate() {
+        let clef = if voice.is_percussion() {
+            "perc"
+        } else if voice.is_low() {
+            "bass"
+        } else {
+            "treble"
+        };
+
+        writeln!(
+            abc,
+            "V:{} name=\"{}\" clef={clef}",
+            voice_index + 1,
+            abc_text(&voice.name)
+        )
+        .unwrap();
+
+        if voice.is_percussion() {
+            writeln!(abc, "%%MIDI channel 10").unwrap();
+        } else {
+            writeln!(abc, "%%MIDI program {}", voice.midi_preset.program).unwrap();
+        }
+
+        let bars = score.bars(voice);
+
+        for (bar_index, bar) in bars.iter().enumerate() {
+            let mut alterations = HashMap::new();
+
+            for (chord_index, chord) in bar.iter().enumerate() {
+                if chord_index > 0 && chord.start % ticks_per_beat == 0 {
+                    abc.push(' ');
+                }
+
+                abc.push_str(&abc_chord(chord, score.key_signature, &mut alterations));
+            }
+
+            if bar_index == bars.len() - 1 {
+                abc.push_str(" |]\n");
+            } else if (bar_index + 1) % ABC_BARS_PER_LINE == 0 {
+                abc.push_str(" |\n");
+            } else {
+                abc.push_str(" | ");
+            }
+        }
+    }
+
+    abc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert::MidiPreset;
+    use crate::notation::{NotationNote, NotationVoice};
+
+    #[test]
+    fn accidentals_last_until_the_end_of_the_bar() {
+        let notes = [67, 66, 65, 65]
+            .into_iter()
+            .enumerate()
+            .map(|(note_index, key)| NotationNote {
+                start: note_index * 48,
+                end: (note_index + 1) * 48,
+                key,
+            })
+            .collect::<Vec<_>>();
+
+        let score = Score {
+            title: Some("Demo".to_string()),
+            bpm: 120.0,
+            time_signature: (4, 4),
+            key_signature: KeySignature {
+                sharps: 1,
+                minor: false,
+            },
+            voices: vec![NotationVoice::new(
+                "Lead",
+                0,
+                MidiPreset {
+                    bank: 0,
+                    program: 81,
+                },
+                &notes,
+            )],
+        };
+
+        assert_eq!(
+            abc(&score),
+            "X:1\nT:Demo\nM:4/4\nL:1/8\nQ:1/4=120\nK:G\n\
+             V:1 name=\"Lead\" clef=treble\n%%MIDI program 81\n\
+             G2 F2 =F2 F2 |]\n"
+        );
+    }
+
+    #[test]
+    fn lengths_are_multiples_of_an_eighth() {
+        assert_eq!(abc_length(24), "");
+        assert_eq!(abc_length(96), "4");
+        assert_eq!(abc_length(36), "3/2");
+        assert_eq!(abc_length(12), "/");
+        assert_eq!(abc_length(6), "/4");
+    }
+}
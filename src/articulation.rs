@@ -0,0 +1,154 @@
+use std::str::FromStr;
+
+use midly::num::{u4, u7};
+use midly::{MidiMessage, TrackEventKind};
+
+use crate::midi::AbsoluteTrackEvent;
+
+// Gate ratio = note length / time until the next note starts
+//
+// +----------------+-------------+-----------+---------------+
+// | Gate ratio     | Articulation| CC value  | Keyswitch key |
+// +----------------+-------------+-----------+---------------+
+// | <= 0.5         | Staccato    | 0         | 1st key       |
+// | 0.5 < r < 1.0  | Normal      | 64        | 2nd key       |
+// | >= 1.0         | Legato      | 127       | 3rd key       |
+// +----------------+-------------+-----------+---------------+
+
+const STACCATO_MAX_GATE_RATIO: f32 = 0.5;
+const LEGATO_MIN_GATE_RATIO: f32 = 1.0;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Articulation {
+    Staccato,
+    Normal,
+    Legato,
+}
+
+/// How articulation changes are marked in the MIDI output
+#[derive(Debug, Copy, Clone)]
+pub enum ArticulationMarker {
+    /// A controller change with the articulation's CC value
+    Controller(u7),
+
+    /// A short keyswitch note (staccato, normal and legato keys)
+    Keyswitch([u7; 3]),
+}
+
+impl FromStr for ArticulationMarker {
+    type Err = String;
+
+    /// Parses "cc:<controller>" or "keyswitch:<staccato key>,<normal key>,<legato key>"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_u7 = |value: &str| {
+            value
+                .trim()
+                .parse::<u8>()
+                .ok()
+                .filter(|value| *value < 128)
+                .map(u7::from)
+                .ok_or_else(|| format!("invalid MIDI value '{value}'"))
+        };
+
+        match s.split_once(':') {
+            Some(("cc", controller)) => Ok(ArticulationMarker::Controller(parse_u7(controller)?)),
+            Some(("keyswitch", keys)) => {
+                let keys = keys.split(',').map(parse_u7).collect::<Result<Vec<_>, _>>()?;
+                let keys = <[u7; 3]>::try_from(keys)
+                    .map_err(|_| "expected three keyswitch keys".to_owned())?;
+                Ok(ArticulationMarker::Keyswitch(keys))
+            }
+            _ => Err(format!(
+                "expected 'cc:<controller>' or 'keyswitch:<staccato>,<normal>,<legato>', found '{s}'"
+            )),
+        }
+    }
+}
+
+/// Infers the articulation of each note from its gate length.
+/// Notes are `(ticks_start, length)` pairs sorted by their start.
+pub fn infer_articulations(notes: &[(usize, usize)]) -> Vec<Articulation> {
+    let mut results = Vec::with_capacity(notes.len());
+
+    for (note_index, &(ticks_start, length)) in notes.iter().enumerate() {
+        let next_ticks_start = notes[note_index..]
+            .iter()
+            .map(|&(next_ticks_start, _)| next_ticks_start)
+            .find(|&next_ticks_start| next_ticks_start > ticks_start);
+
+        let articulation = match next_ticks_start {
+            Some(next_ticks_start) => {
+                let gate_ratio = length as f32 / (next_ticks_start - ticks_start) as f32;
+
+                if gate_ratio <= STACCATO_MAX_GATE_RATIO {
+                    Articulation::Staccato
+                } else if gate_ratio >= LEGATO_MIN_GATE_RATIO {
+                    Articulation::Legato
+                } else {
+                    Articulation::Normal
+                }
+            }
+            // The last notes keep the articulation of the phrase they end
+            None => results.last().copied().unwrap_or(Articulation::Normal),
+        };
+
+        results.push(articulation);
+    }
+
+    results
+}
+
+/// Events marking the start of an articulation region
+pub fn articulation_events(
+    ticks: usize,
+    articulation: Articulation,
+    articulation_marker: ArticulationMarker,
+    midi_channel: u4,
+) -> Vec<AbsoluteTrackEvent<'static>> {
+    let articulation_index = match articulation {
+        Articulation::Staccato => 0,
+        Articulation::Normal => 1,
+        Articulation::Legato => 2,
+    };
+
+    match articulation_marker {
+        ArticulationMarker::Controller(controller) => vec![AbsoluteTrackEvent {
+            ticks,
+            ticks_event_start: ticks,
+            kind: TrackEventKind::Midi {
+                channel: midi_channel,
+                message: MidiMessage::Controller {
+                    controller,
+                    value: u7::from([0, 64, 127][articulation_index]),
+                },
+            },
+            precise_value: None,
+        }],
+        ArticulationMarker::Keyswitch(keys) => vec![
+            AbsoluteTrackEvent {
+                ticks,
+                ticks_event_start: ticks,
+                kind: TrackEventKind::Midi {
+                    channel: midi_channel,
+                    message: MidiMessage::NoteOn {
+                        key: keys[articulation_index],
+                        vel: u7::from(100),
+                    },
+                },
+                precise_value: None,
+            },
+            AbsoluteTrackEvent {
+                ticks: ticks + 1,
+                ticks_event_start: ticks,
+                kind: TrackEventKind::Midi {
+                    channel: midi_channel,
+                    message: MidiMessage::NoteOff {
+                        key: keys[articulation_index],
+                        vel: u7::from(100),
+                    },
+                },
+                precise_value: None,
+            },
+        ],
+    }
+}
@@ -0,0 +1,172 @@
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+use crate::lmms_model::LmmsProject;
+use crate::{note, warning};
+
+/// A file that ends up in the bundle
+struct BundleEntry {
+    /// File name inside the bundle
+    name: String,
+
+    /// File contents
+    data: Vec<u8>,
+}
+
+/// Resolves an `sf2player`/`gigplayer` source path the same way LMMS does: absolute paths
+/// are used as-is, relative paths are looked up next to the project file.
+pub fn resolve_soundfont_path(project_path: &Path, soundfont_src: &str) -> Option<PathBuf> {
+    let soundfont_path = Path::new(soundfont_src);
+
+    if soundfont_path.is_absolute() {
+        return soundfont_path.is_file().then(|| soundfont_path.to_owned());
+    }
+
+    let project_dir = project_path.parent().unwrap_or_else(|| Path::new("."));
+    let soundfont_path = project_dir.join(soundfont_path);
+    soundfont_path.is_file().then_some(soundfont_path)
+}
+
+/// Packages the converted MIDI file together with the referenced soundfonts,
+/// a manifest and a playback note into a folder or a `.zip` archive.
+pub fn create_bundle(
+    bundle_path: &Path,
+    project_path: &Path,
+    midi_path: &Path,
+    lmms_project: &LmmsProject,
+) -> Result<(), Box<dyn Error>> {
+    let midi_name = file_name(midi_path)?;
+
+    let mut entries = vec![BundleEntry {
+        name: midi_name.clone(),
+        data: fs::read(midi_path)?,
+    }];
+
+    let mut manifest = String::new();
+    manifest.push_str(&format!("MIDI file: {midi_name}\n"));
+    manifest.push_str(&format!("Source project: {}\n", file_name(project_path)?));
+    manifest.push('\n');
+    manifest.push_str("Tracks:\n");
+
+    // (sf2player source path, file name inside the bundle or empty if missing)
+    let mut soundfont_names: Vec<(String, String)> = Vec::new();
+
+    for lmms_track in lmms_project.sampler_tracks() {
+        let (Some(sample_src), Some(bank), Some(patch)) = (
+            lmms_track.sample_src(),
+            lmms_track.bank(),
+            lmms_track.patch(),
+        ) else {
+            continue;
+        };
+
+        manifest.push_str(&format!(
+            "  {} -> {} (bank {}, patch {})\n",
+            lmms_track.name, sample_src, bank, patch,
+        ));
+
+        if sample_src.is_empty()
+            || soundfont_names
+                .iter()
+                .any(|(soundfont_src, _)| *soundfont_src == sample_src)
+        {
+            continue;
+        }
+
+        match resolve_soundfont_path(project_path, sample_src) {
+            Some(soundfont_path) => {
+                let soundfont_name = file_name(&soundfont_path)?;
+
+                if entries.iter().any(|entry| entry.name == soundfont_name) {
+                    warning!(
+                        code: "duplicate-soundfont-name";
+                        "soundfont file name '{}' is used by multiple soundfonts",
+                        soundfont_name.escape_default(),
+                    );
+                    note!("only the first one will be bundled");
+                    continue;
+                }
+
+                entries.push(BundleEntry {
+                    name: soundfont_name.clone(),
+                    data: fs::read(&soundfont_path)?,
+                });
+                soundfont_names.push((sample_src.to_owned(), soundfont_name));
+            }
+            None => {
+                warning!(
+                    code: "missing-soundfont";
+                    "soundfont '{}' could not be found",
+                    sample_src.escape_default(),
+                );
+                note!("the bundle will not contain this soundfont");
+                soundfont_names.push((sample_src.to_owned(), String::new()));
+            }
+        }
+    }
+
+    manifest.push('\n');
+    manifest.push_str("Soundfonts:\n");
+
+    for (soundfont_src, soundfont_name) in &soundfont_names {
+        if soundfont_name.is_empty() {
+            manifest.push_str(&format!("  {soundfont_src} (missing)\n"));
+        } else {
+            manifest.push_str(&format!("  {soundfont_name} (from {soundfont_src})\n"));
+        }
+    }
+
+    let mut playback_note = String::new();
+    playback_note.push_str(&format!(
+        "This package contains '{midi_name}', converted from an LMMS project.\n\n"
+    ));
+    playback_note.push_str(
+        "To hear the song the way it was written, load the bundled soundfont(s) into\n\
+         your MIDI player or synthesizer (e.g. FluidSynth, VLC, foobar2000) before\n\
+         playing the MIDI file:\n\n",
+    );
+    playback_note.push_str(&format!("    fluidsynth <soundfont.sf2> {midi_name}\n\n"));
+    playback_note.push_str("See MANIFEST.txt for the instruments used by each track.\n");
+
+    entries.push(BundleEntry {
+        name: "MANIFEST.txt".to_owned(),
+        data: manifest.into_bytes(),
+    });
+
+    entries.push(BundleEntry {
+        name: "README.txt".to_owned(),
+        data: playback_note.into_bytes(),
+    });
+
+    if bundle_path.extension().and_then(OsStr::to_str) == Some("zip") {
+        let mut zip_writer = ZipWriter::new(File::create(bundle_path)?);
+
+        for entry in &entries {
+            zip_writer.start_file(entry.name.as_str(), SimpleFileOptions::default())?;
+            zip_writer.write_all(&entry.data)?;
+        }
+
+        zip_writer.finish()?;
+    } else {
+        fs::create_dir_all(bundle_path)?;
+
+        for entry in &entries {
+            fs::write(bundle_path.join(&entry.name), &entry.data)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn file_name(path: &Path) -> Result<String, Box<dyn Error>> {
+    path.file_name()
+        .and_then(OsStr::to_str)
+        .map(str::to_owned)
+        .ok_or_else(|| format!("Invalid file name '{}'", path.display()).into())
+}
@@ -0,0 +1,158 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use midly::num::u4;
+
+use crate::convert::{ChannelAssignment, MidiPreset};
+use crate::lmms_model::LmmsProject;
+
+// Track to channel/program assignments shared by a series of projects, keyed
+// by track name. MIDI channels are numbered from 1.
+//
+//     ["Lead"]
+//     channels = [1]
+//     bank = 0
+//     program = 81
+//
+//     ["Drums"]
+//     channels = [10]
+//     bank = 128
+//     program = 0
+
+#[derive(Debug, Clone)]
+pub struct ChannelMapEntry {
+    pub midi_channels: Vec<u4>,
+    pub midi_preset: MidiPreset,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChannelMap {
+    /// Where the map is read from and written back to
+    path: PathBuf,
+
+    entries: Vec<(String, ChannelMapEntry)>,
+}
+
+impl ChannelMap {
+    /// Loads the map, or starts an empty one if the file does not exist yet
+    pub fn load_from_path(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut channel_map = ChannelMap {
+            path: path.to_owned(),
+            entries: Vec::new(),
+        };
+
+        if !path.exists() {
+            return Ok(channel_map);
+        }
+
+        let table = fs::read_to_string(path)?.parse::<toml::Table>()?;
+
+        for (track_name, value) in table {
+            let invalid_entry = || format!("Invalid channel map entry for '{track_name}'");
+
+            let integer = |key: &str| {
+                value
+                    .get(key)
+                    .and_then(toml::Value::as_integer)
+                    .ok_or_else(invalid_entry)
+            };
+
+            let midi_channels = value
+                .get("channels")
+                .and_then(toml::Value::as_array)
+                .ok_or_else(invalid_entry)?
+                .iter()
+                .map(|midi_channel| match midi_channel.as_integer() {
+                    Some(midi_channel @ 1..=16) => Ok(u4::from(midi_channel as u8 - 1)),
+                    _ => Err(invalid_entry()),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            if midi_channels.is_empty() {
+                return Err(invalid_entry().into());
+            }
+
+            let midi_preset = format!("{}:{}", integer("bank")?, integer("program")?).parse()?;
+
+            channel_map.entries.push((
+                track_name,
+                ChannelMapEntry {
+                    midi_channels,
+                    midi_preset,
+                },
+            ));
+        }
+
+        Ok(channel_map)
+    }
+
+    pub fn get(&self, track_name: &str) -> Option<&ChannelMapEntry> {
+        self.entries
+            .iter()
+            .find(|(entry_track_name, _)| entry_track_name == track_name)
+            .map(|(_, channel_map_entry)| channel_map_entry)
+    }
+
+    /// Records the assignments of a conversion and writes the map back to its
+    /// file, keeping the entries of tracks that are not part of this project
+    pub fn save(
+        &self,
+        lmms_project: &LmmsProject,
+        channel_assignments: &[ChannelAssignment],
+    ) -> Result<(), Box<dyn Error>> {
+        let mut entries = self.entries.clone();
+
+        for channel_assignment in channel_assignments {
+            let track_name = &channel_assignment.lmms_track(lmms_project).name;
+
+            let channel_map_entry = ChannelMapEntry {
+                midi_channels: channel_assignment.midi_channels.clone(),
+                midi_preset: channel_assignment.midi_preset,
+            };
+
+            match entries
+                .iter_mut()
+                .find(|(entry_track_name, _)| entry_track_name == track_name)
+            {
+                Some((_, existing_entry)) => *existing_entry = channel_map_entry,
+                None => entries.push((track_name.clone(), channel_map_entry)),
+            }
+        }
+
+        let mut table = toml::Table::new();
+
+        for (track_name, channel_map_entry) in entries {
+            let mut entry_table = toml::Table::new();
+
+            entry_table.insert(
+                "channels".to_owned(),
+                toml::Value::Array(
+                    channel_map_entry
+                        .midi_channels
+                        .iter()
+                        .map(|midi_channel| toml::Value::Integer(midi_channel.as_int() as i64 + 1))
+                        .collect(),
+                ),
+            );
+            entry_table.insert(
+                "bank".to_owned(),
+                toml::Value::Integer(channel_map_entry.midi_preset.bank as i64),
+            );
+            entry_table.insert(
+                "program".to_owned(),
+                toml::Value::Integer(channel_map_entry.midi_preset.program as i64),
+            );
+
+            table.insert(track_name, toml::Value::Table(entry_table));
+        }
+
+        fs::write(&self.path, table.to_string())?;
+        Ok(())
+    }
+}
+
+/// Command line value parser for channel map files
+pub fn parse_channel_map(s: &str) -> Result<ChannelMap, String> {
+    ChannelMap::load_from_path(Path::new(s)).map_err(|err| err.to_string())
+}
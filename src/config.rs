@@ -0,0 +1,173 @@
+use std::env;
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Args};
+
+use crate::convert::ConvertOptions;
+use crate::warning;
+
+// Defaults for the conversion options, keyed by their long option names.
+// Flags are booleans, repeatable options arrays, and per-track options may be
+// tables keyed by track name. Relative paths are relative to the file.
+//
+//     loop-style = ["emidi-global", "rpg-maker"]
+//     volume-curve = "gm-squared"
+//     target = "sc55"
+//     sustain-pedal = true
+//
+//     [instrument-map]
+//     "Lead" = "81"
+//     "Strings" = "0:48"
+
+pub const CONFIG_FILE_NAME: &str = "lmms2mid.toml";
+
+// Options whose values are files, read by their value parsers
+const CONFIG_PATH_OPTIONS: [&str; 5] = [
+    "lyrics",
+    "mt32-map-file",
+    "instrument-map-file",
+    "channel-map-file",
+    "drum-map",
+];
+
+/// Configuration files applying to a project, the user's own first and the
+/// one next to the project last, so that it takes precedence
+pub fn config_paths(project_dir: Option<&Path>) -> Vec<PathBuf> {
+    let user_config_dir = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .filter(|config_home| !config_home.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+    };
+
+    user_config_dir
+        .map(|user_config_dir| user_config_dir.join("lmms2mid").join(CONFIG_FILE_NAME))
+        .into_iter()
+        .chain(project_dir.map(|project_dir| project_dir.join(CONFIG_FILE_NAME)))
+        .filter(|config_path| config_path.is_file())
+        .collect()
+}
+
+/// A setting of a configuration file, with the file it came from
+struct ConfigValue {
+    config_dir: PathBuf,
+    value: toml::Value,
+}
+
+/// Command line arguments of a setting, e.g. `--loop-style=emidi-global`
+fn setting_args(
+    option_name: &str,
+    config_value: &ConfigValue,
+    args: &mut Vec<OsString>,
+) -> Result<(), String> {
+    let value_arg = |value: &str| -> OsString {
+        if CONFIG_PATH_OPTIONS.contains(&option_name) {
+            let mut arg = OsString::from(format!("--{option_name}="));
+            arg.push(config_value.config_dir.join(value));
+            arg
+        } else {
+            OsString::from(format!("--{option_name}={value}"))
+        }
+    };
+
+    let scalar_arg = |value: &toml::Value| match value {
+        toml::Value::String(s) => Ok(Some(value_arg(s))),
+        toml::Value::Integer(i) => Ok(Some(value_arg(&i.to_string()))),
+        toml::Value::Float(f) => Ok(Some(value_arg(&f.to_string()))),
+        toml::Value::Boolean(true) => Ok(Some(OsString::from(format!("--{option_name}")))),
+        toml::Value::Boolean(false) => Ok(None),
+        _ => Err(format!("invalid value for '{option_name}'")),
+    };
+
+    match &config_value.value {
+        toml::Value::Array(values) => {
+            for value in values {
+                args.extend(scalar_arg(value)?);
+            }
+        }
+        toml::Value::Table(table) => {
+            for (track_name, value) in table {
+                let value = match value {
+                    toml::Value::String(s) => s.clone(),
+                    toml::Value::Integer(_) | toml::Value::Float(_) => value.to_string(),
+                    _ => {
+                        return Err(format!(
+                            "invalid value for '{track_name}' of '{option_name}'"
+                        ))
+                    }
+                };
+
+                args.push(OsString::from(format!(
+                    "--{option_name}={track_name}={value}"
+                )));
+            }
+        }
+        value => args.extend(scalar_arg(value)?),
+    }
+
+    Ok(())
+}
+
+/// Command line arguments of the conversion options set in the configuration
+/// files and not on the command line, the later files overriding the earlier
+/// ones. Unknown settings are warned about and ignored.
+pub fn config_args(
+    config_paths: &[PathBuf],
+    matches: &ArgMatches,
+) -> Result<Vec<OsString>, Box<dyn Error>> {
+    let convert_command = ConvertOptions::augment_args(clap::Command::new("lmms2mid"));
+    let mut settings: Vec<(String, ConfigValue)> = Vec::new();
+
+    for config_path in config_paths {
+        let table = fs::read_to_string(config_path)?
+            .parse::<toml::Table>()
+            .map_err(|err| {
+                format!(
+                    "Invalid configuration file '{}': {err}",
+                    config_path.display()
+                )
+            })?;
+
+        let config_dir = config_path.parent().unwrap_or(Path::new(".")).to_owned();
+
+        for (key, value) in table {
+            let option_name = key.replace('_', "-");
+            let config_value = ConfigValue {
+                config_dir: config_dir.clone(),
+                value,
+            };
+
+            match settings.iter_mut().find(|(name, _)| *name == option_name) {
+                Some((_, setting)) => *setting = config_value,
+                None => settings.push((option_name, config_value)),
+            }
+        }
+    }
+
+    let mut args = Vec::new();
+
+    for (option_name, config_value) in &settings {
+        let Some(arg) = convert_command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(option_name))
+        else {
+            warning!(code: "unknown-setting"; "unknown setting '{option_name}' in the configuration file");
+            continue;
+        };
+
+        // Options given on the command line win
+        if matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine) {
+            continue;
+        }
+
+        setting_args(option_name, config_value, &mut args)?;
+    }
+
+    Ok(args)
+}
@@ -0,0 +1,303 @@
+use std::f32::consts::TAU;
+use std::ops::Range;
+
+use midly::num::{u4, u7};
+use midly::{MidiMessage, TrackEventKind};
+
+use crate::lmms_model::*;
+use crate::midi::*;
+use crate::{note, warning};
+
+// +------+------------------+
+// | Wave | LFO waveform     |
+// +------+------------------+
+// |    0 | Sine             |
+// |    1 | Triangle         |
+// |    2 | Saw              |
+// |    3 | Square           |
+// |    4 | Moog saw         |
+// |    5 | Exponential      |
+// |    6 | White noise      |
+// |    7 | User defined     |
+// +------+------------------+
+
+const LFO_WAVE_NAMES: &[&str] = &[
+    "sine",
+    "triangle",
+    "saw",
+    "square",
+    "moog saw",
+    "exponential",
+    "white noise",
+    "user defined",
+];
+
+// LFO and automation CC events are sampled every 1/32 note
+const LFO_CC_STEP: usize = LMMS_TICKS_PER_BAR / 32;
+
+/// A track parameter which can be driven by a controller
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ControlledParameter {
+    Volume,
+    Panning,
+    Pitch,
+}
+
+impl ControlledParameter {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ControlledParameter::Volume => "volume",
+            ControlledParameter::Panning => "panning",
+            ControlledParameter::Pitch => "pitch",
+        }
+    }
+}
+
+impl LmmsLfoController {
+    /// Period of one LFO cycle in seconds
+    pub fn period(&self) -> f32 {
+        let speed = self.speed.unwrap_or(2.0);
+
+        match self.multiplier.unwrap_or(0) {
+            1 => speed / 100.0,
+            2 => speed * 100.0,
+            _ => speed,
+        }
+    }
+
+    pub fn wave_name(&self) -> &'static str {
+        LFO_WAVE_NAMES
+            .get(self.wave.unwrap_or(0))
+            .copied()
+            .unwrap_or("unknown")
+    }
+
+    /// Controller output (0.0..=1.0) at the given time, or `None` for
+    /// waveforms which cannot be reproduced (noise, user defined)
+    pub fn value_at(&self, seconds: f32) -> Option<f32> {
+        let phase = (seconds / self.period() + self.phase.unwrap_or(0.0) / 360.0).fract();
+
+        let sample = match self.wave.unwrap_or(0) {
+            0 => (phase * TAU).sin(),
+            1 if phase < 0.25 => phase * 4.0,
+            1 if phase < 0.75 => 2.0 - phase * 4.0,
+            1 => phase * 4.0 - 4.0,
+            2 => phase * 2.0 - 1.0,
+            3 if phase <= 0.5 => 1.0,
+            3 => -1.0,
+            4 if phase < 0.5 => phase * 4.0 - 1.0,
+            4 => 1.0 - (phase - 0.5) * 4.0,
+            5 if phase < 0.5 => phase * phase * 8.0 - 1.0,
+            5 => (1.0 - phase) * (1.0 - phase) * 8.0 - 1.0,
+            _ => return None,
+        };
+
+        let value = self.base.unwrap_or(0.5) + self.amount.unwrap_or(1.0) * sample / 2.0;
+        Some(value.clamp(0.0, 1.0))
+    }
+}
+
+/// Controller ids connected to the track's own parameters
+pub fn controller_connections(lmms_track: &LmmsTrack) -> Vec<(ControlledParameter, usize)> {
+    let Some(lmms_connections) = lmms_track
+        .instrument_track
+        .as_ref()
+        .and_then(|instrument_track| instrument_track.connections.as_ref())
+    else {
+        return Vec::new();
+    };
+
+    [
+        (
+            ControlledParameter::Volume,
+            lmms_connections.volume.as_ref().and_then(|c| c.id),
+        ),
+        (
+            ControlledParameter::Panning,
+            lmms_connections.panning.as_ref().and_then(|c| c.id),
+        ),
+        (
+            ControlledParameter::Pitch,
+            lmms_connections.pitch.as_ref().and_then(|c| c.id),
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(parameter, controller_id)| Some((parameter, controller_id?)))
+    .collect()
+}
+
+/// Diagnostics for controller connections which cannot be exported
+pub fn check_controller_connections(lmms_project: &LmmsProject, lmms_track: &LmmsTrack) {
+    for (parameter, controller_id) in controller_connections(lmms_track) {
+        let reason = match lmms_project.controller(controller_id) {
+            Some(LmmsController::Lfo(lmms_lfo)) if lmms_lfo.value_at(0.0).is_none() => {
+                format!(
+                    "LFO '{}' uses a {} waveform",
+                    lmms_lfo.name.escape_default(),
+                    lmms_lfo.wave_name()
+                )
+            }
+            Some(LmmsController::Lfo(_)) => continue,
+            Some(LmmsController::Peak(lmms_peak)) => {
+                format!("'{}' is a peak controller", lmms_peak.name.escape_default())
+            }
+            Some(LmmsController::Midi(_)) | Some(LmmsController::Generic(_)) => {
+                "only LFO controllers are supported".to_owned()
+            }
+            None => format!("controller #{controller_id} does not exist"),
+        };
+
+        warning!(
+            code: "unsupported-controller", track: &lmms_track.name;
+            "{} controller of track '{}' cannot be exported",
+            parameter.name(),
+            lmms_track.name.escape_default(),
+        );
+        note!("{reason}");
+    }
+}
+
+/// CC events approximating the LFO controllers connected to the track,
+/// sampled over the given tick range
+pub fn controller_events(
+    lmms_project: &LmmsProject,
+    lmms_track: &LmmsTrack,
+    ticks_range: Range<usize>,
+    lfo_controller: u7,
+    dynamics_controller: u7,
+    pan_width: f32,
+    midi_channels: &[u4],
+) -> Vec<AbsoluteTrackEvent<'static>> {
+    let mut midi_track_events = Vec::new();
+
+    // LMMS ticks are 48 per quarter note
+    let seconds_per_tick = 60.0 / lmms_project.head.bpm as f32 / (LMMS_TICKS_PER_BAR / 4) as f32;
+
+    for (parameter, controller_id) in controller_connections(lmms_track) {
+        let Some(LmmsController::Lfo(lmms_lfo)) = lmms_project.controller(controller_id) else {
+            continue;
+        };
+
+        // Panning keeps its own controller, tremolo and vibrato go to the chosen ones
+        let controller = match parameter {
+            ControlledParameter::Panning => u7::from(MIDI_CC_PANNING),
+            ControlledParameter::Volume => dynamics_controller,
+            ControlledParameter::Pitch => lfo_controller,
+        };
+
+        let mut last_value = None;
+
+        for ticks in ticks_range.clone().step_by(LFO_CC_STEP) {
+            let Some(value) = lmms_lfo.value_at(ticks as f32 * seconds_per_tick) else {
+                break;
+            };
+
+            let value = match parameter {
+                ControlledParameter::Panning => 0.5 + (value - 0.5) * pan_width,
+                ControlledParameter::Volume | ControlledParameter::Pitch => value,
+            };
+
+            let unrounded_value = remap_clamp_range(value, 0.0..=1.0, 0.0..=127.0, |value| value);
+            let value = unrounded_value as u8;
+
+            if last_value == Some(value) {
+                continue;
+            }
+
+            last_value = Some(value);
+
+            for midi_channel in midi_channels {
+                midi_track_events.push(AbsoluteTrackEvent {
+                    ticks,
+                    ticks_event_start: ticks,
+                    kind: TrackEventKind::Midi {
+                        channel: *midi_channel,
+                        message: MidiMessage::Controller {
+                            controller,
+                            value: u7::from(value),
+                        },
+                    },
+                    precise_value: Some(precise_value(unrounded_value)),
+                });
+            }
+        }
+    }
+
+    midi_track_events
+}
+
+/// CC events following the automation of the track's volume knob. On the channel
+/// volume controller the automated volume replaces the fader value, on others it
+/// is relative to it.
+pub fn volume_automation_events(
+    lmms_project: &LmmsProject,
+    lmms_track: &LmmsTrack,
+    dynamics_controller: u7,
+    volume_curve: fn(f32) -> f32,
+    midi_channels: &[u4],
+) -> Vec<AbsoluteTrackEvent<'static>> {
+    let Some(instrument_track) = lmms_track.instrument_track() else {
+        return Vec::new();
+    };
+
+    let Some(ref volume_model) = instrument_track.volume_model else {
+        return Vec::new();
+    };
+
+    let automation_points = lmms_project.automation_points(volume_model.id);
+    let fader_volume = instrument_track.volume();
+
+    let mut midi_track_events = Vec::new();
+    let mut last_value = None;
+
+    for (point_index, point) in automation_points.iter().enumerate() {
+        let next_point = automation_points
+            .get(point_index + 1)
+            .filter(|next_point| point.linear && next_point.ticks > point.ticks);
+
+        let ticks_end = next_point.map_or(point.ticks + 1, |next_point| next_point.ticks);
+
+        for ticks in (point.ticks..ticks_end).step_by(LFO_CC_STEP) {
+            let volume = match next_point {
+                Some(next_point) => {
+                    let progress =
+                        (ticks - point.ticks) as f32 / (next_point.ticks - point.ticks) as f32;
+                    point.value + (next_point.value - point.value) * progress
+                }
+                None => point.value,
+            };
+
+            let volume = if dynamics_controller == MIDI_CC_VOLUME {
+                volume
+            } else {
+                volume / fader_volume * 100.0
+            };
+
+            let unrounded_value = remap_clamp_range(volume, 0.0..=100.0, 0.0..=127.0, volume_curve);
+            let value = unrounded_value as u8;
+
+            if last_value == Some(value) {
+                continue;
+            }
+
+            last_value = Some(value);
+
+            for midi_channel in midi_channels {
+                midi_track_events.push(AbsoluteTrackEvent {
+                    ticks,
+                    ticks_event_start: ticks,
+                    kind: TrackEventKind::Midi {
+                        channel: *midi_channel,
+                        message: MidiMessage::Controller {
+                            controller: dynamics_controller,
+                            value: u7::from(value),
+                        },
+                    },
+                    precise_value: Some(precise_value(unrounded_value)),
+                });
+            }
+        }
+    }
+
+    midi_track_events
+}
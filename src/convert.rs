@@ -0,0 +1,4453 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use std::str::FromStr;
+
+use clap::{Args, FromArgMatches, ValueEnum};
+use midly::num::{u15, u24, u28, u4, u7};
+use midly::{
+    Format, Fps, Header, MetaMessage, MidiMessage, Smf, Timing, Track, TrackEvent, TrackEventKind,
+};
+
+use crate::abc::abc;
+use crate::articulation::{articulation_events, infer_articulations, ArticulationMarker};
+use crate::channel_map::{parse_channel_map, ChannelMap};
+use crate::controllers::{
+    check_controller_connections, controller_events, volume_automation_events,
+};
+use crate::drums::{
+    guess_gm_drum_key, is_gm_drum_key, parse_drum_key, parse_drum_map, remap_gm_drum_key, DrumMap,
+};
+use crate::error::ConvertError;
+use crate::hmp::{hmp, HMP_TICKS_PER_SECOND};
+use crate::humanize::{humanize_notes, parse_humanize, Humanize};
+use crate::instrument_map::{parse_instrument_map, InstrumentMap};
+use crate::key_signature::{infer_key_signature, KeySignature, KeySignatureMode};
+use crate::lilypond::lilypond;
+use crate::lmms_model::*;
+use crate::location::SourceLocator;
+use crate::log::StrictClass;
+use crate::lyrics::{parse_lyrics, Karaoke, Lyrics, KARAOKE_FILE_ID};
+use crate::midi::*;
+use crate::midicsv::midicsv;
+use crate::mml::{mml, MmlDialect};
+use crate::mt32::{parse_mt32_map, Mt32Map};
+use crate::mus::{mus, MUS_TICKS_PER_SECOND};
+use crate::musicxml::musicxml;
+use crate::notation::{NotationNote, NotationVoice, Score};
+use crate::overlaps::{resolve_note_overlaps, NoteOverlap};
+use crate::polyphony::{
+    limit_polyphony, parse_channel_polyphony, PolyphonyLimits, PolyphonyPolicy,
+};
+use crate::portamento::portamento_events;
+use crate::progress::{self, Phase};
+use crate::rmid::rmid;
+use crate::rng::SplitMix64;
+use crate::roundtrip::roundtrip_metadata;
+use crate::sustain::synthesize_sustain;
+use crate::target::{BankSelect, Target, TargetProfile, MIDI_MELODIC_CHANNELS};
+use crate::text_encoding::TextEncoding;
+use crate::ump::{MidiClip, UMP_FLEX_COMPOSITION_NAME, UMP_FLEX_COPYRIGHT_NOTICE};
+use crate::xmi::{xmi, XMI_TICKS_PER_SECOND};
+use crate::{debug, info, note, warning};
+
+#[derive(Debug, Copy, Clone, ValueEnum)]
+pub enum MidiLoopStyle {
+    ///RPG Maker style loops with MIDI CC#111 events
+    RpgMaker,
+
+    /// EMIDI style loops with MIDI CC#116/117 events
+    EmidiLocal,
+
+    /// EMIDI style loops with MIDI CC#118/119 events
+    EmidiGlobal,
+
+    /// Final Fantasy style loops with "loopstart"/"loopend" MIDI marker events
+    FinalFantasy,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum LoopCcCollision {
+    /// Keep the loop CCs on channel 1 and report the colliding positions
+    #[default]
+    Warn,
+
+    /// Move the loop CCs to a channel without colliding controller events
+    Move,
+}
+
+#[derive(Debug, Default, Copy, Clone, ValueEnum)]
+pub enum SpreadMode {
+    /// Cycle through the channels note by note
+    #[default]
+    RoundRobin,
+
+    /// Split the track's key range into equally sized bands
+    PitchRange,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum GmDrumCheck {
+    /// Report percussion notes outside the GM drum map (keys 27-87)
+    Warn,
+
+    /// Report and move them onto the closest GM drum sound
+    Remap,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum MidiFormat {
+    /// Format 0, a single merged track for legacy players
+    #[default]
+    #[value(name = "0")]
+    SingleTrack,
+
+    /// Format 1, one track per LMMS track for editing in DAWs
+    #[value(name = "1")]
+    MultiTrack,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Standard MIDI File
+    #[default]
+    Midi,
+
+    /// MIDI 2.0 Clip File of Universal MIDI Packets, with 16-bit velocities and
+    /// 32-bit controller values (always a single clip)
+    #[value(name = "midi2")]
+    Midi2,
+
+    /// Standard MIDI File in a RIFF "RMID" container, with the song name,
+    /// copyright and comment in an INFO list
+    Rmid,
+
+    /// XMIDI of the Miles Sound System for DOS games, timed at 120 Hz
+    Xmi,
+
+    /// DMX MUS of Doom engines, timed at 140 Hz, with at most 15 melodic
+    /// channels and a score of up to 64 KiB
+    Mus,
+
+    /// HMP of the Human Machine Interfaces Sound Operating System (Descent and
+    /// other DOS games), timed at 120 Hz, with HMI loop and branch controllers
+    Hmp,
+
+    /// The Standard MIDI File as text in midicsv's CSV format, for diffing and
+    /// scripting, convertible back with csvmidi
+    Csv,
+
+    /// ABC notation with a voice per track, notes rounded to 1/32 notes
+    Abc,
+
+    /// Uncompressed MusicXML with a part per track, notes rounded to 1/32
+    /// notes, for MuseScore, Finale and other notation software
+    #[value(name = "musicxml")]
+    MusicXml,
+
+    /// LilyPond source with a staff per track in the project's key and time
+    /// signature, notes rounded to 1/32 notes
+    Ly,
+
+    /// Music Macro Language for the sound driver of `--mml-dialect`, notes
+    /// rounded to 1/32 notes
+    Mml,
+}
+
+impl OutputFormat {
+    /// File name extension of the format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Midi => "mid",
+            OutputFormat::Midi2 => "midi2",
+            OutputFormat::Rmid => "rmi",
+            OutputFormat::Xmi => "xmi",
+            OutputFormat::Mus => "mus",
+            OutputFormat::Hmp => "hmp",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Abc => "abc",
+            OutputFormat::MusicXml => "musicxml",
+            OutputFormat::Ly => "ly",
+            OutputFormat::Mml => "mml",
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum ChannelOverflow {
+    /// Drop the tracks that do not fit
+    Drop,
+
+    /// Let SF2 tracks with identical presets share a channel, drop the rest
+    #[default]
+    MergeSimilar,
+
+    /// Spill the tracks onto additional MIDI ports (requires `--midi-format 1`)
+    MultiPort,
+
+    /// Fail the conversion
+    Error,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum ResetSysEx {
+    /// General MIDI System On
+    Gm,
+
+    /// General MIDI Level 2 System On
+    Gm2,
+
+    /// Roland GS Reset
+    Gs,
+
+    /// Yamaha XG System On
+    Xg,
+
+    /// No reset message
+    #[default]
+    None,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum NoteOffStyle {
+    /// Note Off messages, keeping the release velocity
+    #[default]
+    #[value(name = "noteoff")]
+    NoteOff,
+
+    /// Note On messages with zero velocity, for old players and drivers
+    /// (also lets running status cover whole chords)
+    #[value(name = "noteon0")]
+    NoteOn0,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum ShortNotes {
+    /// Keep them and report them
+    #[default]
+    Keep,
+
+    /// Drop them
+    Drop,
+
+    /// Extend them to the minimum note length
+    Extend,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum VolumeCurve {
+    /// Square root, the inverse of the General MIDI 40*log10 volume response
+    #[default]
+    Sqrt,
+
+    /// Linear, for synths with a linear volume response
+    Linear,
+}
+
+impl VolumeCurve {
+    /// Transfer function from the LMMS track volume to the channel volume
+    pub fn transfer_fn(&self) -> fn(f32) -> f32 {
+        match self {
+            VolumeCurve::Sqrt => f32::sqrt,
+            VolumeCurve::Linear => |volume| volume,
+        }
+    }
+}
+
+/// Controller carrying volume changes during the song, the track fader is always
+/// sent once as the channel volume (CC#7)
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum DynamicsCc {
+    /// Channel volume (CC#7), replacing the fader value
+    #[value(name = "7")]
+    Volume,
+
+    /// Expression (CC#11), relative to the fader value so the mix stays adjustable
+    #[default]
+    #[value(name = "11")]
+    Expression,
+}
+
+impl DynamicsCc {
+    pub fn controller(&self) -> u7 {
+        match self {
+            DynamicsCc::Volume => u7::from(MIDI_CC_VOLUME),
+            DynamicsCc::Expression => u7::from(MIDI_CC_EXPRESSION),
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum PanLaw {
+    /// Balance law without center attenuation, same as LMMS
+    #[default]
+    Linear,
+
+    /// Constant power (sin/cos) law with -3 dB at the center, as used by GM synths
+    #[value(name = "-3dB")]
+    Minus3Db,
+
+    /// Linear gain law with -6 dB at the center
+    #[value(name = "-6dB")]
+    Minus6Db,
+}
+
+impl PanLaw {
+    /// Transfer function from the LMMS track panning to the channel panning,
+    /// keeping the LMMS left/right balance under the synth's pan law
+    pub fn transfer_fn(&self) -> fn(f32) -> f32 {
+        match self {
+            PanLaw::Linear => |panning| panning,
+            PanLaw::Minus3Db => |panning| {
+                let (left, right) = lmms_panning_gains(panning);
+                right.atan2(left) / std::f32::consts::FRAC_PI_2
+            },
+            PanLaw::Minus6Db => |panning| {
+                let (left, right) = lmms_panning_gains(panning);
+                right / (left + right)
+            },
+        }
+    }
+}
+
+/// Left and right channel gains of an LMMS panning, given in the 0.0-1.0 range
+fn lmms_panning_gains(panning: f32) -> (f32, f32) {
+    let left = (2.0 * (1.0 - panning)).min(1.0);
+    let right = (2.0 * panning).min(1.0);
+    (left, right)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum SmpteFps {
+    /// 24 frames per second, film
+    #[value(name = "24")]
+    Fps24,
+
+    /// 25 frames per second, PAL video
+    #[value(name = "25")]
+    Fps25,
+
+    /// 29.97 frames per second, NTSC video (drop frame)
+    #[value(name = "29.97")]
+    Fps29,
+
+    /// 30 frames per second, NTSC video (non-drop frame)
+    #[value(name = "30")]
+    Fps30,
+}
+
+impl SmpteFps {
+    pub fn fps(&self) -> Fps {
+        match self {
+            SmpteFps::Fps24 => Fps::Fps24,
+            SmpteFps::Fps25 => Fps::Fps25,
+            SmpteFps::Fps29 => Fps::Fps29,
+            SmpteFps::Fps30 => Fps::Fps30,
+        }
+    }
+}
+
+/// Time division of the output file
+#[derive(Debug, Copy, Clone)]
+pub enum OutputTiming {
+    /// Ticks per quarter note
+    Metrical(u16),
+
+    /// SMPTE frame rate and subframes per frame, at the song's tempo in BPM
+    Timecode(Fps, u8, f32),
+}
+
+impl OutputTiming {
+    pub fn timing(&self) -> Timing {
+        match *self {
+            OutputTiming::Metrical(ppqn) => Timing::Metrical(u15::from(ppqn)),
+            OutputTiming::Timecode(fps, subframes, _) => Timing::Timecode(fps, subframes),
+        }
+    }
+
+    /// Output ticks per LMMS tick
+    fn scale(&self) -> f64 {
+        let lmms_ppqn = (LMMS_TICKS_PER_BAR / 4) as f64;
+
+        match *self {
+            OutputTiming::Metrical(ppqn) => ppqn as f64 / lmms_ppqn,
+            OutputTiming::Timecode(fps, subframes, bpm) => {
+                fps.as_f32() as f64 * subframes as f64 * 60.0 / bpm as f64 / lmms_ppqn
+            }
+        }
+    }
+
+    /// Converts LMMS ticks to ticks of the output time division
+    pub fn ticks(&self, lmms_ticks: usize) -> usize {
+        (lmms_ticks as f64 * self.scale()).round() as usize
+    }
+
+    fn is_exact(&self, lmms_ticks: usize) -> bool {
+        let ticks = lmms_ticks as f64 * self.scale();
+        (ticks - ticks.round()).abs() < 1e-6
+    }
+
+    fn name(&self) -> String {
+        match *self {
+            OutputTiming::Metrical(ppqn) => format!("{ppqn} PPQN"),
+            OutputTiming::Timecode(fps, subframes, _) => {
+                format!("{} fps with {subframes} subframes", fps.as_f32())
+            }
+        }
+    }
+}
+
+/// Position in the song, in LMMS ticks or in bars of the project's time signature
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SongPosition {
+    Ticks(usize),
+    Bars(usize),
+}
+
+impl SongPosition {
+    pub fn lmms_ticks(&self, lmms_project: &LmmsProject) -> usize {
+        match *self {
+            SongPosition::Ticks(ticks) => ticks,
+            SongPosition::Bars(bars) => bars * lmms_project.lmms_ticks_per_bar(),
+        }
+    }
+}
+
+impl FromStr for SongPosition {
+    type Err = String;
+
+    /// Parses "ticks" or "<n>bars"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        match s.strip_suffix("bars").or_else(|| s.strip_suffix("bar")) {
+            Some(bars) => bars.trim().parse::<usize>().map(SongPosition::Bars),
+            None => s.parse::<usize>().map(SongPosition::Ticks),
+        }
+        .map_err(|_| format!("invalid song position '{s}', expected ticks or bars like \"2bars\""))
+    }
+}
+
+impl ResetSysEx {
+    pub fn sysex(&self) -> Option<&'static [u8]> {
+        match self {
+            ResetSysEx::Gm => Some(MIDI_SYSEX_GM_SYSTEM_ON),
+            ResetSysEx::Gm2 => Some(MIDI_SYSEX_GM2_SYSTEM_ON),
+            ResetSysEx::Gs => Some(MIDI_SYSEX_GS_RESET),
+            ResetSysEx::Xg => Some(MIDI_SYSEX_XG_SYSTEM_ON),
+            ResetSysEx::None => None,
+        }
+    }
+}
+
+/// Conversion settings, shared between the command line and the library API
+#[derive(Debug, clap::Args)]
+pub struct ConvertOptions {
+    /// File format of the output
+    #[arg(long, default_value = "midi")]
+    pub output_format: OutputFormat,
+
+    /// Standard MIDI File format of the output
+    #[arg(long, default_value = "0")]
+    pub midi_format: MidiFormat,
+
+    /// Sound driver the MML output is written for (`--output-format mml`)
+    #[arg(long, default_value = "ppmck")]
+    pub mml_dialect: MmlDialect,
+
+    /// Number of virtual MIDI ports (16 channels each) to spread tracks across,
+    /// marked with MIDI Port meta events (requires `--midi-format 1`) [default: 1]
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=128))]
+    pub midi_ports: Option<u8>,
+
+    /// What to do with tracks that do not fit into the available MIDI channels
+    #[arg(long, default_value = "merge-similar")]
+    pub overflow: ChannelOverflow,
+
+    /// Playback device whose constraints (polyphony, controllers, bank selection,
+    /// channels, file size and event rate) the output is fitted to and checked against
+    #[arg(long)]
+    pub target: Option<Target>,
+
+    /// Bank select messages sent with the Program Changes [default: full, or the target's]
+    #[arg(long)]
+    pub bank_select: Option<BankSelect>,
+
+    /// Replace a bank number when selecting it (e.g. "8=0")
+    #[arg(long, value_parser = parse_bank_map)]
+    pub bank_map: Vec<(usize, usize)>,
+
+    /// LRC file with timed lyrics to insert as Lyric events for karaoke players
+    #[arg(long, value_parser = parse_lyrics)]
+    pub lyrics: Option<Lyrics>,
+
+    /// Write the lyrics as a Soft Karaoke (.kar) file instead: "@" header texts and
+    /// syllables split at spaces and hyphens on a "Words" track (always multi-track)
+    #[arg(long, requires = "lyrics")]
+    pub karaoke: bool,
+
+    /// Insert Marker events ("bar 17") at every bar line
+    #[arg(long)]
+    pub bar_markers: bool,
+
+    /// Insert Cue Point events named after the patterns at their start positions
+    #[arg(long)]
+    pub pattern_cue_points: bool,
+
+    /// Store the LMMS track names, presets, timing resolution and loop points in a
+    /// Sequencer Specific meta event for tools reconstructing the project structure
+    #[arg(long)]
+    pub roundtrip_metadata: bool,
+
+    /// Encoding of the names and texts in meta events
+    #[arg(long, default_value = "utf8")]
+    pub text_encoding: TextEncoding,
+
+    /// Make MIDI channel 10 a melodic channel with a GS Part Mode SysEx when the
+    /// project has no percussion tracks, making room for a 16th instrument track
+    #[arg(long)]
+    pub gs_melodic_channel_10: bool,
+
+    /// TOML file overriding the built-in GM to MT-32 program and drum key remapping
+    /// of `--target mt32`
+    #[arg(long, value_parser = parse_mt32_map)]
+    pub mt32_map_file: Option<Mt32Map>,
+
+    /// System reset SysEx sent before the channels are set up [default: none, or the
+    /// target's]
+    #[arg(long)]
+    pub reset_sysex: Option<ResetSysEx>,
+
+    /// Key signature (e.g. "F#m", "Bb") or "auto" to infer it from the notes
+    #[arg(long)]
+    pub key_signature: Option<KeySignatureMode>,
+
+    /// Output resolution in ticks per quarter note, e.g. 480 or 960 [default: 48]
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..=0x7FFF))]
+    pub ppqn: Option<u16>,
+
+    /// Use SMPTE timecode timing at this frame rate instead of musical ticks
+    #[arg(long, conflicts_with = "ppqn")]
+    pub smpte_fps: Option<SmpteFps>,
+
+    /// Subframes per SMPTE frame (requires `--smpte-fps`) [default: 80]
+    #[arg(long, requires = "smpte_fps", value_parser = clap::value_parser!(u8).range(1..))]
+    pub smpte_subframes: Option<u8>,
+
+    /// How note ends are encoded
+    #[arg(long, default_value = "noteoff")]
+    pub noteoff_style: NoteOffStyle,
+
+    /// Loop style
+    #[arg(long)]
+    pub loop_style: Vec<MidiLoopStyle>,
+
+    /// Insert Marker events at the clip positions of skipped sample/automation tracks
+    #[arg(long)]
+    pub skipped_track_markers: bool,
+
+    /// What to do when loop CCs collide with the project's own controller events
+    #[arg(long, default_value = "warn")]
+    pub loop_cc_collision: LoopCcCollision,
+
+    /// Track name
+    #[arg(long)]
+    pub track_name: Option<String>,
+
+    /// Track copyright
+    #[arg(long)]
+    pub track_copyright: Option<String>,
+
+    /// Track comment
+    #[arg(long)]
+    pub track_comment: Option<String>,
+
+    /// Distribute an LMMS track's notes across multiple MIDI channels (e.g. "Piano=2")
+    #[arg(long, value_parser = parse_track_option::<usize>)]
+    pub spread: Vec<(String, usize)>,
+
+    /// Note distribution mode for spread tracks
+    #[arg(long, default_value = "round-robin")]
+    pub spread_mode: SpreadMode,
+
+    /// Map a non-sampler track to a GM program or bank:program (e.g. "Lead=81")
+    #[arg(long, value_parser = parse_track_option::<MidiPreset>)]
+    pub instrument_map: Vec<(String, MidiPreset)>,
+
+    /// TOML/CSV file mapping track or instrument names to GM programs
+    #[arg(long, value_parser = parse_instrument_map)]
+    pub instrument_map_file: Option<InstrumentMap>,
+
+    /// TOML file keeping track to channel/program assignments identical across a
+    /// series of projects, read if present and written after the conversion
+    #[arg(long, value_parser = parse_channel_map)]
+    pub channel_map_file: Option<ChannelMap>,
+
+    /// GM program or bank:program for VeSTige tracks without an instrument mapping
+    #[arg(long, default_value = "0")]
+    pub vst_program: MidiPreset,
+
+    /// GM program or bank:program for all other tracks without an instrument mapping
+    #[arg(long)]
+    pub default_program: Option<MidiPreset>,
+
+    /// Map AudioFileProcessor sample tracks onto GM percussion keys by their names
+    #[arg(long)]
+    pub sample_drums: bool,
+
+    /// GM percussion key of a sample track, overriding the name heuristic (e.g. "Kick 2=36")
+    #[arg(long, value_parser = parse_drum_key)]
+    pub drum_key: Vec<(String, u8)>,
+
+    /// Mark inferred staccato/legato regions of a track with a CC or keyswitch notes
+    /// (e.g. "Strings=cc:68", "Strings=keyswitch:24,25,26")
+    #[arg(long, value_parser = parse_track_option::<ArticulationMarker>)]
+    pub articulation: Vec<(String, ArticulationMarker)>,
+
+    /// MIDI CC approximating LFO controllers connected to a track's pitch
+    /// [default: 1, modulation wheel]
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..128))]
+    pub lfo_cc: Option<u8>,
+
+    /// TOML file remapping the drum keys of soundfonts with non-GM drum layouts
+    /// to GM percussion keys (e.g. `36 = 35`), applied to MIDI channel 10 notes
+    #[arg(long, value_parser = parse_drum_map)]
+    pub drum_map: Option<DrumMap>,
+
+    /// Validate MIDI channel 10 notes against the GM drum map, since most synths
+    /// leave undefined drum keys silent
+    #[arg(long)]
+    pub gm_drum_check: Option<GmDrumCheck>,
+
+    /// Scale all pan values toward (<100%) or away from (>100%) the center,
+    /// 0-200% [default: 100%]
+    #[arg(long, value_parser = parse_pan_width)]
+    pub pan_width: Option<f32>,
+
+    /// Volume response of the target synth the track volumes are mapped for
+    #[arg(long, default_value = "sqrt")]
+    pub volume_curve: VolumeCurve,
+
+    /// Controller for volume LFOs and volume automation
+    #[arg(long, default_value = "11")]
+    pub dynamics_cc: DynamicsCc,
+
+    /// Pan law of the target synth the track pannings are mapped for
+    #[arg(long, default_value = "linear", allow_hyphen_values = true)]
+    pub pan_law: PanLaw,
+
+    /// Transpose all non-percussion tracks by this many semitones
+    #[arg(long, allow_negative_numbers = true, default_value = "0")]
+    pub transpose: isize,
+
+    /// Transpose a track by this many semitones on top of `--transpose` (e.g. "Bass=-12")
+    #[arg(long, value_parser = parse_track_option::<isize>)]
+    pub track_transpose: Vec<(String, isize)>,
+
+    /// Multiply all note velocities by this factor, e.g. 1.5 to boost quiet projects
+    /// [default: 1.0]
+    #[arg(long, value_parser = parse_velocity_scale)]
+    pub velocity_scale: Option<f32>,
+
+    /// Raise note velocities below this value, keeping silent notes from turning
+    /// into zero velocity Note Ons (which many synths treat as Note Offs)
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..128))]
+    pub velocity_min: Option<u8>,
+
+    /// Replace the project tempo in BPM
+    #[arg(long, value_parser = parse_tempo)]
+    pub tempo: Option<f32>,
+
+    /// Speed up (>1.0) or slow down (<1.0) the song by scaling its tempo,
+    /// the note positions stay the same [default: 1.0]
+    #[arg(long, value_parser = parse_tempo_scale)]
+    pub tempo_scale: Option<f32>,
+
+    /// Convert only the bars from the first up to the second (e.g. "17..33" for
+    /// bars 17-32), clipping the notes crossing the boundaries
+    #[arg(long, value_parser = parse_bar_range)]
+    pub range: Option<Range<usize>>,
+
+    /// Shift the song so its first note starts right at the beginning, dropping
+    /// the leading silence
+    #[arg(long)]
+    pub trim_start: bool,
+
+    /// Where the first note starts when trimming, in LMMS ticks (48 per beat)
+    /// or bars (e.g. "1bar") [default: 0]
+    #[arg(long, requires = "trim_start")]
+    pub start_offset: Option<SongPosition>,
+
+    /// Snap note starts to a grid of this note value (e.g. "1/16", "1/12" for triplets)
+    #[arg(long, value_parser = parse_quantize)]
+    pub quantize: Option<usize>,
+
+    /// Snap note lengths to the quantization grid too (requires `--quantize`)
+    #[arg(long, requires = "quantize")]
+    pub quantize_lengths: bool,
+
+    /// Move notes and change their velocities by random amounts up to these
+    /// limits (e.g. "timing=±4,velocity=±10", timing in LMMS ticks)
+    #[arg(long, value_parser = parse_humanize)]
+    pub humanize: Option<Humanize>,
+
+    /// Seed of the humanization, the same seed gives the same output
+    #[arg(long, default_value_t = 1, requires = "humanize")]
+    pub seed: u64,
+
+    /// Play sustained chords of three or more notes as short notes held by the
+    /// sustain pedal (CC#64), lowering the number of held notes
+    #[arg(long)]
+    pub sustain_pedal: bool,
+
+    /// Glide between overlapping notes a step apart on monophonic tracks with
+    /// portamento (CC#65 on/off, CC#5 time), approximating LMMS slides on GS/XG synths
+    #[arg(long)]
+    pub portamento: bool,
+
+    /// Most controller events per beat for each controller, denser automation
+    /// curves are thinned keeping their endpoints and extrema
+    #[arg(long, value_parser = parse_max_cc_rate)]
+    pub max_cc_rate: Option<f32>,
+
+    /// What to do with zero length notes and notes shorter than a tick of the output
+    /// resolution, which end at the same tick they start on
+    #[arg(long, default_value = "keep")]
+    pub short_notes: ShortNotes,
+
+    /// Minimum note length in LMMS ticks (48 per quarter note) below which notes
+    /// are treated as short notes [default: 1]
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+    pub min_note_length: Option<u16>,
+
+    /// How overlapping notes of the same key on the same channel are resolved
+    #[arg(long, default_value = "keep")]
+    pub overlap: NoteOverlap,
+
+    /// Drop or shorten notes to stay within this many voices per MIDI port,
+    /// e.g. 24 for the SC-55
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+    pub max_polyphony: Option<u16>,
+
+    /// Drop or shorten notes to stay within this many voices on a MIDI channel,
+    /// e.g. "10=8" for the drum channel
+    #[arg(long, value_parser = parse_channel_polyphony)]
+    pub channel_polyphony: Vec<(u4, usize)>,
+
+    /// Which notes give way when a polyphony limit is exceeded
+    #[arg(long, default_value = "drop-oldest")]
+    pub polyphony_policy: PolyphonyPolicy,
+
+    /// Treat warnings of these classes as errors, failing the conversion
+    /// without writing the output, e.g. `--strict=polyphony,overlap`. Every
+    /// warning if no class is given.
+    #[arg(
+        long,
+        value_delimiter = ',',
+        num_args = 0..,
+        require_equals = true,
+        default_missing_value = "all"
+    )]
+    pub strict: Vec<StrictClass>,
+}
+
+// The defaults of the command line, for library callers to convert like
+// `lmms2mid` does
+impl Default for ConvertOptions {
+    fn default() -> Self {
+        ConvertOptions::augment_args(clap::Command::new("lmms2mid"))
+            .try_get_matches_from(["lmms2mid"])
+            .and_then(|matches| ConvertOptions::from_arg_matches(&matches))
+            .expect("every conversion option has a default")
+    }
+}
+
+impl ConvertOptions {
+    /// Output resolution, LMMS' own resolution unless chosen otherwise
+    pub fn ppqn(&self) -> u16 {
+        self.ppqn.unwrap_or((LMMS_TICKS_PER_BAR / 4) as u16)
+    }
+
+    /// Time division of the output, SMPTE timecode when a frame rate is chosen
+    pub fn output_timing(&self, lmms_project: &LmmsProject) -> OutputTiming {
+        match self.smpte_fps {
+            Some(smpte_fps) => OutputTiming::Timecode(
+                smpte_fps.fps(),
+                self.smpte_subframes.unwrap_or(80),
+                self.bpm(lmms_project),
+            ),
+            None => OutputTiming::Metrical(self.ppqn()),
+        }
+    }
+
+    /// Tempo of the output in BPM, the project tempo unless overridden or scaled
+    pub fn bpm(&self, lmms_project: &LmmsProject) -> f32 {
+        let bpm = self.tempo.unwrap_or(lmms_project.head.bpm as f32);
+        bpm * self.tempo_scale.unwrap_or(1.0)
+    }
+
+    pub fn target_profile(&self) -> Option<TargetProfile> {
+        self.target.map(|target| target.profile())
+    }
+
+    /// Bank select messages, the target's unless chosen explicitly
+    pub fn bank_select(&self) -> BankSelect {
+        self.bank_select
+            .or(self.target_profile().map(|profile| profile.bank_select))
+            .unwrap_or(BankSelect::MsbLsb)
+    }
+
+    /// Bank number sent for a bank after the user's remapping
+    pub fn mapped_bank(&self, bank: usize) -> usize {
+        self.bank_map
+            .iter()
+            .rev()
+            .find(|(from_bank, _)| *from_bank == bank)
+            .map_or(bank, |(_, to_bank)| *to_bank)
+    }
+
+    /// GM to MT-32 remapping of programs and drum keys, only for `--target mt32`
+    pub fn mt32_map(&self) -> Option<Mt32Map> {
+        (self.target == Some(Target::Mt32)).then(|| self.mt32_map_file.clone().unwrap_or_default())
+    }
+
+    /// System reset SysEx, the target's unless chosen explicitly
+    pub fn reset_sysex(&self) -> ResetSysEx {
+        self.reset_sysex
+            .or(self.target_profile().map(|profile| profile.reset_sysex))
+            .unwrap_or_default()
+    }
+
+    /// MIDI channels melodic tracks can be assigned to on each port
+    pub fn melodic_channels(&self, lmms_project: &LmmsProject) -> Vec<u8> {
+        let mut melodic_channels = self
+            .target_profile()
+            .map_or(MIDI_MELODIC_CHANNELS, |profile| profile.melodic_channels)
+            .to_vec();
+
+        // Channel 10 is the last resort
+        if self.is_channel_10_melodic(lmms_project) {
+            melodic_channels.push(9);
+        }
+
+        melodic_channels
+    }
+
+    /// Number of MIDI channels melodic tracks can be assigned to across all ports
+    pub fn melodic_channel_capacity(&self, lmms_project: &LmmsProject) -> usize {
+        self.melodic_channels(lmms_project).len() * self.midi_port_count()
+    }
+
+    /// Whether channel 10 is switched to a melodic part, only possible without
+    /// percussion tracks
+    pub fn is_channel_10_melodic(&self, lmms_project: &LmmsProject) -> bool {
+        self.gs_melodic_channel_10 && !has_percussion_tracks(lmms_project, self)
+    }
+
+    /// Voice budgets of every MIDI port
+    pub fn polyphony_limits(&self) -> PolyphonyLimits {
+        let mut polyphony_limits = PolyphonyLimits {
+            voices: self
+                .max_polyphony
+                .map(usize::from)
+                .or(self.target_profile().map(|profile| profile.voices)),
+            ..Default::default()
+        };
+
+        for (midi_channel, voices) in &self.channel_polyphony {
+            polyphony_limits.channel_voices[midi_channel.as_int() as usize] = Some(*voices);
+        }
+
+        polyphony_limits
+    }
+
+    /// Number of usable MIDI ports, single track files only have one
+    pub fn midi_port_count(&self) -> usize {
+        match (self.midi_format, self.overflow) {
+            (MidiFormat::SingleTrack, _) => 1,
+            // Unused ports cost nothing, so spilling tracks is only limited explicitly
+            (MidiFormat::MultiTrack, ChannelOverflow::MultiPort) => {
+                self.midi_ports.map_or(MIDI_MAX_PORTS, usize::from)
+            }
+            (MidiFormat::MultiTrack, _) => self.midi_ports.map_or(1, usize::from),
+        }
+    }
+
+    /// Pan scale factor, 1.0 keeps the LMMS panning as-is
+    pub fn pan_width(&self) -> f32 {
+        self.pan_width.unwrap_or(1.0)
+    }
+
+    /// Maps an LMMS note volume (0-200%) to a MIDI note velocity
+    pub fn note_velocity(&self, note_volume: usize) -> u7 {
+        u7::from(self.unrounded_note_velocity(note_volume) as u8)
+    }
+
+    /// Note velocity at MIDI 2.0 resolution, see `precise_value`
+    pub fn precise_note_velocity(&self, note_volume: usize) -> u32 {
+        precise_value(self.unrounded_note_velocity(note_volume))
+    }
+
+    fn unrounded_note_velocity(&self, note_volume: usize) -> f32 {
+        let note_velocity = remap_clamp_range(
+            note_volume as f32 * self.velocity_scale.unwrap_or(1.0),
+            0.0..=200.0,
+            0.0..=127.0,
+            |velocity| velocity,
+        );
+
+        note_velocity.max(self.velocity_min.unwrap_or(0) as f32)
+    }
+
+    /// Number of MIDI channels an LMMS track is spread across
+    pub fn track_spread(&self, track_name: &str) -> usize {
+        self.spread
+            .iter()
+            .rev()
+            .find(|(spread_track_name, _)| spread_track_name == track_name)
+            .map_or(1, |(_, spread)| (*spread).max(1))
+    }
+
+    /// Semitones the notes of an LMMS track are transposed by, percussion
+    /// tracks are only transposed explicitly
+    pub fn track_transpose(&self, lmms_track: &LmmsTrack, midi_preset: MidiPreset) -> isize {
+        let transpose = if midi_preset.is_percussion() {
+            0
+        } else {
+            self.transpose
+        };
+
+        transpose
+            + self
+                .track_transpose
+                .iter()
+                .rev()
+                .find(|(track_name, _)| *track_name == lmms_track.name)
+                .map_or(0, |(_, track_transpose)| *track_transpose)
+    }
+
+    pub fn track_articulation(&self, track_name: &str) -> Option<ArticulationMarker> {
+        self.articulation
+            .iter()
+            .rev()
+            .find(|(articulation_track_name, _)| articulation_track_name == track_name)
+            .map(|(_, articulation_marker)| *articulation_marker)
+    }
+
+    /// GM percussion key an AudioFileProcessor sample track is mapped to
+    pub fn sample_drum_key(&self, lmms_track: &LmmsTrack) -> Option<u7> {
+        if !self.sample_drums || lmms_track.audio_file_name().is_none() {
+            return None;
+        }
+
+        self.drum_key
+            .iter()
+            .rev()
+            .find(|(track_name, _)| *track_name == lmms_track.name)
+            .map(|(_, key)| *key)
+            .or_else(|| guess_gm_drum_key(&lmms_track.name))
+            .or_else(|| lmms_track.audio_file_name().and_then(guess_gm_drum_key))
+            .map(u7::from)
+    }
+
+    /// The MIDI bank and program an LMMS track will be converted with, if any
+    pub fn track_preset(&self, lmms_track: &LmmsTrack) -> Option<MidiPreset> {
+        lmms_track.instrument_track.as_ref()?;
+
+        if lmms_track.is_sampler_track() {
+            return Some(MidiPreset {
+                bank: lmms_track.bank()?,
+                program: lmms_track.patch()?,
+            });
+        }
+
+        self.mapped_preset(lmms_track)
+            .or_else(|| lmms_track.is_vestige_track().then_some(self.vst_program))
+            .or_else(|| {
+                self.default_program
+                    .filter(|_| self.sample_drum_key(lmms_track).is_none())
+            })
+    }
+
+    /// The MIDI bank and program the user mapped an LMMS track to
+    pub fn mapped_preset(&self, lmms_track: &LmmsTrack) -> Option<MidiPreset> {
+        lmms_track.instrument_track.as_ref()?;
+
+        self.instrument_map
+            .iter()
+            .rev()
+            .find(|(track_name, _)| *track_name == lmms_track.name)
+            .map(|(_, midi_preset)| *midi_preset)
+            .or_else(|| {
+                self.instrument_map_file
+                    .as_ref()
+                    .and_then(|instrument_map| {
+                        let mut names = vec![lmms_track.name.as_str()];
+                        names.extend(lmms_track.vst_plugin_name());
+                        names.extend(
+                            lmms_track
+                                .instrument()
+                                .map(|instrument| instrument.name.as_str()),
+                        );
+                        instrument_map.get(&names)
+                    })
+            })
+    }
+}
+
+/// MIDI bank and program of a channel
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct MidiPreset {
+    pub bank: usize,
+    pub program: usize,
+}
+
+impl MidiPreset {
+    pub fn is_percussion(&self) -> bool {
+        self.bank == 128
+    }
+}
+
+impl FromStr for MidiPreset {
+    type Err = String;
+
+    /// Parses "program" or "bank:program"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (bank, program) = s.split_once(':').unwrap_or(("0", s));
+
+        let bank = bank
+            .trim()
+            .parse::<usize>()
+            .ok()
+            .filter(|bank| *bank < 0x4000)
+            .ok_or_else(|| format!("invalid bank '{bank}'"))?;
+
+        let program = program
+            .trim()
+            .parse::<usize>()
+            .ok()
+            .filter(|program| *program < 128)
+            .ok_or_else(|| format!("invalid program '{program}'"))?;
+
+        Ok(MidiPreset { bank, program })
+    }
+}
+
+/// Command line value parser for pan widths ("50%", "150")
+pub fn parse_pan_width(s: &str) -> Result<f32, String> {
+    s.trim()
+        .trim_end_matches('%')
+        .parse::<f32>()
+        .ok()
+        .filter(|pan_width| (0.0..=200.0).contains(pan_width))
+        .map(|pan_width| pan_width / 100.0)
+        .ok_or_else(|| format!("invalid pan width '{s}', expected 0-200%"))
+}
+
+/// Command line value parser for tempos in BPM ("120", "92.5")
+pub fn parse_tempo(s: &str) -> Result<f32, String> {
+    s.trim()
+        .parse::<f32>()
+        .ok()
+        .filter(|tempo| (4.0..=1000.0).contains(tempo))
+        .ok_or_else(|| format!("invalid tempo '{s}', expected 4-1000 BPM"))
+}
+
+/// Command line value parser for tempo scale factors ("0.5", "1.25")
+pub fn parse_tempo_scale(s: &str) -> Result<f32, String> {
+    s.trim()
+        .parse::<f32>()
+        .ok()
+        .filter(|tempo_scale| tempo_scale.is_finite() && *tempo_scale > 0.0)
+        .ok_or_else(|| format!("invalid tempo scale '{s}', expected a positive factor"))
+}
+
+/// Command line value parser for quantization grids ("1/16", "1/8"), returns
+/// the grid size in LMMS ticks
+pub fn parse_quantize(s: &str) -> Result<usize, String> {
+    let (numerator, denominator) = s.trim().split_once('/').unwrap_or((s.trim(), "1"));
+
+    numerator
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .zip(denominator.trim().parse::<usize>().ok())
+        .filter(|(numerator, denominator)| {
+            *numerator > 0
+                && *denominator > 0
+                && (numerator * LMMS_TICKS_PER_BAR).is_multiple_of(*denominator)
+        })
+        .map(|(numerator, denominator)| numerator * LMMS_TICKS_PER_BAR / denominator)
+        .ok_or_else(|| format!("invalid quantization grid '{s}', expected a note value like 1/16"))
+}
+
+/// Command line value parser for controller event rates ("8")
+pub fn parse_max_cc_rate(s: &str) -> Result<f32, String> {
+    s.trim()
+        .parse::<f32>()
+        .ok()
+        .filter(|max_cc_rate| max_cc_rate.is_finite() && *max_cc_rate > 0.0)
+        .ok_or_else(|| format!("invalid controller event rate '{s}', expected events per beat"))
+}
+
+/// Command line value parser for velocity scale factors ("1.5")
+pub fn parse_velocity_scale(s: &str) -> Result<f32, String> {
+    s.trim()
+        .parse::<f32>()
+        .ok()
+        .filter(|velocity_scale| velocity_scale.is_finite() && *velocity_scale >= 0.0)
+        .ok_or_else(|| format!("invalid velocity scale '{s}', expected a non-negative factor"))
+}
+
+/// Command line value parser for bank remappings ("8=0")
+pub fn parse_bank_map(s: &str) -> Result<(usize, usize), String> {
+    let parse_bank = |bank: &str| {
+        bank.trim()
+            .parse::<usize>()
+            .ok()
+            .filter(|bank| *bank < 0x4000)
+            .ok_or_else(|| format!("invalid bank '{bank}'"))
+    };
+
+    let (from_bank, to_bank) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected '<bank>=<bank>', found '{s}'"))?;
+
+    Ok((parse_bank(from_bank)?, parse_bank(to_bank)?))
+}
+
+/// Command line value parser for bar ranges ("17..33"), bars are numbered from 1
+pub fn parse_bar_range(s: &str) -> Result<Range<usize>, String> {
+    let (start_bar, end_bar) = s
+        .split_once("..")
+        .ok_or_else(|| format!("expected '<start bar>..<end bar>', found '{s}'"))?;
+
+    let parse_bar = |bar: &str| {
+        bar.trim()
+            .parse::<usize>()
+            .ok()
+            .filter(|bar| *bar > 0)
+            .ok_or_else(|| format!("invalid bar '{bar}', bars are numbered from 1"))
+    };
+
+    let bar_range = parse_bar(start_bar)?..parse_bar(end_bar)?;
+
+    if bar_range.is_empty() {
+        return Err(format!("empty bar range '{s}'"));
+    }
+
+    Ok(bar_range)
+}
+
+pub fn parse_track_option<T>(s: &str) -> Result<(String, T), String>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    let (track_name, value) = s
+        .rsplit_once('=')
+        .ok_or_else(|| format!("expected '<track name>=<value>', found '{s}'"))?;
+
+    let value = value
+        .trim()
+        .parse()
+        .map_err(|err| format!("invalid value '{value}': {err}"))?;
+
+    Ok((track_name.to_owned(), value))
+}
+
+/// The MIDI channels an LMMS track was assigned to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelAssignment {
+    /// Index of the LMMS track in the song's track container
+    pub track_index: usize,
+
+    /// MIDI channels the track's notes are distributed across
+    pub midi_channels: Vec<u4>,
+
+    /// Bank and program selected on the MIDI channels
+    pub midi_preset: MidiPreset,
+
+    /// Percussion key all notes are played on (sample drum tracks)
+    pub drum_key: Option<u7>,
+
+    /// Virtual MIDI port of the channels, 0 unless multiple ports are used
+    pub midi_port: usize,
+}
+
+impl ChannelAssignment {
+    pub fn lmms_track<'a>(&self, lmms_project: &'a LmmsProject) -> &'a LmmsTrack {
+        &lmms_project.song.track_container.tracks[self.track_index]
+    }
+}
+
+/// LMMS tracks that can be converted, along with their track index and MIDI preset
+pub fn convertible_tracks<'a>(
+    lmms_project: &'a LmmsProject,
+    options: &'a ConvertOptions,
+) -> impl Iterator<Item = (usize, &'a LmmsTrack, MidiPreset)> + 'a {
+    lmms_project
+        .song
+        .track_container
+        .tracks
+        .iter()
+        .enumerate()
+        .filter_map(|(track_index, lmms_track)| {
+            options
+                .track_preset(lmms_track)
+                .map(|midi_preset| (track_index, lmms_track, midi_preset))
+        })
+}
+
+/// Whether the project has tracks converted onto the percussion channel
+pub fn has_percussion_tracks(lmms_project: &LmmsProject, options: &ConvertOptions) -> bool {
+    convertible_tracks(lmms_project, options).any(|(_, _, midi_preset)| midi_preset.is_percussion())
+        || lmms_project
+            .song
+            .track_container
+            .tracks
+            .iter()
+            .filter(|lmms_track| options.track_preset(lmms_track).is_none())
+            .any(|lmms_track| options.sample_drum_key(lmms_track).is_some())
+}
+
+/// Melodic SF2 tracks sharing the MIDI channel of an earlier track with the identical
+/// soundfont preset, as `(track index, shared track index)` pairs. Tracks are only
+/// merged when the project has more melodic tracks than available MIDI channels.
+pub fn merged_tracks(lmms_project: &LmmsProject, options: &ConvertOptions) -> Vec<(usize, usize)> {
+    let melodic_tracks = convertible_tracks(lmms_project, options)
+        .filter(|(_, _, midi_preset)| !midi_preset.is_percussion())
+        .collect::<Vec<_>>();
+
+    let lmms_instrument_channel_count = melodic_tracks
+        .iter()
+        .map(|(_, lmms_track, _)| options.track_spread(&lmms_track.name))
+        .sum::<usize>();
+
+    if options.overflow != ChannelOverflow::MergeSimilar
+        || lmms_instrument_channel_count <= options.melodic_channel_capacity(lmms_project)
+    {
+        return Vec::new();
+    }
+
+    let is_mergeable = |lmms_track: &LmmsTrack| {
+        lmms_track.is_sampler_track() && options.track_spread(&lmms_track.name) == 1
+    };
+
+    let mut results = Vec::new();
+
+    for (track_position, (track_index, lmms_track, midi_preset)) in
+        melodic_tracks.iter().enumerate()
+    {
+        if !is_mergeable(lmms_track) {
+            continue;
+        }
+
+        let shared_track = melodic_tracks[..track_position].iter().find(
+            |(shared_track_index, shared_lmms_track, shared_midi_preset)| {
+                is_mergeable(shared_lmms_track)
+                    && shared_midi_preset == midi_preset
+                    && shared_lmms_track.sample_src() == lmms_track.sample_src()
+                    && !results
+                        .iter()
+                        .any(|(merged_track_index, _)| merged_track_index == shared_track_index)
+            },
+        );
+
+        if let Some((shared_track_index, _, _)) = shared_track {
+            results.push((*track_index, *shared_track_index));
+        }
+    }
+
+    results
+}
+
+/// Sanity check for LMMS instrument/percussion track counts
+pub fn check_project(lmms_project: &LmmsProject, options: &ConvertOptions) {
+    let merged_tracks = merged_tracks(lmms_project, options);
+
+    for (track_index, shared_track_index) in &merged_tracks {
+        let tracks = &lmms_project.song.track_container.tracks;
+
+        note!(
+            "LMMS track '{}' shares the MIDI channel of '{}' (identical soundfont preset)",
+            tracks[*track_index].name.escape_default(),
+            tracks[*shared_track_index].name.escape_default(),
+        );
+    }
+
+    let lmms_instrument_channel_count = convertible_tracks(lmms_project, options)
+        .filter(|(_, _, midi_preset)| !midi_preset.is_percussion())
+        .map(|(_, lmms_track, _)| options.track_spread(&lmms_track.name))
+        .sum::<usize>()
+        - merged_tracks.len();
+
+    let lmms_instrument_channel_capacity = options.melodic_channel_capacity(lmms_project);
+
+    if options.gs_melodic_channel_10 && has_percussion_tracks(lmms_project, options) {
+        warning!(code: "melodic-channel-10"; "MIDI channel 10 cannot be made melodic, the project has percussion tracks");
+    }
+
+    if lmms_instrument_channel_count > lmms_instrument_channel_capacity {
+        warning!(code: "channel-overflow"; "LMMS project has more instrument tracks than available MIDI channels ({lmms_instrument_channel_count}/{lmms_instrument_channel_capacity})");
+
+        if options.overflow == ChannelOverflow::Error {
+            note!("the conversion will fail, use `--overflow` to choose another strategy");
+        } else {
+            note!("unassignable instrument tracks will be dropped");
+        }
+    }
+
+    if (options.midi_ports.is_some_and(|midi_ports| midi_ports > 1)
+        || options.overflow == ChannelOverflow::MultiPort)
+        && options.midi_format == MidiFormat::SingleTrack
+    {
+        warning!(code: "midi-ports-format"; "MIDI ports are only supported in multi-track files");
+        note!("use `--midi-format 1`, only the first port will be used otherwise");
+    }
+
+    for (track_name, _) in &options.spread {
+        let matching_tracks = convertible_tracks(lmms_project, options)
+            .filter(|(_, lmms_track, _)| lmms_track.name == *track_name)
+            .collect::<Vec<_>>();
+
+        if matching_tracks.is_empty() {
+            warning!(
+                code: "spread-track-not-found", track: track_name;
+                "no convertible track named '{}' to spread",
+                track_name.escape_default()
+            );
+        }
+
+        if matching_tracks
+            .iter()
+            .any(|(_, _, midi_preset)| midi_preset.is_percussion())
+        {
+            warning!(
+                code: "spread-percussion", track: track_name;
+                "percussion track '{}' cannot be spread",
+                track_name.escape_default()
+            );
+            note!("percussion tracks always use MIDI channel 10");
+        }
+    }
+
+    for (_, lmms_track, _) in convertible_tracks(lmms_project, options) {
+        check_controller_connections(lmms_project, lmms_track);
+    }
+
+    for lmms_track in &lmms_project.song.track_container.tracks {
+        if lmms_track.instrument_track.is_none() {
+            continue;
+        }
+
+        if lmms_track.is_vestige_track() && options.mapped_preset(lmms_track).is_none() {
+            warning!(
+                code: "no-instrument-mapping", track: &lmms_track.name;
+                "VeSTige track '{}' ({}) has no instrument mapping",
+                lmms_track.name.escape_default(),
+                lmms_track
+                    .vst_plugin_name()
+                    .unwrap_or_default()
+                    .escape_default(),
+            );
+            note!(
+                "using program {}:{}, use `--vst-program` or an instrument map to change it",
+                options.vst_program.bank,
+                options.vst_program.program,
+            );
+        }
+
+        if options.sample_drums
+            && lmms_track.audio_file_name().is_some()
+            && options.track_preset(lmms_track).is_none()
+        {
+            if options.sample_drum_key(lmms_track).is_none() {
+                warning!(
+                    code: "unknown-drum-key", track: &lmms_track.name;
+                    "could not guess the GM percussion key of sample track '{}' ({})",
+                    lmms_track.name.escape_default(),
+                    lmms_track
+                        .audio_file_name()
+                        .unwrap_or_default()
+                        .escape_default(),
+                );
+                note!("use `--drum-key \"<track name>=<key>\"` to convert this track, it will be dropped otherwise");
+            }
+
+            continue;
+        }
+
+        if !lmms_track.is_sampler_track()
+            && !lmms_track.is_vestige_track()
+            && options.mapped_preset(lmms_track).is_none()
+        {
+            let instrument_name = if lmms_track.is_opulenz_track() {
+                "OpulenZ"
+            } else {
+                lmms_track
+                    .instrument()
+                    .map_or("", |instrument| &instrument.name)
+            };
+
+            let code = if options.default_program.is_some() {
+                "no-instrument-mapping"
+            } else {
+                "dropped-track"
+            };
+
+            warning!(
+                code: code, track: &lmms_track.name;
+                "{} track '{}' has no instrument mapping",
+                instrument_name,
+                lmms_track.name.escape_default()
+            );
+
+            if let Some(default_program) = options.default_program {
+                note!(
+                    "using the default program {}:{}",
+                    default_program.bank,
+                    default_program.program,
+                );
+            } else {
+                note!("use `--instrument-map \"<track name>=<program>\"`, `--instrument-map-file` or `--default-program` to convert this track, it will be dropped otherwise");
+            }
+        }
+    }
+
+    check_timeline(lmms_project, options);
+    report_skipped_tracks(lmms_project);
+
+    if lmms_project.ticks_per_bar() != LMMS_TICKS_PER_BAR {
+        warning!(
+            code: "timebase";
+            "LMMS project uses a timebase of {} ticks per bar",
+            lmms_project.ticks_per_bar(),
+        );
+        note!("positions will be rescaled to {LMMS_TICKS_PER_BAR} ticks per bar");
+    }
+
+    let lmms_percussion_tracks = convertible_tracks(lmms_project, options)
+        .filter(|(_, _, midi_preset)| midi_preset.is_percussion())
+        .collect::<Vec<_>>();
+
+    if let [(_, first_lmms_track, first_midi_preset), other_percussion_tracks @ ..] =
+        &lmms_percussion_tracks[..]
+    {
+        for (_, lmms_track, midi_preset) in other_percussion_tracks {
+            warning!(
+                code: "merged-percussion", track: &lmms_track.name;
+                "percussion track '{}' is merged onto MIDI channel 10",
+                lmms_track.name.escape_default(),
+            );
+
+            if midi_preset.program == first_midi_preset.program {
+                note!(
+                    "it uses the same drum kit ({}) as '{}'",
+                    midi_preset.program,
+                    first_lmms_track.name.escape_default(),
+                );
+            } else {
+                note!(
+                    "its drum kit ({}) is replaced by the kit of '{}' ({}), whose ProgramChange wins",
+                    midi_preset.program,
+                    first_lmms_track.name.escape_default(),
+                    first_midi_preset.program,
+                );
+            }
+        }
+    }
+}
+
+/// LMMS tracks without notes, which the conversion skips
+pub fn skipped_tracks(lmms_project: &LmmsProject) -> impl Iterator<Item = &LmmsTrack> {
+    let global_automation_tracks =
+        lmms_project
+            .song
+            .global_automation_tracks
+            .iter()
+            .filter(|lmms_track| {
+                lmms_track
+                    .automation_patterns
+                    .iter()
+                    .any(|lmms_automation_pattern| !lmms_automation_pattern.points.is_empty())
+            });
+
+    lmms_project
+        .song
+        .track_container
+        .tracks
+        .iter()
+        .filter(|lmms_track| lmms_track.instrument_track.is_none())
+        .chain(global_automation_tracks)
+}
+
+/// Why a track without notes is skipped, along with its clip count
+pub(crate) fn skipped_track_reason(lmms_track: &LmmsTrack) -> (&'static str, &'static str, usize) {
+    match lmms_track.r#type {
+        LMMS_TRACK_TYPE_SAMPLE => (
+            "sample track",
+            "audio clips cannot be represented in MIDI",
+            lmms_track.sample_clips.len(),
+        ),
+        LMMS_TRACK_TYPE_AUTOMATION | LMMS_TRACK_TYPE_HIDDEN_AUTOMATION => (
+            "automation track",
+            "only the bank and patch automation of SF2 players is exported",
+            lmms_track.automation_patterns.len(),
+        ),
+        LMMS_TRACK_TYPE_PATTERN => (
+            "beat/bassline track",
+            "beat/bassline tracks are not supported",
+            lmms_track.pattern_clips.len(),
+        ),
+        _ => ("track", "unsupported track type", 0),
+    }
+}
+
+fn report_skipped_tracks(lmms_project: &LmmsProject) {
+    let skipped_tracks = skipped_tracks(lmms_project).collect::<Vec<_>>();
+
+    if skipped_tracks.is_empty() {
+        return;
+    }
+
+    warning!(
+        code: "skipped-tracks";
+        "{} LMMS tracks without notes were skipped",
+        skipped_tracks.len()
+    );
+
+    for lmms_track in skipped_tracks {
+        let (track_type, reason, clip_count) = skipped_track_reason(lmms_track);
+
+        note!(
+            "{} '{}' (type {}, {} clips): {}",
+            track_type,
+            lmms_track.name.escape_default(),
+            lmms_track.r#type,
+            clip_count,
+            reason,
+        );
+    }
+}
+
+/// Placeholder marker texts at the clip positions of skipped tracks
+pub fn skipped_track_markers(lmms_project: &LmmsProject) -> Vec<(usize, String)> {
+    let mut results = Vec::new();
+
+    for lmms_track in skipped_tracks(lmms_project) {
+        for lmms_sample_clip in &lmms_track.sample_clips {
+            let sample_name = lmms_sample_clip
+                .src
+                .rsplit(['/', '\\'])
+                .next()
+                .unwrap_or_default();
+
+            results.push((
+                lmms_project.to_lmms_ticks(lmms_sample_clip.position),
+                format!("{}: {}", lmms_track.name, sample_name),
+            ));
+        }
+
+        for lmms_automation_pattern in &lmms_track.automation_patterns {
+            if lmms_automation_pattern.points.is_empty() {
+                continue;
+            }
+
+            results.push((
+                lmms_project.to_lmms_ticks(lmms_automation_pattern.position),
+                format!("{}: {}", lmms_track.name, lmms_automation_pattern.name),
+            ));
+        }
+
+        for lmms_pattern_clip in &lmms_track.pattern_clips {
+            results.push((
+                lmms_project.to_lmms_ticks(lmms_pattern_clip.position),
+                format!("{}: {}", lmms_track.name, lmms_pattern_clip.name),
+            ));
+        }
+    }
+
+    results
+}
+
+/// Cue point texts at the start of each pattern of the converted tracks, named
+/// after the pattern or its track if unnamed
+pub fn pattern_cue_points(
+    lmms_project: &LmmsProject,
+    channel_assignments: &[ChannelAssignment],
+) -> Vec<(usize, String)> {
+    let mut results = Vec::new();
+
+    for channel_assignment in channel_assignments {
+        let lmms_track = channel_assignment.lmms_track(lmms_project);
+
+        for lmms_pattern in &lmms_track.patterns {
+            if lmms_pattern.notes.is_empty() {
+                continue;
+            }
+
+            let cue_point = (
+                lmms_project.to_lmms_ticks(lmms_pattern.position),
+                if lmms_pattern.name.is_empty() {
+                    lmms_track.name.clone()
+                } else {
+                    lmms_pattern.name.clone()
+                },
+            );
+
+            // Tracks sharing a channel repeat the same patterns
+            if !results.contains(&cue_point) {
+                results.push(cue_point);
+            }
+        }
+    }
+
+    results
+}
+
+/// Diagnostics for missing, duplicate or oddly encoded loop points
+fn check_timeline(lmms_project: &LmmsProject, options: &ConvertOptions) {
+    if options.loop_style.is_empty() {
+        return;
+    }
+
+    let timelines = &lmms_project.song.timelines;
+    let ticks_per_bar = lmms_project.ticks_per_bar();
+    let loop_points = lmms_project.loop_points();
+
+    if timelines.is_empty() {
+        warning!(code: "no-timeline"; "LMMS project has no timeline, loop points default to the song start");
+        return;
+    }
+
+    if timelines.len() > 1 {
+        let conflicting_timelines = timelines
+            .iter()
+            .skip(1)
+            .filter(|lmms_timeline| {
+                lmms_timeline.loop_points(ticks_per_bar) != timelines[0].loop_points(ticks_per_bar)
+            })
+            .count();
+
+        if conflicting_timelines > 0 {
+            warning!(
+                code: "conflicting-timelines";
+                "LMMS project has {} song-level timelines with conflicting loop points",
+                timelines.len(),
+            );
+            note!(
+                "using the first timeline (loop {}..{})",
+                loop_points.start,
+                loop_points.end,
+            );
+        }
+    }
+
+    let lmms_timeline = &timelines[0];
+
+    if lmms_timeline.loop_start.fract() != 0.0 || lmms_timeline.loop_end.fract() != 0.0 {
+        warning!(
+            code: "loop-points";
+            "fractional loop positions ({}..{}) were rounded to whole ticks",
+            lmms_timeline.loop_start,
+            lmms_timeline.loop_end,
+        );
+    }
+
+    if lmms_timeline.loop_start > lmms_timeline.loop_end {
+        warning!(code: "loop-points"; "loop start is after the loop end, the loop points were swapped");
+    }
+
+    if loop_points.converted_from_bars {
+        warning!(
+            code: "loop-points";
+            "loop positions ({}..{}) look like bar numbers, converted to ticks ({}..{})",
+            lmms_timeline.loop_start,
+            lmms_timeline.loop_end,
+            loop_points.start,
+            loop_points.end,
+        );
+    }
+
+    if loop_points.start == loop_points.end {
+        warning!(
+            code: "loop-points",
+            tick: loop_points.start,
+            position: lmms_project.musical_position(loop_points.start);
+            "LMMS project has an empty loop"
+        );
+    }
+}
+
+/// LMMS track -> MIDI channel assignment
+pub fn assign_channels(
+    lmms_project: &LmmsProject,
+    options: &ConvertOptions,
+) -> Vec<ChannelAssignment> {
+    let mut results: Vec<ChannelAssignment> = Vec::new();
+
+    // Tracks pinned by the channel map file
+    if let Some(channel_map) = &options.channel_map_file {
+        for (track_index, lmms_track, _) in convertible_tracks(lmms_project, options) {
+            let Some(channel_map_entry) = channel_map.get(&lmms_track.name) else {
+                continue;
+            };
+
+            let conflicting_assignment = results.iter().find(|channel_assignment| {
+                channel_assignment.midi_preset != channel_map_entry.midi_preset
+                    && channel_assignment
+                        .midi_channels
+                        .iter()
+                        .any(|midi_channel| channel_map_entry.midi_channels.contains(midi_channel))
+            });
+
+            if let Some(conflicting_assignment) = conflicting_assignment {
+                warning!(
+                    code: "channel-map-collision", track: &lmms_track.name;
+                    "channel map entry of LMMS track '{}' collides with '{}'",
+                    lmms_track.name.escape_default(),
+                    conflicting_assignment
+                        .lmms_track(lmms_project)
+                        .name
+                        .escape_default(),
+                );
+                note!("the track will be assigned a channel automatically");
+                continue;
+            }
+
+            if channel_map_entry.midi_channels.contains(&u4::from(9))
+                != channel_map_entry.midi_preset.is_percussion()
+            {
+                warning!(
+                    code: "channel-map-percussion", track: &lmms_track.name;
+                    "channel map entry of LMMS track '{}' mixes up MIDI channel 10 and percussion",
+                    lmms_track.name.escape_default(),
+                );
+                note!("the track will be assigned a channel automatically");
+                continue;
+            }
+
+            results.push(ChannelAssignment {
+                track_index,
+                midi_channels: channel_map_entry.midi_channels.clone(),
+                midi_preset: channel_map_entry.midi_preset,
+                drum_key: None,
+                midi_port: 0,
+            });
+        }
+    }
+
+    let pinned_tracks = results
+        .iter()
+        .map(|channel_assignment| channel_assignment.track_index)
+        .collect::<Vec<_>>();
+
+    // Instrument tracks
+    {
+        let pinned_channels = results
+            .iter()
+            .flat_map(|channel_assignment| channel_assignment.midi_channels.clone())
+            .collect::<Vec<_>>();
+
+        // Free MIDI channels of each port, channel map entries are pinned to the first port
+        let mut port_channels = (0..options.midi_port_count())
+            .map(|midi_port| {
+                options
+                    .melodic_channels(lmms_project)
+                    .into_iter()
+                    .map(u4::from)
+                    .filter(|midi_channel| {
+                        midi_port != 0 || !pinned_channels.contains(midi_channel)
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let merged_tracks = merged_tracks(lmms_project, options);
+
+        for (track_index, lmms_track, midi_preset) in convertible_tracks(lmms_project, options)
+            .filter(|(_, _, midi_preset)| !midi_preset.is_percussion())
+            .filter(|(track_index, _, _)| !pinned_tracks.contains(track_index))
+        {
+            let shared_channels = merged_tracks
+                .iter()
+                .find(|(merged_track_index, _)| *merged_track_index == track_index)
+                .and_then(|(_, shared_track_index)| {
+                    results.iter().find(|channel_assignment| {
+                        channel_assignment.track_index == *shared_track_index
+                    })
+                })
+                .map(|channel_assignment| {
+                    (
+                        channel_assignment.midi_port,
+                        channel_assignment.midi_channels.clone(),
+                    )
+                });
+
+            if let Some((shared_port, shared_channels)) = shared_channels {
+                results.push(ChannelAssignment {
+                    track_index,
+                    midi_channels: shared_channels,
+                    midi_preset,
+                    drum_key: None,
+                    midi_port: shared_port,
+                });
+                continue;
+            }
+
+            // A track's channels never span multiple ports, it goes to the
+            // first port with room for all of them or else to the roomiest one
+            let spread = options.track_spread(&lmms_track.name);
+            let Some((track_port, free_channels)) = port_channels
+                .iter_mut()
+                .enumerate()
+                .filter(|(_, free_channels)| !free_channels.is_empty())
+                .max_by_key(|(midi_port, free_channels)| {
+                    (free_channels.len().min(spread), Reverse(*midi_port))
+                })
+            else {
+                continue;
+            };
+
+            let track_channels = free_channels
+                .drain(..spread.min(free_channels.len()))
+                .collect::<Vec<_>>();
+
+            if track_channels.len() < spread {
+                warning!(
+                    code: "spread-channels", track: &lmms_track.name;
+                    "LMMS track '{}' could only be spread across {}/{} MIDI channels",
+                    lmms_track.name.escape_default(),
+                    track_channels.len(),
+                    spread,
+                );
+            }
+
+            results.push(ChannelAssignment {
+                track_index,
+                midi_channels: track_channels,
+                midi_preset,
+                drum_key: None,
+                midi_port: track_port,
+            });
+        }
+    }
+
+    // Percussion tracks, merged onto channel 10
+    results.extend(
+        convertible_tracks(lmms_project, options)
+            .filter(|(_, _, midi_preset)| midi_preset.is_percussion())
+            .filter(|(track_index, _, _)| !pinned_tracks.contains(track_index))
+            .map(|(track_index, _, midi_preset)| ChannelAssignment {
+                track_index,
+                midi_channels: vec![u4::from(9)],
+                midi_preset,
+                drum_key: None,
+                midi_port: 0,
+            }),
+    );
+
+    // Sample drum tracks, sharing the percussion channel
+    {
+        let midi_preset = results
+            .iter()
+            .find(|channel_assignment| channel_assignment.midi_preset.is_percussion())
+            .map_or(
+                MidiPreset {
+                    bank: 128,
+                    program: 0,
+                },
+                |channel_assignment| channel_assignment.midi_preset,
+            );
+
+        for (track_index, lmms_track) in lmms_project.song.track_container.tracks.iter().enumerate()
+        {
+            if options.track_preset(lmms_track).is_some() {
+                continue;
+            }
+
+            if let Some(drum_key) = options.sample_drum_key(lmms_track) {
+                results.push(ChannelAssignment {
+                    track_index,
+                    midi_channels: vec![u4::from(9)],
+                    midi_preset,
+                    drum_key: Some(drum_key),
+                    midi_port: 0,
+                });
+            }
+        }
+    }
+
+    results.sort_by_key(|channel_assignment| {
+        (
+            channel_assignment.midi_port,
+            channel_assignment.midi_channels[0],
+        )
+    });
+    results
+}
+
+/// Generates the note events of a single LMMS track
+pub fn convert_track_notes(
+    lmms_project: &LmmsProject,
+    options: &ConvertOptions,
+    channel_assignment: &ChannelAssignment,
+) -> Result<Vec<AbsoluteTrackEvent<'static>>, ConvertError> {
+    let lmms_track = channel_assignment.lmms_track(lmms_project);
+    let instrument_track = lmms_track
+        .instrument_track()
+        .ok_or_else(|| ConvertError::NoInstrument(lmms_track.name.clone()))?;
+    let midi_channels = &channel_assignment.midi_channels;
+    let mut midi_track_events = Vec::new();
+
+    let mut lmms_notes = lmms_track
+        .patterns
+        .iter()
+        .flat_map(|lmms_pattern| {
+            lmms_pattern.notes.iter().filter_map(move |lmms_note| {
+                let (note_position, note_length) =
+                    lmms_pattern.note_span(lmms_note, lmms_project.ticks_per_bar())?;
+                let position = lmms_pattern.position + note_position;
+
+                Some((
+                    lmms_project.to_lmms_ticks(position),
+                    lmms_project.to_lmms_ticks(position + note_length),
+                    lmms_note,
+                ))
+            })
+        })
+        .collect::<Vec<_>>();
+
+    if let Some(grid) = options.quantize {
+        let mut quantized_notes = 0;
+
+        for (ticks_start, ticks_end, _) in &mut lmms_notes {
+            let snap = |ticks: usize| (ticks + grid / 2) / grid * grid;
+
+            let quantized_start = snap(*ticks_start);
+            let quantized_end = if options.quantize_lengths {
+                quantized_start + snap(*ticks_end - *ticks_start).max(grid)
+            } else {
+                quantized_start + (*ticks_end - *ticks_start)
+            };
+
+            if (quantized_start, quantized_end) != (*ticks_start, *ticks_end) {
+                quantized_notes += 1;
+            }
+
+            *ticks_start = quantized_start;
+            *ticks_end = quantized_end;
+        }
+
+        if quantized_notes > 0 {
+            note!(
+                "{} notes of track '{}' were moved by quantization",
+                quantized_notes,
+                lmms_track.name.escape_default(),
+            );
+        }
+    }
+
+    {
+        let output_timing = options.output_timing(lmms_project);
+        let min_note_length = options.min_note_length.map_or(1, usize::from);
+
+        let is_short_note = |ticks_start: usize, ticks_end: usize| {
+            ticks_end < ticks_start + min_note_length
+                || output_timing.ticks(ticks_end) == output_timing.ticks(ticks_start)
+        };
+
+        let short_notes = lmms_notes
+            .iter()
+            .filter(|(ticks_start, ticks_end, _)| is_short_note(*ticks_start, *ticks_end))
+            .count();
+
+        if short_notes > 0 {
+            match options.short_notes {
+                ShortNotes::Keep => {
+                    warning!(
+                        code: "short-notes", track: &lmms_track.name;
+                        "track '{}' has {} notes shorter than {} ticks or a tick of {}",
+                        lmms_track.name.escape_default(),
+                        short_notes,
+                        min_note_length,
+                        output_timing.name(),
+                    );
+                    note!("some players mishandle these, use `--short-notes drop` or `--short-notes extend` to repair them");
+                }
+                ShortNotes::Drop => {
+                    lmms_notes.retain(|(ticks_start, ticks_end, _)| {
+                        !is_short_note(*ticks_start, *ticks_end)
+                    });
+
+                    note!(
+                        "dropped {} short notes of track '{}'",
+                        short_notes,
+                        lmms_track.name.escape_default(),
+                    );
+                }
+                ShortNotes::Extend => {
+                    for (ticks_start, ticks_end, _) in &mut lmms_notes {
+                        while is_short_note(*ticks_start, *ticks_end) {
+                            *ticks_end += 1;
+                        }
+                    }
+
+                    note!(
+                        "extended {} short notes of track '{}'",
+                        short_notes,
+                        lmms_track.name.escape_default(),
+                    );
+                }
+            }
+        }
+    }
+
+    lmms_notes.sort_by_key(|(ticks_start, _ticks_end, _lmms_note)| *ticks_start);
+
+    let ticks_range = lmms_notes
+        .first()
+        .map_or(0, |(ticks_start, _, _)| *ticks_start)
+        ..lmms_notes
+            .iter()
+            .map(|(_, ticks_end, _)| *ticks_end)
+            .max()
+            .unwrap_or(0);
+
+    let (min_key, max_key) = lmms_notes.iter().fold(
+        (usize::MAX, usize::MIN),
+        |(min_key, max_key), (_, _, lmms_note)| {
+            (min_key.min(lmms_note.key), max_key.max(lmms_note.key))
+        },
+    );
+
+    let articulation_marker = options.track_articulation(&lmms_track.name);
+    let articulations = infer_articulations(
+        &lmms_notes
+            .iter()
+            .map(|(ticks_start, ticks_end, _)| (*ticks_start, ticks_end - ticks_start))
+            .collect::<Vec<_>>(),
+    );
+    let mut current_articulation = None;
+
+    let transpose = options.track_transpose(lmms_track, channel_assignment.midi_preset);
+    let mt32_map = options.mt32_map();
+    let is_percussion_channel = |midi_channel: u4| {
+        midi_channel == u4::from(9) && !options.is_channel_10_melodic(lmms_project)
+    };
+    let mut out_of_range_notes = 0;
+    let mut midi_notes = Vec::new();
+
+    for (note_index, (ticks_start, ticks_end, lmms_note)) in lmms_notes.into_iter().enumerate() {
+        if let Some(articulation_marker) = articulation_marker {
+            let articulation = articulations[note_index];
+
+            if current_articulation != Some(articulation) {
+                for midi_channel in midi_channels {
+                    midi_track_events.extend(articulation_events(
+                        ticks_start,
+                        articulation,
+                        articulation_marker,
+                        *midi_channel,
+                    ));
+                }
+
+                current_articulation = Some(articulation);
+            }
+        }
+
+        let midi_channel = match options.spread_mode {
+            SpreadMode::RoundRobin => midi_channels[note_index % midi_channels.len()],
+            SpreadMode::PitchRange => {
+                let key_range = max_key - min_key + 1;
+                midi_channels[(lmms_note.key - min_key) * midi_channels.len() / key_range]
+            }
+        };
+
+        let mut note_key = lmms_note.key as isize;
+        note_key += 69 - instrument_track.base_note as isize;
+
+        if instrument_track.use_master_pitch == 1 {
+            note_key += lmms_project.head.master_pitch;
+        };
+
+        note_key += transpose;
+
+        if let Some(drum_key) = channel_assignment.drum_key {
+            note_key = drum_key.as_int() as isize;
+        }
+
+        if !(0..128).contains(&note_key) {
+            out_of_range_notes += 1;
+            continue;
+        }
+
+        // Sample drum keys are GM percussion keys already
+        if let Some(ref drum_map) = options.drum_map {
+            if is_percussion_channel(midi_channel) && channel_assignment.drum_key.is_none() {
+                note_key = drum_map.get(note_key as u8) as isize;
+            }
+        }
+
+        if let Some(gm_drum_check) = options.gm_drum_check {
+            if is_percussion_channel(midi_channel) && !is_gm_drum_key(note_key as u8) {
+                let source_location = SourceLocator::new(lmms_project, [channel_assignment], 0)
+                    .locate(midi_channel, ticks_start);
+
+                warning!(
+                    code: "gm-drum-map",
+                    tick: ticks_start,
+                    position: lmms_project.musical_position(ticks_start),
+                    track: &lmms_track.name,
+                    pattern: source_location.pattern_name(),
+                    key: note_key as u8;
+                    "percussion note {} of track '{}' is outside the GM drum map",
+                    note_key,
+                    lmms_track.name.escape_default(),
+                );
+
+                if gm_drum_check == GmDrumCheck::Remap {
+                    let remapped_key = remap_gm_drum_key(note_key as u8);
+                    note!("remapped to {remapped_key}");
+                    note_key = remapped_key as isize;
+                }
+            }
+        }
+
+        if let Some(ref mt32_map) = mt32_map {
+            if is_percussion_channel(midi_channel) {
+                note_key = mt32_map.drum_key(note_key as u8) as isize;
+            }
+        }
+
+        midi_notes.push(MidiNote {
+            ticks_start,
+            ticks_end,
+            channel: midi_channel,
+            key: u7::from(note_key as u8),
+            velocity: options.note_velocity(lmms_note.volume),
+            precise_velocity: options.precise_note_velocity(lmms_note.volume),
+        });
+    }
+
+    if out_of_range_notes > 0 {
+        warning!(
+            code: "key-range", track: &lmms_track.name;
+            "{} notes of track '{}' outside the MIDI key range were dropped",
+            out_of_range_notes,
+            lmms_track.name.escape_default(),
+        );
+    }
+
+    if let Some(humanize) = options.humanize {
+        // Seeded per track, so re-exporting a single track gives the same result
+        let mut rng = SplitMix64::new(
+            options
+                .seed
+                .wrapping_add(channel_assignment.track_index as u64),
+        );
+        humanize_notes(&mut midi_notes, humanize, &mut rng);
+    }
+
+    let resolved_overlaps = resolve_note_overlaps(&mut midi_notes, options.overlap, midi_channels);
+
+    if resolved_overlaps > 0 {
+        note!(
+            "resolved {} note overlaps of track '{}'",
+            resolved_overlaps,
+            lmms_track.name.escape_default(),
+        );
+    }
+
+    if options.portamento {
+        midi_track_events.extend(portamento_events(&midi_notes, is_percussion_channel));
+    }
+
+    if options.sustain_pedal {
+        let pedal_events = synthesize_sustain(&mut midi_notes, is_percussion_channel);
+
+        if !pedal_events.is_empty() {
+            note!(
+                "{} chords of track '{}' are held by the sustain pedal",
+                pedal_events.len() / 2,
+                lmms_track.name.escape_default(),
+            );
+        }
+
+        midi_track_events.extend(pedal_events);
+    }
+
+    midi_track_events.extend(midi_notes.iter().flat_map(MidiNote::events));
+    midi_track_events.extend(preset_change_events(
+        lmms_project,
+        options,
+        channel_assignment,
+    ));
+
+    midi_track_events.extend(controller_events(
+        lmms_project,
+        lmms_track,
+        ticks_range,
+        u7::from(options.lfo_cc.unwrap_or(MIDI_CC_MODULATION)),
+        options.dynamics_cc.controller(),
+        options.pan_width(),
+        midi_channels,
+    ));
+
+    midi_track_events.extend(volume_automation_events(
+        lmms_project,
+        lmms_track,
+        options.dynamics_cc.controller(),
+        options.volume_curve.transfer_fn(),
+        midi_channels,
+    ));
+
+    sort_events(&mut midi_track_events);
+    dedup_cc_events(&mut midi_track_events);
+
+    if let Some(max_cc_rate) = options.max_cc_rate {
+        let thinned_events = thin_cc_events(&mut midi_track_events, max_cc_rate);
+
+        if thinned_events > 0 {
+            note!(
+                "thinned {} controller events of track '{}'",
+                thinned_events,
+                lmms_track.name.escape_default(),
+            );
+        }
+    }
+
+    Ok(midi_track_events)
+}
+
+/// Generates the loop point events for the selected loop styles
+pub fn convert_loop_points(
+    lmms_project: &LmmsProject,
+    options: &ConvertOptions,
+    loop_channel: u4,
+) -> Vec<AbsoluteTrackEvent<'static>> {
+    let mut midi_track_events = Vec::new();
+
+    for loop_style in &options.loop_style {
+        let loop_points = lmms_project.loop_points();
+        let loop_start = loop_points.start;
+        let loop_end = loop_points.end;
+
+        match loop_style {
+            MidiLoopStyle::RpgMaker => {
+                midi_track_events.push(AbsoluteTrackEvent {
+                    ticks: loop_start,
+                    ticks_event_start: loop_start,
+                    kind: TrackEventKind::Midi {
+                        channel: loop_channel,
+                        message: MidiMessage::Controller {
+                            controller: u7::from(MIDI_CC_RPG_LOOP_START),
+                            value: u7::from(0),
+                        },
+                    },
+                    precise_value: None,
+                });
+            }
+            MidiLoopStyle::EmidiLocal => {
+                midi_track_events.push(AbsoluteTrackEvent {
+                    ticks: loop_start,
+                    ticks_event_start: loop_start,
+                    kind: TrackEventKind::Midi {
+                        channel: loop_channel,
+                        message: MidiMessage::Controller {
+                            controller: u7::from(MIDI_CC_EMIDI_LOCAL_LOOP_START),
+                            value: u7::from(0),
+                        },
+                    },
+                    precise_value: None,
+                });
+
+                midi_track_events.push(AbsoluteTrackEvent {
+                    ticks: loop_end,
+                    ticks_event_start: loop_end,
+                    kind: TrackEventKind::Midi {
+                        channel: loop_channel,
+                        message: MidiMessage::Controller {
+                            controller: u7::from(MIDI_CC_EMIDI_LOCAL_LOOP_END),
+                            value: u7::from(0),
+                        },
+                    },
+                    precise_value: None,
+                });
+            }
+            MidiLoopStyle::EmidiGlobal => {
+                midi_track_events.push(AbsoluteTrackEvent {
+                    ticks: loop_start,
+                    ticks_event_start: loop_start,
+                    kind: TrackEventKind::Midi {
+                        channel: loop_channel,
+                        message: MidiMessage::Controller {
+                            controller: u7::from(MIDI_CC_EMIDI_GLOBAL_LOOP_START),
+                            value: u7::from(0),
+                        },
+                    },
+                    precise_value: None,
+                });
+
+                midi_track_events.push(AbsoluteTrackEvent {
+                    ticks: loop_end,
+                    ticks_event_start: loop_end,
+                    kind: TrackEventKind::Midi {
+                        channel: loop_channel,
+                        message: MidiMessage::Controller {
+                            controller: u7::from(MIDI_CC_EMIDI_GLOBAL_LOOP_END),
+                            value: u7::from(0),
+                        },
+                    },
+                    precise_value: None,
+                });
+            }
+            MidiLoopStyle::FinalFantasy => {
+                midi_track_events.push(AbsoluteTrackEvent {
+                    ticks: loop_start,
+                    ticks_event_start: loop_start,
+                    kind: TrackEventKind::Meta(MetaMessage::Marker(b"loopstart")),
+                    precise_value: None,
+                });
+
+                midi_track_events.push(AbsoluteTrackEvent {
+                    ticks: loop_end,
+                    ticks_event_start: loop_end,
+                    kind: TrackEventKind::Meta(MetaMessage::Marker(b"loopend")),
+                    precise_value: None,
+                });
+            }
+        }
+    }
+
+    midi_track_events
+}
+
+/// Music controller events on the loop channel that use one of the loop
+/// controllers, as `(ticks, controller)` pairs
+pub fn find_loop_cc_collisions(
+    track_events: &[AbsoluteTrackEvent],
+    loop_events: &[AbsoluteTrackEvent],
+) -> Vec<(usize, u7)> {
+    let loop_controllers = loop_events
+        .iter()
+        .filter_map(|event| match event.kind {
+            TrackEventKind::Midi {
+                channel,
+                message: MidiMessage::Controller { controller, .. },
+            } => Some((channel, controller)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    track_events
+        .iter()
+        .filter_map(|event| match event.kind {
+            TrackEventKind::Midi {
+                channel,
+                message: MidiMessage::Controller { controller, .. },
+            } if loop_controllers.contains(&(channel, controller)) => {
+                Some((event.ticks, controller))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Sorts events into their playback order
+pub fn sort_events(midi_track_events: &mut [AbsoluteTrackEvent]) {
+    midi_track_events.sort_by_key(
+        |&AbsoluteTrackEvent {
+             ticks,
+             ticks_event_start,
+             kind,
+             ..
+         }| {
+            (
+                ticks,
+                ticks_event_start,
+                !kind.is_meta_event(),
+                !kind.is_cc_event(),
+                !kind.is_program_change(),
+                !kind.is_note_on(),
+                !kind.is_note_off(),
+            )
+        },
+    );
+}
+
+/// Removes controller events over sorted events which repeat the value their
+/// controller already has on the same channel
+pub fn dedup_cc_events(midi_track_events: &mut Vec<AbsoluteTrackEvent>) {
+    let mut controller_values = HashMap::new();
+
+    midi_track_events.retain(|event| match event.kind {
+        TrackEventKind::Midi {
+            channel,
+            message: MidiMessage::Controller { controller, value },
+        } if is_stateful_controller(controller.as_int()) => {
+            controller_values.insert((channel.as_int(), controller.as_int()), value.as_int())
+                != Some(value.as_int())
+        }
+        _ => true,
+    });
+}
+
+/// Thins controller curves over sorted, deduplicated events to at most the given
+/// number of events per beat for each controller. The first and last values of
+/// the curves and their turning points are always kept. Returns the number of
+/// removed events.
+pub fn thin_cc_events(midi_track_events: &mut Vec<AbsoluteTrackEvent>, max_cc_rate: f32) -> usize {
+    let min_spacing = (LMMS_TICKS_PER_BAR / 4) as f32 / max_cc_rate;
+    let mut curves: HashMap<_, Vec<_>> = HashMap::new();
+
+    for (event_index, event) in midi_track_events.iter().enumerate() {
+        if let TrackEventKind::Midi {
+            channel,
+            message: MidiMessage::Controller { controller, value },
+        } = event.kind
+        {
+            if is_stateful_controller(controller.as_int()) {
+                curves.entry((channel, controller)).or_default().push((
+                    event_index,
+                    event.ticks,
+                    value.as_int() as i16,
+                ));
+            }
+        }
+    }
+
+    let mut removed_events = vec![false; midi_track_events.len()];
+    let mut thinned_events = 0;
+
+    for curve in curves.values() {
+        let mut last_kept_ticks = None;
+
+        for (point_index, (event_index, ticks, value)) in curve.iter().copied().enumerate() {
+            let previous = point_index.checked_sub(1).map(|previous| curve[previous]);
+            let next = curve.get(point_index + 1).copied();
+
+            // The last value before a pause is held, so it ends a curve
+            let is_endpoint = match (previous, next) {
+                (Some(_), Some((_, next_ticks, _))) => (next_ticks - ticks) as f32 > min_spacing,
+                _ => true,
+            };
+
+            let is_extremum = match (previous, next) {
+                (Some((_, _, previous_value)), Some((_, _, next_value))) => {
+                    (value - previous_value).signum() * (next_value - value).signum() < 0
+                }
+                _ => false,
+            };
+
+            let is_due = last_kept_ticks
+                .is_none_or(|last_kept_ticks| (ticks - last_kept_ticks) as f32 >= min_spacing);
+
+            if is_endpoint || is_extremum || is_due {
+                last_kept_ticks = Some(ticks);
+            } else {
+                removed_events[event_index] = true;
+                thinned_events += 1;
+            }
+        }
+    }
+
+    let mut removed_events = removed_events.into_iter();
+    midi_track_events.retain(|_| !removed_events.next().unwrap());
+
+    thinned_events
+}
+
+/// Polyphony and note overlap diagnostics over sorted events
+pub fn check_events(
+    midi_track_events: &[AbsoluteTrackEvent],
+    polyphony_limits: &PolyphonyLimits,
+    source_locator: &SourceLocator,
+) {
+    {
+        let mut current_polyphony = 0;
+        let mut already_warned = false;
+
+        let mut channel_polyphony = [0; 16];
+        let mut channel_already_warned = [false; 16];
+
+        for event in midi_track_events.iter() {
+            let TrackEventKind::Midi { channel, .. } = event.kind else {
+                continue;
+            };
+            let channel_index = channel.as_int() as usize;
+            let channel_voices = polyphony_limits.channel_voices(channel);
+
+            if event.kind.is_note_on() {
+                current_polyphony += 1;
+                channel_polyphony[channel_index] += 1;
+
+                if (current_polyphony > polyphony_limits.port_voices()) && !already_warned {
+                    warning!(
+                        code: "polyphony", tick: event.ticks, position: source_locator.position(event.ticks);
+                        "excessive polyphony, more than {} voices",
+                        polyphony_limits.port_voices()
+                    );
+                    already_warned = true;
+                }
+
+                if channel_voices.is_some_and(|voices| channel_polyphony[channel_index] > voices)
+                    && !channel_already_warned[channel_index]
+                {
+                    let source_location = source_locator.locate(channel, event.ticks);
+
+                    warning!(
+                        code: "polyphony",
+                        tick: event.ticks,
+                        position: source_locator.position(event.ticks),
+                        track: source_location.track_name(),
+                        pattern: source_location.pattern_name();
+                        "excessive polyphony on MIDI channel {}, more than {} voices",
+                        channel_index + 1,
+                        channel_voices.unwrap_or_default()
+                    );
+                    channel_already_warned[channel_index] = true;
+                }
+            }
+
+            if event.kind.is_note_off() {
+                assert!(current_polyphony > 0);
+                current_polyphony -= 1;
+                channel_polyphony[channel_index] -= 1;
+
+                if (current_polyphony <= polyphony_limits.port_voices()) && already_warned {
+                    already_warned = false;
+                }
+
+                if channel_voices.is_none_or(|voices| channel_polyphony[channel_index] <= voices) {
+                    channel_already_warned[channel_index] = false;
+                }
+            }
+        }
+    }
+
+    {
+        let mut current_note_counts = HashMap::new();
+
+        for event in midi_track_events.iter() {
+            if let TrackEventKind::Midi {
+                channel,
+                message: MidiMessage::NoteOn { key, .. },
+            } = event.kind
+            {
+                let note_count = current_note_counts.entry((channel, key)).or_insert(0);
+                *note_count += 1;
+
+                if *note_count >= 2 {
+                    let source_location = source_locator.locate(channel, event.ticks);
+
+                    warning!(
+                        code: "note-overlap",
+                        tick: event.ticks,
+                        position: source_locator.position(event.ticks),
+                        track: source_location.track_name(),
+                        pattern: source_location.pattern_name(),
+                        key: key.as_int();
+                        "note overlap on MIDI channel {}",
+                        channel.as_int() + 1
+                    );
+                }
+            }
+
+            if let TrackEventKind::Midi {
+                channel,
+                message: MidiMessage::NoteOff { key, .. },
+            } = event.kind
+            {
+                let note_count = current_note_counts
+                    .get_mut(&(channel, key))
+                    .expect("failed to get note count");
+
+                assert!(*note_count > 0);
+                *note_count -= 1;
+
+                if *note_count == 0 {
+                    current_note_counts.remove(&(channel, key));
+                }
+            }
+        }
+    }
+}
+
+/// A converted LMMS project, keeping the events of each LMMS track separately
+/// so edits to a single track can be re-exported without a full conversion.
+pub struct Conversion {
+    channel_assignments: Vec<ChannelAssignment>,
+
+    /// Note events of each assigned LMMS track, parallel to `channel_assignments`
+    track_events: Vec<Vec<AbsoluteTrackEvent<'static>>>,
+
+    loop_events: Vec<AbsoluteTrackEvent<'static>>,
+
+    /// Marker texts standing in for skipped tracks
+    placeholder_markers: Vec<(usize, String)>,
+
+    /// Cue point texts at the pattern starts
+    cue_points: Vec<(usize, String)>,
+
+    /// Marker texts at the bar lines
+    bar_markers: Vec<(usize, String)>,
+
+    /// Lyric lines at their positions
+    lyrics: Vec<(usize, String)>,
+
+    /// Soft Karaoke texts, replacing the lyric lines
+    karaoke: Option<Karaoke>,
+
+    key_signature: Option<KeySignature>,
+
+    /// Sequencer Specific meta event data describing the LMMS project structure
+    roundtrip_metadata: Option<Vec<u8>>,
+
+    /// Meta event texts in the output encoding, UTF-8 texts are not stored
+    encoded_texts: HashMap<String, Vec<u8>>,
+
+    /// Ticks the events were shifted back by, to find them in the project
+    source_offset: isize,
+}
+
+impl Conversion {
+    /// Locator of the converted events in the project, of the tracks of a
+    /// MIDI port or of all tracks
+    fn source_locator<'a>(
+        &'a self,
+        lmms_project: &'a LmmsProject,
+        midi_port: Option<usize>,
+    ) -> SourceLocator<'a> {
+        SourceLocator::new(
+            lmms_project,
+            self.channel_assignments
+                .iter()
+                .filter(|channel_assignment| {
+                    midi_port.is_none_or(|midi_port| channel_assignment.midi_port == midi_port)
+                }),
+            self.source_offset,
+        )
+    }
+
+    pub fn new(lmms_project: &LmmsProject, options: &ConvertOptions) -> Result<Self, ConvertError> {
+        check_project(lmms_project, options);
+
+        info!("assigning MIDI channels");
+        let channel_assignments = assign_channels(lmms_project, options);
+
+        for channel_assignment in &channel_assignments {
+            debug!(
+                "LMMS track '{}' is assigned MIDI channels {:?} on port {}, preset {}:{}",
+                channel_assignment.lmms_track(lmms_project).name,
+                channel_assignment
+                    .midi_channels
+                    .iter()
+                    .map(|midi_channel| midi_channel.as_int() + 1)
+                    .collect::<Vec<_>>(),
+                channel_assignment.midi_port + 1,
+                options.mapped_bank(channel_assignment.midi_preset.bank),
+                channel_assignment.midi_preset.program
+            );
+        }
+
+        if options.overflow == ChannelOverflow::Error {
+            let dropped_tracks = convertible_tracks(lmms_project, options)
+                .filter(|(track_index, _, _)| {
+                    !channel_assignments
+                        .iter()
+                        .any(|channel_assignment| channel_assignment.track_index == *track_index)
+                })
+                .map(|(_, lmms_track, _)| lmms_track.name.clone())
+                .collect::<Vec<_>>();
+
+            if !dropped_tracks.is_empty() {
+                return Err(ConvertError::ChannelOverflow(dropped_tracks));
+            }
+        }
+
+        info!(
+            "converting the notes of {} tracks",
+            channel_assignments.len()
+        );
+        progress::start_phase(Phase::Convert, Some(channel_assignments.len()));
+
+        let mut track_events = channel_assignments
+            .iter()
+            .map(|channel_assignment| {
+                let midi_track_events =
+                    convert_track_notes(lmms_project, options, channel_assignment)?;
+                progress::advance();
+
+                debug!(
+                    "LMMS track '{}' is converted to {} events",
+                    channel_assignment.lmms_track(lmms_project).name,
+                    midi_track_events.len()
+                );
+
+                Ok(midi_track_events)
+            })
+            .collect::<Result<Vec<_>, ConvertError>>()?;
+
+        let polyphony_limits = options.polyphony_limits();
+
+        if polyphony_limits.is_enforced() {
+            info!("enforcing the polyphony limits");
+
+            for midi_port in 0..options.midi_port_count() {
+                let mut port_events = channel_assignments
+                    .iter()
+                    .zip(track_events.iter_mut())
+                    .filter(|(channel_assignment, _)| channel_assignment.midi_port == midi_port)
+                    .map(|(_, midi_track_events)| midi_track_events)
+                    .collect::<Vec<_>>();
+
+                let changed_notes = limit_polyphony(
+                    &mut port_events,
+                    &polyphony_limits,
+                    options.polyphony_policy,
+                );
+
+                for midi_track_events in port_events {
+                    sort_events(midi_track_events);
+                }
+
+                if changed_notes > 0 {
+                    note!(
+                        "dropped or shortened {changed_notes} notes to stay within the polyphony limits"
+                    );
+                }
+            }
+        }
+
+        if let Some(target_profile) = options.target_profile() {
+            for channel_assignment in &channel_assignments {
+                target_profile.check_preset(
+                    &channel_assignment.lmms_track(lmms_project).name,
+                    MidiPreset {
+                        bank: options.mapped_bank(channel_assignment.midi_preset.bank),
+                        ..channel_assignment.midi_preset
+                    },
+                );
+            }
+
+            target_profile.check_controllers(&track_events.concat());
+        }
+
+        let mut conversion = Conversion {
+            channel_assignments,
+            track_events,
+            loop_events: Vec::new(),
+            placeholder_markers: if options.skipped_track_markers {
+                skipped_track_markers(lmms_project)
+            } else {
+                Vec::new()
+            },
+            cue_points: Vec::new(),
+            bar_markers: Vec::new(),
+            lyrics: Vec::new(),
+            karaoke: None,
+            key_signature: None,
+            roundtrip_metadata: None,
+            encoded_texts: HashMap::new(),
+            source_offset: 0,
+        };
+
+        if let Some(ref lyrics) = options.lyrics {
+            if options.karaoke {
+                conversion.karaoke =
+                    Some(lyrics.to_karaoke(options.bpm(lmms_project), LMMS_TICKS_PER_BAR / 4));
+            } else {
+                conversion.lyrics =
+                    lyrics.to_lmms_ticks(options.bpm(lmms_project), LMMS_TICKS_PER_BAR / 4);
+            }
+        }
+
+        if options.pattern_cue_points {
+            conversion.cue_points =
+                pattern_cue_points(lmms_project, &conversion.channel_assignments);
+        }
+
+        if options.roundtrip_metadata {
+            conversion.roundtrip_metadata = Some(roundtrip_metadata(
+                lmms_project,
+                &conversion.channel_assignments,
+            ));
+        }
+
+        conversion.convert_key_signature(options);
+
+        info!("converting the loop points");
+        conversion.convert_loops(lmms_project, options);
+
+        if options.bar_markers {
+            conversion.convert_bar_markers(lmms_project);
+        }
+
+        if let Some(ref bar_range) = options.range {
+            let lmms_ticks_per_bar = lmms_project.lmms_ticks_per_bar();
+
+            conversion.slice_range(
+                (bar_range.start - 1) * lmms_ticks_per_bar
+                    ..(bar_range.end - 1) * lmms_ticks_per_bar,
+            );
+
+            if !conversion
+                .track_events
+                .iter()
+                .flatten()
+                .any(|event| event.kind.is_note_on())
+            {
+                warning!(
+                    code: "empty-range";
+                    "bars {}..{} contain no notes",
+                    bar_range.start,
+                    bar_range.end
+                );
+            }
+        }
+
+        if options.trim_start {
+            let start_offset = options
+                .start_offset
+                .map_or(0, |start_offset| start_offset.lmms_ticks(lmms_project));
+
+            conversion.trim_start(start_offset);
+        }
+
+        conversion.encode_texts(lmms_project, options);
+
+        Ok(conversion)
+    }
+
+    /// Generates the loop point events, keeping them clear of the music's own
+    /// controller events
+    fn convert_loops(&mut self, lmms_project: &LmmsProject, options: &ConvertOptions) {
+        let track_events = self.track_events.concat();
+
+        let mut loop_events = convert_loop_points(lmms_project, options, u4::from(0));
+        let loop_cc_collisions = find_loop_cc_collisions(&track_events, &loop_events);
+
+        if !loop_cc_collisions.is_empty() {
+            let free_channel = (0..16).map(u4::from).find(|midi_channel| {
+                find_loop_cc_collisions(
+                    &track_events,
+                    &convert_loop_points(lmms_project, options, *midi_channel),
+                )
+                .is_empty()
+            });
+
+            match (options.loop_cc_collision, free_channel) {
+                (LoopCcCollision::Move, Some(free_channel)) => {
+                    warning!(
+                        code: "loop-cc-collision";
+                        "loop CCs collide with {} controller events on MIDI channel 1",
+                        loop_cc_collisions.len(),
+                    );
+                    note!(
+                        "loop CCs were moved to MIDI channel {}",
+                        free_channel.as_int() + 1
+                    );
+                    loop_events = convert_loop_points(lmms_project, options, free_channel);
+                }
+                _ => {
+                    warning!(
+                        code: "loop-cc-collision";
+                        "loop CCs collide with controller events on MIDI channel 1 at {}",
+                        loop_cc_collisions
+                            .iter()
+                            .map(|(ticks, controller)| format!("{ticks} (CC#{controller})"))
+                            .collect::<Vec<_>>()
+                            .join(", "),
+                    );
+
+                    if options.loop_cc_collision == LoopCcCollision::Move {
+                        note!("no MIDI channel is free of colliding controller events");
+                    } else {
+                        note!("some players mis-trigger loops on such collisions, use `--loop-cc-collision move` to avoid them");
+                    }
+                }
+            }
+        }
+
+        self.loop_events = loop_events;
+    }
+
+    /// Sets the key signature of `--key-signature`, inferring it from the
+    /// notes of the tracks with `auto`
+    fn convert_key_signature(&mut self, options: &ConvertOptions) {
+        self.key_signature = match options.key_signature {
+            Some(KeySignatureMode::Fixed(key_signature)) => Some(key_signature),
+            Some(KeySignatureMode::Auto) => {
+                let key_signature = infer_key_signature(&self.track_events.concat());
+
+                if key_signature.is_none() {
+                    warning!(code: "key-signature"; "the key signature cannot be inferred without melodic notes");
+                }
+
+                key_signature
+            }
+            None => None,
+        };
+    }
+
+    /// Generates the bar markers up to the end of the song
+    fn convert_bar_markers(&mut self, lmms_project: &LmmsProject) {
+        let ticks_end = self
+            .track_events
+            .iter()
+            .flatten()
+            .chain(&self.loop_events)
+            .map(|event| event.ticks)
+            .max()
+            .unwrap_or(0);
+
+        self.bar_markers = (0..ticks_end.max(1))
+            .step_by(lmms_project.lmms_ticks_per_bar())
+            .enumerate()
+            .map(|(bar_index, ticks)| (ticks, format!("bar {}", bar_index + 1)))
+            .collect();
+    }
+
+    /// Keeps only the events within the range and moves them to the start. Notes
+    /// crossing the boundaries are clipped, the last controller, pitch bend and
+    /// program values before the range are sent at its start.
+    fn slice_range(&mut self, ticks_range: Range<usize>) {
+        self.source_offset += ticks_range.start as isize;
+
+        for midi_track_events in &mut self.track_events {
+            // Notes sounding at the start of the range
+            let mut sounding_notes: HashMap<_, usize> = HashMap::new();
+
+            for event in midi_track_events.iter() {
+                if let TrackEventKind::Midi {
+                    channel,
+                    message: MidiMessage::NoteOff { key, .. },
+                } = event.kind
+                {
+                    if event.ticks_event_start < ticks_range.start
+                        && event.ticks > ticks_range.start
+                    {
+                        *sounding_notes
+                            .entry((channel, key, event.ticks_event_start))
+                            .or_default() += 1;
+                    }
+                }
+            }
+
+            // Indices of the last values before the range
+            let mut chased_events = HashMap::new();
+
+            for (event_index, event) in midi_track_events.iter().enumerate() {
+                if event.ticks >= ticks_range.start {
+                    break;
+                }
+
+                match event.kind {
+                    TrackEventKind::Midi {
+                        channel,
+                        message: MidiMessage::Controller { controller, .. },
+                    } if is_stateful_controller(controller.as_int())
+                        || controller.as_int() == MIDI_CC_BANK_SELECT_COARSE
+                        || controller.as_int() == MIDI_CC_BANK_SELECT_FINE =>
+                    {
+                        chased_events.insert((channel, controller.as_int() as usize), event_index);
+                    }
+                    TrackEventKind::Midi {
+                        channel,
+                        message: MidiMessage::PitchBend { .. },
+                    } => {
+                        chased_events.insert((channel, 128), event_index);
+                    }
+                    TrackEventKind::Midi {
+                        channel,
+                        message: MidiMessage::ProgramChange { .. },
+                    } => {
+                        chased_events.insert((channel, 129), event_index);
+                    }
+                    _ => {}
+                }
+            }
+
+            let chased_events = chased_events.into_values().collect::<HashSet<_>>();
+            let mut event_index = 0;
+
+            midi_track_events.retain_mut(|event| {
+                let is_chased = chased_events.contains(&event_index);
+                event_index += 1;
+
+                let keep = match event.kind {
+                    TrackEventKind::Midi {
+                        channel,
+                        message: MidiMessage::NoteOn { key, .. },
+                    } if event.ticks < ticks_range.start => sounding_notes
+                        .get_mut(&(channel, key, event.ticks))
+                        .filter(|count| **count > 0)
+                        .map(|count| *count -= 1)
+                        .is_some(),
+                    TrackEventKind::Midi {
+                        message: MidiMessage::NoteOff { .. },
+                        ..
+                    } => {
+                        event.ticks_event_start < ticks_range.end
+                            && (event.ticks_event_start >= ticks_range.start
+                                || event.ticks > ticks_range.start)
+                    }
+                    _ if event.ticks < ticks_range.start => is_chased,
+                    _ => event.ticks < ticks_range.end,
+                };
+
+                let clip = |ticks: usize| {
+                    ticks.clamp(ticks_range.start, ticks_range.end) - ticks_range.start
+                };
+
+                event.ticks = clip(event.ticks);
+                event.ticks_event_start = clip(event.ticks_event_start);
+                keep
+            });
+
+            sort_events(midi_track_events);
+        }
+
+        // Loop end points can sit right on the end of the range
+        self.loop_events.retain_mut(|event| {
+            let keep = (ticks_range.start..=ticks_range.end).contains(&event.ticks);
+            event.ticks = event.ticks.saturating_sub(ticks_range.start);
+            event.ticks_event_start = event.ticks_event_start.saturating_sub(ticks_range.start);
+            keep
+        });
+
+        for texts in [
+            &mut self.placeholder_markers,
+            &mut self.cue_points,
+            &mut self.bar_markers,
+            &mut self.lyrics,
+        ]
+        .into_iter()
+        .chain(
+            self.karaoke
+                .iter_mut()
+                .map(|karaoke| &mut karaoke.syllables),
+        ) {
+            texts.retain_mut(|(ticks, _)| {
+                let keep = ticks_range.contains(ticks);
+                *ticks = ticks.saturating_sub(ticks_range.start);
+                keep
+            });
+        }
+    }
+
+    /// Shifts all events so the first note starts at the given position, events
+    /// before the first note are moved to the start or dropped if they are texts
+    fn trim_start(&mut self, start_offset: usize) {
+        let Some(first_note_ticks) = self
+            .track_events
+            .iter()
+            .flatten()
+            .filter(|event| event.kind.is_note_on())
+            .map(|event| event.ticks)
+            .min()
+        else {
+            return;
+        };
+
+        if first_note_ticks == start_offset {
+            return;
+        }
+
+        let shift = |ticks: usize| (ticks + start_offset).checked_sub(first_note_ticks);
+        self.source_offset += first_note_ticks as isize - start_offset as isize;
+
+        for event in self
+            .track_events
+            .iter_mut()
+            .flatten()
+            .chain(&mut self.loop_events)
+        {
+            event.ticks = shift(event.ticks).unwrap_or(0);
+            event.ticks_event_start = shift(event.ticks_event_start).unwrap_or(0);
+        }
+
+        // Events moved to the start may have changed places
+        for midi_track_events in &mut self.track_events {
+            sort_events(midi_track_events);
+        }
+        sort_events(&mut self.loop_events);
+
+        for texts in [
+            &mut self.placeholder_markers,
+            &mut self.cue_points,
+            &mut self.bar_markers,
+            &mut self.lyrics,
+        ]
+        .into_iter()
+        .chain(
+            self.karaoke
+                .iter_mut()
+                .map(|karaoke| &mut karaoke.syllables),
+        ) {
+            texts.retain_mut(|(ticks, _)| match shift(*ticks) {
+                Some(shifted_ticks) => {
+                    *ticks = shifted_ticks;
+                    true
+                }
+                None => false,
+            });
+        }
+
+        if first_note_ticks > start_offset {
+            note!(
+                "trimmed {} ticks of leading silence",
+                first_note_ticks - start_offset
+            );
+        }
+    }
+
+    /// Transcodes the meta event texts into the output encoding, texts already
+    /// transcoded by an earlier update are kept
+    fn encode_texts(&mut self, lmms_project: &LmmsProject, options: &ConvertOptions) {
+        if options.text_encoding == TextEncoding::Utf8 {
+            return;
+        }
+
+        let song_texts = [
+            &options.track_name,
+            &options.track_copyright,
+            &options.track_comment,
+        ];
+
+        let track_texts = lmms_project
+            .song
+            .track_container
+            .tracks
+            .iter()
+            .flat_map(|lmms_track| {
+                [
+                    Some(&lmms_track.name),
+                    lmms_track.instrument().map(|instrument| &instrument.name),
+                ]
+            });
+
+        let conversion_texts = self
+            .placeholder_markers
+            .iter()
+            .chain(&self.cue_points)
+            .chain(&self.bar_markers)
+            .chain(&self.lyrics)
+            .chain(self.karaoke.iter().flat_map(|karaoke| &karaoke.syllables))
+            .map(|(_, text)| text)
+            .chain(self.karaoke.iter().flat_map(|karaoke| &karaoke.headers));
+
+        let mut encoded_texts = HashMap::new();
+
+        for text in song_texts
+            .into_iter()
+            .flatten()
+            .chain(track_texts.flatten())
+            .chain(conversion_texts)
+        {
+            if text.is_ascii() || encoded_texts.contains_key(text) {
+                continue;
+            }
+
+            let encoded_text = self.encoded_texts.remove(text).unwrap_or_else(|| {
+                let (encoded_text, replaced) = options.text_encoding.encode(text);
+
+                if replaced {
+                    warning!(
+                        code: "text-encoding";
+                        "'{}' cannot be fully represented in {}, unsupported characters were replaced by '?'",
+                        text.escape_default(),
+                        options.text_encoding.name(),
+                    );
+                }
+
+                encoded_text
+            });
+
+            encoded_texts.insert(text.clone(), encoded_text);
+        }
+
+        self.encoded_texts = encoded_texts;
+    }
+
+    /// Bytes of a meta event text in the output encoding
+    fn text<'s>(&'s self, text: &'s str) -> &'s [u8] {
+        self.encoded_texts
+            .get(text)
+            .map_or(text.as_bytes(), Vec::as_slice)
+    }
+
+    pub fn channel_assignments(&self) -> &[ChannelAssignment] {
+        &self.channel_assignments
+    }
+
+    /// Note events of each assigned LMMS track, parallel to `channel_assignments()`
+    pub fn track_events(&self) -> &[Vec<AbsoluteTrackEvent<'static>>] {
+        &self.track_events
+    }
+
+    /// Regenerates the events of a changed LMMS track. Falls back to a full
+    /// conversion when the edit affected the channel assignment.
+    pub fn update_track(
+        &mut self,
+        lmms_project: &LmmsProject,
+        options: &ConvertOptions,
+        track_index: usize,
+    ) -> Result<(), ConvertError> {
+        if track_index >= lmms_project.song.track_container.tracks.len() {
+            return Err(ConvertError::NoSuchTrack(track_index));
+        }
+
+        let channel_assignments = assign_channels(lmms_project, options);
+
+        // Polyphony limits and the trimmed start depend on the notes of all
+        // tracks, the sliced range on the loop points and markers too
+        if channel_assignments != self.channel_assignments
+            || options.polyphony_limits().is_enforced()
+            || options.trim_start
+            || options.range.is_some()
+        {
+            *self = Conversion::new(lmms_project, options)?;
+            return Ok(());
+        }
+
+        if let Some(assignment_index) = self
+            .channel_assignments
+            .iter()
+            .position(|channel_assignment| channel_assignment.track_index == track_index)
+        {
+            self.track_events[assignment_index] = convert_track_notes(
+                lmms_project,
+                options,
+                &self.channel_assignments[assignment_index],
+            )?;
+
+            // The inferred key follows the edited notes
+            self.convert_key_signature(options);
+            self.convert_loops(lmms_project, options);
+        }
+
+        if options.pattern_cue_points {
+            self.cue_points = pattern_cue_points(lmms_project, &self.channel_assignments);
+        }
+
+        if options.bar_markers {
+            self.convert_bar_markers(lmms_project);
+        }
+
+        // Track names may have changed
+        if options.roundtrip_metadata {
+            self.roundtrip_metadata =
+                Some(roundtrip_metadata(lmms_project, &self.channel_assignments));
+        }
+
+        self.encode_texts(lmms_project, options);
+
+        Ok(())
+    }
+
+    /// Regenerates the events of the LMMS track owning a changed pattern
+    pub fn update_pattern(
+        &mut self,
+        lmms_project: &LmmsProject,
+        options: &ConvertOptions,
+        track_index: usize,
+        pattern_index: usize,
+    ) -> Result<(), ConvertError> {
+        let lmms_track = lmms_project
+            .song
+            .track_container
+            .tracks
+            .get(track_index)
+            .ok_or(ConvertError::NoSuchTrack(track_index))?;
+
+        if pattern_index >= lmms_track.patterns.len() {
+            return Err(ConvertError::NoSuchPattern {
+                track_index,
+                pattern_index,
+            });
+        }
+
+        self.update_track(lmms_project, options, track_index)
+    }
+
+    /// All events merged and sorted into playback order
+    pub fn events(&self) -> Vec<AbsoluteTrackEvent<'_>> {
+        let mut midi_track_events = self
+            .track_events
+            .iter()
+            .flatten()
+            .chain(self.loop_events.iter())
+            .copied()
+            .collect::<Vec<_>>();
+
+        midi_track_events.extend(self.marker_events());
+        midi_track_events.extend(self.cue_point_events());
+        midi_track_events.extend(self.lyric_events());
+        midi_track_events.extend(self.karaoke_events());
+
+        sort_events(&mut midi_track_events);
+        midi_track_events
+    }
+
+    /// Saves the conversion in the selected output format
+    pub fn save(
+        &self,
+        lmms_project: &LmmsProject,
+        options: &ConvertOptions,
+        path: impl AsRef<Path>,
+    ) -> Result<(), ConvertError> {
+        Ok(fs::write(path, self.to_bytes(lmms_project, options)?)?)
+    }
+
+    /// File contents of the conversion in the selected output format
+    pub fn to_bytes(
+        &self,
+        lmms_project: &LmmsProject,
+        options: &ConvertOptions,
+    ) -> Result<Vec<u8>, ConvertError> {
+        match options.output_format {
+            OutputFormat::Midi => {
+                let mut midi_bytes = Vec::new();
+                self.to_smf(lmms_project, options)
+                    .write_std(&mut midi_bytes)?;
+                Ok(midi_bytes)
+            }
+            OutputFormat::Midi2 => Ok(self.to_midi2_clip(lmms_project, options)),
+            OutputFormat::Rmid => Ok(self.to_rmid(lmms_project, options)?),
+            OutputFormat::Xmi => Ok(self.to_xmi(lmms_project, options)),
+            OutputFormat::Mus => self.to_mus(lmms_project, options),
+            OutputFormat::Hmp => Ok(self.to_hmp(lmms_project, options)),
+            OutputFormat::Csv => Ok(midicsv(&self.to_smf(lmms_project, options)).into_bytes()),
+            OutputFormat::Abc => Ok(abc(&self.score(lmms_project, options)).into_bytes()),
+            OutputFormat::MusicXml => Ok(musicxml(&self.score(lmms_project, options)).into_bytes()),
+            OutputFormat::Ly => Ok(lilypond(&self.score(lmms_project, options)).into_bytes()),
+            OutputFormat::Mml => {
+                Ok(mml(&self.score(lmms_project, options), options.mml_dialect).into_bytes())
+            }
+        }
+    }
+
+    /// Writes the conversion as a Standard MIDI File of the selected format
+    pub fn to_smf<'a>(
+        &'a self,
+        lmms_project: &'a LmmsProject,
+        options: &'a ConvertOptions,
+    ) -> Smf<'a> {
+        // Karaoke players expect the words on a track of their own
+        let midi_document = match (options.midi_format, options.karaoke) {
+            (MidiFormat::SingleTrack, false) => self.to_smf_single_track(lmms_project, options),
+            (MidiFormat::MultiTrack, _) | (_, true) => {
+                self.to_smf_multi_track(lmms_project, options)
+            }
+        };
+
+        if let Some(target_profile) = options.target_profile() {
+            target_profile.check_event_rate(
+                &self.events(),
+                options.bpm(lmms_project),
+                &self.source_locator(lmms_project, None),
+            );
+
+            let mut midi_bytes = Vec::new();
+            if midi_document.write_std(&mut midi_bytes).is_ok() {
+                target_profile.check_file_size(midi_bytes.len());
+            }
+        }
+
+        midi_document
+    }
+
+    /// SMF Format 0 output: everything merged into a single track
+    pub fn to_smf_single_track<'a>(
+        &'a self,
+        lmms_project: &'a LmmsProject,
+        options: &'a ConvertOptions,
+    ) -> Smf<'a> {
+        let mut midi_document = Smf::new(Header::new(
+            Format::SingleTrack,
+            options.output_timing(lmms_project).timing(),
+        ));
+
+        let mut midi_track = Track::new();
+        push_song_meta(&mut midi_track, lmms_project, options, self);
+
+        // MIDI channel initialization
+
+        let mut initialized_channels = Vec::new();
+        let mut channel_events = Vec::new();
+
+        for channel_assignment in &self.channel_assignments {
+            init_channels(
+                &mut channel_events,
+                &mut initialized_channels,
+                channel_assignment,
+                lmms_project,
+                options,
+                self,
+            );
+        }
+
+        push_channel_events(&mut midi_track, &channel_events);
+
+        progress::start_phase(Phase::Sort, None);
+        let midi_track_events = self.events();
+
+        progress::start_phase(Phase::Validate, None);
+        check_events(
+            &midi_track_events,
+            &options.polyphony_limits(),
+            &self.source_locator(lmms_project, None),
+        );
+        check_timing_rounding(&midi_track_events, options.output_timing(lmms_project));
+
+        progress::start_phase(Phase::Write, None);
+        push_events(
+            &mut midi_track,
+            &midi_track_events,
+            options.output_timing(lmms_project),
+            options.noteoff_style,
+        );
+
+        midi_document.tracks.push(midi_track);
+        midi_document
+    }
+
+    /// SMF Format 1 output: tempo, song meta data and loop points in the first
+    /// track, followed by one track per assigned LMMS track
+    pub fn to_smf_multi_track<'a>(
+        &'a self,
+        lmms_project: &'a LmmsProject,
+        options: &'a ConvertOptions,
+    ) -> Smf<'a> {
+        let mut midi_document = Smf::new(Header::new(
+            Format::Parallel,
+            options.output_timing(lmms_project).timing(),
+        ));
+
+        // Notes on different ports never collide
+        progress::start_phase(Phase::Sort, Some(options.midi_port_count()));
+
+        let port_events = (0..options.midi_port_count())
+            .map(|midi_port| {
+                let mut midi_track_events = self
+                    .channel_assignments
+                    .iter()
+                    .zip(&self.track_events)
+                    .filter(|(channel_assignment, _)| channel_assignment.midi_port == midi_port)
+                    .flat_map(|(_, track_events)| track_events.iter().copied())
+                    .collect::<Vec<_>>();
+
+                sort_events(&mut midi_track_events);
+                progress::advance();
+                midi_track_events
+            })
+            .collect::<Vec<_>>();
+
+        progress::start_phase(Phase::Validate, Some(port_events.len()));
+        check_timing_rounding(&self.events(), options.output_timing(lmms_project));
+
+        for (midi_port, midi_track_events) in port_events.iter().enumerate() {
+            check_events(
+                midi_track_events,
+                &options.polyphony_limits(),
+                &self.source_locator(lmms_project, Some(midi_port)),
+            );
+            progress::advance();
+        }
+
+        progress::start_phase(Phase::Write, Some(self.channel_assignments.len()));
+
+        // Conductor track
+        {
+            let mut midi_track = Track::new();
+
+            if self.karaoke.is_some() {
+                midi_track.push(TrackEvent {
+                    delta: u28::from(0),
+                    kind: TrackEventKind::Meta(MetaMessage::Text(KARAOKE_FILE_ID)),
+                });
+            }
+
+            push_song_meta(&mut midi_track, lmms_project, options, self);
+
+            let mut midi_track_events = self.loop_events.clone();
+            midi_track_events.extend(self.marker_events());
+            midi_track_events.extend(self.cue_point_events());
+            midi_track_events.extend(self.lyric_events());
+            sort_events(&mut midi_track_events);
+
+            push_events(
+                &mut midi_track,
+                &midi_track_events,
+                options.output_timing(lmms_project),
+                options.noteoff_style,
+            );
+            midi_document.tracks.push(midi_track);
+        }
+
+        // Soft Karaoke words track
+        if let Some(ref karaoke) = self.karaoke {
+            let mut midi_track = Track::new();
+
+            midi_track.push(TrackEvent {
+                delta: u28::from(0),
+                kind: TrackEventKind::Meta(MetaMessage::TrackName(b"Words")),
+            });
+
+            for header in &karaoke.headers {
+                midi_track.push(TrackEvent {
+                    delta: u28::from(0),
+                    kind: TrackEventKind::Meta(MetaMessage::Text(self.text(header))),
+                });
+            }
+
+            let midi_track_events = self.karaoke_events().collect::<Vec<_>>();
+
+            push_events(
+                &mut midi_track,
+                &midi_track_events,
+                options.output_timing(lmms_project),
+                options.noteoff_style,
+            );
+            midi_document.tracks.push(midi_track);
+        }
+
+        // LMMS tracks
+        let mut initialized_channels = Vec::new();
+
+        for (channel_assignment, track_events) in
+            self.channel_assignments.iter().zip(&self.track_events)
+        {
+            let lmms_track = channel_assignment.lmms_track(lmms_project);
+            let mut midi_track = Track::new();
+
+            // DAW track lists show the LMMS track names
+            let track_name = lmms_track.display_name();
+
+            if !track_name.is_empty() {
+                midi_track.push(TrackEvent {
+                    delta: u28::from(0),
+                    kind: TrackEventKind::Meta(MetaMessage::TrackName(self.text(track_name))),
+                });
+            }
+
+            if options.midi_port_count() > 1 {
+                midi_track.push(TrackEvent {
+                    delta: u28::from(0),
+                    kind: TrackEventKind::Meta(MetaMessage::MidiPort(u7::from(
+                        channel_assignment.midi_port as u8,
+                    ))),
+                });
+            }
+
+            let mut channel_events = Vec::new();
+            init_channels(
+                &mut channel_events,
+                &mut initialized_channels,
+                channel_assignment,
+                lmms_project,
+                options,
+                self,
+            );
+            push_channel_events(&mut midi_track, &channel_events);
+
+            let mut midi_track_events = track_events.clone();
+            sort_events(&mut midi_track_events);
+
+            push_events(
+                &mut midi_track,
+                &midi_track_events,
+                options.output_timing(lmms_project),
+                options.noteoff_style,
+            );
+            midi_document.tracks.push(midi_track);
+            progress::advance();
+        }
+
+        midi_document
+    }
+
+    /// RIFF MIDI output, for players and game engines that only take .rmi files
+    pub fn to_rmid(
+        &self,
+        lmms_project: &LmmsProject,
+        options: &ConvertOptions,
+    ) -> io::Result<Vec<u8>> {
+        let mut midi_bytes = Vec::new();
+        self.to_smf(lmms_project, options)
+            .write_std(&mut midi_bytes)?;
+
+        let mut info: Vec<(&[u8; 4], &[u8])> = Vec::new();
+
+        if let Some(ref track_name) = options.track_name {
+            info.push((b"INAM", self.text(track_name)));
+        }
+
+        if let Some(ref track_copyright) = options.track_copyright {
+            info.push((b"ICOP", self.text(track_copyright)));
+        }
+
+        if let Some(ref track_comment) = options.track_comment {
+            info.push((b"ICMT", self.text(track_comment)));
+        }
+
+        Ok(rmid(&midi_bytes, &info))
+    }
+
+    /// XMIDI output: the events of the single-track output, retimed from the
+    /// song tempo to XMIDI's fixed rate
+    pub fn to_xmi(&self, lmms_project: &LmmsProject, options: &ConvertOptions) -> Vec<u8> {
+        let midi_track_events =
+            self.fixed_rate_events(lmms_project, options, self.events(), XMI_TICKS_PER_SECOND);
+
+        let percussion_channel =
+            (!options.is_channel_10_melodic(lmms_project)).then_some(u4::from(9));
+
+        xmi(&midi_track_events, percussion_channel)
+    }
+
+    /// DMX MUS output for Doom engines, checked against the limits of the format
+    pub fn to_mus(
+        &self,
+        lmms_project: &LmmsProject,
+        options: &ConvertOptions,
+    ) -> Result<Vec<u8>, ConvertError> {
+        if options.is_channel_10_melodic(lmms_project) {
+            warning!(code: "mus-channel-10"; "MUS has no melodic channel 10, its notes will play as percussion");
+        }
+
+        if options.midi_port_count() > 1 {
+            warning!(
+                code: "midi-ports-merged";
+                "MUS has a single set of 16 channels, the tracks of all MIDI ports are merged"
+            );
+        }
+
+        let midi_track_events =
+            self.fixed_rate_events(lmms_project, options, self.events(), MUS_TICKS_PER_SECOND);
+
+        mus(&midi_track_events).map_err(ConvertError::Unsupported)
+    }
+
+    /// HMP output for Human Machine Interfaces engines. The loop points become
+    /// the HMI global loop controllers in place of the `--loop-style`
+    /// controllers, and the cue points become branch locations.
+    pub fn to_hmp(&self, lmms_project: &LmmsProject, options: &ConvertOptions) -> Vec<u8> {
+        let mut events = self.track_events.concat();
+        events.extend(self.cue_point_events());
+        sort_events(&mut events);
+
+        let midi_track_events =
+            self.fixed_rate_events(lmms_project, options, events, HMP_TICKS_PER_SECOND);
+
+        let loop_points = lmms_project.loop_points();
+        let hmp_ticks =
+            |ticks| fixed_rate_ticks(ticks, lmms_project, options, HMP_TICKS_PER_SECOND);
+
+        let loop_ticks = (!options.loop_style.is_empty() && loop_points.end > loop_points.start)
+            .then(|| hmp_ticks(loop_points.start)..hmp_ticks(loop_points.end));
+
+        hmp(&midi_track_events, loop_ticks)
+    }
+
+    /// The notes of the tracks as sheet music, in the project's meter and the
+    /// key signature of `--key-signature` or else the inferred one
+    pub fn score(&self, lmms_project: &LmmsProject, options: &ConvertOptions) -> Score {
+        let voices = self
+            .channel_assignments
+            .iter()
+            .zip(&self.track_events)
+            .map(|(channel_assignment, track_events)| {
+                let lmms_track = channel_assignment.lmms_track(lmms_project);
+
+                let track_name = lmms_track.display_name();
+
+                let notes = track_events
+                    .iter()
+                    .filter_map(|event| match event.kind {
+                        TrackEventKind::Midi {
+                            message: MidiMessage::NoteOff { key, .. },
+                            ..
+                        } => Some(NotationNote {
+                            start: event.ticks_event_start,
+                            end: event.ticks,
+                            key: key.as_int(),
+                        }),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+
+                NotationVoice::new(
+                    track_name,
+                    channel_assignment
+                        .midi_channels
+                        .first()
+                        .map_or(0, |midi_channel| midi_channel.as_int()),
+                    channel_assignment.midi_preset,
+                    &notes,
+                )
+            })
+            .filter(|voice| !voice.notes.is_empty())
+            .collect();
+
+        Score {
+            title: options.track_name.clone(),
+            bpm: options.bpm(lmms_project),
+            time_signature: (
+                lmms_project.head.time_signature_numerator.max(1),
+                lmms_project.head.time_signature_denominator.max(1),
+            ),
+            key_signature: self
+                .key_signature
+                .or_else(|| infer_key_signature(&self.track_events.concat()))
+                .unwrap_or(KeySignature {
+                    sharps: 0,
+                    minor: false,
+                }),
+            voices,
+        }
+    }
+
+    /// Channel setup followed by the given events of the conversion, timed in
+    /// ticks of a fixed rate at the song tempo, for formats without tempo changes
+    pub(crate) fn fixed_rate_events<'a>(
+        &'a self,
+        lmms_project: &'a LmmsProject,
+        options: &ConvertOptions,
+        events: Vec<AbsoluteTrackEvent<'a>>,
+        ticks_per_second: f32,
+    ) -> Vec<AbsoluteTrackEvent<'a>> {
+        let mut midi_track_events = Vec::new();
+
+        if let Some(sysex) = options.reset_sysex().sysex() {
+            midi_track_events.push(AbsoluteTrackEvent {
+                ticks: 0,
+                ticks_event_start: 0,
+                kind: TrackEventKind::SysEx(sysex),
+                precise_value: None,
+            });
+        }
+
+        if options.is_channel_10_melodic(lmms_project) {
+            midi_track_events.push(AbsoluteTrackEvent {
+                ticks: 0,
+                ticks_event_start: 0,
+                kind: TrackEventKind::SysEx(MIDI_SYSEX_GS_PART10_MELODIC),
+                precise_value: None,
+            });
+        }
+
+        let mut initialized_channels = Vec::new();
+
+        for channel_assignment in &self.channel_assignments {
+            init_channels(
+                &mut midi_track_events,
+                &mut initialized_channels,
+                channel_assignment,
+                lmms_project,
+                options,
+                self,
+            );
+        }
+
+        progress::start_phase(Phase::Validate, None);
+        check_events(
+            &events,
+            &options.polyphony_limits(),
+            &self.source_locator(lmms_project, None),
+        );
+        midi_track_events.extend(events);
+
+        progress::start_phase(Phase::Write, None);
+
+        for event in &mut midi_track_events {
+            event.ticks = fixed_rate_ticks(event.ticks, lmms_project, options, ticks_per_second);
+            event.ticks_event_start = fixed_rate_ticks(
+                event.ticks_event_start,
+                lmms_project,
+                options,
+                ticks_per_second,
+            );
+        }
+
+        midi_track_events
+    }
+
+    /// MIDI 2.0 Clip File output: the events of the single-track output at
+    /// MIDI 2.0 resolution, meta events besides the tempo and the song name and
+    /// copyright have no place in a clip
+    pub fn to_midi2_clip(&self, lmms_project: &LmmsProject, options: &ConvertOptions) -> Vec<u8> {
+        if options.smpte_fps.is_some() {
+            warning!(
+                code: "midi2-timing";
+                "MIDI 2.0 clips have no SMPTE time division, using {} PPQN",
+                options.ppqn()
+            );
+        }
+
+        let output_timing = OutputTiming::Metrical(options.ppqn());
+        let mut midi_clip = MidiClip::new(options.ppqn());
+
+        midi_clip.push_tempo(options.bpm(lmms_project));
+
+        if let Some(ref track_name) = options.track_name {
+            midi_clip.push_text(UMP_FLEX_COMPOSITION_NAME, track_name);
+        }
+
+        if let Some(ref track_copyright) = options.track_copyright {
+            midi_clip.push_text(UMP_FLEX_COPYRIGHT_NOTICE, track_copyright);
+        }
+
+        if let Some(sysex) = options.reset_sysex().sysex() {
+            midi_clip.push_event(0, TrackEventKind::SysEx(sysex), None);
+        }
+
+        if options.is_channel_10_melodic(lmms_project) {
+            midi_clip.push_event(0, TrackEventKind::SysEx(MIDI_SYSEX_GS_PART10_MELODIC), None);
+        }
+
+        let mut initialized_channels = Vec::new();
+        let mut channel_events = Vec::new();
+
+        for channel_assignment in &self.channel_assignments {
+            init_channels(
+                &mut channel_events,
+                &mut initialized_channels,
+                channel_assignment,
+                lmms_project,
+                options,
+                self,
+            );
+        }
+
+        progress::start_phase(Phase::Sort, None);
+        let midi_track_events = self.events();
+
+        progress::start_phase(Phase::Validate, None);
+        check_events(
+            &midi_track_events,
+            &options.polyphony_limits(),
+            &self.source_locator(lmms_project, None),
+        );
+        check_timing_rounding(&midi_track_events, output_timing);
+
+        progress::start_phase(Phase::Write, None);
+
+        // Channel and instrument names are only labels
+        for event in &channel_events {
+            midi_clip.push_event(0, event.kind, event.precise_value);
+        }
+
+        let mut skipped_events = 0;
+
+        for event in &midi_track_events {
+            if !midi_clip.push_event(
+                output_timing.ticks(event.ticks),
+                event.kind,
+                event.precise_value,
+            ) {
+                skipped_events += 1;
+            }
+        }
+
+        if skipped_events > 0 {
+            note!(
+                "{skipped_events} meta events (markers, lyrics, loop points) are not stored in MIDI 2.0 clips"
+            );
+        }
+
+        let ticks_end = midi_track_events
+            .last()
+            .map_or(0, |event| output_timing.ticks(event.ticks));
+
+        midi_clip.finish(ticks_end)
+    }
+
+    fn marker_events(&self) -> impl Iterator<Item = AbsoluteTrackEvent<'_>> {
+        self.placeholder_markers
+            .iter()
+            .chain(&self.bar_markers)
+            .map(|(ticks, text)| AbsoluteTrackEvent {
+                ticks: *ticks,
+                ticks_event_start: *ticks,
+                kind: TrackEventKind::Meta(MetaMessage::Marker(self.text(text))),
+                precise_value: None,
+            })
+    }
+
+    fn lyric_events(&self) -> impl Iterator<Item = AbsoluteTrackEvent<'_>> {
+        self.lyrics.iter().map(|(ticks, text)| AbsoluteTrackEvent {
+            ticks: *ticks,
+            ticks_event_start: *ticks,
+            kind: TrackEventKind::Meta(MetaMessage::Lyric(self.text(text))),
+            precise_value: None,
+        })
+    }
+
+    fn karaoke_events(&self) -> impl Iterator<Item = AbsoluteTrackEvent<'_>> {
+        self.karaoke
+            .iter()
+            .flat_map(|karaoke| &karaoke.syllables)
+            .map(|(ticks, text)| AbsoluteTrackEvent {
+                ticks: *ticks,
+                ticks_event_start: *ticks,
+                kind: TrackEventKind::Meta(MetaMessage::Text(self.text(text))),
+                precise_value: None,
+            })
+    }
+
+    fn cue_point_events(&self) -> impl Iterator<Item = AbsoluteTrackEvent<'_>> {
+        self.cue_points
+            .iter()
+            .map(|(ticks, text)| AbsoluteTrackEvent {
+                ticks: *ticks,
+                ticks_event_start: *ticks,
+                kind: TrackEventKind::Meta(MetaMessage::CuePoint(self.text(text))),
+                precise_value: None,
+            })
+    }
+}
+
+/// LMMS ticks in ticks of a fixed rate at the song tempo
+pub(crate) fn fixed_rate_ticks(
+    lmms_ticks: usize,
+    lmms_project: &LmmsProject,
+    options: &ConvertOptions,
+    ticks_per_second: f32,
+) -> usize {
+    // LMMS ticks are 48 per quarter note
+    let ticks_per_lmms_tick =
+        ticks_per_second * 60.0 / options.bpm(lmms_project) / (LMMS_TICKS_PER_BAR / 4) as f32;
+
+    (lmms_ticks as f32 * ticks_per_lmms_tick).round() as usize
+}
+
+/// Song name, copyright, comment, tempo and the reset SysEx at the start of a track
+fn push_song_meta<'a>(
+    midi_track: &mut Track<'a>,
+    lmms_project: &LmmsProject,
+    options: &'a ConvertOptions,
+    conversion: &'a Conversion,
+) {
+    if let Some(ref track_name) = options.track_name {
+        midi_track.push(TrackEvent {
+            delta: u28::from(0),
+            kind: TrackEventKind::Meta(MetaMessage::TrackName(conversion.text(track_name))),
+        });
+    }
+
+    if let Some(ref track_copyright) = options.track_copyright {
+        midi_track.push(TrackEvent {
+            delta: u28::from(0),
+            kind: TrackEventKind::Meta(MetaMessage::Copyright(conversion.text(track_copyright))),
+        });
+    }
+
+    if let Some(ref track_comment) = options.track_comment {
+        midi_track.push(TrackEvent {
+            delta: u28::from(0),
+            kind: TrackEventKind::Meta(MetaMessage::Text(conversion.text(track_comment))),
+        });
+    }
+
+    midi_track.push(TrackEvent {
+        delta: u28::from(0),
+        kind: TrackEventKind::Meta(MetaMessage::Tempo(u24::from(
+            ((60_000_000.0 / options.bpm(lmms_project)) as u32).clamp(1, 0xFF_FFFF),
+        ))),
+    });
+
+    if let Some(key_signature) = conversion.key_signature {
+        midi_track.push(TrackEvent {
+            delta: u28::from(0),
+            kind: TrackEventKind::Meta(MetaMessage::KeySignature(
+                key_signature.sharps,
+                key_signature.minor,
+            )),
+        });
+    }
+
+    if let Some(sysex) = options.reset_sysex().sysex() {
+        midi_track.push(TrackEvent {
+            delta: u28::from(0),
+            kind: TrackEventKind::SysEx(sysex),
+        });
+    }
+
+    if options.is_channel_10_melodic(lmms_project) {
+        midi_track.push(TrackEvent {
+            delta: u28::from(0),
+            kind: TrackEventKind::SysEx(MIDI_SYSEX_GS_PART10_MELODIC),
+        });
+    }
+
+    if let Some(ref roundtrip_metadata) = conversion.roundtrip_metadata {
+        midi_track.push(TrackEvent {
+            delta: u28::from(0),
+            kind: TrackEventKind::Meta(MetaMessage::SequencerSpecific(roundtrip_metadata)),
+        });
+    }
+}
+
+/// Sets up the channels of an assignment, skipping the ones already set up
+/// by an earlier track sharing them
+fn init_channels<'a>(
+    channel_events: &mut Vec<AbsoluteTrackEvent<'a>>,
+    initialized_channels: &mut Vec<(usize, u4)>,
+    channel_assignment: &ChannelAssignment,
+    lmms_project: &'a LmmsProject,
+    options: &ConvertOptions,
+    conversion: &'a Conversion,
+) {
+    let lmms_track = channel_assignment.lmms_track(lmms_project);
+
+    for midi_channel in &channel_assignment.midi_channels {
+        // Channels shared by multiple tracks are set up by the first one
+        if initialized_channels.contains(&(channel_assignment.midi_port, *midi_channel)) {
+            continue;
+        }
+
+        initialized_channels.push((channel_assignment.midi_port, *midi_channel));
+
+        init_channel(
+            channel_events,
+            *midi_channel,
+            lmms_track,
+            channel_assignment.midi_preset,
+            options,
+            conversion,
+        );
+    }
+}
+
+/// Diagnostics for event positions that fall between the ticks of the output time division
+fn check_timing_rounding(midi_track_events: &[AbsoluteTrackEvent], output_timing: OutputTiming) {
+    let rounded_events = midi_track_events
+        .iter()
+        .filter(|event| !output_timing.is_exact(event.ticks))
+        .count();
+
+    if rounded_events > 0 {
+        warning!(
+            code: "timing-rounded";
+            "{rounded_events} event positions were rounded to the output resolution of {}",
+            output_timing.name()
+        );
+
+        match output_timing {
+            OutputTiming::Metrical(_) => note!(
+                "use a multiple of {} PPQN to keep the timing exact",
+                LMMS_TICKS_PER_BAR / 4
+            ),
+            OutputTiming::Timecode(..) => {
+                note!("use more subframes to reduce the rounding error")
+            }
+        }
+    }
+
+    let collapsed_notes = midi_track_events
+        .iter()
+        .filter(|event| event.kind.is_note_off())
+        .filter(|event| {
+            event.ticks > event.ticks_event_start
+                && output_timing.ticks(event.ticks) == output_timing.ticks(event.ticks_event_start)
+        })
+        .count();
+
+    if collapsed_notes > 0 {
+        warning!(
+            code: "zero-length-notes";
+            "{collapsed_notes} notes became zero length at {}",
+            output_timing.name()
+        );
+    }
+}
+
+/// Appends the channel setup events at the start of a track
+fn push_channel_events<'a>(midi_track: &mut Track<'a>, channel_events: &[AbsoluteTrackEvent<'a>]) {
+    midi_track.extend(channel_events.iter().map(|event| TrackEvent {
+        delta: u28::from(0),
+        kind: event.kind,
+    }));
+}
+
+/// Appends sorted absolute-time events as delta-time events and ends the track
+fn push_events<'a>(
+    midi_track: &mut Track<'a>,
+    midi_track_events: &[AbsoluteTrackEvent<'a>],
+    output_timing: OutputTiming,
+    noteoff_style: NoteOffStyle,
+) {
+    let mut ticks_before = 0;
+
+    for event in midi_track_events {
+        let ticks = output_timing.ticks(event.ticks);
+        assert!(ticks_before <= ticks);
+
+        let kind = match (noteoff_style, event.kind) {
+            (
+                NoteOffStyle::NoteOn0,
+                TrackEventKind::Midi {
+                    channel,
+                    message: MidiMessage::NoteOff { key, .. },
+                },
+            ) => TrackEventKind::Midi {
+                channel,
+                message: MidiMessage::NoteOn {
+                    key,
+                    vel: u7::from(0),
+                },
+            },
+            (_, kind) => kind,
+        };
+
+        midi_track.push(TrackEvent {
+            delta: u28::from((ticks - ticks_before) as u32),
+            kind,
+        });
+
+        ticks_before = ticks;
+    }
+
+    midi_track.push(TrackEvent {
+        delta: u28::from(0),
+        kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+    });
+}
+
+/// Bank select and Program Change messages selecting a preset
+pub fn preset_events(
+    midi_channel: u4,
+    midi_preset: MidiPreset,
+    options: &ConvertOptions,
+) -> Vec<TrackEventKind<'static>> {
+    let bank = options.mapped_bank(midi_preset.bank);
+    let bank_coarse = u7::from((bank >> 7) as u8);
+    let bank_fine = u7::from((bank & 0x7F) as u8);
+
+    let program = match options.mt32_map() {
+        Some(mt32_map) if !midi_preset.is_percussion() => {
+            mt32_map.program(midi_preset.program as u8)
+        }
+        _ => midi_preset.program as u8,
+    };
+
+    let bank_select = options.bank_select();
+    let mut events = Vec::new();
+
+    if bank_select != BankSelect::None {
+        events.push(TrackEventKind::Midi {
+            channel: midi_channel,
+            message: MidiMessage::Controller {
+                controller: u7::from(MIDI_CC_BANK_SELECT_COARSE),
+                value: bank_coarse,
+            },
+        });
+    }
+
+    if bank_select == BankSelect::MsbLsb {
+        events.push(TrackEventKind::Midi {
+            channel: midi_channel,
+            message: MidiMessage::Controller {
+                controller: u7::from(MIDI_CC_BANK_SELECT_FINE),
+                value: bank_fine,
+            },
+        });
+    }
+
+    events.push(TrackEventKind::Midi {
+        channel: midi_channel,
+        message: MidiMessage::ProgramChange {
+            program: u7::from(program),
+        },
+    });
+
+    events
+}
+
+/// Preset changes where the bank or patch knob of an SF2 player track is
+/// automated, the automation values are held until the next point
+fn preset_change_events(
+    lmms_project: &LmmsProject,
+    options: &ConvertOptions,
+    channel_assignment: &ChannelAssignment,
+) -> Vec<AbsoluteTrackEvent<'static>> {
+    let lmms_track = channel_assignment.lmms_track(lmms_project);
+
+    let Some(sf2_player) = lmms_track
+        .instrument()
+        .and_then(|instrument| instrument.sf2_player.as_ref())
+    else {
+        return Vec::new();
+    };
+
+    // Drum sample tracks play a single key of the drum kit
+    if channel_assignment.drum_key.is_some() {
+        return Vec::new();
+    }
+
+    let bank_points = sf2_player
+        .bank_model
+        .as_ref()
+        .map_or_else(Vec::new, |model| lmms_project.automation_points(model.id));
+
+    let patch_points = sf2_player
+        .patch_model
+        .as_ref()
+        .map_or_else(Vec::new, |model| lmms_project.automation_points(model.id));
+
+    let mut changes = bank_points
+        .into_iter()
+        .map(|point| {
+            (
+                point.ticks,
+                Some(point.value.round().max(0.0) as usize),
+                None,
+            )
+        })
+        .chain(patch_points.into_iter().map(|point| {
+            (
+                point.ticks,
+                None,
+                Some(point.value.round().clamp(0.0, 127.0) as usize),
+            )
+        }))
+        .collect::<Vec<_>>();
+
+    changes.sort_by_key(|(ticks, _, _)| *ticks);
+
+    let mut midi_preset = channel_assignment.midi_preset;
+    let mut midi_track_events = Vec::new();
+    let mut skipped_changes = 0;
+
+    for (ticks, bank, program) in changes {
+        let next_midi_preset = MidiPreset {
+            bank: bank.unwrap_or(midi_preset.bank),
+            program: program.unwrap_or(midi_preset.program),
+        };
+
+        if next_midi_preset == midi_preset {
+            continue;
+        }
+
+        // Melodic tracks cannot move to the drum channel and back
+        if next_midi_preset.is_percussion() != channel_assignment.midi_preset.is_percussion() {
+            skipped_changes += 1;
+            continue;
+        }
+
+        midi_preset = next_midi_preset;
+
+        for midi_channel in &channel_assignment.midi_channels {
+            midi_track_events.extend(
+                preset_events(*midi_channel, midi_preset, options)
+                    .into_iter()
+                    .map(|kind| AbsoluteTrackEvent {
+                        ticks,
+                        ticks_event_start: ticks,
+                        kind,
+                        precise_value: None,
+                    }),
+            );
+        }
+    }
+
+    if skipped_changes > 0 {
+        warning!(
+            code: "bank-automation", track: &lmms_track.name;
+            "{} automated preset changes of track '{}' switch between melodic and percussion banks and were skipped",
+            skipped_changes,
+            lmms_track.name.escape_default(),
+        );
+    }
+
+    midi_track_events
+}
+
+fn init_channel<'a>(
+    channel_events: &mut Vec<AbsoluteTrackEvent<'a>>,
+    midi_channel: u4,
+    lmms_track: &'a LmmsTrack,
+    midi_preset: MidiPreset,
+    options: &ConvertOptions,
+    conversion: &'a Conversion,
+) {
+    channel_events.push(AbsoluteTrackEvent {
+        ticks: 0,
+        ticks_event_start: 0,
+        kind: TrackEventKind::Meta(MetaMessage::MidiChannel(midi_channel)),
+        precise_value: None,
+    });
+
+    if !lmms_track.name.is_empty() {
+        if options.text_encoding == TextEncoding::Utf8 && !lmms_track.name.is_ascii() {
+            warning!(
+                code: "non-ascii-name", track: &lmms_track.name;
+                "non-ASCII LMMS track name '{}'",
+                lmms_track.name.escape_default(),
+            );
+            note!("these track names may be mishandled by other music software, see `--text-encoding`");
+        }
+
+        channel_events.push(AbsoluteTrackEvent {
+            ticks: 0,
+            ticks_event_start: 0,
+            kind: TrackEventKind::Meta(MetaMessage::InstrumentName(
+                conversion.text(&lmms_track.name),
+            )),
+            precise_value: None,
+        });
+    }
+
+    for kind in preset_events(midi_channel, midi_preset, options) {
+        channel_events.push(AbsoluteTrackEvent {
+            ticks: 0,
+            ticks_event_start: 0,
+            kind,
+            precise_value: None,
+        });
+    }
+
+    // Tracks without instrument settings fail to convert in the first place
+    let Some(instrument_track) = lmms_track.instrument_track() else {
+        return;
+    };
+
+    {
+        let channel_volume = remap_clamp_range(
+            instrument_track.volume(),
+            0.0..=100.0,
+            0.0..=127.0,
+            options.volume_curve.transfer_fn(),
+        );
+
+        channel_events.push(AbsoluteTrackEvent {
+            ticks: 0,
+            ticks_event_start: 0,
+            kind: TrackEventKind::Midi {
+                channel: midi_channel,
+                message: MidiMessage::Controller {
+                    controller: u7::from(MIDI_CC_VOLUME),
+                    value: u7::from(channel_volume as u8),
+                },
+            },
+            precise_value: Some(precise_value(channel_volume)),
+        });
+    }
+
+    {
+        let channel_panning = remap_clamp_range(
+            instrument_track.panning * options.pan_width(),
+            -100.0..=100.0,
+            0.0..=127.0,
+            options.pan_law.transfer_fn(),
+        );
+
+        channel_events.push(AbsoluteTrackEvent {
+            ticks: 0,
+            ticks_event_start: 0,
+            kind: TrackEventKind::Midi {
+                channel: midi_channel,
+                message: MidiMessage::Controller {
+                    controller: u7::from(MIDI_CC_PANNING),
+                    value: u7::from(channel_panning as u8),
+                },
+            },
+            precise_value: Some(precise_value(channel_panning)),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_options_match_the_command_line() {
+        let options = ConvertOptions::default();
+
+        assert_eq!(options.seed, 1);
+        assert_eq!(options.overflow, ChannelOverflow::MergeSimilar);
+        assert_eq!(options.volume_curve, VolumeCurve::Sqrt);
+        assert_eq!(options.dynamics_cc, DynamicsCc::Expression);
+        assert_eq!(options.transpose, 0);
+    }
+
+    #[test]
+    fn parses_midi_presets() {
+        assert_eq!(
+            "42".parse(),
+            Ok(MidiPreset {
+                bank: 0,
+                program: 42
+            })
+        );
+        assert_eq!(
+            "128:0".parse(),
+            Ok(MidiPreset {
+                bank: 128,
+                program: 0
+            })
+        );
+        assert!("128".parse::<MidiPreset>().is_err());
+        assert!("16384:0".parse::<MidiPreset>().is_err());
+        assert!("drums".parse::<MidiPreset>().is_err());
+    }
+
+    #[test]
+    fn parses_instrument_map_entries() {
+        assert_eq!(
+            parse_track_option::<MidiPreset>("Lead = A=8:81"),
+            Ok((
+                "Lead = A".to_owned(),
+                MidiPreset {
+                    bank: 8,
+                    program: 81
+                }
+            ))
+        );
+        assert!(parse_track_option::<MidiPreset>("Lead").is_err());
+    }
+
+    #[test]
+    fn parses_pan_laws() {
+        assert_eq!(PanLaw::from_str("linear", false), Ok(PanLaw::Linear));
+        assert_eq!(PanLaw::from_str("-3dB", false), Ok(PanLaw::Minus3Db));
+        assert_eq!(PanLaw::from_str("-6dB", false), Ok(PanLaw::Minus6Db));
+        assert!(PanLaw::from_str("-4.5dB", false).is_err());
+    }
+
+    #[test]
+    fn pan_laws_keep_the_center_and_the_edges() {
+        for pan_law in PanLaw::value_variants() {
+            let transfer_fn = pan_law.transfer_fn();
+
+            assert_eq!(transfer_fn(0.0), 0.0, "{pan_law:?}");
+            assert_eq!(transfer_fn(0.5), 0.5, "{pan_law:?}");
+            assert_eq!(transfer_fn(1.0), 1.0, "{pan_law:?}");
+        }
+
+        // Half right in LMMS is the left channel at half gain
+        assert!((PanLaw::Minus3Db.transfer_fn()(0.75) - 0.7048).abs() < 0.0001);
+        assert!((PanLaw::Minus6Db.transfer_fn()(0.75) - 0.6667).abs() < 0.0001);
+    }
+
+    #[test]
+    fn parses_dynamics_controllers() {
+        assert_eq!(DynamicsCc::from_str("7", false), Ok(DynamicsCc::Volume));
+        assert_eq!(
+            DynamicsCc::from_str("11", false),
+            Ok(DynamicsCc::Expression)
+        );
+        assert!(DynamicsCc::from_str("volume", false).is_err());
+
+        assert_eq!(DynamicsCc::Volume.controller(), MIDI_CC_VOLUME);
+        assert_eq!(DynamicsCc::Expression.controller(), MIDI_CC_EXPRESSION);
+    }
+}
@@ -0,0 +1,485 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use midly::{Format, MetaMessage, MidiMessage, Smf, Timing, TrackEventKind};
+
+use crate::dump::{hex_bytes, musical_position, note_name, quoted_text, time_signatures};
+
+// Largest common resolution the files are compared at
+const DIFF_MAX_TICKS_PER_BEAT: usize = 0x7FFF;
+
+/// A note of either file, with its channel 0-15
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+struct DiffNote {
+    channel: u8,
+    key: u8,
+    start: usize,
+    end: usize,
+    velocity: u8,
+}
+
+/// Any other event, compared by what it sets and on which channel
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct DiffEvent {
+    ticks: usize,
+    channel: Option<u8>,
+    kind: String,
+    value: String,
+}
+
+/// Differences between two MIDI files
+#[derive(Debug, Clone)]
+pub struct MidiDiff {
+    /// Differences in playback order, followed by a summary
+    pub report: String,
+
+    pub difference_count: usize,
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// The notes and other events of all tracks, track layout being irrelevant to
+/// playback, with the ticks scaled by the factor
+fn collect_events(midi_document: &Smf, scale: usize) -> (Vec<DiffNote>, Vec<DiffEvent>) {
+    let mut events = midi_document
+        .tracks
+        .iter()
+        .flat_map(|midi_track| {
+            midi_track.iter().scan(0, |ticks, event| {
+                *ticks += event.delta.as_int() as usize;
+                Some((*ticks * scale, event.kind))
+            })
+        })
+        .collect::<Vec<_>>();
+    events.sort_by_key(|(ticks, _)| *ticks);
+
+    let mut notes = Vec::new();
+    let mut other_events = Vec::new();
+    let mut pending_notes: HashMap<(u8, u8), Vec<(usize, u8)>> = HashMap::new();
+
+    for (ticks, kind) in events {
+        let (channel, kind, value) = match kind {
+            TrackEventKind::Midi { channel, message } => {
+                let channel = channel.as_int();
+
+                match message {
+                    MidiMessage::NoteOn { key, vel } if vel > 0 => {
+                        pending_notes
+                            .entry((channel, key.as_int()))
+                            .or_default()
+                            .push((ticks, vel.as_int()));
+                        continue;
+                    }
+                    MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                        let pending_notes =
+                            pending_notes.entry((channel, key.as_int())).or_default();
+
+                        if !pending_notes.is_empty() {
+                            let (start, velocity) = pending_notes.remove(0);
+
+                            notes.push(DiffNote {
+                                channel,
+                                key: key.as_int(),
+                                start,
+                                end: ticks,
+                                velocity,
+                            });
+                        }
+                        continue;
+                    }
+                    MidiMessage::Aftertouch { key, vel } => (
+                        Some(channel),
+                        format!("aftertouch {}", note_name(key.as_int())),
+                        vel.to_string(),
+                    ),
+                    MidiMessage::Controller { controller, value } => {
+                        (Some(channel), format!("CC#{controller}"), value.to_string())
+                    }
+                    MidiMessage::ProgramChange { program } => {
+                        (Some(channel), "program".to_string(), program.to_string())
+                    }
+                    MidiMessage::ChannelAftertouch { vel } => (
+                        Some(channel),
+                        "channel aftertouch".to_string(),
+                        vel.to_string(),
+                    ),
+                    MidiMessage::PitchBend { bend } => (
+                        Some(channel),
+                        "pitch bend".to_string(),
+                        format!("{:+}", bend.as_int()),
+                    ),
+                }
+            }
+            TrackEventKind::SysEx(data) => {
+                (None, "sysex".to_string(), format!("F0 {}", hex_bytes(data)))
+            }
+            TrackEventKind::Escape(data) => (None, "escape".to_string(), hex_bytes(data)),
+            TrackEventKind::Meta(meta_message) => match meta_message {
+                // Track layout and the end of tracks
+                MetaMessage::EndOfTrack
+                | MetaMessage::MidiPort(_)
+                | MetaMessage::MidiChannel(_) => continue,
+                MetaMessage::TrackNumber(number) => {
+                    (None, "sequence number".to_string(), format!("{number:?}"))
+                }
+                MetaMessage::Text(text) => (None, "text".to_string(), quoted_text(text)),
+                MetaMessage::Copyright(text) => (None, "copyright".to_string(), quoted_text(text)),
+                MetaMessage::TrackName(text) => (None, "track name".to_string(), quoted_text(text)),
+                MetaMessage::InstrumentName(text) => {
+                    (None, "instrument name".to_string(), quoted_text(text))
+                }
+                MetaMessage::Lyric(text) => (None, "lyric".to_string(), quoted_text(text)),
+                MetaMessage::Marker(text) => (None, "marker".to_string(), quoted_text(text)),
+                MetaMessage::CuePoint(text) => (None, "cue point".to_string(), quoted_text(text)),
+                MetaMessage::ProgramName(text) => {
+                    (None, "program name".to_string(), quoted_text(text))
+                }
+                MetaMessage::DeviceName(text) => {
+                    (None, "device name".to_string(), quoted_text(text))
+                }
+                MetaMessage::Tempo(tempo) => (
+                    None,
+                    "tempo".to_string(),
+                    format!("{:.2} BPM", 60_000_000.0 / tempo.as_int() as f64),
+                ),
+                MetaMessage::SmpteOffset(smpte_time) => (
+                    None,
+                    "SMPTE offset".to_string(),
+                    format!(
+                        "{:02}:{:02}:{:02}:{:02}.{:02}",
+                        smpte_time.hour(),
+                        smpte_time.minute(),
+                        smpte_time.second(),
+                        smpte_time.frame(),
+                        smpte_time.subframe(),
+                    ),
+                ),
+                MetaMessage::TimeSignature(numerator, denominator, _, _) => (
+                    None,
+                    "time signature".to_string(),
+                    format!("{numerator}/{}", 1u32 << denominator),
+                ),
+                MetaMessage::KeySignature(key, minor) => (
+                    None,
+                    "key signature".to_string(),
+                    format!("{key:+} {}", if minor { "minor" } else { "major" }),
+                ),
+                MetaMessage::SequencerSpecific(data) => {
+                    (None, "sequencer specific".to_string(), hex_bytes(data))
+                }
+                MetaMessage::Unknown(meta_type, data) => {
+                    (None, format!("meta {meta_type:#04X}"), hex_bytes(data))
+                }
+            },
+        };
+
+        other_events.push(DiffEvent {
+            ticks,
+            channel,
+            kind,
+            value,
+        });
+    }
+
+    (notes, other_events)
+}
+
+/// Removes the items found in both lists, counting duplicates
+fn remove_common<T: Clone + Eq + std::hash::Hash>(a: &mut Vec<T>, b: &mut Vec<T>) {
+    let mut counts: HashMap<T, usize> = HashMap::new();
+    for item in b.iter() {
+        *counts.entry(item.clone()).or_default() += 1;
+    }
+
+    let mut common: HashMap<T, usize> = HashMap::new();
+    a.retain(|item| match counts.get_mut(item) {
+        Some(count) if *count > 0 => {
+            *count -= 1;
+            *common.entry(item.clone()).or_default() += 1;
+            false
+        }
+        _ => true,
+    });
+
+    b.retain(|item| match common.get_mut(item) {
+        Some(count) if *count > 0 => {
+            *count -= 1;
+            false
+        }
+        _ => true,
+    });
+}
+
+/// Pairs the items of both lists the function gives a distance for, closest
+/// pairs first. Returns the pairs and the unpaired items of each list.
+fn pair_closest<T: Copy, F: Fn(&T, &T) -> Option<usize>>(
+    a: &[T],
+    b: &[T],
+    distance: F,
+) -> (Vec<(T, T)>, Vec<T>, Vec<T>) {
+    let mut candidates = Vec::new();
+
+    for (a_index, a_item) in a.iter().enumerate() {
+        for (b_index, b_item) in b.iter().enumerate() {
+            if let Some(distance) = distance(a_item, b_item) {
+                candidates.push((distance, a_index, b_index));
+            }
+        }
+    }
+
+    candidates.sort();
+
+    let mut a_paired = vec![false; a.len()];
+    let mut b_paired = vec![false; b.len()];
+    let mut pairs = Vec::new();
+
+    for (_, a_index, b_index) in candidates {
+        if !a_paired[a_index] && !b_paired[b_index] {
+            a_paired[a_index] = true;
+            b_paired[b_index] = true;
+            pairs.push((a[a_index], b[b_index]));
+        }
+    }
+
+    let unpaired = |items: &[T], paired: &[bool]| {
+        items
+            .iter()
+            .zip(paired)
+            .filter(|(_, paired)| !**paired)
+            .map(|(item, _)| *item)
+            .collect::<Vec<_>>()
+    };
+
+    (pairs, unpaired(a, &a_paired), unpaired(b, &b_paired))
+}
+
+/// Compares two MIDI files in musical terms: notes added, removed, moved or
+/// changed in velocity or length, and controllers, programs, tempos and other
+/// events added, removed or changed. Track layout and running status do not
+/// count, files of different resolutions are compared at a common one.
+pub fn diff(a: &Smf, b: &Smf) -> MidiDiff {
+    let mut report = String::new();
+    let mut lines: Vec<(usize, String)> = Vec::new();
+
+    let format_name = |format: Format| match format {
+        Format::SingleTrack => "0",
+        Format::Parallel => "1",
+        Format::Sequential => "2",
+    };
+
+    if a.header.format != b.header.format {
+        writeln!(
+            report,
+            "~ header: format {} -> {}",
+            format_name(a.header.format),
+            format_name(b.header.format)
+        )
+        .unwrap();
+    }
+
+    // Metrical files are compared at the least common multiple of their
+    // resolutions, timecode files tick by tick
+    let (a_scale, b_scale, ticks_per_beat) = match (a.header.timing, b.header.timing) {
+        (Timing::Metrical(a_ticks_per_beat), Timing::Metrical(b_ticks_per_beat)) => {
+            let a_ticks_per_beat = a_ticks_per_beat.as_int().max(1) as usize;
+            let b_ticks_per_beat = b_ticks_per_beat.as_int().max(1) as usize;
+            let common =
+                a_ticks_per_beat / gcd(a_ticks_per_beat, b_ticks_per_beat) * b_ticks_per_beat;
+
+            if a_ticks_per_beat != b_ticks_per_beat {
+                writeln!(
+                    report,
+                    "~ header: {a_ticks_per_beat} -> {b_ticks_per_beat} ticks per quarter note"
+                )
+                .unwrap();
+            }
+
+            if common <= DIFF_MAX_TICKS_PER_BEAT {
+                (
+                    common / a_ticks_per_beat,
+                    common / b_ticks_per_beat,
+                    Some(common),
+                )
+            } else {
+                (1, 1, Some(b_ticks_per_beat))
+            }
+        }
+        (a_timing, b_timing) => {
+            if a_timing != b_timing {
+                writeln!(
+                    report,
+                    "~ header: time division differs, compared tick by tick"
+                )
+                .unwrap();
+            }
+
+            (1, 1, None)
+        }
+    };
+
+    let mut time_signatures = time_signatures(b);
+    for (ticks, _, _) in &mut time_signatures {
+        *ticks *= b_scale;
+    }
+
+    let position = |ticks: usize| {
+        let musical_position = ticks_per_beat.map_or(String::new(), |ticks_per_beat| {
+            musical_position(ticks, ticks_per_beat, &time_signatures)
+        });
+
+        format!("{ticks:>9}  {musical_position:<10}")
+    };
+
+    let (mut a_notes, mut a_events) = collect_events(a, a_scale);
+    let (mut b_notes, mut b_events) = collect_events(b, b_scale);
+
+    remove_common(&mut a_notes, &mut b_notes);
+    remove_common(&mut a_events, &mut b_events);
+
+    // Notes of the same key and channel within a beat are the same note moved
+    // or changed
+    let move_range = ticks_per_beat.unwrap_or(0);
+    let (note_pairs, removed_notes, added_notes) =
+        pair_closest(&a_notes, &b_notes, |a_note, b_note| {
+            (a_note.channel == b_note.channel && a_note.key == b_note.key)
+                .then(|| a_note.start.abs_diff(b_note.start))
+                .filter(|distance| *distance <= move_range)
+        });
+
+    let (mut moved_notes, mut changed_notes) = (0, 0);
+
+    for (a_note, b_note) in &note_pairs {
+        let mut changes = Vec::new();
+
+        if a_note.start != b_note.start {
+            changes.push(format!(
+                "moved {:+} ticks",
+                b_note.start as isize - a_note.start as isize
+            ));
+            moved_notes += 1;
+        } else {
+            changed_notes += 1;
+        }
+
+        if a_note.velocity != b_note.velocity {
+            changes.push(format!(
+                "velocity {} -> {}",
+                a_note.velocity, b_note.velocity
+            ));
+        }
+
+        let (a_length, b_length) = (a_note.end - a_note.start, b_note.end - b_note.start);
+        if a_length != b_length {
+            changes.push(format!("length {a_length} -> {b_length} ticks"));
+        }
+
+        lines.push((
+            b_note.start,
+            format!(
+                "~ {}  ch {:<2}  note {} ({}) {}",
+                position(b_note.start),
+                b_note.channel + 1,
+                note_name(b_note.key),
+                b_note.key,
+                changes.join(", ")
+            ),
+        ));
+    }
+
+    for (sign, notes) in [("-", &removed_notes), ("+", &added_notes)] {
+        for note in notes {
+            lines.push((
+                note.start,
+                format!(
+                    "{sign} {}  ch {:<2}  note {} ({}) vel {}, {} ticks",
+                    position(note.start),
+                    note.channel + 1,
+                    note_name(note.key),
+                    note.key,
+                    note.velocity,
+                    note.end - note.start
+                ),
+            ));
+        }
+    }
+
+    // Events of the same kind at the same time are the same event changed
+    let a_event_refs = a_events.iter().collect::<Vec<_>>();
+    let b_event_refs = b_events.iter().collect::<Vec<_>>();
+    let (event_pairs, removed_events, added_events) =
+        pair_closest(&a_event_refs, &b_event_refs, |a_event, b_event| {
+            (a_event.ticks == b_event.ticks
+                && a_event.channel == b_event.channel
+                && a_event.kind == b_event.kind)
+                .then_some(0)
+        });
+
+    let channel_text = |channel: Option<u8>| {
+        channel.map_or(String::new(), |channel| format!("ch {:<2}  ", channel + 1))
+    };
+
+    for (a_event, b_event) in &event_pairs {
+        lines.push((
+            b_event.ticks,
+            format!(
+                "~ {}  {}{} {} -> {}",
+                position(b_event.ticks),
+                channel_text(b_event.channel),
+                b_event.kind,
+                a_event.value,
+                b_event.value
+            ),
+        ));
+    }
+
+    for (sign, events) in [("-", &removed_events), ("+", &added_events)] {
+        for event in events {
+            lines.push((
+                event.ticks,
+                format!(
+                    "{sign} {}  {}{} {}",
+                    position(event.ticks),
+                    channel_text(event.channel),
+                    event.kind,
+                    event.value
+                ),
+            ));
+        }
+    }
+
+    lines.sort_by_key(|(ticks, _)| *ticks);
+
+    for (_, line) in &lines {
+        writeln!(report, "{line}").unwrap();
+    }
+
+    let difference_count = report.lines().count();
+
+    if difference_count == 0 {
+        writeln!(report, "no differences").unwrap();
+    } else {
+        writeln!(
+            report,
+            "\n{} notes added, {} removed, {} moved, {} changed; {} events added, {} removed, {} changed",
+            added_notes.len(),
+            removed_notes.len(),
+            moved_notes,
+            changed_notes,
+            added_events.len(),
+            removed_events.len(),
+            event_pairs.len(),
+        )
+        .unwrap();
+    }
+
+    MidiDiff {
+        report,
+        difference_count,
+    }
+}
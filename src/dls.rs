@@ -0,0 +1,739 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use midly::num::u4;
+use midly::{MidiMessage, TrackEventKind};
+
+use crate::bundle::resolve_soundfont_path;
+use crate::convert::{preset_events, ChannelAssignment, ConvertOptions};
+use crate::lmms_model::LmmsProject;
+use crate::midi::{MIDI_CC_BANK_SELECT_COARSE, MIDI_CC_BANK_SELECT_FINE};
+use crate::rmid::riff_chunk;
+use crate::{note, warning};
+
+// Bank flag of DLS instrument locales marking drum kits
+pub(crate) const DLS_F_INSTRUMENT_DRUMS: u32 = 0x8000_0000;
+
+// SF2 generators carried over into the DLS regions
+const SF2_GEN_RELEASE_VOL_ENV: u16 = 38;
+const SF2_GEN_INITIAL_ATTENUATION: u16 = 48;
+const SF2_GEN_INSTRUMENT: u16 = 41;
+const SF2_GEN_KEY_RANGE: u16 = 43;
+const SF2_GEN_VELOCITY_RANGE: u16 = 44;
+const SF2_GEN_COARSE_TUNE: u16 = 51;
+const SF2_GEN_FINE_TUNE: u16 = 52;
+const SF2_GEN_SAMPLE_ID: u16 = 53;
+const SF2_GEN_SAMPLE_MODES: u16 = 54;
+const SF2_GEN_OVERRIDING_ROOT_KEY: u16 = 58;
+
+// SF2 sample types of samples stored in sound ROMs instead of the file
+const SF2_SAMPLE_TYPE_ROM: u16 = 0x8000;
+
+// Volume envelope release of instrument zones without one, in timecents
+const SF2_DEFAULT_RELEASE_VOL_ENV: i16 = -12000;
+
+/// Generator operator and amount
+type Sf2Generator = (u16, [u8; 2]);
+
+/// Generator amounts of an SF2 preset or instrument zone by generator operator
+type Sf2Zone = HashMap<u16, [u8; 2]>;
+
+/// Loaded soundfonts by path
+pub(crate) type SoundFonts = HashMap<PathBuf, SoundFont>;
+
+/// Type and chunks (four character code and data) of a RIFF form or list
+type RiffList<'a> = ([u8; 4], Vec<([u8; 4], &'a [u8])>);
+
+pub(crate) struct Sf2SampleHeader {
+    pub start: usize,
+    pub end: usize,
+    pub loop_start: usize,
+    pub loop_end: usize,
+    pub sample_rate: u32,
+    original_pitch: u8,
+    pitch_correction: i8,
+    sample_type: u16,
+}
+
+impl Sf2SampleHeader {
+    /// Whether the loop points lie within the sample
+    pub fn has_valid_loop(&self) -> bool {
+        self.start <= self.loop_start
+            && self.loop_start < self.loop_end
+            && self.loop_end <= self.end
+    }
+}
+
+/// The parts of an SF2 file needed to rebuild its presets
+pub(crate) struct SoundFont {
+    /// 16-bit little-endian samples
+    pub sample_data: Vec<u8>,
+
+    /// Name, preset number, bank number and zones of the presets
+    presets: Vec<(String, u16, u16, Vec<Sf2Zone>)>,
+
+    /// Zones of the instruments
+    instruments: Vec<Vec<Sf2Zone>>,
+
+    pub sample_headers: Vec<Sf2SampleHeader>,
+}
+
+/// A sample of a preset played over a key and velocity range
+pub(crate) struct PresetRegion {
+    pub key_range: (u8, u8),
+    pub velocity_range: (u8, u8),
+    pub sample_index: usize,
+    pub unity_note: u8,
+
+    /// Cents
+    pub fine_tune: i16,
+
+    /// Centibels
+    pub attenuation: i32,
+
+    pub is_looped: bool,
+
+    /// Seconds of the volume envelope release
+    pub release_time: f32,
+}
+
+/// An SF2 preset the project plays, addressed by the bank and program its
+/// MIDI channels select
+pub(crate) struct ProjectPreset {
+    /// Bank in DLS notation: MSB in bits 8-14, LSB in bits 0-6 and the drums flag
+    pub midi_bank: u32,
+    pub midi_program: u32,
+    pub name: String,
+    pub soundfont_path: PathBuf,
+    pub regions: Vec<PresetRegion>,
+}
+
+impl SoundFont {
+    fn load(path: &Path) -> Result<SoundFont, Box<dyn Error>> {
+        let data = fs::read(path)?;
+
+        let (form_type, chunks) = riff_form(&data).ok_or("Not a RIFF file")?;
+        if &form_type != b"sfbk" {
+            return Err("Not an SF2 file".into());
+        }
+
+        let mut sample_data = Vec::new();
+        let mut pdta_chunks = HashMap::new();
+
+        for (list_type, list_chunks) in chunks
+            .iter()
+            .filter(|(id, _)| id == b"LIST")
+            .filter_map(|(_, list)| riff_list(list))
+        {
+            for (id, chunk) in list_chunks {
+                match (&list_type, &id) {
+                    (b"sdta", b"smpl") => sample_data = chunk.to_vec(),
+                    (b"pdta", _) => {
+                        pdta_chunks.insert(id, chunk);
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let pdta_records = |id: &[u8; 4], record_size: usize| -> Result<_, Box<dyn Error>> {
+            let chunk = pdta_chunks
+                .get(id)
+                .ok_or_else(|| format!("Missing SF2 {} chunk", String::from_utf8_lossy(id)))?;
+            Ok(chunk.chunks_exact(record_size).collect::<Vec<_>>())
+        };
+
+        let bags = |id: &[u8; 4]| -> Result<Vec<usize>, Box<dyn Error>> {
+            Ok(pdta_records(id, 4)?
+                .iter()
+                .map(|record| read_u16(record, 0) as usize)
+                .collect())
+        };
+
+        let generators = |id: &[u8; 4]| -> Result<Vec<Sf2Generator>, Box<dyn Error>> {
+            Ok(pdta_records(id, 4)?
+                .iter()
+                .map(|record| (read_u16(record, 0), [record[2], record[3]]))
+                .collect())
+        };
+
+        let preset_bags = bags(b"pbag")?;
+        let preset_generators = generators(b"pgen")?;
+        let instrument_bags = bags(b"ibag")?;
+        let instrument_generators = generators(b"igen")?;
+
+        // Header lists end with a terminal record marking the end of the last zone list
+        let preset_headers = pdta_records(b"phdr", 38)?;
+        let presets = preset_headers
+            .windows(2)
+            .map(|records| {
+                (
+                    read_name(records[0]),
+                    read_u16(records[0], 20),
+                    read_u16(records[0], 22),
+                    zones(
+                        &preset_bags,
+                        &preset_generators,
+                        read_u16(records[0], 24) as usize,
+                        read_u16(records[1], 24) as usize,
+                        SF2_GEN_INSTRUMENT,
+                    ),
+                )
+            })
+            .collect();
+
+        let instrument_headers = pdta_records(b"inst", 22)?;
+        let instruments = instrument_headers
+            .windows(2)
+            .map(|records| {
+                zones(
+                    &instrument_bags,
+                    &instrument_generators,
+                    read_u16(records[0], 20) as usize,
+                    read_u16(records[1], 20) as usize,
+                    SF2_GEN_SAMPLE_ID,
+                )
+            })
+            .collect();
+
+        let sample_headers = pdta_records(b"shdr", 46)?
+            .iter()
+            .map(|record| Sf2SampleHeader {
+                start: read_u32(record, 20) as usize,
+                end: read_u32(record, 24) as usize,
+                loop_start: read_u32(record, 28) as usize,
+                loop_end: read_u32(record, 32) as usize,
+                sample_rate: read_u32(record, 36),
+                original_pitch: record[40],
+                pitch_correction: record[41] as i8,
+                sample_type: read_u16(record, 44),
+            })
+            .collect();
+
+        Ok(SoundFont {
+            sample_data,
+            presets,
+            instruments,
+            sample_headers,
+        })
+    }
+
+    /// Name and regions of a preset, from the instrument zones within the
+    /// preset zones' key and velocity ranges
+    fn preset_regions(&self, bank: usize, program: usize) -> Option<(String, Vec<PresetRegion>)> {
+        let (preset_name, _, _, preset_zones) =
+            self.presets.iter().find(|(_, preset, preset_bank, _)| {
+                *preset as usize == program && *preset_bank as usize == bank
+            })?;
+
+        let mut regions = Vec::new();
+
+        for preset_zone in preset_zones {
+            let Some(instrument_zones) = preset_zone
+                .get(&SF2_GEN_INSTRUMENT)
+                .and_then(|amount| self.instruments.get(u16::from_le_bytes(*amount) as usize))
+            else {
+                continue;
+            };
+
+            for instrument_zone in instrument_zones {
+                let Some(sample_index) = instrument_zone
+                    .get(&SF2_GEN_SAMPLE_ID)
+                    .map(|amount| u16::from_le_bytes(*amount) as usize)
+                    .filter(|sample_index| {
+                        self.sample_headers
+                            .get(*sample_index)
+                            .is_some_and(|sample_header| {
+                                sample_header.sample_type & SF2_SAMPLE_TYPE_ROM == 0
+                                    && sample_header.start < sample_header.end
+                                    && sample_header.end * 2 <= self.sample_data.len()
+                            })
+                    })
+                else {
+                    continue;
+                };
+
+                let sample_header = &self.sample_headers[sample_index];
+
+                let range = |generator| {
+                    let (preset_low, preset_high) = zone_range(preset_zone, generator);
+                    let (instrument_low, instrument_high) = zone_range(instrument_zone, generator);
+                    (
+                        preset_low.max(instrument_low),
+                        preset_high.min(instrument_high),
+                    )
+                };
+
+                let key_range = range(SF2_GEN_KEY_RANGE);
+                let velocity_range = range(SF2_GEN_VELOCITY_RANGE);
+
+                if key_range.0 > key_range.1 || velocity_range.0 > velocity_range.1 {
+                    continue;
+                }
+
+                // Tuning and attenuation of preset zones add to the instrument's
+                let amount = |generator| {
+                    zone_amount(preset_zone, generator) + zone_amount(instrument_zone, generator)
+                };
+
+                let root_key = instrument_zone
+                    .get(&SF2_GEN_OVERRIDING_ROOT_KEY)
+                    .map(|amount| i16::from_le_bytes(*amount))
+                    .filter(|root_key| (0..128).contains(root_key))
+                    .unwrap_or(match sample_header.original_pitch {
+                        original_pitch @ 0..=127 => original_pitch as i16,
+                        _ => 60,
+                    });
+
+                let release_time_cents = instrument_zone
+                    .get(&SF2_GEN_RELEASE_VOL_ENV)
+                    .map_or(SF2_DEFAULT_RELEASE_VOL_ENV, |amount| {
+                        i16::from_le_bytes(*amount)
+                    })
+                    + zone_amount(preset_zone, SF2_GEN_RELEASE_VOL_ENV);
+
+                regions.push(PresetRegion {
+                    key_range,
+                    velocity_range,
+                    sample_index,
+                    unity_note: (root_key - amount(SF2_GEN_COARSE_TUNE)).clamp(0, 127) as u8,
+                    fine_tune: amount(SF2_GEN_FINE_TUNE) + sample_header.pitch_correction as i16,
+                    attenuation: amount(SF2_GEN_INITIAL_ATTENUATION) as i32,
+                    is_looped: zone_amount(instrument_zone, SF2_GEN_SAMPLE_MODES) & 1 == 1,
+                    release_time: 2f32.powf(release_time_cents as f32 / 1200.0),
+                });
+            }
+        }
+
+        Some((preset_name.clone(), regions))
+    }
+}
+
+/// The SF2 presets played by the project's tracks, along with their loaded
+/// soundfonts. Presets that cannot be found are reported and left out.
+pub(crate) fn project_presets(
+    project_path: &Path,
+    lmms_project: &LmmsProject,
+    channel_assignments: &[ChannelAssignment],
+    options: &ConvertOptions,
+) -> Result<(SoundFonts, Vec<ProjectPreset>), Box<dyn Error>> {
+    let mut soundfonts = SoundFonts::new();
+    let mut presets: Vec<ProjectPreset> = Vec::new();
+
+    for channel_assignment in channel_assignments {
+        let lmms_track = channel_assignment.lmms_track(lmms_project);
+
+        if lmms_track.sf2_player().is_none() {
+            continue;
+        }
+
+        let (Some(sample_src), Some(bank), Some(patch)) = (
+            lmms_track.sample_src(),
+            lmms_track.bank(),
+            lmms_track.patch(),
+        ) else {
+            continue;
+        };
+
+        let (midi_bank, midi_program) = dls_locale(channel_assignment, options);
+
+        if presets
+            .iter()
+            .any(|preset| (preset.midi_bank, preset.midi_program) == (midi_bank, midi_program))
+        {
+            continue;
+        }
+
+        let Some(soundfont_path) = resolve_soundfont_path(project_path, sample_src) else {
+            warning!(
+                code: "missing-soundfont", track: &lmms_track.name;
+                "soundfont '{}' could not be found",
+                sample_src.escape_default(),
+            );
+            note!(
+                "the preset of track '{}' is not embedded",
+                lmms_track.name.escape_default(),
+            );
+            continue;
+        };
+
+        if !soundfonts.contains_key(&soundfont_path) {
+            let soundfont = SoundFont::load(&soundfont_path)
+                .map_err(|err| format!("{}: {err}", soundfont_path.display()))?;
+            soundfonts.insert(soundfont_path.clone(), soundfont);
+        }
+
+        let soundfont = &soundfonts[&soundfont_path];
+
+        let Some((name, regions)) = soundfont.preset_regions(bank, patch) else {
+            warning!(
+                code: "missing-preset", track: &lmms_track.name;
+                "soundfont '{}' has no preset {}:{} used by track '{}'",
+                sample_src.escape_default(),
+                bank,
+                patch,
+                lmms_track.name.escape_default(),
+            );
+            continue;
+        };
+
+        presets.push(ProjectPreset {
+            midi_bank,
+            midi_program,
+            name,
+            soundfont_path,
+            regions,
+        });
+    }
+
+    Ok((soundfonts, presets))
+}
+
+/// Converts the SF2 presets played by the project into a DLS collection, each
+/// instrument selected by the bank and program of its MIDI channels. Envelopes,
+/// filters and modulators of the presets are not converted.
+pub fn soundfont_dls(
+    project_path: &Path,
+    lmms_project: &LmmsProject,
+    channel_assignments: &[ChannelAssignment],
+    options: &ConvertOptions,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let (soundfonts, presets) =
+        project_presets(project_path, lmms_project, channel_assignments, options)?;
+
+    if presets.is_empty() {
+        warning!(code: "empty-dls"; "the project plays no SF2 presets, the DLS collection is empty");
+    }
+
+    // Wave pool of the samples used, shared between instruments
+    let mut wave_pool: Vec<(&PathBuf, usize)> = Vec::new();
+    let mut instrument_lists = Vec::new();
+
+    for preset in &presets {
+        let mut region_lists = Vec::new();
+
+        for region in &preset.regions {
+            let wave_key = (&preset.soundfont_path, region.sample_index);
+            let table_index = match wave_pool.iter().position(|wave| *wave == wave_key) {
+                Some(table_index) => table_index,
+                None => {
+                    wave_pool.push(wave_key);
+                    wave_pool.len() - 1
+                }
+            };
+
+            let sample_header =
+                &soundfonts[&preset.soundfont_path].sample_headers[region.sample_index];
+
+            let mut rgnh = Vec::new();
+            for value in [
+                region.key_range.0 as u16,
+                region.key_range.1 as u16,
+                region.velocity_range.0 as u16,
+                region.velocity_range.1 as u16,
+                // Self non-exclusive, no key group
+                1,
+                0,
+            ] {
+                rgnh.extend(value.to_le_bytes());
+            }
+
+            let mut wsmp = Vec::new();
+            wsmp.extend(20u32.to_le_bytes());
+            wsmp.extend((region.unity_note as u16).to_le_bytes());
+            wsmp.extend(region.fine_tune.to_le_bytes());
+            // Centibels of attenuation in units of 1/655360 dB of gain
+            wsmp.extend((-region.attenuation * 65536).to_le_bytes());
+            wsmp.extend(0u32.to_le_bytes());
+
+            if region.is_looped && sample_header.has_valid_loop() {
+                wsmp.extend(1u32.to_le_bytes());
+                wsmp.extend(16u32.to_le_bytes());
+                // Forward loop
+                wsmp.extend(0u32.to_le_bytes());
+                wsmp.extend(
+                    ((sample_header.loop_start - sample_header.start) as u32).to_le_bytes(),
+                );
+                wsmp.extend(
+                    ((sample_header.loop_end - sample_header.loop_start) as u32).to_le_bytes(),
+                );
+            } else {
+                wsmp.extend(0u32.to_le_bytes());
+            }
+
+            let mut wlnk = Vec::new();
+            wlnk.extend(0u16.to_le_bytes());
+            wlnk.extend(0u16.to_le_bytes());
+            // Left (mono) channel
+            wlnk.extend(1u32.to_le_bytes());
+            wlnk.extend((table_index as u32).to_le_bytes());
+
+            region_lists.push(riff_list_chunk(
+                b"rgn ",
+                &[
+                    riff_chunk(b"rgnh", &rgnh),
+                    riff_chunk(b"wsmp", &wsmp),
+                    riff_chunk(b"wlnk", &wlnk),
+                ],
+            ));
+        }
+
+        let mut insh = Vec::new();
+        insh.extend((preset.regions.len() as u32).to_le_bytes());
+        insh.extend(preset.midi_bank.to_le_bytes());
+        insh.extend(preset.midi_program.to_le_bytes());
+
+        instrument_lists.push(riff_list_chunk(
+            b"ins ",
+            &[
+                riff_chunk(b"insh", &insh),
+                riff_list_chunk(b"lrgn", &region_lists),
+                info_list(&preset.name),
+            ],
+        ));
+    }
+
+    let mut wave_lists = Vec::new();
+
+    for (soundfont_path, sample_index) in &wave_pool {
+        let soundfont = &soundfonts[*soundfont_path];
+        let sample_header = &soundfont.sample_headers[*sample_index];
+
+        // 16-bit mono PCM
+        let mut fmt = Vec::new();
+        fmt.extend(1u16.to_le_bytes());
+        fmt.extend(1u16.to_le_bytes());
+        fmt.extend(sample_header.sample_rate.to_le_bytes());
+        fmt.extend((sample_header.sample_rate * 2).to_le_bytes());
+        fmt.extend(2u16.to_le_bytes());
+        fmt.extend(16u16.to_le_bytes());
+
+        wave_lists.push(riff_list_chunk(
+            b"wave",
+            &[
+                riff_chunk(b"fmt ", &fmt),
+                riff_chunk(
+                    b"data",
+                    &soundfont.sample_data[sample_header.start * 2..sample_header.end * 2],
+                ),
+            ],
+        ));
+    }
+
+    // Pool table offsets count from the first wave list
+    let mut ptbl = Vec::new();
+    ptbl.extend(8u32.to_le_bytes());
+    ptbl.extend((wave_lists.len() as u32).to_le_bytes());
+
+    let mut wave_offset = 0;
+    for wave_list in &wave_lists {
+        ptbl.extend((wave_offset as u32).to_le_bytes());
+        wave_offset += wave_list.len();
+    }
+
+    let mut colh = Vec::new();
+    colh.extend((instrument_lists.len() as u32).to_le_bytes());
+
+    let mut dls_data = b"DLS ".to_vec();
+    for chunk in [
+        riff_chunk(b"colh", &colh),
+        riff_list_chunk(b"lins", &instrument_lists),
+        riff_chunk(b"ptbl", &ptbl),
+        riff_list_chunk(b"wvpl", &wave_lists),
+    ] {
+        dls_data.extend(chunk);
+    }
+
+    note!(
+        "embedded {} SF2 presets with {} samples as DLS instruments",
+        instrument_lists.len(),
+        wave_lists.len(),
+    );
+
+    Ok(riff_chunk(b"RIFF", &dls_data))
+}
+
+/// DLS bank and program of a channel assignment, the way its Program Change
+/// and bank select messages address them
+fn dls_locale(channel_assignment: &ChannelAssignment, options: &ConvertOptions) -> (u32, u32) {
+    let mut midi_bank = 0;
+    let mut midi_program = 0;
+
+    for kind in preset_events(u4::from(0), channel_assignment.midi_preset, options) {
+        if let TrackEventKind::Midi { message, .. } = kind {
+            match message {
+                MidiMessage::Controller { controller, value } => match controller.as_int() {
+                    MIDI_CC_BANK_SELECT_COARSE => midi_bank |= (value.as_int() as u32) << 8,
+                    MIDI_CC_BANK_SELECT_FINE => midi_bank |= value.as_int() as u32,
+                    _ => {}
+                },
+                MidiMessage::ProgramChange { program } => midi_program = program.as_int() as u32,
+                _ => {}
+            }
+        }
+    }
+
+    if channel_assignment.midi_preset.is_percussion() {
+        midi_bank |= DLS_F_INSTRUMENT_DRUMS;
+    }
+
+    (midi_bank, midi_program)
+}
+
+/// Zones of a preset or instrument, with the generators of a leading global
+/// zone (one without the terminal generator) applied to the others
+fn zones(
+    bags: &[usize],
+    generators: &[Sf2Generator],
+    bag_start: usize,
+    bag_end: usize,
+    terminal_generator: u16,
+) -> Vec<Sf2Zone> {
+    let mut zones = (bag_start..bag_end)
+        .filter_map(|bag_index| {
+            let generator_range = *bags.get(bag_index)?..*bags.get(bag_index + 1)?;
+            Some(
+                generators
+                    .get(generator_range)?
+                    .iter()
+                    .copied()
+                    .collect::<Sf2Zone>(),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    if zones
+        .first()
+        .is_some_and(|zone| !zone.contains_key(&terminal_generator))
+    {
+        let global_zone = zones.remove(0);
+
+        for zone in &mut zones {
+            for (generator, amount) in &global_zone {
+                zone.entry(*generator).or_insert(*amount);
+            }
+        }
+    }
+
+    zones
+}
+
+fn zone_range(zone: &Sf2Zone, generator: u16) -> (u8, u8) {
+    zone.get(&generator)
+        .map_or((0, 127), |[low, high]| (*low, *high))
+}
+
+fn zone_amount(zone: &Sf2Zone, generator: u16) -> i16 {
+    zone.get(&generator)
+        .map_or(0, |amount| i16::from_le_bytes(*amount))
+}
+
+/// LIST chunk of the given type
+fn riff_list_chunk(list_type: &[u8; 4], chunks: &[Vec<u8>]) -> Vec<u8> {
+    let mut list_data = list_type.to_vec();
+    list_data.extend(chunks.concat());
+    riff_chunk(b"LIST", &list_data)
+}
+
+fn info_list(name: &str) -> Vec<u8> {
+    let mut name = name.as_bytes().to_vec();
+    name.push(0);
+    riff_list_chunk(b"INFO", &[riff_chunk(b"INAM", &name)])
+}
+
+/// Form type and chunks of a RIFF file
+fn riff_form(data: &[u8]) -> Option<RiffList<'_>> {
+    if data.get(..4)? != b"RIFF" {
+        return None;
+    }
+
+    let size = read_u32(data.get(4..8)?, 0) as usize;
+    riff_list(data.get(8..8 + size).or_else(|| data.get(8..))?)
+}
+
+/// Type and chunks of a list's data
+fn riff_list(data: &[u8]) -> Option<RiffList<'_>> {
+    let list_type = data.get(..4)?.try_into().ok()?;
+    let mut chunks = Vec::new();
+    let mut offset = 4;
+
+    while let Some(header) = data.get(offset..offset + 8) {
+        let size = read_u32(header, 4) as usize;
+        let chunk = data.get(offset + 8..offset + 8 + size)?;
+
+        chunks.push((header[..4].try_into().ok()?, chunk));
+        offset += 8 + size + size % 2;
+    }
+
+    Some((list_type, chunks))
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+/// Zero-terminated name of a header record
+fn read_name(record: &[u8]) -> String {
+    let name = &record[..20];
+    let length = name.iter().position(|byte| *byte == 0).unwrap_or(20);
+    String::from_utf8_lossy(&name[..length]).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_read_back_chunk_by_chunk() {
+        let riff_bytes = riff_chunk(
+            b"RIFF",
+            &[
+                b"DLS " as &[u8],
+                &riff_chunk(b"colh", &1u32.to_le_bytes()),
+                &info_list("Harp"),
+            ]
+            .concat(),
+        );
+
+        let (form_type, chunks) = riff_form(&riff_bytes).unwrap();
+        assert_eq!(&form_type, b"DLS ");
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0], (*b"colh", &[1, 0, 0, 0][..]));
+
+        let (list_type, info_chunks) = riff_list(chunks[1].1).unwrap();
+        assert_eq!(&list_type, b"INFO");
+        // Odd-length chunks are padded, the padding is not part of the data
+        assert_eq!(info_chunks, [(*b"INAM", &b"Harp\0"[..])]);
+    }
+
+    #[test]
+    fn global_zone_applies_to_the_other_zones() {
+        let bags = [0, 1, 3, 4];
+        let generators = [
+            (SF2_GEN_KEY_RANGE, [0, 63]),
+            (SF2_GEN_COARSE_TUNE, [2, 0]),
+            (SF2_GEN_INSTRUMENT, [0, 0]),
+            (SF2_GEN_INSTRUMENT, [1, 0]),
+        ];
+
+        let zones = zones(&bags, &generators, 0, 3, SF2_GEN_INSTRUMENT);
+
+        assert_eq!(zones.len(), 2);
+        assert_eq!(zone_range(&zones[0], SF2_GEN_KEY_RANGE), (0, 63));
+        assert_eq!(zone_amount(&zones[0], SF2_GEN_COARSE_TUNE), 2);
+        assert_eq!(zone_range(&zones[1], SF2_GEN_KEY_RANGE), (0, 63));
+        assert_eq!(zone_amount(&zones[1], SF2_GEN_INSTRUMENT), 1);
+        assert_eq!(zone_range(&zones[1], SF2_GEN_VELOCITY_RANGE), (0, 127));
+    }
+}
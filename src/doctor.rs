@@ -0,0 +1,262 @@
+use std::fs;
+use std::path::Path;
+
+use crate::bundle::resolve_soundfont_path;
+use crate::controllers::controller_connections;
+use crate::convert::{convertible_tracks, merged_tracks, skipped_tracks, ConvertOptions};
+use crate::lmms_model::*;
+
+/// How urgently a finding has to be fixed, most urgent first
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The conversion fails or produces an unusable file
+    Error,
+
+    /// Parts of the song are lost or sound wrong
+    Warning,
+
+    /// The output differs from LMMS playback in minor ways
+    Hint,
+}
+
+impl Severity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Hint => "hint",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Finding {
+    pub severity: Severity,
+    pub problem: String,
+    pub fix: String,
+}
+
+/// Checks the environment and the project for anything that would make the
+/// conversion fail or sound different from LMMS, most urgent findings first
+pub fn diagnose(
+    project_path: &Path,
+    output_path: Option<&Path>,
+    options: &ConvertOptions,
+) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    if let Some(output_path) = output_path {
+        check_output_path(output_path, &mut findings);
+    }
+
+    match LmmsProject::load_from_path(project_path) {
+        Ok(lmms_project) => check_project(project_path, &lmms_project, options, &mut findings),
+        Err(err) => findings.push(Finding {
+            severity: Severity::Error,
+            problem: format!("the project cannot be read ({err})"),
+            fix: "make sure it is an .mmp/.mmpz file and re-save it with a recent LMMS version"
+                .to_owned(),
+        }),
+    }
+
+    findings.sort_by_key(|finding| finding.severity);
+    findings
+}
+
+fn check_output_path(output_path: &Path, findings: &mut Vec<Finding>) {
+    let output_dir = match output_path.parent() {
+        Some(output_dir) if !output_dir.as_os_str().is_empty() => output_dir,
+        _ => Path::new("."),
+    };
+
+    if !output_dir.is_dir() {
+        findings.push(Finding {
+            severity: Severity::Error,
+            problem: format!(
+                "the output directory '{}' does not exist",
+                output_dir.display()
+            ),
+            fix: "create it or choose another output path".to_owned(),
+        });
+        return;
+    }
+
+    let probe_path = output_dir.join(".lmms2mid-doctor");
+
+    match fs::write(&probe_path, []) {
+        Ok(()) => {
+            let _ = fs::remove_file(&probe_path);
+        }
+        Err(err) => findings.push(Finding {
+            severity: Severity::Error,
+            problem: format!(
+                "the output directory '{}' is not writable ({err})",
+                output_dir.display()
+            ),
+            fix: "check its permissions or choose another output path".to_owned(),
+        }),
+    }
+}
+
+fn check_project(
+    project_path: &Path,
+    lmms_project: &LmmsProject,
+    options: &ConvertOptions,
+    findings: &mut Vec<Finding>,
+) {
+    let mut checked_soundfonts = Vec::new();
+
+    for lmms_track in lmms_project.sampler_tracks() {
+        let Some(sample_src) = lmms_track.sample_src() else {
+            continue;
+        };
+
+        if checked_soundfonts.contains(&sample_src) {
+            continue;
+        }
+
+        checked_soundfonts.push(sample_src);
+
+        if sample_src.is_empty() {
+            findings.push(Finding {
+                severity: Severity::Warning,
+                problem: format!(
+                    "track '{}' has no soundfont loaded",
+                    lmms_track.name.escape_default()
+                ),
+                fix: "load a soundfont in LMMS so its bank and patch are meaningful".to_owned(),
+            });
+        } else if resolve_soundfont_path(project_path, sample_src).is_none() {
+            findings.push(Finding {
+                severity: Severity::Warning,
+                problem: format!(
+                    "soundfont '{}' of track '{}' cannot be found",
+                    sample_src.escape_default(),
+                    lmms_track.name.escape_default(),
+                ),
+                fix: "place it next to the project or fix its path in LMMS, `--bundle` needs it"
+                    .to_owned(),
+            });
+        }
+    }
+
+    for lmms_track in &lmms_project.song.track_container.tracks {
+        let Some(instrument) = lmms_track.instrument() else {
+            continue;
+        };
+
+        if options.track_preset(lmms_track).is_some() {
+            continue;
+        }
+
+        if options.sample_drums && options.sample_drum_key(lmms_track).is_some() {
+            continue;
+        }
+
+        findings.push(Finding {
+            severity: Severity::Warning,
+            problem: format!(
+                "{} track '{}' has no MIDI program and will be dropped",
+                instrument.name,
+                lmms_track.name.escape_default(),
+            ),
+            fix: "use `--instrument-map`, `--instrument-map-file` or `--default-program`"
+                .to_owned(),
+        });
+    }
+
+    let lmms_instrument_channel_count = convertible_tracks(lmms_project, options)
+        .filter(|(_, _, midi_preset)| !midi_preset.is_percussion())
+        .map(|(_, lmms_track, _)| options.track_spread(&lmms_track.name))
+        .sum::<usize>()
+        - merged_tracks(lmms_project, options).len();
+
+    let lmms_instrument_channel_capacity = options.melodic_channel_capacity(lmms_project);
+
+    if lmms_instrument_channel_count > lmms_instrument_channel_capacity {
+        findings.push(Finding {
+            severity: Severity::Warning,
+            problem: format!(
+                "the project needs {lmms_instrument_channel_count} melodic MIDI channels, only {lmms_instrument_channel_capacity} are available"
+            ),
+            fix: "use `--midi-format 1 --midi-ports <count>`, merge tracks in LMMS or reduce `--spread`"
+                .to_owned(),
+        });
+    }
+
+    for lmms_track in skipped_tracks(lmms_project) {
+        findings.push(Finding {
+            severity: Severity::Hint,
+            problem: format!(
+                "track '{}' has no notes (type {}) and will be skipped",
+                lmms_track.name.escape_default(),
+                lmms_track.r#type,
+            ),
+            fix: "use `--skipped-track-markers` to keep its clip positions as markers".to_owned(),
+        });
+    }
+
+    for (_, lmms_track, _) in convertible_tracks(lmms_project, options) {
+        for (parameter, controller_id) in controller_connections(lmms_track) {
+            let exportable = matches!(
+                lmms_project.controller(controller_id),
+                Some(LmmsController::Lfo(lmms_lfo)) if lmms_lfo.value_at(0.0).is_some()
+            );
+
+            if !exportable {
+                findings.push(Finding {
+                    severity: Severity::Hint,
+                    problem: format!(
+                        "the {} controller of track '{}' cannot be exported",
+                        parameter.name(),
+                        lmms_track.name.escape_default(),
+                    ),
+                    fix: "replace it with an LFO controller or automation".to_owned(),
+                });
+            }
+        }
+
+        if !lmms_track.name.is_ascii() {
+            findings.push(Finding {
+                severity: Severity::Hint,
+                problem: format!(
+                    "track name '{}' is not ASCII",
+                    lmms_track.name.escape_default()
+                ),
+                fix: "rename the track, many MIDI players cannot display it".to_owned(),
+            });
+        }
+    }
+
+    if lmms_project.ticks_per_bar() != LMMS_TICKS_PER_BAR {
+        findings.push(Finding {
+            severity: Severity::Hint,
+            problem: format!(
+                "the project uses a timebase of {} ticks per bar",
+                lmms_project.ticks_per_bar()
+            ),
+            fix: format!("positions are rescaled to {LMMS_TICKS_PER_BAR} ticks per bar, re-save the project in LMMS to avoid rounding"),
+        });
+    }
+}
+
+/// Prints the findings as a numbered fix-it list
+pub fn print_findings(findings: &[Finding]) {
+    if findings.is_empty() {
+        println!("No problems found.");
+        return;
+    }
+
+    println!("{} problem(s) found, most urgent first:", findings.len());
+
+    for (finding_index, finding) in findings.iter().enumerate() {
+        println!();
+        println!(
+            "{}. [{}] {}",
+            finding_index + 1,
+            finding.severity.name(),
+            finding.problem
+        );
+        println!("   fix: {}", finding.fix);
+    }
+}
@@ -0,0 +1,163 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+// Name fragments of drum samples and their GM percussion keys, more specific
+// fragments first
+const GM_DRUM_NAMES: &[(&str, u8)] = &[
+    ("open hat", 46),
+    ("openhat", 46),
+    ("open hh", 46),
+    ("ohh", 46),
+    ("pedal hat", 44),
+    ("pedal hh", 44),
+    ("closed hat", 42),
+    ("closedhat", 42),
+    ("chh", 42),
+    ("hihat", 42),
+    ("hi-hat", 42),
+    ("hi hat", 42),
+    ("hat", 42),
+    ("hh", 42),
+    ("kick", 36),
+    ("bass drum", 36),
+    ("bassdrum", 36),
+    ("bd", 36),
+    ("rimshot", 37),
+    ("rim", 37),
+    ("side stick", 37),
+    ("snare", 38),
+    ("sd", 38),
+    ("clap", 39),
+    ("floor tom", 43),
+    ("low tom", 45),
+    ("mid tom", 47),
+    ("high tom", 50),
+    ("hi tom", 50),
+    ("tom", 47),
+    ("ride bell", 53),
+    ("ride", 51),
+    ("china", 52),
+    ("splash", 55),
+    ("crash", 49),
+    ("cymbal", 49),
+    ("tambourine", 54),
+    ("cowbell", 56),
+    ("bongo", 61),
+    ("conga", 63),
+    ("timbale", 65),
+    ("agogo", 67),
+    ("cabasa", 69),
+    ("maracas", 70),
+    ("shaker", 70),
+    ("whistle", 71),
+    ("guiro", 73),
+    ("clave", 75),
+    ("woodblock", 76),
+    ("wood block", 76),
+    ("triangle", 81),
+];
+
+// Keys with a defined drum sound in the GM/GS percussion map
+pub const GM_DRUM_KEY_MIN: u8 = 27;
+pub const GM_DRUM_KEY_MAX: u8 = 87;
+
+pub fn is_gm_drum_key(key: u8) -> bool {
+    (GM_DRUM_KEY_MIN..=GM_DRUM_KEY_MAX).contains(&key)
+}
+
+/// Moves a key outside the GM percussion map into it by whole octaves, then
+/// snaps it to the closest key of the drum table
+pub fn remap_gm_drum_key(key: u8) -> u8 {
+    let mut key = key;
+
+    while key < GM_DRUM_KEY_MIN {
+        key += 12;
+    }
+
+    while key > GM_DRUM_KEY_MAX {
+        key -= 12;
+    }
+
+    GM_DRUM_NAMES
+        .iter()
+        .map(|(_, drum_key)| *drum_key)
+        .min_by_key(|drum_key| drum_key.abs_diff(key))
+        .unwrap_or(key)
+}
+
+/// Guesses the GM percussion key of a drum sample from its track or file name
+pub fn guess_gm_drum_key(name: &str) -> Option<u8> {
+    let name = name.to_lowercase().replace(['_', '-', '.'], " ");
+
+    GM_DRUM_NAMES
+        .iter()
+        .find(|(fragment, _)| {
+            if fragment.len() <= 3 {
+                // Short abbreviations ("bd", "sd", "hh") must be whole words
+                name.split(|c: char| !c.is_ascii_alphabetic())
+                    .any(|word| word == *fragment)
+            } else {
+                name.contains(fragment)
+            }
+        })
+        .map(|(_, key)| *key)
+}
+
+// Remaps the drum keys of soundfonts with non-GM layouts to GM percussion keys,
+// keys without an entry are kept.
+//
+//     36 = 35
+//     38 = 40
+
+#[derive(Debug, Clone)]
+pub struct DrumMap {
+    keys: [u8; 128],
+}
+
+impl DrumMap {
+    pub fn load_from_path(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let table = fs::read_to_string(path)?.parse::<toml::Table>()?;
+        let mut keys = std::array::from_fn(|key| key as u8);
+
+        for (from, to) in table {
+            let invalid_entry = || format!("Invalid drum map entry '{from}'");
+
+            let from_key = from
+                .trim()
+                .parse::<u8>()
+                .ok()
+                .filter(|from_key| *from_key < 128)
+                .ok_or_else(invalid_entry)?;
+
+            let to_key = to
+                .as_integer()
+                .filter(|to_key| (0..128).contains(to_key))
+                .ok_or_else(invalid_entry)?;
+
+            keys[from_key as usize] = to_key as u8;
+        }
+
+        Ok(DrumMap { keys })
+    }
+
+    pub fn get(&self, key: u8) -> u8 {
+        self.keys[key as usize]
+    }
+}
+
+/// Command line value parser for drum map files
+pub fn parse_drum_map(s: &str) -> Result<DrumMap, String> {
+    DrumMap::load_from_path(Path::new(s)).map_err(|err| err.to_string())
+}
+
+/// Command line value parser for `<track name>=<GM percussion key>` pairs
+pub fn parse_drum_key(s: &str) -> Result<(String, u8), String> {
+    let (track_name, key) = crate::convert::parse_track_option::<u8>(s)?;
+
+    if key >= 128 {
+        return Err(format!("invalid MIDI key '{key}'"));
+    }
+
+    Ok((track_name, key))
+}
@@ -0,0 +1,220 @@
+use std::fmt::Write;
+
+use midly::{Format, MetaMessage, MidiMessage, Smf, Timing, TrackEventKind};
+
+const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+/// Note name with its octave, middle C (key 60) being C4
+pub(crate) fn note_name(key: u8) -> String {
+    format!("{}{}", NOTE_NAMES[key as usize % 12], key as i32 / 12 - 1)
+}
+
+/// Bytes as hexadecimal numbers
+pub(crate) fn hex_bytes(data: &[u8]) -> String {
+    data.iter()
+        .map(|byte| format!("{byte:02X}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Text of a meta event, quoted
+pub(crate) fn quoted_text(text: &[u8]) -> String {
+    format!("{:?}", String::from_utf8_lossy(text))
+}
+
+/// Description of an event
+fn describe_event(kind: &TrackEventKind) -> String {
+    match kind {
+        TrackEventKind::Midi { channel, message } => {
+            let channel = channel.as_int() + 1;
+
+            match message {
+                MidiMessage::NoteOff { key, vel } => format!(
+                    "ch {channel:<2}  note off  {} ({key}) vel {vel}",
+                    note_name(key.as_int())
+                ),
+                MidiMessage::NoteOn { key, vel } => format!(
+                    "ch {channel:<2}  note on   {} ({key}) vel {vel}",
+                    note_name(key.as_int())
+                ),
+                MidiMessage::Aftertouch { key, vel } => format!(
+                    "ch {channel:<2}  aftertouch {} ({key}) {vel}",
+                    note_name(key.as_int())
+                ),
+                MidiMessage::Controller { controller, value } => {
+                    format!("ch {channel:<2}  control   CC#{controller} = {value}")
+                }
+                MidiMessage::ProgramChange { program } => {
+                    format!("ch {channel:<2}  program   {program}")
+                }
+                MidiMessage::ChannelAftertouch { vel } => {
+                    format!("ch {channel:<2}  channel aftertouch {vel}")
+                }
+                MidiMessage::PitchBend { bend } => {
+                    format!("ch {channel:<2}  pitch bend {:+}", bend.as_int())
+                }
+            }
+        }
+        TrackEventKind::SysEx(data) => format!("sysex     F0 {}", hex_bytes(data)),
+        TrackEventKind::Escape(data) => format!("escape    {}", hex_bytes(data)),
+        TrackEventKind::Meta(meta_message) => match meta_message {
+            MetaMessage::TrackNumber(number) => format!("sequence number {number:?}"),
+            MetaMessage::Text(text) => format!("text {}", quoted_text(text)),
+            MetaMessage::Copyright(text) => format!("copyright {}", quoted_text(text)),
+            MetaMessage::TrackName(text) => format!("track name {}", quoted_text(text)),
+            MetaMessage::InstrumentName(text) => format!("instrument name {}", quoted_text(text)),
+            MetaMessage::Lyric(text) => format!("lyric {}", quoted_text(text)),
+            MetaMessage::Marker(text) => format!("marker {}", quoted_text(text)),
+            MetaMessage::CuePoint(text) => format!("cue point {}", quoted_text(text)),
+            MetaMessage::ProgramName(text) => format!("program name {}", quoted_text(text)),
+            MetaMessage::DeviceName(text) => format!("device name {}", quoted_text(text)),
+            MetaMessage::MidiChannel(channel) => format!("channel prefix {}", channel.as_int() + 1),
+            MetaMessage::MidiPort(port) => format!("port {port}"),
+            MetaMessage::EndOfTrack => "end of track".to_string(),
+            MetaMessage::Tempo(tempo) => format!(
+                "tempo {tempo} us/quarter ({:.2} BPM)",
+                60_000_000.0 / tempo.as_int() as f64
+            ),
+            MetaMessage::SmpteOffset(smpte_time) => format!(
+                "SMPTE offset {:02}:{:02}:{:02}:{:02}.{:02}",
+                smpte_time.hour(),
+                smpte_time.minute(),
+                smpte_time.second(),
+                smpte_time.frame(),
+                smpte_time.subframe(),
+            ),
+            MetaMessage::TimeSignature(numerator, denominator, _, _) => {
+                format!("time signature {numerator}/{}", 1u32 << denominator)
+            }
+            MetaMessage::KeySignature(key, minor) => format!(
+                "key signature {key:+} {}",
+                if *minor { "minor" } else { "major" }
+            ),
+            MetaMessage::SequencerSpecific(data) => {
+                format!("sequencer specific {}", hex_bytes(data))
+            }
+            MetaMessage::Unknown(meta_type, data) => {
+                format!("meta {meta_type:#04X} {}", hex_bytes(data))
+            }
+        },
+    }
+}
+
+/// Musical position of metrical ticks as bar:beat:tick, counted from 1:1:0,
+/// following the time signature changes (4/4 until the first one)
+pub(crate) fn musical_position(
+    ticks: usize,
+    ticks_per_beat: usize,
+    time_signatures: &[(usize, u8, u8)],
+) -> String {
+    let mut bar = 0;
+    let mut segment_start = 0;
+    let mut ticks_per_signature_beat = ticks_per_beat.max(1);
+    let mut ticks_per_bar = ticks_per_signature_beat * 4;
+
+    for (signature_ticks, numerator, denominator) in time_signatures {
+        if *signature_ticks > ticks {
+            break;
+        }
+
+        // Bars are counted up to the change, partial bars rounded up
+        bar += (signature_ticks - segment_start).div_ceil(ticks_per_bar);
+        segment_start = *signature_ticks;
+
+        ticks_per_signature_beat = ((ticks_per_beat * 4) >> denominator).max(1);
+        ticks_per_bar = ticks_per_signature_beat * *numerator as usize;
+    }
+
+    let ticks_in_segment = ticks - segment_start;
+    let ticks_in_bar = ticks_in_segment % ticks_per_bar;
+
+    format!(
+        "{}:{}:{:02}",
+        bar + ticks_in_segment / ticks_per_bar + 1,
+        ticks_in_bar / ticks_per_signature_beat + 1,
+        ticks_in_bar % ticks_per_signature_beat,
+    )
+}
+
+/// Time signature changes of all tracks as `(ticks, numerator, denominator
+/// power of two)`, like sequencers read them from the conductor track
+pub(crate) fn time_signatures(midi_document: &Smf) -> Vec<(usize, u8, u8)> {
+    let mut time_signatures = Vec::new();
+
+    for midi_track in &midi_document.tracks {
+        let mut ticks = 0;
+
+        for event in midi_track {
+            ticks += event.delta.as_int() as usize;
+
+            if let TrackEventKind::Meta(MetaMessage::TimeSignature(numerator, denominator, _, _)) =
+                event.kind
+            {
+                time_signatures.push((ticks, numerator.max(1), denominator.min(8)));
+            }
+        }
+    }
+
+    time_signatures.sort_by_key(|(ticks, _, _)| *ticks);
+    time_signatures
+}
+
+/// Readable listing of a Standard MIDI File: the header, then every event of
+/// every track on a line of its own with its absolute time in ticks, and for
+/// metrical timing its bar:beat:tick position
+pub fn dump(midi_document: &Smf) -> String {
+    let format = match midi_document.header.format {
+        Format::SingleTrack => "0 (single track)",
+        Format::Parallel => "1 (parallel tracks)",
+        Format::Sequential => "2 (sequential tracks)",
+    };
+
+    let (timing, ticks_per_beat) = match midi_document.header.timing {
+        Timing::Metrical(ticks_per_beat) => (
+            format!("{ticks_per_beat} ticks per quarter note"),
+            Some(ticks_per_beat.as_int() as usize),
+        ),
+        Timing::Timecode(fps, subframes) => (
+            format!("{} fps, {subframes} ticks per frame", fps.as_f32()),
+            None,
+        ),
+    };
+
+    let time_signatures = time_signatures(midi_document);
+
+    let mut listing = format!(
+        "format {format}, {} tracks, {timing}\n",
+        midi_document.tracks.len()
+    );
+
+    for (track_index, midi_track) in midi_document.tracks.iter().enumerate() {
+        writeln!(
+            listing,
+            "\ntrack {} ({} events)",
+            track_index + 1,
+            midi_track.len()
+        )
+        .unwrap();
+
+        let mut ticks = 0;
+
+        for event in midi_track {
+            ticks += event.delta.as_int() as usize;
+
+            let position = ticks_per_beat.map_or(String::new(), |ticks_per_beat| {
+                musical_position(ticks, ticks_per_beat, &time_signatures)
+            });
+
+            writeln!(
+                listing,
+                "{ticks:>9}  {position:<10}  {}",
+                describe_event(&event.kind)
+            )
+            .unwrap();
+        }
+    }
+
+    listing
+}
@@ -0,0 +1,92 @@
+use std::io;
+use std::str::Utf8Error;
+
+use miniz_oxide::inflate::DecompressError;
+use strong_xml::XmlError;
+use thiserror::Error;
+
+use crate::exit_code::ExitCode;
+
+/// Failure of loading, converting or saving an LMMS project. Malformed
+/// projects end up here, panics are left to bugs of the converter.
+#[derive(Debug, Error)]
+pub enum ConvertError {
+    /// Reading or writing a file failed
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    /// Not an LMMS project file, judging by the file extension
+    #[error("Not an LMMS project file")]
+    NotAProject,
+
+    /// The compressed project is too short to hold its header
+    #[error("Compressed LMMS project file is truncated")]
+    Truncated,
+
+    /// The compressed project data is corrupt
+    #[error("Corrupt compressed LMMS project: {0}")]
+    Decompress(#[from] DecompressError),
+
+    /// The project XML is not valid UTF-8
+    #[error("LMMS project is not valid UTF-8: {0}")]
+    Utf8(#[from] Utf8Error),
+
+    /// The project XML is malformed or misses required data
+    #[error(transparent)]
+    Xml(#[from] XmlError),
+
+    /// Names of the tracks not fitting into the MIDI channels with
+    /// `--overflow error`
+    #[error(
+        "LMMS tracks do not fit into the available MIDI channels: {}",
+        quoted_names(.0)
+    )]
+    ChannelOverflow(Vec<String>),
+
+    /// An instrument track missing its instrument settings
+    #[error("LMMS track '{}' has no instrument settings", .0.escape_default())]
+    NoInstrument(String),
+
+    /// The output format cannot represent the project, e.g. a MUS score over
+    /// the size limit of the format
+    #[error("{0}")]
+    Unsupported(String),
+
+    /// A track index past the tracks of the project
+    #[error("LMMS project has no track {0}")]
+    NoSuchTrack(usize),
+
+    /// A pattern index past the patterns of a track
+    #[error("LMMS track {track_index} has no pattern {pattern_index}")]
+    NoSuchPattern {
+        track_index: usize,
+        pattern_index: usize,
+    },
+}
+
+impl ConvertError {
+    /// Exit code of the failure, for scripts telling failures apart
+    pub fn exit_code(&self) -> ExitCode {
+        match self {
+            ConvertError::Io(_) => ExitCode::Io,
+            ConvertError::NotAProject
+            | ConvertError::Truncated
+            | ConvertError::Decompress(_)
+            | ConvertError::Utf8(_)
+            | ConvertError::Xml(_)
+            | ConvertError::NoInstrument(_) => ExitCode::Parse,
+            ConvertError::ChannelOverflow(_) => ExitCode::ChannelOverflow,
+            ConvertError::Unsupported(_) => ExitCode::Unsupported,
+            ConvertError::NoSuchTrack(_) | ConvertError::NoSuchPattern { .. } => ExitCode::Failure,
+        }
+    }
+}
+
+/// Track names quoted and listed for messages, e.g. `'Bass', 'Lead'`
+fn quoted_names(track_names: &[String]) -> String {
+    track_names
+        .iter()
+        .map(|track_name| format!("'{}'", track_name.escape_default()))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,96 @@
+use std::error::Error;
+use std::fmt::Write;
+use std::io;
+
+/// Exit codes of lmms2mid, so that scripts can tell failures apart. Panics
+/// exit with 101 like any Rust program.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ExitCode {
+    /// The command succeeded
+    Success = 0,
+
+    /// Any other failure, e.g. the differences found by `diff`, the errors
+    /// found by `doctor` or projects of a batch failing differently
+    Failure = 1,
+
+    /// Invalid command line arguments
+    Usage = 2,
+
+    /// The project, a configuration file or a soundfont could not be parsed
+    Parse = 3,
+
+    /// The output format or the soundfonts cannot represent the project, e.g.
+    /// a MUS score over the size limit of the format
+    Unsupported = 4,
+
+    /// Tracks do not fit into the MIDI channels with `--overflow error`
+    ChannelOverflow = 5,
+
+    /// Reading or writing a file failed
+    Io = 6,
+
+    /// Warnings were treated as errors with `--strict`
+    Strict = 7,
+}
+
+impl ExitCode {
+    pub const ALL: [ExitCode; 8] = [
+        ExitCode::Success,
+        ExitCode::Failure,
+        ExitCode::Usage,
+        ExitCode::Parse,
+        ExitCode::Unsupported,
+        ExitCode::ChannelOverflow,
+        ExitCode::Io,
+        ExitCode::Strict,
+    ];
+
+    pub fn code(self) -> i32 {
+        self as i32
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            ExitCode::Success => "success",
+            ExitCode::Failure => "other failure",
+            ExitCode::Usage => "invalid command line arguments",
+            ExitCode::Parse => "the project or an input file could not be parsed",
+            ExitCode::Unsupported => "the output format cannot represent the project",
+            ExitCode::ChannelOverflow => "tracks do not fit into the MIDI channels",
+            ExitCode::Io => "reading or writing a file failed",
+            ExitCode::Strict => "warnings treated as errors with `--strict`",
+        }
+    }
+
+    /// Exit code of a file failing to load, I/O errors apart from the files
+    /// failing to parse
+    pub fn of_load_error(err: &(dyn Error + 'static)) -> ExitCode {
+        if err.is::<io::Error>() {
+            ExitCode::Io
+        } else {
+            ExitCode::Parse
+        }
+    }
+
+    /// Exit code of a batch of failures, the shared one or `Failure` when
+    /// they differ
+    pub fn of_batch(exit_codes: &[ExitCode]) -> ExitCode {
+        match exit_codes.split_first() {
+            None => ExitCode::Success,
+            Some((first, rest)) if rest.iter().all(|exit_code| exit_code == first) => *first,
+            Some(_) => ExitCode::Failure,
+        }
+    }
+}
+
+/// The exit codes as a list for the help, e.g. "  3  the project or an input
+/// file could not be parsed"
+pub fn exit_status_help() -> String {
+    let mut help = String::from("Exit status:\n");
+
+    for exit_code in ExitCode::ALL {
+        writeln!(help, "  {}  {}", exit_code.code(), exit_code.description()).unwrap();
+    }
+
+    help
+}
@@ -0,0 +1,207 @@
+use std::fmt::Write;
+
+use crate::lmms_model::LMMS_TICKS_PER_BAR;
+use crate::rng::SplitMix64;
+
+/// Characteristics of a synthesized LMMS project
+#[derive(Debug, Clone, clap::Args)]
+pub struct FixtureOptions {
+    /// Total number of notes, spread evenly across the tracks
+    #[arg(long, default_value_t = 100)]
+    pub notes: usize,
+
+    /// Number of instrument tracks (more than 16 exceeds the MIDI channel limit)
+    #[arg(long, default_value_t = 4)]
+    pub tracks: usize,
+
+    /// Number of chords exceeding the MIDI polyphony limit, placed on the first track
+    #[arg(long, default_value_t = 0)]
+    pub polyphony_spikes: usize,
+
+    /// Number of overlapping same-key note pairs, placed on the first track
+    #[arg(long, default_value_t = 0)]
+    pub overlaps: usize,
+
+    /// Make the last track a percussion track (SF2 bank 128)
+    #[arg(long)]
+    pub percussion: bool,
+
+    /// Enable loop points around the second half of the song
+    #[arg(long)]
+    pub loop_points: bool,
+
+    /// Tempo in beats per minute
+    #[arg(long, default_value_t = 140)]
+    pub bpm: usize,
+
+    /// Seed of the pseudo-random note generator, the same seed gives the same project
+    #[arg(long, default_value_t = 1)]
+    pub seed: u64,
+}
+
+impl Default for FixtureOptions {
+    fn default() -> Self {
+        FixtureOptions {
+            notes: 100,
+            tracks: 4,
+            polyphony_spikes: 0,
+            overlaps: 0,
+            percussion: false,
+            loop_points: false,
+            bpm: 140,
+            seed: 1,
+        }
+    }
+}
+
+// Notes are placed on a sixteenth note grid
+const FIXTURE_NOTE_STEP: usize = LMMS_TICKS_PER_BAR / 16;
+
+// Chord size of polyphony spikes, above MIDI_MAX_POLYPHONY
+const FIXTURE_SPIKE_POLYPHONY: usize = 32;
+
+/// Notes of a single fixture track as `(position, length, key, volume)`
+fn generate_track_notes(
+    rng: &mut SplitMix64,
+    note_count: usize,
+    percussion: bool,
+) -> Vec<(usize, usize, usize, usize)> {
+    let mut results = Vec::with_capacity(note_count);
+    let mut position = 0;
+
+    for _ in 0..note_count {
+        let (length, key) = if percussion {
+            (FIXTURE_NOTE_STEP, [36, 38, 42, 46][rng.range(0, 4)])
+        } else {
+            (FIXTURE_NOTE_STEP * rng.range(1, 5), rng.range(48, 84))
+        };
+
+        results.push((position, length, key, rng.range(60, 101)));
+        position += length;
+    }
+
+    results
+}
+
+/// Synthesizes an uncompressed LMMS project (.mmp) with the requested characteristics
+pub fn generate_fixture(options: &FixtureOptions) -> String {
+    let mut rng = SplitMix64::new(options.seed);
+
+    let mut track_notes = (0..options.tracks)
+        .map(|track_index| {
+            let note_count = options.notes / options.tracks.max(1)
+                + usize::from(track_index < options.notes % options.tracks.max(1));
+            let percussion = options.percussion && track_index + 1 == options.tracks;
+            generate_track_notes(&mut rng, note_count, percussion)
+        })
+        .collect::<Vec<_>>();
+
+    let song_length = track_notes
+        .iter()
+        .flatten()
+        .map(|&(position, length, _, _)| position + length)
+        .max()
+        .unwrap_or(0)
+        .next_multiple_of(LMMS_TICKS_PER_BAR);
+
+    if let Some(first_track_notes) = track_notes.first_mut() {
+        for spike_index in 0..options.polyphony_spikes {
+            let position = (song_length / (options.polyphony_spikes + 1)) * (spike_index + 1);
+            let position = position - position % FIXTURE_NOTE_STEP;
+
+            for key in 0..FIXTURE_SPIKE_POLYPHONY {
+                first_track_notes.push((position, FIXTURE_NOTE_STEP * 4, 36 + key, 80));
+            }
+        }
+
+        for overlap_index in 0..options.overlaps {
+            let position = (song_length / (options.overlaps + 1)) * (overlap_index + 1);
+            let position = position - position % FIXTURE_NOTE_STEP;
+            let key = rng.range(48, 84);
+
+            first_track_notes.push((position, FIXTURE_NOTE_STEP * 4, key, 80));
+            first_track_notes.push((position + FIXTURE_NOTE_STEP, FIXTURE_NOTE_STEP * 4, key, 80));
+        }
+
+        first_track_notes.sort_by_key(|&(position, _, _, _)| position);
+    }
+
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\"?>\n");
+    xml.push_str("<!DOCTYPE lmms-project>\n");
+    writeln!(
+        xml,
+        "<lmms-project type=\"song\" version=\"1.0\" creator=\"lmms2mid\" creatorversion=\"{}\">",
+        env!("CARGO_PKG_VERSION"),
+    )
+    .unwrap();
+    writeln!(
+        xml,
+        "  <head timesig_denominator=\"4\" timesig_numerator=\"4\" bpm=\"{}\" masterpitch=\"0\" mastervol=\"100\"/>",
+        options.bpm,
+    )
+    .unwrap();
+    xml.push_str("  <song>\n");
+    xml.push_str(
+        "    <trackcontainer visible=\"1\" minimized=\"0\" maximized=\"0\" x=\"0\" y=\"0\" width=\"600\" height=\"300\" type=\"song\">\n",
+    );
+
+    for (track_index, notes) in track_notes.iter().enumerate() {
+        let percussion = options.percussion && track_index + 1 == options.tracks;
+        let (bank, patch) = if percussion {
+            (128, 0)
+        } else {
+            (0, track_index % 128)
+        };
+
+        writeln!(
+            xml,
+            "      <track name=\"Track {}\" muted=\"0\" type=\"0\" solo=\"0\">",
+            track_index + 1,
+        )
+        .unwrap();
+        xml.push_str(
+            "        <instrumenttrack vol=\"100\" pan=\"0\" pitchrange=\"1\" fxch=\"0\" usemasterpitch=\"1\" pitch=\"0\" basenote=\"57\">\n",
+        );
+        xml.push_str("          <instrument name=\"sf2player\">\n");
+        writeln!(
+            xml,
+            "            <sf2player src=\"fixture.sf2\" bank=\"{bank}\" patch=\"{patch}\" gain=\"1\" \
+             reverbOn=\"0\" reverbLevel=\"0\" reverbDamping=\"0\" reverbWidth=\"0\" reverbRoomSize=\"0\" \
+             chorusOn=\"0\" chorusLevel=\"0\" chorusNum=\"0\" chorusDepth=\"0\" chorusSpeed=\"0\"/>",
+        )
+        .unwrap();
+        xml.push_str("          </instrument>\n");
+        xml.push_str("        </instrumenttrack>\n");
+        writeln!(
+            xml,
+            "        <pattern name=\"\" muted=\"0\" pos=\"0\" steps=\"16\" type=\"1\">",
+        )
+        .unwrap();
+
+        for &(position, length, key, volume) in notes {
+            writeln!(
+                xml,
+                "          <note vol=\"{volume}\" pan=\"0\" pos=\"{position}\" len=\"{length}\" key=\"{key}\"/>",
+            )
+            .unwrap();
+        }
+
+        xml.push_str("        </pattern>\n");
+        xml.push_str("      </track>\n");
+    }
+
+    xml.push_str("    </trackcontainer>\n");
+    writeln!(
+        xml,
+        "    <timeline lpstate=\"{}\" lp0pos=\"{}\" lp1pos=\"{}\"/>",
+        usize::from(options.loop_points),
+        song_length / 2 - (song_length / 2) % LMMS_TICKS_PER_BAR,
+        song_length.max(LMMS_TICKS_PER_BAR),
+    )
+    .unwrap();
+    xml.push_str("  </song>\n");
+    xml.push_str("</lmms-project>\n");
+
+    xml
+}
@@ -0,0 +1,301 @@
+// Samples per channel in a frame, the block size of common encoders
+const FLAC_BLOCK_SIZE: usize = 4096;
+
+// Metadata block types
+const FLAC_METADATA_STREAMINFO: u8 = 0;
+const FLAC_METADATA_VORBIS_COMMENT: u8 = 4;
+
+// Largest Rice parameter of 4-bit parameter fields, 15 being the escape code
+const FLAC_MAX_RICE_PARAMETER: u32 = 14;
+
+/// Writes values of up to 32 bits, most significant bit first
+struct BitWriter {
+    bytes: Vec<u8>,
+    bit_count: usize,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter {
+            bytes: Vec::new(),
+            bit_count: 0,
+        }
+    }
+
+    fn write(&mut self, value: u32, bits: u32) {
+        for bit_index in (0..bits).rev() {
+            if self.bit_count.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+
+            let bit = (value >> bit_index) & 1;
+            *self.bytes.last_mut().unwrap() |= (bit as u8) << (7 - self.bit_count % 8);
+            self.bit_count += 1;
+        }
+    }
+
+    fn write_signed(&mut self, value: i32, bits: u32) {
+        self.write(value as u32 & (u32::MAX >> (32 - bits)), bits);
+    }
+
+    /// Rice code of a zigzag-encoded residual: the quotient in unary, followed
+    /// by the remainder
+    fn write_rice(&mut self, value: u32, parameter: u32) {
+        let quotient = value >> parameter;
+
+        for _ in 0..quotient {
+            self.write(0, 1);
+        }
+
+        self.write(1, 1);
+        self.write(value & ((1 << parameter) - 1), parameter);
+    }
+
+    /// Pads the last byte with zero bits
+    fn align(&mut self) {
+        self.bit_count = self.bytes.len() * 8;
+    }
+}
+
+fn crc8(bytes: &[u8]) -> u8 {
+    let mut crc = 0u8;
+
+    for byte in bytes {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+
+    crc
+}
+
+fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc = 0u16;
+
+    for byte in bytes {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+    }
+
+    crc
+}
+
+fn zigzag(residual: i32) -> u32 {
+    ((residual << 1) ^ (residual >> 31)) as u32
+}
+
+/// Residuals of the fixed linear predictor of the given order, for the samples
+/// after the warm-up samples
+fn fixed_residuals(samples: &[i32], order: usize) -> Vec<i32> {
+    (order..samples.len())
+        .map(|index| {
+            let s = |offset: usize| samples[index - offset];
+
+            match order {
+                0 => s(0),
+                1 => s(0) - s(1),
+                2 => s(0) - 2 * s(1) + s(2),
+                3 => s(0) - 3 * s(1) + 3 * s(2) - s(3),
+                _ => s(0) - 4 * s(1) + 6 * s(2) - 4 * s(3) + s(4),
+            }
+        })
+        .collect()
+}
+
+/// Best Rice parameter of the residuals and the bits they take with it
+fn rice_parameter(residuals: &[i32]) -> (u32, usize) {
+    let values = residuals.iter().map(|residual| zigzag(*residual));
+
+    (0..=FLAC_MAX_RICE_PARAMETER)
+        .map(|parameter| {
+            let bits = values
+                .clone()
+                .map(|value| (value >> parameter) as usize + 1 + parameter as usize)
+                .sum();
+            (parameter, bits)
+        })
+        .min_by_key(|(_, bits)| *bits)
+        .unwrap()
+}
+
+/// Subframe of a channel's block: constant for silence, otherwise the fixed
+/// predictor coding the residuals in the fewest bits, or verbatim samples if
+/// no prediction helps
+fn write_subframe(writer: &mut BitWriter, samples: &[i32], bits_per_sample: u32) {
+    if samples.iter().all(|sample| *sample == samples[0]) {
+        writer.write(0b0000_0000, 8);
+        writer.write_signed(samples[0], bits_per_sample);
+        return;
+    }
+
+    let verbatim_bits = samples.len() * bits_per_sample as usize;
+
+    let best_prediction = (0..=4.min(samples.len() - 1))
+        .map(|order| {
+            let residuals = fixed_residuals(samples, order);
+            let (parameter, residual_bits) = rice_parameter(&residuals);
+            let bits = order * bits_per_sample as usize + 10 + residual_bits;
+
+            (order, residuals, parameter, bits)
+        })
+        .min_by_key(|(.., bits)| *bits)
+        .filter(|(.., bits)| *bits < verbatim_bits);
+
+    match best_prediction {
+        Some((order, residuals, parameter, _)) => {
+            writer.write(0b0001_0000 | (order as u32) << 1, 8);
+
+            for sample in &samples[..order] {
+                writer.write_signed(*sample, bits_per_sample);
+            }
+
+            // Rice coding with 4-bit parameters, a single partition
+            writer.write(0b00, 2);
+            writer.write(0, 4);
+            writer.write(parameter, 4);
+
+            for residual in residuals {
+                writer.write_rice(zigzag(residual), parameter);
+            }
+        }
+        None => {
+            writer.write(0b0000_0010, 8);
+
+            for sample in samples {
+                writer.write_signed(*sample, bits_per_sample);
+            }
+        }
+    }
+}
+
+/// Frame number in the UTF-8 like coding of FLAC frame headers
+fn write_frame_number(writer: &mut BitWriter, frame_number: u32) {
+    if frame_number < 0x80 {
+        writer.write(frame_number, 8);
+        return;
+    }
+
+    let continuation_bytes = match frame_number {
+        0x80..0x800 => 1,
+        0x800..0x1_0000 => 2,
+        0x1_0000..0x20_0000 => 3,
+        0x20_0000..0x400_0000 => 4,
+        _ => 5,
+    };
+
+    let leading_ones = (0xFF00u32 >> (continuation_bytes + 1)) & 0xFF;
+    writer.write(leading_ones | frame_number >> (6 * continuation_bytes), 8);
+
+    for byte_index in (0..continuation_bytes).rev() {
+        writer.write(0x80 | ((frame_number >> (6 * byte_index)) & 0x3F), 8);
+    }
+}
+
+fn metadata_block(block_type: u8, is_last: bool, data: &[u8]) -> Vec<u8> {
+    let mut block = vec![(is_last as u8) << 7 | block_type];
+    block.extend(&(data.len() as u32).to_be_bytes()[1..]);
+    block.extend(data);
+    block
+}
+
+/// FLAC file of 16-bit stereo frames with the given Vorbis comments
+/// ("LOOPSTART=...")
+pub fn flac(frames: &[(i16, i16)], sample_rate: u32, comments: &[String]) -> Vec<u8> {
+    let mut streaminfo = BitWriter::new();
+    streaminfo.write(FLAC_BLOCK_SIZE as u32, 16);
+    streaminfo.write(FLAC_BLOCK_SIZE as u32, 16);
+    // Frame sizes unknown
+    streaminfo.write(0, 24);
+    streaminfo.write(0, 24);
+    streaminfo.write(sample_rate, 20);
+    streaminfo.write(2 - 1, 3);
+    streaminfo.write(16 - 1, 5);
+    streaminfo.write((frames.len() as u64 >> 32) as u32, 4);
+    streaminfo.write(frames.len() as u32, 32);
+    // No MD5 signature
+    streaminfo.bytes.extend([0; 16]);
+
+    let vendor = concat!("lmms2mid ", env!("CARGO_PKG_VERSION"));
+    let mut vorbis_comment = Vec::new();
+    vorbis_comment.extend((vendor.len() as u32).to_le_bytes());
+    vorbis_comment.extend(vendor.as_bytes());
+    vorbis_comment.extend((comments.len() as u32).to_le_bytes());
+    for comment in comments {
+        vorbis_comment.extend((comment.len() as u32).to_le_bytes());
+        vorbis_comment.extend(comment.as_bytes());
+    }
+
+    let mut flac_bytes = b"fLaC".to_vec();
+    flac_bytes.extend(metadata_block(
+        FLAC_METADATA_STREAMINFO,
+        false,
+        &streaminfo.bytes,
+    ));
+    flac_bytes.extend(metadata_block(
+        FLAC_METADATA_VORBIS_COMMENT,
+        true,
+        &vorbis_comment,
+    ));
+
+    for (frame_number, block) in frames.chunks(FLAC_BLOCK_SIZE).enumerate() {
+        let mut writer = BitWriter::new();
+
+        // Sync code, fixed block size
+        writer.write(0b1111_1111_1111_1000, 16);
+
+        // Block size: 4096 or given at the end of the header, 44.1 kHz or
+        // given by the STREAMINFO block
+        writer.write(
+            if block.len() == FLAC_BLOCK_SIZE {
+                0b1100
+            } else {
+                0b0111
+            },
+            4,
+        );
+        writer.write(if sample_rate == 44100 { 0b1001 } else { 0b0000 }, 4);
+
+        // Independent left and right channels, 16 bits per sample
+        writer.write(0b0001, 4);
+        writer.write(0b100, 3);
+        writer.write(0, 1);
+
+        write_frame_number(&mut writer, frame_number as u32);
+
+        if block.len() != FLAC_BLOCK_SIZE {
+            writer.write(block.len() as u32 - 1, 16);
+        }
+
+        writer.write(crc8(&writer.bytes) as u32, 8);
+
+        let left = block
+            .iter()
+            .map(|(left, _)| *left as i32)
+            .collect::<Vec<_>>();
+        let right = block
+            .iter()
+            .map(|(_, right)| *right as i32)
+            .collect::<Vec<_>>();
+
+        write_subframe(&mut writer, &left, 16);
+        write_subframe(&mut writer, &right, 16);
+
+        writer.align();
+        writer.write(crc16(&writer.bytes) as u32, 16);
+
+        flac_bytes.extend(writer.bytes);
+    }
+
+    flac_bytes
+}
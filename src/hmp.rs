@@ -0,0 +1,261 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use midly::{MetaMessage, MidiMessage, TrackEventKind};
+
+use crate::midi::{write_vlq, AbsoluteTrackEvent};
+use crate::warning;
+
+// HMP sequences are commonly timed at 120 ticks per second
+pub const HMP_TICKS_PER_SECOND: f32 = 120.0;
+
+const HMP_FILE_ID: &[u8] = b"HMIMIDIP";
+
+// The track chunks follow the header, the channel priorities and the device
+// mapping tables
+const HMP_HEADER_LENGTH: usize = 0x308;
+
+// Length of the track number, chunk length and channel fields of a track chunk
+const HMP_TRACK_HEADER_LENGTH: usize = 12;
+
+// Sound Operating System controllers: branch locations engines jump to by
+// number, and the global loop of all tracks (start value is the loop count,
+// 0 looping forever)
+const HMI_CC_BRANCH_LOCATION: u8 = 103;
+const HMI_CC_GLOBAL_LOOP_START: u8 = 110;
+const HMI_CC_GLOBAL_LOOP_END: u8 = 111;
+
+const HMI_MAX_BRANCH_LOCATIONS: usize = 127;
+
+/// Variable-length quantity of HMP delays: 7-bit groups from the lowest, the
+/// last group marked by the high bit
+fn write_hmp_vlq(bytes: &mut Vec<u8>, mut value: usize) {
+    while value > 0x7F {
+        bytes.push((value & 0x7F) as u8);
+        value >>= 7;
+    }
+
+    bytes.push(value as u8 | 0x80);
+}
+
+/// Track chunk data of events in time order
+fn hmp_track_data(track_events: &[(usize, Vec<u8>)]) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut ticks_before = 0;
+
+    for (ticks, bytes) in track_events {
+        write_hmp_vlq(&mut data, ticks - ticks_before);
+        data.extend(bytes);
+        ticks_before = *ticks;
+    }
+
+    // End of Track
+    write_hmp_vlq(&mut data, 0);
+    data.extend([0xFF, 0x2F, 0x00]);
+    data
+}
+
+/// HMP file of the events, sorted and timed in HMP ticks. The first track holds
+/// the SysEx messages, followed by a track per MIDI channel. Each channel track
+/// carries the HMI global loop controllers at the loop points and a branch
+/// location at every cue point, numbered from 1.
+pub fn hmp(midi_track_events: &[AbsoluteTrackEvent], loop_ticks: Option<Range<usize>>) -> Vec<u8> {
+    let mut sysex_events = Vec::new();
+    let mut channel_events: BTreeMap<u8, Vec<(usize, Vec<u8>)>> = BTreeMap::new();
+    let mut branch_locations = Vec::new();
+
+    for event in midi_track_events {
+        match event.kind {
+            TrackEventKind::Midi { channel, message } => {
+                let channel_bits = channel.as_int();
+
+                let bytes = match message {
+                    MidiMessage::NoteOff { key, vel } => {
+                        vec![0x80 | channel_bits, key.as_int(), vel.as_int()]
+                    }
+                    MidiMessage::NoteOn { key, vel } => {
+                        vec![0x90 | channel_bits, key.as_int(), vel.as_int()]
+                    }
+                    MidiMessage::Aftertouch { key, vel } => {
+                        vec![0xA0 | channel_bits, key.as_int(), vel.as_int()]
+                    }
+                    MidiMessage::Controller { controller, value } => {
+                        vec![0xB0 | channel_bits, controller.as_int(), value.as_int()]
+                    }
+                    MidiMessage::ProgramChange { program } => {
+                        vec![0xC0 | channel_bits, program.as_int()]
+                    }
+                    MidiMessage::ChannelAftertouch { vel } => {
+                        vec![0xD0 | channel_bits, vel.as_int()]
+                    }
+                    MidiMessage::PitchBend { bend } => {
+                        let bend = bend.0.as_int();
+                        vec![0xE0 | channel_bits, (bend & 0x7F) as u8, (bend >> 7) as u8]
+                    }
+                };
+
+                channel_events
+                    .entry(channel_bits)
+                    .or_default()
+                    .push((event.ticks, bytes));
+            }
+            TrackEventKind::SysEx(data) => {
+                let mut bytes = vec![0xF0];
+                write_vlq(&mut bytes, data.len());
+                bytes.extend(data);
+
+                sysex_events.push((event.ticks, bytes));
+            }
+            TrackEventKind::Meta(MetaMessage::CuePoint(_)) => {
+                branch_locations.push(event.ticks);
+            }
+            // The tempo is already applied to the fixed-rate delays, and HMP
+            // stores no texts or other meta events
+            TrackEventKind::Escape(_) | TrackEventKind::Meta(_) => {}
+        }
+    }
+
+    // Cue points of patterns starting together share a branch location
+    branch_locations.dedup();
+
+    if branch_locations.len() > HMI_MAX_BRANCH_LOCATIONS {
+        warning!(
+            code: "cue-points-dropped";
+            "{} cue points were dropped, HMP has at most {} branch locations",
+            branch_locations.len() - HMI_MAX_BRANCH_LOCATIONS,
+            HMI_MAX_BRANCH_LOCATIONS,
+        );
+        branch_locations.truncate(HMI_MAX_BRANCH_LOCATIONS);
+    }
+
+    let ticks_end = midi_track_events.last().map_or(0, |event| event.ticks);
+
+    let mut tracks = vec![(0, hmp_track_data(&sysex_events))];
+
+    for (channel, mut track_events) in channel_events {
+        let mut hmi_events = Vec::new();
+
+        for (branch_index, ticks) in branch_locations.iter().enumerate() {
+            hmi_events.push((
+                *ticks,
+                vec![
+                    0xB0 | channel,
+                    HMI_CC_BRANCH_LOCATION,
+                    branch_index as u8 + 1,
+                ],
+            ));
+        }
+
+        if let Some(ref loop_ticks) = loop_ticks {
+            hmi_events.push((
+                loop_ticks.start,
+                vec![0xB0 | channel, HMI_CC_GLOBAL_LOOP_START, 0],
+            ));
+            hmi_events.push((
+                loop_ticks.end,
+                vec![0xB0 | channel, HMI_CC_GLOBAL_LOOP_END, 127],
+            ));
+        }
+
+        // At the same time the loop end follows the notes being released and
+        // precedes the rest, so no note is left sounding by the jump back. The
+        // other controllers precede the events of the track.
+        track_events.splice(0..0, hmi_events);
+        track_events.sort_by_key(|(ticks, bytes)| {
+            let order = match bytes[..] {
+                [status, _, 0] if status & 0xF0 == 0x90 => 0,
+                [status, ..] if status & 0xF0 == 0x80 => 0,
+                [status, HMI_CC_GLOBAL_LOOP_END, _] if status & 0xF0 == 0xB0 => 1,
+                _ => 2,
+            };
+
+            (*ticks, order)
+        });
+
+        tracks.push((channel as u32, hmp_track_data(&track_events)));
+    }
+
+    let mut hmp_bytes = HMP_FILE_ID.to_vec();
+    hmp_bytes.resize(HMP_HEADER_LENGTH, 0);
+
+    let file_length = HMP_HEADER_LENGTH
+        + tracks
+            .iter()
+            .map(|(_, data)| HMP_TRACK_HEADER_LENGTH + data.len())
+            .sum::<usize>();
+
+    hmp_bytes[0x20..0x24].copy_from_slice(&(file_length as u32).to_le_bytes());
+    hmp_bytes[0x30..0x34].copy_from_slice(&(tracks.len() as u32).to_le_bytes());
+    hmp_bytes[0x38..0x3C].copy_from_slice(&(HMP_TICKS_PER_SECOND as u32).to_le_bytes());
+
+    // Song length in seconds
+    let song_seconds = (ticks_end as f32 / HMP_TICKS_PER_SECOND).ceil() as u32;
+    hmp_bytes[0x3C..0x40].copy_from_slice(&song_seconds.to_le_bytes());
+
+    for (track_index, (channel, data)) in tracks.iter().enumerate() {
+        hmp_bytes.extend((track_index as u32).to_le_bytes());
+        hmp_bytes.extend(((HMP_TRACK_HEADER_LENGTH + data.len()) as u32).to_le_bytes());
+        hmp_bytes.extend(channel.to_le_bytes());
+        hmp_bytes.extend(data);
+    }
+
+    hmp_bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use midly::num::{u4, u7};
+
+    use super::*;
+
+    fn event(ticks: usize, message: MidiMessage) -> AbsoluteTrackEvent<'static> {
+        AbsoluteTrackEvent {
+            ticks,
+            ticks_event_start: ticks,
+            kind: TrackEventKind::Midi {
+                channel: u4::from(2),
+                message,
+            },
+            precise_value: None,
+        }
+    }
+
+    #[test]
+    fn header_counts_the_tracks_and_their_lengths() {
+        let (key, vel) = (u7::from(60), u7::from(100));
+        let hmp_bytes = hmp(
+            &[
+                event(0, MidiMessage::NoteOn { key, vel }),
+                event(200, MidiMessage::NoteOff { key, vel }),
+            ],
+            Some(0..200),
+        );
+
+        assert_eq!(&hmp_bytes[..8], HMP_FILE_ID);
+        assert_eq!(hmp_bytes[0x20..0x24], 825u32.to_le_bytes());
+        assert_eq!(hmp_bytes[0x30..0x34], 2u32.to_le_bytes());
+        assert_eq!(hmp_bytes[0x38..0x3C], 120u32.to_le_bytes());
+        assert_eq!(hmp_bytes[0x3C..0x40], 2u32.to_le_bytes());
+
+        assert_eq!(
+            hmp_bytes[HMP_HEADER_LENGTH..],
+            [
+                // SysEx track
+                &0u32.to_le_bytes() as &[u8],
+                &16u32.to_le_bytes(),
+                &0u32.to_le_bytes(),
+                &[0x80, 0xFF, 0x2F, 0x00],
+                // Channel 3 track, the loop end after the note is released
+                &1u32.to_le_bytes(),
+                &33u32.to_le_bytes(),
+                &2u32.to_le_bytes(),
+                &[0x80, 0xB2, HMI_CC_GLOBAL_LOOP_START, 0],
+                &[0x80, 0x92, 60, 100],
+                &[0x48, 0x81, 0x82, 60, 100],
+                &[0x80, 0xB2, HMI_CC_GLOBAL_LOOP_END, 127],
+                &[0x80, 0xFF, 0x2F, 0x00],
+            ]
+            .concat()
+        );
+    }
+}
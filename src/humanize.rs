@@ -0,0 +1,107 @@
+use midly::num::u7;
+
+use crate::midi::{precise_value, MidiNote};
+use crate::rng::SplitMix64;
+
+/// Largest random offsets applied to the notes
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Humanize {
+    /// Note start offset in LMMS ticks
+    pub timing: usize,
+
+    /// Velocity offset
+    pub velocity: u8,
+}
+
+/// Command line value parser for humanization amounts ("timing=±4,velocity=±10"),
+/// either part can be left out
+pub fn parse_humanize(s: &str) -> Result<Humanize, String> {
+    let mut humanize = Humanize::default();
+
+    for part in s.split(',') {
+        let (name, amount) = part
+            .split_once('=')
+            .ok_or_else(|| format!("expected '<timing|velocity>=±<amount>', found '{part}'"))?;
+
+        let amount = amount.trim();
+        let amount = amount
+            .strip_prefix('±')
+            .or_else(|| amount.strip_prefix("+-"))
+            .unwrap_or(amount);
+
+        match name.trim() {
+            "timing" => {
+                humanize.timing = amount.parse::<usize>().map_err(|_| {
+                    format!("invalid timing offset '{amount}', expected LMMS ticks")
+                })?;
+            }
+            "velocity" => {
+                humanize.velocity = amount
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|velocity| *velocity < 128)
+                    .ok_or_else(|| format!("invalid velocity offset '{amount}', expected 0-127"))?;
+            }
+            name => {
+                return Err(format!(
+                    "unknown humanization '{name}', expected timing or velocity"
+                ))
+            }
+        }
+    }
+
+    Ok(humanize)
+}
+
+/// Moves the notes and changes their velocities by random amounts within the
+/// limits, note lengths stay the same. Velocities stay above zero so no Note On
+/// turns into a Note Off.
+pub fn humanize_notes(midi_notes: &mut [MidiNote], humanize: Humanize, rng: &mut SplitMix64) {
+    for midi_note in midi_notes {
+        let timing_offset = rng.offset(humanize.timing);
+        let velocity_offset = rng.offset(humanize.velocity as usize);
+
+        let ticks_start = midi_note.ticks_start.saturating_add_signed(timing_offset);
+        midi_note.ticks_end = ticks_start + (midi_note.ticks_end - midi_note.ticks_start);
+        midi_note.ticks_start = ticks_start;
+
+        let velocity = midi_note.velocity.as_int() as isize + velocity_offset;
+        midi_note.velocity = u7::from(velocity.clamp(1, 127) as u8);
+
+        let precise_velocity =
+            midi_note.precise_velocity as i64 + velocity_offset as i64 * precise_value(1.0) as i64;
+        midi_note.precise_velocity =
+            precise_velocity.clamp(precise_value(1.0) as i64, u32::MAX as i64) as u32;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_both_offsets() {
+        assert_eq!(
+            parse_humanize("timing=±4,velocity=+-10"),
+            Ok(Humanize {
+                timing: 4,
+                velocity: 10
+            })
+        );
+        assert_eq!(
+            parse_humanize("velocity=3"),
+            Ok(Humanize {
+                timing: 0,
+                velocity: 3
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_offsets() {
+        assert!(parse_humanize("timing").is_err());
+        assert!(parse_humanize("timing=-4").is_err());
+        assert!(parse_humanize("velocity=±128").is_err());
+        assert!(parse_humanize("pitch=±1").is_err());
+    }
+}
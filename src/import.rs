@@ -0,0 +1,429 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::Write;
+
+use midly::{MetaMessage, MidiMessage, Smf, Timing, TrackEventKind};
+
+use crate::lmms_model::LMMS_TICKS_PER_BAR;
+use crate::midi::*;
+use crate::roundtrip::{parse_roundtrip_metadata, RoundtripMetadata};
+use crate::{note, warning};
+
+// LMMS keys are 69 - base note below the MIDI keys, tracks keep the default
+// base note unless they have notes below C0
+const LMMS_DEFAULT_BASE_NOTE: usize = 57;
+const LMMS_MIDI_BASE_NOTE: usize = 69;
+
+// Tempo of files without a Set Tempo meta event
+const IMPORT_DEFAULT_BPM: f64 = 120.0;
+
+/// A note in LMMS ticks
+#[derive(Debug, Copy, Clone)]
+struct ImportedNote {
+    position: usize,
+    length: usize,
+    key: usize,
+    volume: usize,
+}
+
+/// The notes of a MIDI channel of an SMF track, an SF2 Player track of the project
+#[derive(Debug, Clone)]
+struct ImportedTrack {
+    name: String,
+    bank: usize,
+    patch: usize,
+    base_note: usize,
+
+    /// Notes with their LMMS keys
+    notes: Vec<ImportedNote>,
+}
+
+/// Bank and program a channel is first set up with, and how often its
+/// program changes
+#[derive(Debug, Default, Copy, Clone)]
+struct ChannelPreset {
+    bank: Option<usize>,
+    program: Option<usize>,
+    program_changes: usize,
+}
+
+/// Text of an attribute value
+fn xml_attribute(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Groups the notes of a track into patterns, a pattern ending at a bar
+/// without notes. Returns the bar-aligned pattern positions with the notes
+/// relative to them.
+fn split_patterns(notes: &[ImportedNote], ticks_per_bar: usize) -> Vec<(usize, Vec<ImportedNote>)> {
+    let mut patterns: Vec<(usize, Vec<ImportedNote>)> = Vec::new();
+    let mut pattern_end_bar = 0;
+
+    for note in notes {
+        let start_bar = note.position / ticks_per_bar;
+        let end_bar = (note.position + note.length).div_ceil(ticks_per_bar);
+
+        if patterns.is_empty() || start_bar > pattern_end_bar {
+            patterns.push((start_bar * ticks_per_bar, Vec::new()));
+        }
+
+        let (pattern_position, pattern_notes) = patterns.last_mut().unwrap();
+        pattern_notes.push(ImportedNote {
+            position: note.position - *pattern_position,
+            ..*note
+        });
+
+        pattern_end_bar = pattern_end_bar.max(end_bar);
+    }
+
+    patterns
+}
+
+/// Converts a Standard MIDI File into an uncompressed LMMS project (.mmp)
+/// with an SF2 Player track per MIDI channel of every MIDI track. Tempo,
+/// time signature and loop points (round-trip metadata, Final Fantasy
+/// markers, EMIDI or RPG Maker controllers) are carried over; tempo and
+/// program changes within the song are not.
+pub fn import_midi(midi_document: &Smf, soundfont_path: &str) -> String {
+    let absolute_events = || {
+        midi_document
+            .tracks
+            .iter()
+            .enumerate()
+            .flat_map(|(track_index, midi_track)| {
+                midi_track.iter().scan(0, move |ticks, event| {
+                    *ticks += event.delta.as_int() as usize;
+                    Some((track_index, *ticks, event.kind))
+                })
+            })
+    };
+
+    // Song setup, the earliest event winning
+
+    let mut tempos = absolute_events()
+        .filter_map(|(_, ticks, kind)| match kind {
+            TrackEventKind::Meta(MetaMessage::Tempo(tempo)) => Some((ticks, tempo.as_int())),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    tempos.sort_by_key(|(ticks, _)| *ticks);
+
+    let bpm = tempos.first().map_or(IMPORT_DEFAULT_BPM, |(_, tempo)| {
+        60_000_000.0 / *tempo as f64
+    });
+
+    if tempos.iter().any(|(_, tempo)| *tempo != tempos[0].1) {
+        warning!(code: "tempo-changes"; "tempo changes are not imported, the song plays at {bpm:.0} BPM");
+    }
+
+    let time_signature = absolute_events()
+        .filter_map(|(_, ticks, kind)| match kind {
+            TrackEventKind::Meta(MetaMessage::TimeSignature(numerator, denominator, _, _)) => {
+                Some((
+                    ticks,
+                    numerator.max(1) as usize,
+                    1usize << denominator.min(5),
+                ))
+            }
+            _ => None,
+        })
+        .min_by_key(|(ticks, _, _)| *ticks)
+        .map_or((4, 4), |(_, numerator, denominator)| {
+            (numerator, denominator)
+        });
+
+    let ticks_per_bar = (LMMS_TICKS_PER_BAR * time_signature.0 / time_signature.1).max(1);
+
+    let lmms_ticks = |ticks: usize| match midi_document.header.timing {
+        Timing::Metrical(ticks_per_beat) => {
+            let ticks_per_beat = ticks_per_beat.as_int().max(1) as usize;
+            (ticks * (LMMS_TICKS_PER_BAR / 4) + ticks_per_beat / 2) / ticks_per_beat
+        }
+        Timing::Timecode(fps, subframes) => {
+            let seconds = ticks as f64 / (fps.as_f32() as f64 * subframes.max(1) as f64);
+            (seconds * bpm / 60.0 * (LMMS_TICKS_PER_BAR / 4) as f64).round() as usize
+        }
+    };
+
+    // Notes, presets and loop points
+
+    let mut roundtrip_metadata: Option<RoundtripMetadata> = None;
+    let mut marker_loop = (None, None);
+    let mut emidi_global_loop = (None, None);
+    let mut emidi_local_loop = (None, None);
+    let mut rpg_maker_loop_start = None;
+
+    let mut channel_presets = [ChannelPreset::default(); 16];
+    let mut track_names: HashMap<usize, String> = HashMap::new();
+    let mut track_notes: BTreeMap<(usize, u8), Vec<ImportedNote>> = BTreeMap::new();
+    let mut pending_notes: HashMap<(usize, u8, u8), VecDeque<(usize, u8)>> = HashMap::new();
+    let mut song_end = 0;
+
+    let end_note = |track_notes: &mut BTreeMap<(usize, u8), Vec<ImportedNote>>,
+                    (track_index, channel, key): (usize, u8, u8),
+                    (start, velocity): (usize, u8),
+                    end: usize| {
+        let position = lmms_ticks(start);
+
+        track_notes
+            .entry((track_index, channel))
+            .or_default()
+            .push(ImportedNote {
+                position,
+                length: lmms_ticks(end).saturating_sub(position).max(1),
+                key: key as usize,
+                // The inverse of the truncating volume to velocity mapping
+                volume: (velocity as usize * 200).div_ceil(127),
+            });
+    };
+
+    for (track_index, ticks, kind) in absolute_events() {
+        song_end = song_end.max(ticks);
+
+        match kind {
+            TrackEventKind::Meta(MetaMessage::TrackName(name)) => {
+                track_names
+                    .entry(track_index)
+                    .or_insert_with(|| String::from_utf8_lossy(name).trim().to_string());
+            }
+            TrackEventKind::Meta(MetaMessage::Marker(text)) => {
+                match String::from_utf8_lossy(text).trim().to_lowercase().as_str() {
+                    "loopstart" => marker_loop.0 = Some(ticks),
+                    "loopend" => marker_loop.1 = Some(ticks),
+                    _ => {}
+                }
+            }
+            TrackEventKind::Meta(MetaMessage::SequencerSpecific(data)) => {
+                if let Some(metadata) = parse_roundtrip_metadata(data) {
+                    roundtrip_metadata = Some(metadata);
+                }
+            }
+            TrackEventKind::Midi { channel, message } => {
+                let channel = channel.as_int();
+                let channel_preset = &mut channel_presets[channel as usize];
+
+                match message {
+                    MidiMessage::NoteOn { key, vel } if vel > 0 => {
+                        pending_notes
+                            .entry((track_index, channel, key.as_int()))
+                            .or_default()
+                            .push_back((ticks, vel.as_int()));
+                    }
+                    MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                        let note = (track_index, channel, key.as_int());
+
+                        if let Some(start) =
+                            pending_notes.get_mut(&note).and_then(VecDeque::pop_front)
+                        {
+                            end_note(&mut track_notes, note, start, ticks);
+                        }
+                    }
+                    MidiMessage::ProgramChange { program } => {
+                        channel_preset
+                            .program
+                            .get_or_insert(program.as_int() as usize);
+                        channel_preset.program_changes += 1;
+                    }
+                    MidiMessage::Controller { controller, value } => match controller.as_int() {
+                        // Bank selects after the first program change are not used
+                        MIDI_CC_BANK_SELECT_COARSE if channel_preset.program.is_none() => {
+                            channel_preset.bank = Some(value.as_int() as usize);
+                        }
+                        MIDI_CC_EMIDI_GLOBAL_LOOP_START => emidi_global_loop.0 = Some(ticks),
+                        MIDI_CC_EMIDI_GLOBAL_LOOP_END => emidi_global_loop.1 = Some(ticks),
+                        MIDI_CC_EMIDI_LOCAL_LOOP_START => emidi_local_loop.0 = Some(ticks),
+                        MIDI_CC_EMIDI_LOCAL_LOOP_END => emidi_local_loop.1 = Some(ticks),
+                        MIDI_CC_RPG_LOOP_START => rpg_maker_loop_start = Some(ticks),
+                        _ => {}
+                    },
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Notes still held end with the song
+    for (note, starts) in pending_notes {
+        for start in starts {
+            end_note(&mut track_notes, note, start, song_end);
+        }
+    }
+
+    for (channel, channel_preset) in channel_presets.iter().enumerate() {
+        if channel_preset.program_changes > 1 {
+            note!(
+                "channel {} changes its program during the song, its tracks keep program {}",
+                channel + 1,
+                channel_preset.program.unwrap_or(0),
+            );
+        }
+    }
+
+    let channel_count = |track_index: usize| {
+        track_notes
+            .keys()
+            .filter(|(note_track_index, _)| *note_track_index == track_index)
+            .count()
+    };
+
+    let imported_tracks = track_notes
+        .iter()
+        .map(|(&(track_index, channel), notes)| {
+            let channel_preset = channel_presets[channel as usize];
+            let roundtrip_track = roundtrip_metadata.as_ref().and_then(|metadata| {
+                metadata
+                    .tracks
+                    .iter()
+                    .find(|roundtrip_track| roundtrip_track.midi_channel == channel)
+            });
+
+            let name = match (roundtrip_track, track_names.get(&track_index)) {
+                (Some(roundtrip_track), _) => roundtrip_track.name.clone(),
+                (None, Some(track_name)) if !track_name.is_empty() => {
+                    if channel_count(track_index) > 1 {
+                        format!("{track_name} (channel {})", channel + 1)
+                    } else {
+                        track_name.clone()
+                    }
+                }
+                _ => format!("Channel {}", channel + 1),
+            };
+
+            let (bank, patch) = match roundtrip_track {
+                Some(roundtrip_track) => (roundtrip_track.bank, roundtrip_track.program),
+                None if channel == 9 => (128, channel_preset.program.unwrap_or(0)),
+                None => (
+                    channel_preset.bank.unwrap_or(0),
+                    channel_preset.program.unwrap_or(0),
+                ),
+            };
+
+            let lowest_key = notes.iter().map(|note| note.key).min().unwrap_or(0);
+            let base_note =
+                LMMS_MIDI_BASE_NOTE - lowest_key.min(LMMS_MIDI_BASE_NOTE - LMMS_DEFAULT_BASE_NOTE);
+
+            let mut notes = notes
+                .iter()
+                .map(|note| ImportedNote {
+                    key: note.key + base_note - LMMS_MIDI_BASE_NOTE,
+                    ..*note
+                })
+                .collect::<Vec<_>>();
+            notes.sort_by_key(|note| (note.position, note.key));
+
+            ImportedTrack {
+                name,
+                bank,
+                patch,
+                base_note,
+                notes,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let loop_points = if let Some(ref metadata) = roundtrip_metadata {
+        Some((
+            metadata.loop_enabled,
+            metadata.loop_start,
+            metadata.loop_end,
+        ))
+    } else {
+        let song_end_bar = lmms_ticks(song_end).next_multiple_of(ticks_per_bar);
+
+        [marker_loop, emidi_global_loop, emidi_local_loop]
+            .into_iter()
+            .find_map(|(start, end)| start.zip(end))
+            .map(|(start, end)| (true, lmms_ticks(start), lmms_ticks(end)))
+            .or_else(|| rpg_maker_loop_start.map(|start| (true, lmms_ticks(start), song_end_bar)))
+    };
+
+    // Project XML
+
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\"?>\n");
+    xml.push_str("<!DOCTYPE lmms-project>\n");
+    writeln!(
+        xml,
+        "<lmms-project type=\"song\" version=\"1.0\" creator=\"lmms2mid\" creatorversion=\"{}\">",
+        env!("CARGO_PKG_VERSION"),
+    )
+    .unwrap();
+    writeln!(
+        xml,
+        "  <head timesig_denominator=\"{}\" timesig_numerator=\"{}\" bpm=\"{}\" masterpitch=\"0\" mastervol=\"100\"/>",
+        time_signature.1,
+        time_signature.0,
+        bpm.round() as usize,
+    )
+    .unwrap();
+    xml.push_str("  <song>\n");
+    xml.push_str(
+        "    <trackcontainer visible=\"1\" minimized=\"0\" maximized=\"0\" x=\"0\" y=\"0\" width=\"600\" height=\"300\" type=\"song\">\n",
+    );
+
+    for imported_track in &imported_tracks {
+        writeln!(
+            xml,
+            "      <track name=\"{}\" muted=\"0\" type=\"0\" solo=\"0\">",
+            xml_attribute(&imported_track.name),
+        )
+        .unwrap();
+        writeln!(
+            xml,
+            "        <instrumenttrack vol=\"100\" pan=\"0\" pitchrange=\"1\" fxch=\"0\" usemasterpitch=\"1\" pitch=\"0\" basenote=\"{}\">",
+            imported_track.base_note,
+        )
+        .unwrap();
+        xml.push_str("          <instrument name=\"sf2player\">\n");
+        writeln!(
+            xml,
+            "            <sf2player src=\"{}\" bank=\"{}\" patch=\"{}\" gain=\"1\" \
+             reverbOn=\"0\" reverbLevel=\"0\" reverbDamping=\"0\" reverbWidth=\"0\" reverbRoomSize=\"0\" \
+             chorusOn=\"0\" chorusLevel=\"0\" chorusNum=\"0\" chorusDepth=\"0\" chorusSpeed=\"0\"/>",
+            xml_attribute(soundfont_path),
+            imported_track.bank,
+            imported_track.patch,
+        )
+        .unwrap();
+        xml.push_str("          </instrument>\n");
+        xml.push_str("        </instrumenttrack>\n");
+
+        for (pattern_position, notes) in split_patterns(&imported_track.notes, ticks_per_bar) {
+            writeln!(
+                xml,
+                "        <pattern name=\"\" muted=\"0\" pos=\"{pattern_position}\" steps=\"16\" type=\"1\">",
+            )
+            .unwrap();
+
+            for note in notes {
+                writeln!(
+                    xml,
+                    "          <note vol=\"{}\" pan=\"0\" pos=\"{}\" len=\"{}\" key=\"{}\"/>",
+                    note.volume, note.position, note.length, note.key,
+                )
+                .unwrap();
+            }
+
+            xml.push_str("        </pattern>\n");
+        }
+
+        xml.push_str("      </track>\n");
+    }
+
+    xml.push_str("    </trackcontainer>\n");
+
+    let (loop_enabled, loop_start, loop_end) = loop_points.unwrap_or((false, 0, ticks_per_bar));
+    writeln!(
+        xml,
+        "    <timeline lpstate=\"{}\" lp0pos=\"{loop_start}\" lp1pos=\"{loop_end}\"/>",
+        usize::from(loop_enabled),
+    )
+    .unwrap();
+    xml.push_str("  </song>\n");
+    xml.push_str("</lmms-project>\n");
+
+    xml
+}
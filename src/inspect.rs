@@ -0,0 +1,332 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+
+use midly::{MidiMessage, TrackEventKind};
+
+use crate::bundle::resolve_soundfont_path;
+use crate::convert::{skipped_tracks, Conversion, ConvertOptions};
+use crate::lmms_model::*;
+
+// Rows of the polyphony profile, longer songs are summarized over several bars per row
+const INSPECT_PROFILE_ROWS: usize = 32;
+
+/// Name of a track type
+fn track_type_name(lmms_track: &LmmsTrack) -> String {
+    match lmms_track.r#type {
+        LMMS_TRACK_TYPE_INSTRUMENT => match lmms_track.instrument() {
+            Some(lmms_instrument) => format!("instrument ({})", lmms_instrument.name),
+            None => "instrument".to_owned(),
+        },
+        LMMS_TRACK_TYPE_PATTERN => "beat/bassline".to_owned(),
+        LMMS_TRACK_TYPE_SAMPLE => "sample".to_owned(),
+        LMMS_TRACK_TYPE_AUTOMATION | LMMS_TRACK_TYPE_HIDDEN_AUTOMATION => "automation".to_owned(),
+        track_type => format!("type {track_type}"),
+    }
+}
+
+/// Song time of LMMS ticks as minutes and seconds
+fn song_time(ticks: usize, bpm: f32) -> String {
+    let seconds = ticks as f32 / (LMMS_TICKS_PER_BAR / 4) as f32 * 60.0 / bpm.max(1.0);
+    format!("{}:{:04.1}", (seconds / 60.0) as usize, seconds % 60.0)
+}
+
+/// Highest number of notes sounding at once in each span of bars, notes
+/// given as `(start, end)` ticks
+fn polyphony_profile(
+    notes: &[(usize, usize)],
+    ticks_per_span: usize,
+    span_count: usize,
+) -> Vec<usize> {
+    // Note ends sort before the span probes, span probes before note starts
+    let mut changes = notes
+        .iter()
+        .flat_map(|(start, end)| [(*start, 1), (*end, -1)])
+        .chain((0..span_count).map(|span_index| (span_index * ticks_per_span, 0)))
+        .collect::<Vec<(usize, isize)>>();
+    changes.sort();
+
+    let mut peaks = vec![0; span_count];
+    let mut sounding = 0;
+
+    for (ticks, change) in changes {
+        sounding += change;
+
+        if change >= 0 {
+            let span_index = (ticks / ticks_per_span).min(span_count - 1);
+            peaks[span_index] = peaks[span_index].max(sounding as usize);
+        }
+    }
+
+    peaks
+}
+
+/// Overview of a project as it converts: its tracks by type, note counts,
+/// length and tempo, how many notes sound at once over the song, which MIDI
+/// channels are used by which tracks, and the soundfonts it references
+pub fn inspect(
+    project_path: &Path,
+    lmms_project: &LmmsProject,
+    conversion: &Conversion,
+    options: &ConvertOptions,
+) -> String {
+    let mut report = String::new();
+
+    let bpm = options.bpm(lmms_project);
+    let ticks_per_bar = lmms_project.lmms_ticks_per_bar();
+
+    let track_notes = conversion
+        .track_events()
+        .iter()
+        .map(|track_events| {
+            track_events
+                .iter()
+                .filter_map(|event| match event.kind {
+                    TrackEventKind::Midi {
+                        channel,
+                        message: MidiMessage::NoteOff { .. },
+                    } => Some((channel.as_int(), event.ticks_event_start, event.ticks)),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let song_end = track_notes
+        .iter()
+        .flatten()
+        .map(|(_, _, end)| *end)
+        .max()
+        .unwrap_or(0);
+    let bar_count = song_end.div_ceil(ticks_per_bar);
+
+    writeln!(report, "project: {}", project_path.display()).unwrap();
+
+    if !lmms_project.creator_version.is_empty() {
+        writeln!(
+            report,
+            "  created with {} {}",
+            lmms_project.creator, lmms_project.creator_version
+        )
+        .unwrap();
+    }
+
+    writeln!(
+        report,
+        "  tempo {bpm} BPM, {}/{}",
+        lmms_project.head.time_signature_numerator, lmms_project.head.time_signature_denominator
+    )
+    .unwrap();
+    writeln!(
+        report,
+        "  length {bar_count} bars ({}) up to the last note",
+        song_time(song_end, bpm)
+    )
+    .unwrap();
+
+    let loop_points = lmms_project.loop_points();
+    if loop_points.enabled && loop_points.end > loop_points.start {
+        writeln!(
+            report,
+            "  loop bars {}-{}",
+            loop_points.start / ticks_per_bar + 1,
+            loop_points.end.div_ceil(ticks_per_bar)
+        )
+        .unwrap();
+    }
+
+    // Tracks
+
+    let mut track_type_counts = BTreeMap::new();
+    for lmms_track in lmms_project
+        .song
+        .track_container
+        .tracks
+        .iter()
+        .chain(&lmms_project.song.global_automation_tracks)
+    {
+        *track_type_counts
+            .entry(track_type_name(lmms_track))
+            .or_insert(0) += 1;
+    }
+
+    writeln!(report).unwrap();
+    writeln!(
+        report,
+        "tracks: {}",
+        track_type_counts.values().sum::<usize>()
+    )
+    .unwrap();
+
+    for (track_type, track_count) in &track_type_counts {
+        writeln!(report, "  {track_count:>4}  {track_type}").unwrap();
+    }
+
+    let skipped_track_count = skipped_tracks(lmms_project).count();
+    let dropped_track_count = lmms_project
+        .song
+        .track_container
+        .tracks
+        .iter()
+        .filter(|lmms_track| lmms_track.instrument_track.is_some())
+        .count()
+        .saturating_sub(conversion.channel_assignments().len());
+
+    if skipped_track_count > 0 || dropped_track_count > 0 {
+        writeln!(
+            report,
+            "  {} converted, {skipped_track_count} without notes, {dropped_track_count} without a MIDI program",
+            conversion.channel_assignments().len()
+        )
+        .unwrap();
+    }
+
+    // Notes
+
+    writeln!(report).unwrap();
+    writeln!(
+        report,
+        "notes: {}",
+        track_notes.iter().map(Vec::len).sum::<usize>()
+    )
+    .unwrap();
+
+    for (channel_assignment, notes) in conversion.channel_assignments().iter().zip(&track_notes) {
+        writeln!(
+            report,
+            "  {:>6}  {}",
+            notes.len(),
+            channel_assignment
+                .lmms_track(lmms_project)
+                .name
+                .escape_default()
+        )
+        .unwrap();
+    }
+
+    // Polyphony
+
+    if bar_count > 0 {
+        let bars_per_row = bar_count.div_ceil(INSPECT_PROFILE_ROWS);
+        let row_count = bar_count.div_ceil(bars_per_row);
+
+        let notes = track_notes
+            .iter()
+            .flatten()
+            .map(|(_, start, end)| (*start, *end))
+            .collect::<Vec<_>>();
+        let peaks = polyphony_profile(&notes, ticks_per_bar * bars_per_row, row_count);
+        let peak = peaks.iter().copied().max().unwrap_or(0);
+
+        writeln!(report).unwrap();
+        writeln!(report, "polyphony: up to {peak} notes at once").unwrap();
+
+        for (row_index, row_peak) in peaks.iter().enumerate() {
+            let first_bar = row_index * bars_per_row + 1;
+            let last_bar = ((row_index + 1) * bars_per_row).min(bar_count);
+
+            let bars = if first_bar == last_bar {
+                format!("bar {first_bar}")
+            } else {
+                format!("bars {first_bar}-{last_bar}")
+            };
+
+            writeln!(
+                report,
+                "  {bars:<13} {row_peak:>3} {}",
+                "#".repeat(*row_peak)
+            )
+            .unwrap();
+        }
+    }
+
+    // Channels
+
+    let mut channel_usage: BTreeMap<(usize, u8), (Vec<String>, usize)> = BTreeMap::new();
+
+    for (channel_assignment, notes) in conversion.channel_assignments().iter().zip(&track_notes) {
+        let lmms_track = channel_assignment.lmms_track(lmms_project);
+
+        for midi_channel in &channel_assignment.midi_channels {
+            let (track_names, note_count) = channel_usage
+                .entry((channel_assignment.midi_port, midi_channel.as_int()))
+                .or_default();
+
+            let track_name = format!("'{}'", lmms_track.name.escape_default());
+            if !track_names.contains(&track_name) {
+                track_names.push(track_name);
+            }
+
+            *note_count += notes
+                .iter()
+                .filter(|(channel, _, _)| *channel == midi_channel.as_int())
+                .count();
+        }
+    }
+
+    writeln!(report).unwrap();
+    writeln!(
+        report,
+        "channels: {} of {} used",
+        channel_usage.len(),
+        16 * options.midi_port_count()
+    )
+    .unwrap();
+
+    for ((midi_port, midi_channel), (track_names, note_count)) in &channel_usage {
+        let port = if options.midi_port_count() > 1 {
+            format!("port {} ", midi_port + 1)
+        } else {
+            String::new()
+        };
+
+        writeln!(
+            report,
+            "  {port}ch {:<2}  {note_count:>6} notes  {}",
+            midi_channel + 1,
+            track_names.join(", ")
+        )
+        .unwrap();
+    }
+
+    // Soundfonts
+
+    let mut soundfonts: Vec<(&str, usize)> = Vec::new();
+
+    for lmms_track in lmms_project.sampler_tracks() {
+        let Some(sample_src) = lmms_track.sample_src() else {
+            continue;
+        };
+
+        match soundfonts.iter_mut().find(|(src, _)| *src == sample_src) {
+            Some((_, track_count)) => *track_count += 1,
+            None => soundfonts.push((sample_src, 1)),
+        }
+    }
+
+    writeln!(report).unwrap();
+    writeln!(report, "soundfonts: {}", soundfonts.len()).unwrap();
+
+    for (sample_src, track_count) in soundfonts {
+        let status = if sample_src.is_empty() {
+            "none loaded"
+        } else if resolve_soundfont_path(project_path, sample_src).is_some() {
+            "found"
+        } else {
+            "missing"
+        };
+
+        writeln!(
+            report,
+            "  {} ({track_count} tracks, {status})",
+            if sample_src.is_empty() {
+                "-"
+            } else {
+                sample_src
+            }
+        )
+        .unwrap();
+    }
+
+    report
+}
@@ -0,0 +1,104 @@
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+
+use crate::convert::MidiPreset;
+
+// Maps LMMS track names, VST plugin file names or instrument plugin names to
+// MIDI presets.
+//
+// TOML:
+//
+//     "Lead" = 81
+//     "Strings" = "1:48"
+//     tripleoscillator = 80
+//     "Synth1.dll" = 81
+//
+// CSV:
+//
+//     name,bank,program
+//     Lead,0,81
+//     tripleoscillator,80
+
+#[derive(Debug, Clone, Default)]
+pub struct InstrumentMap {
+    entries: Vec<(String, MidiPreset)>,
+}
+
+impl InstrumentMap {
+    pub fn load_from_path(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let contents = fs::read_to_string(path)?;
+
+        match path.extension().and_then(OsStr::to_str) {
+            Some("toml") => InstrumentMap::from_toml(&contents),
+            Some("csv") => InstrumentMap::from_csv(&contents),
+            _ => Err("Not a TOML or CSV instrument map file".into()),
+        }
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Self, Box<dyn Error>> {
+        let table = contents.parse::<toml::Table>()?;
+        let mut entries = Vec::new();
+
+        for (name, value) in table {
+            let midi_preset = match value {
+                toml::Value::Integer(program) => program.to_string().parse()?,
+                toml::Value::String(preset) => preset.parse()?,
+                _ => return Err(format!("Invalid preset for '{name}'").into()),
+            };
+
+            entries.push((name, midi_preset));
+        }
+
+        Ok(InstrumentMap { entries })
+    }
+
+    pub fn from_csv(contents: &str) -> Result<Self, Box<dyn Error>> {
+        let mut entries = Vec::new();
+
+        for (line_index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty()
+                || line.starts_with('#')
+                || (line_index == 0 && line.starts_with("name,"))
+            {
+                continue;
+            }
+
+            let (name, midi_preset) = match line.rsplitn(3, ',').collect::<Vec<_>>()[..] {
+                [program, bank, name] if bank.trim().parse::<usize>().is_ok() => {
+                    (name, format!("{}:{}", bank.trim(), program.trim()))
+                }
+                [program, _, ..] => (
+                    &line[..line.len() - program.len() - 1],
+                    program.trim().to_owned(),
+                ),
+                _ => return Err(format!("Invalid instrument map line {}", line_index + 1).into()),
+            };
+
+            let midi_preset = midi_preset.parse::<MidiPreset>()?;
+            entries.push((name.trim().trim_matches('"').to_owned(), midi_preset));
+        }
+
+        Ok(InstrumentMap { entries })
+    }
+
+    /// Looks up a preset by the first matching name, in order of preference
+    /// (e.g. track name, VST plugin name, instrument plugin name)
+    pub fn get(&self, names: &[&str]) -> Option<MidiPreset> {
+        names.iter().find_map(|name| {
+            self.entries
+                .iter()
+                .rev()
+                .find(|(entry_name, _)| entry_name == name)
+                .map(|(_, midi_preset)| *midi_preset)
+        })
+    }
+}
+
+/// Command line value parser for instrument map files
+pub fn parse_instrument_map(s: &str) -> Result<InstrumentMap, String> {
+    InstrumentMap::load_from_path(Path::new(s)).map_err(|err| err.to_string())
+}
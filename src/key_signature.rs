@@ -0,0 +1,177 @@
+use std::fmt;
+use std::str::FromStr;
+
+use midly::{MidiMessage, TrackEventKind};
+
+use crate::midi::AbsoluteTrackEvent;
+
+// Krumhansl-Kessler key profiles, starting from the tonic
+const MAJOR_KEY_PROFILE: [f32; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+const MINOR_KEY_PROFILE: [f32; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+
+// Sharps (positive) or flats (negative) of the major key on each pitch class,
+// preferring the spelling with fewer accidentals
+const MAJOR_KEY_SHARPS: [i8; 12] = [0, -5, 2, -3, 4, -1, 6, 1, -4, 3, -2, 5];
+
+// Tonics of the major and minor keys by key signature, from 7 flats to 7 sharps
+const MAJOR_KEY_NAMES: [&str; 15] = [
+    "Cb", "Gb", "Db", "Ab", "Eb", "Bb", "F", "C", "G", "D", "A", "E", "B", "F#", "C#",
+];
+const MINOR_KEY_NAMES: [&str; 15] = [
+    "Ab", "Eb", "Bb", "F", "C", "G", "D", "A", "E", "B", "F#", "C#", "G#", "D#", "A#",
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct KeySignature {
+    /// Number of sharps (positive) or flats (negative), -7..=7
+    pub sharps: i8,
+    pub minor: bool,
+}
+
+impl KeySignature {
+    fn from_tonic(pitch_class: usize, minor: bool) -> Self {
+        // Minor keys share the signature of their relative major
+        let major_pitch_class = if minor {
+            (pitch_class + 3) % 12
+        } else {
+            pitch_class
+        };
+
+        KeySignature {
+            sharps: MAJOR_KEY_SHARPS[major_pitch_class],
+            minor,
+        }
+    }
+}
+
+impl fmt::Display for KeySignature {
+    /// Key name in the format of the command line, e.g. "Bb" or "F#m"
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let index = (self.sharps.clamp(-7, 7) + 7) as usize;
+
+        if self.minor {
+            write!(f, "{}m", MINOR_KEY_NAMES[index])
+        } else {
+            write!(f, "{}", MAJOR_KEY_NAMES[index])
+        }
+    }
+}
+
+impl FromStr for KeySignature {
+    type Err = String;
+
+    /// Parses key names like "C", "Bb", "F#m" or "Ebm"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid_key = || format!("invalid key '{s}', expected e.g. 'C', 'Bb' or 'F#m'");
+
+        let (s_key, minor) = match s.trim().strip_suffix('m') {
+            Some(s_key) => (s_key, true),
+            None => (s.trim(), false),
+        };
+
+        let mut chars = s_key.chars();
+
+        // Sharps of the major and minor key on each natural note
+        let (major_sharps, minor_sharps): (i8, i8) = match chars.next() {
+            Some('C') => (0, -3),
+            Some('D') => (2, -1),
+            Some('E') => (4, 1),
+            Some('F') => (-1, -4),
+            Some('G') => (1, -2),
+            Some('A') => (3, 0),
+            Some('B') => (5, 2),
+            _ => return Err(invalid_key()),
+        };
+
+        let accidental = match chars.as_str() {
+            "" => 0,
+            "#" => 7,
+            "b" => -7,
+            _ => return Err(invalid_key()),
+        };
+
+        let sharps = if minor { minor_sharps } else { major_sharps } + accidental;
+
+        if !(-7..=7).contains(&sharps) {
+            return Err(format!("key '{s}' has no standard key signature"));
+        }
+
+        Ok(KeySignature { sharps, minor })
+    }
+}
+
+/// Key signature setting of the command line
+#[derive(Debug, Copy, Clone)]
+pub enum KeySignatureMode {
+    /// Inferred from the note histogram
+    Auto,
+    Fixed(KeySignature),
+}
+
+impl FromStr for KeySignatureMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(KeySignatureMode::Auto),
+            _ => Ok(KeySignatureMode::Fixed(s.parse()?)),
+        }
+    }
+}
+
+/// Infers the key from the duration-weighted pitch class histogram of the
+/// melodic notes, or `None` when there are no such notes
+pub fn infer_key_signature(midi_track_events: &[AbsoluteTrackEvent]) -> Option<KeySignature> {
+    let mut histogram = [0.0f32; 12];
+
+    for event in midi_track_events {
+        if let TrackEventKind::Midi {
+            channel,
+            message: MidiMessage::NoteOff { key, .. },
+        } = event.kind
+        {
+            // Percussion keys are not pitches
+            if channel.as_int() == 9 {
+                continue;
+            }
+
+            histogram[key.as_int() as usize % 12] += (event.ticks - event.ticks_event_start) as f32;
+        }
+    }
+
+    if histogram.iter().all(|weight| *weight == 0.0) {
+        return None;
+    }
+
+    let correlation = |key_profile: &[f32; 12], tonic: usize| {
+        let mean_histogram = histogram.iter().sum::<f32>() / 12.0;
+        let mean_profile = key_profile.iter().sum::<f32>() / 12.0;
+
+        let (mut covariance, mut variance_histogram, mut variance_profile) = (0.0, 0.0, 0.0);
+
+        for pitch_class in 0..12 {
+            let x = histogram[(tonic + pitch_class) % 12] - mean_histogram;
+            let y = key_profile[pitch_class] - mean_profile;
+
+            covariance += x * y;
+            variance_histogram += x * x;
+            variance_profile += y * y;
+        }
+
+        covariance / (variance_histogram * variance_profile).sqrt()
+    };
+
+    (0..12)
+        .flat_map(|tonic| {
+            [
+                (correlation(&MAJOR_KEY_PROFILE, tonic), tonic, false),
+                (correlation(&MINOR_KEY_PROFILE, tonic), tonic, true),
+            ]
+        })
+        .max_by(|(a, _, _), (b, _, _)| a.total_cmp(b))
+        .map(|(_, tonic, minor)| KeySignature::from_tonic(tonic, minor))
+}
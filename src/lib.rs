@@ -0,0 +1,50 @@
+pub mod abc;
+pub mod articulation;
+pub mod bundle;
+pub mod channel_map;
+pub mod config;
+pub mod controllers;
+pub mod convert;
+pub mod diff;
+pub mod dls;
+pub mod doctor;
+pub mod drums;
+pub mod dump;
+pub mod error;
+pub mod exit_code;
+pub mod fixture;
+pub mod flac;
+pub mod hmp;
+pub mod humanize;
+pub mod import;
+pub mod inspect;
+pub mod instrument_map;
+pub mod key_signature;
+pub mod lilypond;
+pub mod lmms_model;
+pub mod location;
+pub mod log;
+pub mod lyrics;
+pub mod midi;
+pub mod midicsv;
+pub mod mml;
+pub mod mt32;
+pub mod mus;
+pub mod musicxml;
+pub mod notation;
+pub mod overlaps;
+pub mod play;
+pub mod polyphony;
+pub mod portamento;
+pub mod preview;
+pub mod progress;
+pub mod render;
+pub mod rmid;
+pub mod rng;
+pub mod roundtrip;
+pub mod sustain;
+pub mod target;
+pub mod text_encoding;
+pub mod tracks;
+pub mod ump;
+pub mod xmi;
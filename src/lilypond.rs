@@ -0,0 +1,225 @@
+use std::fmt::Write;
+
+use crate::key_signature::KeySignature;
+use crate::lmms_model::LMMS_TICKS_PER_BAR;
+use crate::notation::{note_value, Chord, Pitch, Score, LETTER_NAMES};
+
+const LILYPOND_VERSION: &str = "2.24.0";
+
+/// Text of a string literal, without the characters ending it
+fn lilypond_text(text: &str) -> String {
+    text.replace(['\r', '\n'], " ")
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+}
+
+/// Dutch note name suffix of an alteration
+fn alteration_suffix(alteration: i8) -> &'static str {
+    match alteration {
+        1 => "is",
+        -1 => "es",
+        _ => "",
+    }
+}
+
+/// Pitch in absolute octave mode, middle C being "c'"
+fn lilypond_pitch(key: u8, key_signature: KeySignature) -> String {
+    let pitch = Pitch::spell(key, key_signature);
+
+    let mut lilypond_pitch = LETTER_NAMES[pitch.letter].to_ascii_lowercase().to_string();
+    lilypond_pitch.push_str(alteration_suffix(pitch.alteration));
+
+    if pitch.octave >= 3 {
+        lilypond_pitch.extend(std::iter::repeat_n('\'', (pitch.octave - 3) as usize));
+    } else {
+        lilypond_pitch.extend(std::iter::repeat_n(',', (3 - pitch.octave) as usize));
+    }
+
+    lilypond_pitch
+}
+
+/// Duration of a note value ("4."), lengths below the notation grid scaled
+/// from a whole note
+fn lilypond_duration(duration: usize) -> String {
+    match note_value(duration) {
+        Some((denominator, true)) => format!("{denominator}."),
+        Some((denominator, false)) => denominator.to_string(),
+        None => format!("1*{duration}/{LMMS_TICKS_PER_BAR}"),
+    }
+}
+
+/// "\key" command of a key signature, e.g. "\key bes \major"
+fn lilypond_key(key_signature: KeySignature) -> String {
+    let key_name = key_signature.to_string();
+    let tonic = key_name.trim_end_matches('m');
+
+    let mut chars = tonic.chars();
+    let letter = chars.next().unwrap_or('C').to_ascii_lowercase();
+    let suffix = match chars.as_str() {
+        "#" => "is",
+        "b" => "es",
+        _ => "",
+    };
+
+    format!(
+        "\\key {letter}{suffix} \\{}",
+        if key_signature.minor {
+            "minor"
+        } else {
+            "major"
+        }
+    )
+}
+
+/// A note, a chord in angle brackets or a rest, ties following the tied keys
+fn lilypond_chord(chord: &Chord, key_signature: KeySignature) -> String {
+    let duration = lilypond_duration(chord.duration);
+
+    match chord.keys.as_slice() {
+        [] => format!("r{duration}"),
+        [chord_key] => format!(
+            "{}{duration}{}",
+            lilypond_pitch(chord_key.key, key_signature),
+            if chord_key.tied { "~" } else { "" }
+        ),
+        chord_keys => {
+            let pitches = chord_keys
+                .iter()
+                .map(|chord_key| {
+                    format!(
+                        "{}{}",
+                        lilypond_pitch(chord_key.key, key_signature),
+                        if chord_key.tied { "~" } else { "" }
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            format!("<{}>{duration}", pitches.join(" "))
+        }
+    }
+}
+
+/// The score as a LilyPond source file, a staff per track in the song's key
+/// and meter, with a layout and a MIDI block
+pub fn lilypond(score: &Score) -> String {
+    let (numerator, denominator) = score.time_signature;
+
+    let mut lilypond = String::new();
+
+    writeln!(lilypond, "\\version \"{LILYPOND_VERSION}\"").unwrap();
+
+    if let Some(ref title) = score.title {
+        writeln!(lilypond).unwrap();
+        writeln!(lilypond, "\\header {{").unwrap();
+        writeln!(lilypond, "  title = \"{}\"", lilypond_text(title)).unwrap();
+        writeln!(lilypond, "}}").unwrap();
+    }
+
+    writeln!(lilypond).unwrap();
+    writeln!(lilypond, "\\score {{").unwrap();
+    writeln!(lilypond, "  <<").unwrap();
+
+    for (voice_index, voice) in score.voices.iter().enumerate() {
+        let clef = if voice.is_percussion() {
+            "percussion"
+        } else if voice.is_low() {
+            "bass"
+        } else {
+            "treble"
+        };
+
+        writeln!(
+            lilypond,
+            "    \\new Staff \\with {{ instrumentName = \"{}\" }} {{",
+            lilypond_text(&voice.name)
+        )
+        .unwrap();
+        writeln!(lilypond, "      \\clef {clef}").unwrap();
+        writeln!(lilypond, "      {}", lilypond_key(score.key_signature)).unwrap();
+        writeln!(lilypond, "      \\time {numerator}/{denominator}").unwrap();
+
+        if voice_index == 0 {
+            writeln!(lilypond, "      \\tempo 4 = {}", score.bpm.round()).unwrap();
+        }
+
+        for bar in score.bars(voice) {
+            let music = if bar.iter().all(Chord::is_rest) {
+                format!("R1*{numerator}/{denominator}")
+            } else {
+                bar.iter()
+                    .map(|chord| lilypond_chord(chord, score.key_signature))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            };
+
+            writeln!(lilypond, "      {music} |").unwrap();
+        }
+
+        writeln!(lilypond, "    }}").unwrap();
+    }
+
+    writeln!(lilypond, "  >>").unwrap();
+    writeln!(lilypond, "  \\layout {{ }}").unwrap();
+    writeln!(lilypond, "  \\midi {{ }}").unwrap();
+    writeln!(lilypond, "}}").unwrap();
+
+    lilypond
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert::MidiPreset;
+    use crate::notation::{NotationNote, NotationVoice};
+
+    #[test]
+    fn staff_has_the_key_meter_and_chords() {
+        let note = |start, end, key| NotationNote { start, end, key };
+
+        let score = Score {
+            title: Some("Demo".to_string()),
+            bpm: 120.0,
+            time_signature: (4, 4),
+            key_signature: KeySignature {
+                sharps: -2,
+                minor: false,
+            },
+            voices: vec![NotationVoice::new(
+                "Lead",
+                0,
+                MidiPreset::default(),
+                &[note(0, 96, 58), note(0, 96, 62), note(96, 144, 72)],
+            )],
+        };
+
+        assert_eq!(
+            lilypond(&score),
+            "\\version \"2.24.0\"\n\
+             \n\
+             \\header {\n  title = \"Demo\"\n}\n\
+             \n\
+             \\score {\n\
+             \x20 <<\n\
+             \x20   \\new Staff \\with { instrumentName = \"Lead\" } {\n\
+             \x20     \\clef treble\n\
+             \x20     \\key bes \\major\n\
+             \x20     \\time 4/4\n\
+             \x20     \\tempo 4 = 120\n\
+             \x20     <bes d'>2 c''4 r4 |\n\
+             \x20   }\n\
+             \x20 >>\n\
+             \x20 \\layout { }\n\
+             \x20 \\midi { }\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn keys_are_named_in_dutch() {
+        let key = |sharps, minor| lilypond_key(KeySignature { sharps, minor });
+
+        assert_eq!(key(0, false), "\\key c \\major");
+        assert_eq!(key(3, true), "\\key fis \\minor");
+        assert_eq!(key(-3, false), "\\key ees \\major");
+    }
+}
@@ -1,11 +1,15 @@
-use std::error::Error;
 use std::ffi::OsStr;
 use std::path::Path;
 use std::{fs, str};
 
+use miniz_oxide::deflate::compress_to_vec_zlib;
 use miniz_oxide::inflate::decompress_to_vec_zlib;
+use serde::Serialize;
 use strong_xml::XmlRead;
 
+use crate::error::ConvertError;
+use crate::{note, warning};
+
 // +-------+-------+
 // | LMMS  | LMMS  |
 // | ticks | note  |
@@ -28,7 +32,13 @@ use strong_xml::XmlRead;
 
 pub const LMMS_TICKS_PER_BAR: usize = 192;
 
-#[derive(Debug, XmlRead)]
+pub const LMMS_PATTERN_TYPE_BEAT: usize = 0;
+pub const LMMS_PATTERN_TYPE_MELODY: usize = 1;
+
+// Beat patterns have a fixed grid of sixteenth note steps
+pub const LMMS_STEPS_PER_BAR: usize = 16;
+
+#[derive(Debug, XmlRead, Serialize)]
 #[xml(tag = "lmms-project")]
 pub struct LmmsProject {
     #[xml(attr = "type")]
@@ -50,7 +60,7 @@ pub struct LmmsProject {
     pub song: LmmsSong,
 }
 
-#[derive(Debug, XmlRead)]
+#[derive(Debug, XmlRead, Serialize)]
 #[xml(tag = "head")]
 pub struct LmmsHead {
     #[xml(attr = "timesig_denominator")]
@@ -67,26 +77,35 @@ pub struct LmmsHead {
 
     #[xml(attr = "mastervol")]
     pub master_volume: usize,
+
+    // Written by some third-party tools, positions are in 1/timebase bars
+    #[xml(attr = "timebase")]
+    pub timebase: Option<usize>,
 }
 
-#[derive(Debug, XmlRead)]
+#[derive(Debug, XmlRead, Serialize)]
 #[xml(tag = "song")]
 pub struct LmmsSong {
     #[xml(child = "trackcontainer")]
     pub track_container: LmmsTrackContainer,
 
+    // Editors nest their own timeline nodes, only the song-level ones are read here
     #[xml(child = "timeline")]
-    pub timeline: LmmsTimeline,
-    // Skipped: track (automationtrack)
+    pub timelines: Vec<LmmsTimeline>,
+
+    #[xml(child = "track")]
+    pub global_automation_tracks: Vec<LmmsTrack>,
+
+    #[xml(child = "controllers")]
+    pub controllers: Option<LmmsControllers>,
     // Skipped: fxmixer
     // Skipped: ControllerRackView
     // Skipped: pianoroll
     // Skipped: automationeditor
     // Skipped: projectnotes
-    // Skipped: controllers
 }
 
-#[derive(Debug, XmlRead)]
+#[derive(Debug, XmlRead, Serialize)]
 #[xml(tag = "trackcontainer")]
 pub struct LmmsTrackContainer {
     #[xml(attr = "visible")]
@@ -117,7 +136,7 @@ pub struct LmmsTrackContainer {
     pub tracks: Vec<LmmsTrack>,
 }
 
-#[derive(Debug, XmlRead)]
+#[derive(Debug, XmlRead, Serialize)]
 #[xml(tag = "track")]
 pub struct LmmsTrack {
     #[xml(attr = "name")]
@@ -136,17 +155,49 @@ pub struct LmmsTrack {
     pub solo: usize,
 
     #[xml(child = "instrumenttrack")]
-    pub instrument_track: LmmsInstrumentTrack,
+    pub instrument_track: Option<LmmsInstrumentTrack>,
 
     #[xml(child = "pattern")]
     pub patterns: Vec<LmmsPattern>,
+
+    #[xml(child = "sampletco")]
+    pub sample_clips: Vec<LmmsSampleClip>,
+
+    #[xml(child = "automationpattern")]
+    pub automation_patterns: Vec<LmmsAutomationPattern>,
+
+    #[xml(child = "bbtco")]
+    pub pattern_clips: Vec<LmmsPatternClip>,
+    // Skipped: sampletrack
+    // Skipped: automationtrack
+    // Skipped: bbtrack
 }
 
-#[derive(Debug, XmlRead)]
+// +------+------------------------+
+// | Type | LMMS track             |
+// +------+------------------------+
+// |    0 | Instrument track       |
+// |    1 | Beat/Bassline track    |
+// |    2 | Sample track           |
+// |    5 | Automation track       |
+// |    6 | Hidden automation track|
+// +------+------------------------+
+
+pub const LMMS_TRACK_TYPE_INSTRUMENT: usize = 0;
+pub const LMMS_TRACK_TYPE_PATTERN: usize = 1;
+pub const LMMS_TRACK_TYPE_SAMPLE: usize = 2;
+pub const LMMS_TRACK_TYPE_AUTOMATION: usize = 5;
+pub const LMMS_TRACK_TYPE_HIDDEN_AUTOMATION: usize = 6;
+
+#[derive(Debug, XmlRead, Serialize)]
 #[xml(tag = "instrumenttrack")]
 pub struct LmmsInstrumentTrack {
+    // Stored as a child node instead when automated
     #[xml(attr = "vol")]
-    pub volume: f32,
+    pub volume: Option<f32>,
+
+    #[xml(child = "vol")]
+    pub volume_model: Option<LmmsVolumeModel>,
 
     #[xml(attr = "pan")]
     pub panning: f32,
@@ -177,6 +228,9 @@ pub struct LmmsInstrumentTrack {
 
     #[xml(child = "instrument")]
     pub instrument: LmmsInstrument,
+
+    #[xml(child = "connection")]
+    pub connections: Option<LmmsConnections>,
     // Skipped: midicontrollers
     // Skipped: eldata
     // Skipped: chordcreator
@@ -185,7 +239,62 @@ pub struct LmmsInstrumentTrack {
     // Skipped: fxchain
 }
 
-#[derive(Debug, XmlRead)]
+#[derive(Debug, XmlRead, Serialize)]
+#[xml(tag = "vol")]
+pub struct LmmsVolumeModel {
+    #[xml(attr = "id")]
+    pub id: usize,
+
+    #[xml(attr = "value")]
+    pub value: f32,
+}
+
+impl LmmsInstrumentTrack {
+    /// Volume knob in percent
+    pub fn volume(&self) -> f32 {
+        self.volume
+            .or_else(|| self.volume_model.as_ref().map(|model| model.value))
+            .unwrap_or(100.0)
+    }
+}
+
+// Controller connections of the track's own parameters, the `id` attribute
+// indexes into the song's controller list
+#[derive(Debug, XmlRead, Serialize)]
+#[xml(tag = "connection")]
+pub struct LmmsConnections {
+    #[xml(child = "vol")]
+    pub volume: Option<LmmsVolumeConnection>,
+
+    #[xml(child = "pan")]
+    pub panning: Option<LmmsPanningConnection>,
+
+    #[xml(child = "pitch")]
+    pub pitch: Option<LmmsPitchConnection>,
+}
+
+#[derive(Debug, XmlRead, Serialize)]
+#[xml(tag = "vol")]
+pub struct LmmsVolumeConnection {
+    #[xml(attr = "id")]
+    pub id: Option<usize>,
+}
+
+#[derive(Debug, XmlRead, Serialize)]
+#[xml(tag = "pan")]
+pub struct LmmsPanningConnection {
+    #[xml(attr = "id")]
+    pub id: Option<usize>,
+}
+
+#[derive(Debug, XmlRead, Serialize)]
+#[xml(tag = "pitch")]
+pub struct LmmsPitchConnection {
+    #[xml(attr = "id")]
+    pub id: Option<usize>,
+}
+
+#[derive(Debug, XmlRead, Serialize)]
 #[xml(tag = "instrument")]
 pub struct LmmsInstrument {
     #[xml(attr = "name")]
@@ -193,19 +302,39 @@ pub struct LmmsInstrument {
 
     #[xml(child = "sf2player")]
     pub sf2_player: Option<LmmsSf2Player>,
+
+    #[xml(child = "gigplayer")]
+    pub gig_player: Option<LmmsGigPlayer>,
+
+    #[xml(child = "OPL2")]
+    pub opulenz: Option<LmmsOpulenz>,
+
+    #[xml(child = "vestige")]
+    pub vestige: Option<LmmsVestige>,
+
+    #[xml(child = "audiofileprocessor")]
+    pub audio_file_processor: Option<LmmsAudioFileProcessor>,
 }
 
-#[derive(Debug, XmlRead)]
+// Automated knobs are stored as child nodes carrying their model id, which
+// automation patterns refer to, instead of attributes
+#[derive(Debug, XmlRead, Serialize)]
 #[xml(tag = "sf2player")]
 pub struct LmmsSf2Player {
     #[xml(attr = "src")]
     pub src: String,
 
     #[xml(attr = "bank")]
-    pub bank: usize,
+    pub bank: Option<usize>,
+
+    #[xml(child = "bank")]
+    pub bank_model: Option<LmmsSf2BankModel>,
 
     #[xml(attr = "patch")]
-    pub patch: usize,
+    pub patch: Option<usize>,
+
+    #[xml(child = "patch")]
+    pub patch_model: Option<LmmsSf2PatchModel>,
 
     #[xml(attr = "gain")]
     pub gain: f32,
@@ -241,7 +370,93 @@ pub struct LmmsSf2Player {
     pub chorus_speed: f32,
 }
 
-#[derive(Debug, XmlRead)]
+#[derive(Debug, XmlRead, Serialize)]
+#[xml(tag = "bank")]
+pub struct LmmsSf2BankModel {
+    #[xml(attr = "id")]
+    pub id: usize,
+
+    #[xml(attr = "value")]
+    pub value: f32,
+}
+
+#[derive(Debug, XmlRead, Serialize)]
+#[xml(tag = "patch")]
+pub struct LmmsSf2PatchModel {
+    #[xml(attr = "id")]
+    pub id: usize,
+
+    #[xml(attr = "value")]
+    pub value: f32,
+}
+
+impl LmmsSf2Player {
+    pub fn bank(&self) -> usize {
+        self.bank
+            .or_else(|| self.bank_model.as_ref().map(|model| model.value as usize))
+            .unwrap_or(0)
+    }
+
+    pub fn patch(&self) -> usize {
+        self.patch
+            .or_else(|| self.patch_model.as_ref().map(|model| model.value as usize))
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Debug, XmlRead, Serialize)]
+#[xml(tag = "gigplayer")]
+pub struct LmmsGigPlayer {
+    #[xml(attr = "src")]
+    pub src: String,
+
+    #[xml(attr = "bank")]
+    pub bank: usize,
+
+    #[xml(attr = "patch")]
+    pub patch: usize,
+
+    #[xml(attr = "gain")]
+    pub gain: f32,
+}
+
+// OpulenZ stores its FM patch as per-operator register values, of which only
+// the patch-level settings are modelled here.
+#[derive(Debug, XmlRead, Serialize)]
+#[xml(tag = "OPL2")]
+pub struct LmmsOpulenz {
+    #[xml(attr = "feedback")]
+    pub feedback: Option<usize>,
+
+    #[xml(attr = "fm")]
+    pub fm: Option<usize>,
+
+    #[xml(attr = "vib_depth")]
+    pub vibrato_depth: Option<usize>,
+
+    #[xml(attr = "trem_depth")]
+    pub tremolo_depth: Option<usize>,
+}
+
+#[derive(Debug, XmlRead, Serialize)]
+#[xml(tag = "vestige")]
+pub struct LmmsVestige {
+    #[xml(attr = "plugin")]
+    pub plugin: String,
+
+    // The VST plugin's own program number, unrelated to GM programs
+    #[xml(attr = "program")]
+    pub program: Option<usize>,
+}
+
+#[derive(Debug, XmlRead, Serialize)]
+#[xml(tag = "audiofileprocessor")]
+pub struct LmmsAudioFileProcessor {
+    #[xml(attr = "src")]
+    pub src: String,
+}
+
+#[derive(Debug, XmlRead, Serialize)]
 #[xml(tag = "pattern")]
 pub struct LmmsPattern {
     #[xml(attr = "name")]
@@ -263,7 +478,87 @@ pub struct LmmsPattern {
     pub notes: Vec<LmmsNote>,
 }
 
-#[derive(Debug, XmlRead)]
+#[derive(Debug, XmlRead, Serialize)]
+#[xml(tag = "sampletco")]
+pub struct LmmsSampleClip {
+    #[xml(attr = "src")]
+    pub src: String,
+
+    #[xml(attr = "muted")]
+    pub muted: usize,
+
+    #[xml(attr = "pos")]
+    pub position: usize,
+
+    #[xml(attr = "len")]
+    pub length: usize,
+}
+
+#[derive(Debug, XmlRead, Serialize)]
+#[xml(tag = "automationpattern")]
+pub struct LmmsAutomationPattern {
+    #[xml(attr = "name")]
+    pub name: String,
+
+    #[xml(attr = "pos")]
+    pub position: usize,
+
+    #[xml(attr = "len")]
+    pub length: usize,
+
+    #[xml(attr = "prog")]
+    pub progression: Option<usize>,
+
+    #[xml(child = "time")]
+    pub points: Vec<LmmsAutomationPoint>,
+
+    #[xml(child = "object")]
+    pub objects: Vec<LmmsAutomationObject>,
+}
+
+#[derive(Debug, XmlRead, Serialize)]
+#[xml(tag = "time")]
+pub struct LmmsAutomationPoint {
+    #[xml(attr = "pos")]
+    pub position: usize,
+
+    #[xml(attr = "value")]
+    pub value: f32,
+}
+
+/// An automation point placed in the song
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AutomationPoint {
+    /// Position in LMMS ticks
+    pub ticks: usize,
+
+    pub value: f32,
+
+    /// Whether the value moves linearly towards the next point, or jumps there
+    pub linear: bool,
+}
+
+#[derive(Debug, XmlRead, Serialize)]
+#[xml(tag = "object")]
+pub struct LmmsAutomationObject {
+    #[xml(attr = "id")]
+    pub id: usize,
+}
+
+#[derive(Debug, XmlRead, Serialize)]
+#[xml(tag = "bbtco")]
+pub struct LmmsPatternClip {
+    #[xml(attr = "name")]
+    pub name: String,
+
+    #[xml(attr = "pos")]
+    pub position: usize,
+
+    #[xml(attr = "len")]
+    pub length: usize,
+}
+
+#[derive(Debug, XmlRead, Serialize)]
 #[xml(tag = "note")]
 pub struct LmmsNote {
     #[xml(attr = "vol")]
@@ -275,69 +570,579 @@ pub struct LmmsNote {
     #[xml(attr = "pos")]
     pub position: usize,
 
+    // Step notes of beat patterns are stored with a negative length
     #[xml(attr = "len")]
-    pub length: usize,
+    pub length: isize,
 
     #[xml(attr = "key")]
     pub key: usize,
 }
 
-#[derive(Debug, XmlRead)]
+#[derive(Debug, XmlRead, Serialize)]
+#[xml(tag = "controllers")]
+pub struct LmmsControllers {
+    #[xml(
+        child = "lfocontroller",
+        child = "peakcontroller",
+        child = "midicontroller",
+        child = "Controller"
+    )]
+    pub controllers: Vec<LmmsController>,
+}
+
+// Controllers are tagged with their kind in JSON, as the XML element name is
+#[derive(Debug, XmlRead, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum LmmsController {
+    #[xml(tag = "lfocontroller")]
+    Lfo(LmmsLfoController),
+
+    #[xml(tag = "peakcontroller")]
+    Peak(LmmsPeakController),
+
+    #[xml(tag = "midicontroller")]
+    Midi(LmmsMidiController),
+
+    #[xml(tag = "Controller")]
+    Generic(LmmsGenericController),
+}
+
+// Knob values are missing from the attributes when they are automated
+#[derive(Debug, XmlRead, Serialize)]
+#[xml(tag = "lfocontroller")]
+pub struct LmmsLfoController {
+    #[xml(attr = "name")]
+    pub name: String,
+
+    /// Period in seconds, before applying the multiplier
+    #[xml(attr = "speed")]
+    pub speed: Option<f32>,
+
+    #[xml(attr = "amount")]
+    pub amount: Option<f32>,
+
+    #[xml(attr = "base")]
+    pub base: Option<f32>,
+
+    /// Phase offset in degrees
+    #[xml(attr = "phase")]
+    pub phase: Option<f32>,
+
+    #[xml(attr = "wave")]
+    pub wave: Option<usize>,
+
+    #[xml(attr = "multiplier")]
+    pub multiplier: Option<usize>,
+}
+
+#[derive(Debug, XmlRead, Serialize)]
+#[xml(tag = "peakcontroller")]
+pub struct LmmsPeakController {
+    #[xml(attr = "name")]
+    pub name: String,
+}
+
+#[derive(Debug, XmlRead, Serialize)]
+#[xml(tag = "midicontroller")]
+pub struct LmmsMidiController {
+    #[xml(attr = "name")]
+    pub name: Option<String>,
+}
+
+#[derive(Debug, XmlRead, Serialize)]
+#[xml(tag = "Controller")]
+pub struct LmmsGenericController {
+    #[xml(attr = "name")]
+    pub name: Option<String>,
+}
+
+#[derive(Debug, XmlRead, Serialize)]
 #[xml(tag = "timeline")]
 pub struct LmmsTimeline {
     #[xml(attr = "lpstate")]
     pub loop_state: usize,
 
     #[xml(attr = "lp0pos")]
-    pub loop_start: usize,
+    pub loop_start: f64,
 
     #[xml(attr = "lp1pos")]
-    pub loop_end: usize,
+    pub loop_end: f64,
 
     #[xml(attr = "stopbehaviour")]
     pub stop_behaviour: Option<usize>,
 }
 
+/// Loop points normalized to LMMS ticks
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LmmsLoopPoints {
+    pub enabled: bool,
+    pub start: usize,
+    pub end: usize,
+
+    /// The positions were written in bars instead of ticks
+    pub converted_from_bars: bool,
+}
+
+impl LmmsTimeline {
+    /// Loop points in ticks of the given timebase
+    pub fn loop_points(&self, ticks_per_bar: usize) -> LmmsLoopPoints {
+        let mut start = self.loop_start.max(0.0).round() as usize;
+        let mut end = self.loop_end.max(0.0).round() as usize;
+
+        if start > end {
+            std::mem::swap(&mut start, &mut end);
+        }
+
+        // A loop shorter than a bar is implausible in ticks, but common
+        // when the positions are given in bars
+        let converted_from_bars = (end > start) && (end - start < ticks_per_bar);
+
+        if converted_from_bars {
+            start *= ticks_per_bar;
+            end *= ticks_per_bar;
+        }
+
+        LmmsLoopPoints {
+            enabled: self.loop_state != 0,
+            start,
+            end,
+            converted_from_bars,
+        }
+    }
+}
+
+/// XML of a compressed LMMS project (.mmpz): the uncompressed length,
+/// big-endian, followed by the zlib stream of the XML
+pub fn decompress_project(compressed_bin: &[u8]) -> Result<Vec<u8>, ConvertError> {
+    let Some(zlib_stream) = compressed_bin.get(4..) else {
+        return Err(ConvertError::Truncated);
+    };
+
+    Ok(decompress_to_vec_zlib(zlib_stream)?)
+}
+
+/// Compressed LMMS project (.mmpz) of the XML
+pub fn compress_project(uncompressed_bin: &[u8]) -> Vec<u8> {
+    let mut compressed_bin = (uncompressed_bin.len() as u32).to_be_bytes().to_vec();
+    compressed_bin.extend(compress_to_vec_zlib(uncompressed_bin, 6));
+    compressed_bin
+}
+
 impl LmmsProject {
-    pub fn load_from_path(path: &Path) -> Result<Self, Box<dyn Error>> {
+    pub fn load_from_path(path: &Path) -> Result<Self, ConvertError> {
         match path.extension().and_then(OsStr::to_str) {
             Some("mmp") => {
                 let uncompressed_xml = fs::read_to_string(path)?;
-                Ok(LmmsProject::from_str(&uncompressed_xml)?)
+                LmmsProject::from_str_recovering(&uncompressed_xml)
             }
             Some("mmpz") => {
                 let compressed_bin = fs::read(path)?;
-                let uncompressed_bin = decompress_to_vec_zlib(&compressed_bin[4..])?;
+                let uncompressed_bin = decompress_project(&compressed_bin)?;
                 let uncompressed_xml = str::from_utf8(&uncompressed_bin)?;
-                Ok(LmmsProject::from_str(uncompressed_xml)?)
+                LmmsProject::from_str_recovering(uncompressed_xml)
+            }
+            _ => Err(ConvertError::NotAProject),
+        }
+    }
+
+    /// Parses a project from the contents of an .mmp or .mmpz file, telling
+    /// them apart by the XML declaration, for projects piped in without a name
+    pub fn from_bytes(project_bin: &[u8]) -> Result<Self, ConvertError> {
+        let xml_bin = project_bin
+            .strip_prefix(b"\xEF\xBB\xBF")
+            .unwrap_or(project_bin);
+
+        if xml_bin.trim_ascii_start().starts_with(b"<") {
+            LmmsProject::from_str_recovering(str::from_utf8(xml_bin)?)
+        } else {
+            let uncompressed_bin = decompress_project(project_bin)?;
+            LmmsProject::from_str_recovering(str::from_utf8(&uncompressed_bin)?)
+        }
+    }
+
+    /// Parses a project, falling back to salvaging the readable notes of
+    /// patterns whose note data is corrupt or truncated
+    pub fn from_str_recovering(xml: &str) -> Result<Self, ConvertError> {
+        let err = match LmmsProject::from_str(xml) {
+            Ok(lmms_project) => return Ok(lmms_project),
+            Err(err) => err,
+        };
+
+        let (recovered_xml, partial_patterns) = recover_patterns(xml);
+
+        if partial_patterns.is_empty() {
+            return Err(err.into());
+        }
+
+        let lmms_project = LmmsProject::from_str(&recovered_xml).map_err(|_| err)?;
+
+        for partial_pattern in &partial_patterns {
+            warning!(
+                code: "partial-pattern", track: &partial_pattern.track_name, pattern: &partial_pattern.pattern_name;
+                "pattern '{}' of track '{}' at {} is partial",
+                partial_pattern.pattern_name.escape_default(),
+                partial_pattern.track_name.escape_default(),
+                partial_pattern.position,
+            );
+            note!(
+                "its note data is corrupt, only the first {} notes were recovered",
+                partial_pattern.recovered_notes,
+            );
+        }
+
+        Ok(lmms_project)
+    }
+
+    /// Ticks per bar of the positions stored in the project
+    pub fn ticks_per_bar(&self) -> usize {
+        self.head
+            .timebase
+            .filter(|timebase| *timebase > 0)
+            .unwrap_or(LMMS_TICKS_PER_BAR)
+    }
+
+    /// Length of a bar of the project's time signature in LMMS ticks
+    pub fn lmms_ticks_per_bar(&self) -> usize {
+        let numerator = self.head.time_signature_numerator.max(1);
+        let denominator = self.head.time_signature_denominator.max(1);
+        (LMMS_TICKS_PER_BAR * numerator / denominator).max(1)
+    }
+
+    /// Position of LMMS ticks as bar:beat in the project's time signature,
+    /// counted from 1:1 like the LMMS timeline
+    pub fn musical_position(&self, lmms_ticks: usize) -> String {
+        let lmms_ticks_per_bar = self.lmms_ticks_per_bar();
+        let lmms_ticks_per_beat =
+            (LMMS_TICKS_PER_BAR / self.head.time_signature_denominator.max(1)).max(1);
+
+        format!(
+            "{}:{}",
+            lmms_ticks / lmms_ticks_per_bar + 1,
+            lmms_ticks % lmms_ticks_per_bar / lmms_ticks_per_beat + 1
+        )
+    }
+
+    /// Converts a position stored in the project to LMMS ticks
+    pub fn to_lmms_ticks(&self, ticks: usize) -> usize {
+        let ticks_per_bar = self.ticks_per_bar();
+        (ticks * LMMS_TICKS_PER_BAR + ticks_per_bar / 2) / ticks_per_bar
+    }
+
+    /// Loop points of the song-level timeline in LMMS ticks. When a project
+    /// has multiple timelines the first one wins.
+    pub fn loop_points(&self) -> LmmsLoopPoints {
+        match self.song.timelines.first() {
+            Some(lmms_timeline) => {
+                let loop_points = lmms_timeline.loop_points(self.ticks_per_bar());
+
+                LmmsLoopPoints {
+                    start: self.to_lmms_ticks(loop_points.start),
+                    end: self.to_lmms_ticks(loop_points.end),
+                    ..loop_points
+                }
             }
-            _ => Err("Not an LMMS project file".into()),
+            None => LmmsLoopPoints {
+                enabled: false,
+                start: 0,
+                end: 0,
+                converted_from_bars: false,
+            },
         }
     }
 
+    /// Controller referenced by a controller connection
+    pub fn controller(&self, controller_id: usize) -> Option<&LmmsController> {
+        self.song
+            .controllers
+            .as_ref()
+            .and_then(|lmms_controllers| lmms_controllers.controllers.get(controller_id))
+    }
+
+    /// Automation points of a model across the automation tracks, sorted by position
+    pub fn automation_points(&self, model_id: usize) -> Vec<AutomationPoint> {
+        let mut points = self
+            .song
+            .track_container
+            .tracks
+            .iter()
+            .chain(&self.song.global_automation_tracks)
+            .filter(|lmms_track| lmms_track.muted == 0)
+            .flat_map(|lmms_track| &lmms_track.automation_patterns)
+            .filter(|lmms_automation_pattern| {
+                lmms_automation_pattern
+                    .objects
+                    .iter()
+                    .any(|lmms_automation_object| lmms_automation_object.id == model_id)
+            })
+            .flat_map(|lmms_automation_pattern| {
+                let point_count = lmms_automation_pattern.points.len();
+
+                lmms_automation_pattern.points.iter().enumerate().map(
+                    move |(point_index, lmms_point)| AutomationPoint {
+                        ticks: self
+                            .to_lmms_ticks(lmms_automation_pattern.position + lmms_point.position),
+                        value: lmms_point.value,
+                        // Cubic progressions are approximated linearly
+                        linear: lmms_automation_pattern.progression.unwrap_or(0) != 0
+                            && point_index + 1 < point_count,
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+
+        points.sort_by_key(|point| point.ticks);
+        points
+    }
+
     pub fn sf2_tracks(&self) -> impl Iterator<Item = &LmmsTrack> {
+        self.song.track_container.tracks.iter().filter(|track| {
+            track
+                .instrument()
+                .is_some_and(|instrument| instrument.sf2_player.is_some())
+        })
+    }
+
+    /// Tracks using a sample-based player with bank/patch data (SF2 or GIG)
+    pub fn sampler_tracks(&self) -> impl Iterator<Item = &LmmsTrack> {
         self.song
             .track_container
             .tracks
             .iter()
-            .filter(|track| track.instrument_track.instrument.sf2_player.is_some())
+            .filter(|track| track.is_sampler_track())
+    }
+}
+
+impl LmmsPattern {
+    /// Beat (step sequencer) patterns place their notes on a step grid
+    pub fn is_step_pattern(&self) -> bool {
+        self.r#type == LMMS_PATTERN_TYPE_BEAT && self.steps > 0
+    }
+
+    /// Start and length of a note relative to the pattern, in ticks of the given
+    /// timebase. Notes of step patterns are snapped to their step and last one
+    /// step unless they were stretched; notes past the last step are not played.
+    pub fn note_span(&self, lmms_note: &LmmsNote, ticks_per_bar: usize) -> Option<(usize, usize)> {
+        if !self.is_step_pattern() {
+            return Some((lmms_note.position, lmms_note.length.max(0) as usize));
+        }
+
+        let step_length = ticks_per_bar / LMMS_STEPS_PER_BAR;
+        let step = (lmms_note.position + step_length / 2) / step_length;
+
+        if step >= self.steps {
+            return None;
+        }
+
+        let length = if lmms_note.length > 0 {
+            lmms_note.length as usize
+        } else {
+            step_length
+        };
+
+        Some((step * step_length, length))
     }
 }
 
 impl LmmsTrack {
-    pub fn sf2_player(&self) -> &LmmsSf2Player {
+    /// Instrument settings, missing from tracks of other types and from
+    /// malformed instrument tracks
+    pub fn instrument_track(&self) -> Option<&LmmsInstrumentTrack> {
+        self.instrument_track.as_ref()
+    }
+
+    pub fn instrument(&self) -> Option<&LmmsInstrument> {
         self.instrument_track
-            .instrument
-            .sf2_player
             .as_ref()
-            .expect("Not an SF2 track")
+            .map(|instrument_track| &instrument_track.instrument)
+    }
+
+    /// Name of the track as LMMS lists it, unnamed tracks by their instrument
+    pub fn display_name(&self) -> &str {
+        if self.name.is_empty() {
+            self.instrument().map_or("", |instrument| &instrument.name)
+        } else {
+            &self.name
+        }
+    }
+
+    pub fn sf2_player(&self) -> Option<&LmmsSf2Player> {
+        self.instrument()
+            .and_then(|instrument| instrument.sf2_player.as_ref())
+    }
+
+    pub fn is_sampler_track(&self) -> bool {
+        self.instrument().is_some_and(|instrument| {
+            instrument.sf2_player.is_some() || instrument.gig_player.is_some()
+        })
+    }
+
+    pub fn is_opulenz_track(&self) -> bool {
+        self.instrument()
+            .is_some_and(|instrument| instrument.opulenz.is_some())
+    }
+
+    pub fn is_vestige_track(&self) -> bool {
+        self.instrument()
+            .is_some_and(|instrument| instrument.vestige.is_some())
+    }
+
+    /// File name of the VST plugin hosted by a VeSTige track
+    pub fn vst_plugin_name(&self) -> Option<&str> {
+        let vestige = self.instrument()?.vestige.as_ref()?;
+        vestige.plugin.rsplit(['/', '\\']).next()
+    }
+
+    /// File name of the sample played by an AudioFileProcessor track
+    pub fn audio_file_name(&self) -> Option<&str> {
+        let audio_file_processor = self.instrument()?.audio_file_processor.as_ref()?;
+        audio_file_processor.src.rsplit(['/', '\\']).next()
+    }
+
+    /// Sample file (.sf2, .gig) used by the track's player, none for tracks
+    /// other than sampler tracks
+    pub fn sample_src(&self) -> Option<&str> {
+        let instrument = self.instrument()?;
+
+        match (&instrument.sf2_player, &instrument.gig_player) {
+            (Some(sf2_player), _) => Some(&sf2_player.src),
+            (None, Some(gig_player)) => Some(&gig_player.src),
+            (None, None) => None,
+        }
+    }
+
+    pub fn bank(&self) -> Option<usize> {
+        let instrument = self.instrument()?;
+
+        match (&instrument.sf2_player, &instrument.gig_player) {
+            (Some(sf2_player), _) => Some(sf2_player.bank()),
+            (None, Some(gig_player)) => Some(gig_player.bank),
+            (None, None) => None,
+        }
+    }
+
+    pub fn patch(&self) -> Option<usize> {
+        let instrument = self.instrument()?;
+
+        match (&instrument.sf2_player, &instrument.gig_player) {
+            (Some(sf2_player), _) => Some(sf2_player.patch()),
+            (None, Some(gig_player)) => Some(gig_player.patch),
+            (None, None) => None,
+        }
     }
 
     pub fn is_instrument_track(&self) -> bool {
-        self.sf2_player().bank != 128
+        self.bank().is_some_and(|bank| bank != 128)
     }
 
     pub fn is_precussion_track(&self) -> bool {
-        self.sf2_player().bank == 128
+        self.bank() == Some(128)
+    }
+}
+
+/// A pattern whose notes could only be partially read
+#[derive(Debug, Clone)]
+struct LmmsPartialPattern {
+    track_name: String,
+    pattern_name: String,
+    position: String,
+    recovered_notes: usize,
+}
+
+/// Value of an attribute in the text of an XML start tag
+fn xml_attr<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let value_start = tag.find(&format!(" {name}=\""))? + name.len() + 3;
+    let value_len = tag[value_start..].find('"')?;
+    Some(&tag[value_start..value_start + value_len])
+}
+
+/// Cuts the note list of every pattern at its first unreadable note, so a
+/// single corrupt element does not fail the whole project
+fn recover_patterns(xml: &str) -> (String, Vec<LmmsPartialPattern>) {
+    let mut recovered_xml = String::with_capacity(xml.len());
+    let mut partial_patterns = Vec::new();
+    let mut cursor = 0;
+
+    while let Some(pattern_offset) = xml[cursor..].find("<pattern ") {
+        let pattern_start = cursor + pattern_offset;
+
+        let Some(tag_len) = xml[pattern_start..].find('>') else {
+            break;
+        };
+
+        let children_start = pattern_start + tag_len + 1;
+        let pattern_tag = &xml[pattern_start..children_start];
+
+        recovered_xml.push_str(&xml[cursor..children_start]);
+        cursor = children_start;
+
+        if pattern_tag.ends_with("/>") {
+            continue;
+        }
+
+        let Some(pattern_end) = xml[children_start..]
+            .find("</pattern>")
+            .map(|offset| children_start + offset)
+        else {
+            break;
+        };
+
+        let mut recovered_notes = 0;
+        let mut partial = false;
+
+        while cursor < pattern_end {
+            let Some(note_start) = xml[cursor..pattern_end]
+                .find('<')
+                .map(|offset| cursor + offset)
+            else {
+                break;
+            };
+
+            let note_end = if xml[note_start..].starts_with("<note ") {
+                let tag_end = xml[note_start..pattern_end]
+                    .find('>')
+                    .map(|offset| note_start + offset + 1);
+
+                match tag_end {
+                    Some(tag_end) if xml[..tag_end].ends_with("/>") => Some(tag_end),
+                    Some(tag_end) => xml[tag_end..pattern_end]
+                        .find("</note>")
+                        .map(|offset| tag_end + offset + "</note>".len()),
+                    None => None,
+                }
+            } else {
+                None
+            };
+
+            match note_end {
+                Some(note_end) if LmmsNote::from_str(&xml[note_start..note_end]).is_ok() => {
+                    recovered_xml.push_str(&xml[cursor..note_end]);
+                    recovered_notes += 1;
+                    cursor = note_end;
+                }
+                _ => {
+                    partial = true;
+                    break;
+                }
+            }
+        }
+
+        if partial {
+            let track_name = xml[..pattern_start]
+                .rfind("<track ")
+                .and_then(|track_start| xml_attr(&xml[track_start..pattern_start], "name"))
+                .unwrap_or_default();
+
+            partial_patterns.push(LmmsPartialPattern {
+                track_name: track_name.to_owned(),
+                pattern_name: xml_attr(pattern_tag, "name").unwrap_or_default().to_owned(),
+                position: xml_attr(pattern_tag, "pos").unwrap_or_default().to_owned(),
+                recovered_notes,
+            });
+        }
+
+        cursor = pattern_end;
     }
+
+    recovered_xml.push_str(&xml[cursor..]);
+    (recovered_xml, partial_patterns)
 }
@@ -0,0 +1,125 @@
+use midly::num::u4;
+
+use crate::convert::ChannelAssignment;
+use crate::lmms_model::{LmmsPattern, LmmsProject, LmmsTrack};
+
+/// The LMMS track and pattern a converted note comes from
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SourceLocation<'a> {
+    pub track: Option<&'a LmmsTrack>,
+    pub pattern: Option<&'a LmmsPattern>,
+}
+
+impl<'a> SourceLocation<'a> {
+    pub fn track_name(&self) -> Option<&'a str> {
+        self.track.map(|lmms_track| lmms_track.name.as_str())
+    }
+
+    /// Name of the pattern, patterns LMMS shows with the track name have none
+    pub fn pattern_name(&self) -> Option<&'a str> {
+        self.pattern
+            .map(|lmms_pattern| lmms_pattern.name.as_str())
+            .filter(|pattern_name| !pattern_name.is_empty())
+    }
+}
+
+/// Finds where converted events come from in the LMMS project, so that
+/// diagnostics point at the notes to fix in LMMS
+pub struct SourceLocator<'a> {
+    lmms_project: &'a LmmsProject,
+    channel_assignments: Vec<&'a ChannelAssignment>,
+
+    /// Ticks the converted events were shifted back by `--range` and
+    /// `--trim-start`, added to find them in the project
+    source_offset: isize,
+}
+
+impl<'a> SourceLocator<'a> {
+    pub fn new(
+        lmms_project: &'a LmmsProject,
+        channel_assignments: impl IntoIterator<Item = &'a ChannelAssignment>,
+        source_offset: isize,
+    ) -> Self {
+        SourceLocator {
+            lmms_project,
+            channel_assignments: channel_assignments.into_iter().collect(),
+            source_offset,
+        }
+    }
+
+    /// Position of converted ticks in the project
+    pub fn source_ticks(&self, ticks: usize) -> usize {
+        ticks.saturating_add_signed(self.source_offset)
+    }
+
+    /// Position of converted ticks as bar:beat of the project
+    pub fn position(&self, ticks: usize) -> String {
+        self.lmms_project.musical_position(self.source_ticks(ticks))
+    }
+
+    /// Track and pattern of a note sounding on a MIDI channel at the given
+    /// converted ticks. Notes starting there win over notes held there, and
+    /// those over patterns merely spanning the position.
+    pub fn locate(&self, midi_channel: u4, ticks: usize) -> SourceLocation<'a> {
+        let ticks = self.source_ticks(ticks);
+        let ticks_per_bar = self.lmms_project.ticks_per_bar();
+
+        let lmms_tracks = self
+            .channel_assignments
+            .iter()
+            .filter(|channel_assignment| channel_assignment.midi_channels.contains(&midi_channel))
+            .map(|channel_assignment| channel_assignment.lmms_track(self.lmms_project))
+            .collect::<Vec<_>>();
+
+        let mut best_location = SourceLocation {
+            track: lmms_tracks.first().copied(),
+            pattern: None,
+        };
+        let mut best_rank = 0;
+
+        for lmms_track in lmms_tracks {
+            for lmms_pattern in &lmms_track.patterns {
+                let note_spans = lmms_pattern.notes.iter().filter_map(|lmms_note| {
+                    let (note_position, note_length) =
+                        lmms_pattern.note_span(lmms_note, ticks_per_bar)?;
+                    let start = lmms_pattern.position + note_position;
+
+                    Some((
+                        self.lmms_project.to_lmms_ticks(start),
+                        self.lmms_project.to_lmms_ticks(start + note_length),
+                    ))
+                });
+
+                let mut pattern_end = self.lmms_project.to_lmms_ticks(lmms_pattern.position);
+                let mut rank = 0;
+
+                for (start, end) in note_spans {
+                    pattern_end = pattern_end.max(end);
+
+                    if start == ticks {
+                        rank = rank.max(3);
+                    } else if (start..end).contains(&ticks) {
+                        rank = rank.max(2);
+                    }
+                }
+
+                if rank == 0
+                    && (self.lmms_project.to_lmms_ticks(lmms_pattern.position)..pattern_end)
+                        .contains(&ticks)
+                {
+                    rank = 1;
+                }
+
+                if rank > best_rank {
+                    best_rank = rank;
+                    best_location = SourceLocation {
+                        track: Some(lmms_track),
+                        pattern: Some(lmms_pattern),
+                    };
+                }
+            }
+        }
+
+        best_location
+    }
+}
@@ -0,0 +1,468 @@
+use std::env;
+use std::fs::File;
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::dump::note_name;
+use crate::progress;
+
+/// Severity of a diagnostic, messages more detailed than the verbosity are
+/// not printed
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    /// Failures, always printed
+    Error,
+
+    /// Problems of the project or the output, and the notes following them
+    Warning,
+
+    /// Steps of the conversion pipeline (`-v`)
+    Info,
+
+    /// Decisions of the conversion pipeline, track by track (`-vv`)
+    Debug,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum DiagnosticsFormat {
+    /// Lines of text, e.g. `warning[note-overlap]: note overlap on MIDI channel 1`
+    #[default]
+    Text,
+
+    /// A JSON object per line with the level, code, message, tick, track and
+    /// pattern of the diagnostic
+    Json,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    /// Color the diagnostics printed on a terminal, unless `NO_COLOR` is set
+    #[default]
+    Auto,
+
+    /// Color the diagnostics, also when written to a file or a pipe
+    Always,
+
+    /// Never color the diagnostics
+    Never,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum StrictClass {
+    /// Excessive polyphony on a MIDI port or channel
+    Polyphony,
+
+    /// Overlapping notes of the same key on the same channel
+    Overlap,
+
+    /// Tracks dropped for lack of an instrument, drum key or MIDI channel
+    DroppedTracks,
+
+    /// Track names and texts the output encoding mishandles
+    NonAscii,
+
+    /// Every warning
+    All,
+}
+
+impl StrictClass {
+    /// Whether a warning of the given code belongs to the class
+    fn contains(self, code: Option<&str>) -> bool {
+        let codes: &[&str] = match self {
+            StrictClass::Polyphony => &["polyphony"],
+            StrictClass::Overlap => &["note-overlap"],
+            StrictClass::DroppedTracks => {
+                &["dropped-track", "unknown-drum-key", "channel-overflow"]
+            }
+            StrictClass::NonAscii => &["non-ascii-name", "text-encoding"],
+            StrictClass::All => return true,
+        };
+
+        code.is_some_and(|code| codes.contains(&code))
+    }
+}
+
+/// A message of the conversion, with the source location it concerns where
+/// known. Notes follow the warning or error they explain.
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub level: &'static str,
+    pub code: Option<&'static str>,
+    pub message: String,
+    pub tick: Option<usize>,
+
+    /// Musical position of the tick in the project, as bar:beat
+    pub position: Option<String>,
+
+    pub track: Option<String>,
+    pub pattern: Option<String>,
+    pub key: Option<u8>,
+}
+
+impl Diagnostic {
+    pub fn new(level: &'static str, message: String) -> Self {
+        Diagnostic {
+            level,
+            code: None,
+            message,
+            tick: None,
+            position: None,
+            track: None,
+            pattern: None,
+            key: None,
+        }
+    }
+
+    /// Where the diagnostic points in the project, e.g. "track 'Bass',
+    /// pattern 'Verse', bar 18:2 (tick 3312), key C4"
+    fn location(&self) -> Option<String> {
+        // Track names alone are part of the messages already
+        if self.tick.is_none() && self.pattern.is_none() && self.key.is_none() {
+            return None;
+        }
+
+        let mut parts = Vec::new();
+
+        if let Some(ref track) = self.track {
+            parts.push(format!("track '{}'", track.escape_default()));
+        }
+
+        if let Some(ref pattern) = self.pattern {
+            parts.push(format!("pattern '{}'", pattern.escape_default()));
+        }
+
+        match (&self.position, self.tick) {
+            (Some(position), Some(tick)) => parts.push(format!("bar {position} (tick {tick})")),
+            (Some(position), None) => parts.push(format!("bar {position}")),
+            (None, Some(tick)) => parts.push(format!("tick {tick}")),
+            (None, None) => {}
+        }
+
+        if let Some(key) = self.key {
+            parts.push(format!("key {}", note_name(key)));
+        }
+
+        Some(parts.join(", "))
+    }
+
+    /// The diagnostic in the style of rustc, e.g.
+    ///
+    /// ```text
+    /// warning[note-overlap]: note overlap on MIDI channel 1
+    ///   --> track 'Bass', bar 18:2 (tick 3312), key C4
+    /// ```
+    ///
+    /// Notes following a warning or an error are shown as `= note:` lines of it.
+    fn to_text(&self, is_follow_up: bool, is_colored: bool) -> String {
+        let paint = |text: &str, style: &str| {
+            if is_colored {
+                format!("\x1b[{style}m{text}\x1b[0m")
+            } else {
+                text.to_owned()
+            }
+        };
+
+        if is_follow_up {
+            return format!(
+                "  {} {}: {}",
+                paint("=", STYLE_GUTTER),
+                paint(self.level, STYLE_BOLD),
+                self.message
+            );
+        }
+
+        let label = match self.code {
+            Some(code) => format!("{}[{code}]", self.level),
+            None => self.level.to_owned(),
+        };
+
+        let label_style = match self.level {
+            "error" => STYLE_ERROR,
+            "warning" => STYLE_WARNING,
+            "note" => STYLE_NOTE,
+            "info" => STYLE_INFO,
+            _ => STYLE_DEBUG,
+        };
+
+        let mut text = format!(
+            "{}{}",
+            paint(&label, label_style),
+            paint(&format!(": {}", self.message), STYLE_BOLD)
+        );
+
+        if let Some(location) = self.location() {
+            text.push_str(&format!("\n  {} {location}", paint("-->", STYLE_GUTTER)));
+        }
+
+        text
+    }
+
+    /// The diagnostic as a single-line JSON object, absent fields being null
+    fn to_json(&self) -> String {
+        // Strings and numbers always serialize
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+/// Values the diagnostic macros accept for a field, optional ones included
+pub trait DiagnosticField<T> {
+    fn into_field(self) -> Option<T>;
+}
+
+impl<T> DiagnosticField<T> for Option<T> {
+    fn into_field(self) -> Option<T> {
+        self
+    }
+}
+
+impl DiagnosticField<String> for Option<&str> {
+    fn into_field(self) -> Option<String> {
+        self.map(str::to_owned)
+    }
+}
+
+impl DiagnosticField<&'static str> for &'static str {
+    fn into_field(self) -> Option<&'static str> {
+        Some(self)
+    }
+}
+
+impl DiagnosticField<String> for &str {
+    fn into_field(self) -> Option<String> {
+        Some(self.to_owned())
+    }
+}
+
+impl DiagnosticField<String> for &String {
+    fn into_field(self) -> Option<String> {
+        Some(self.clone())
+    }
+}
+
+impl DiagnosticField<String> for String {
+    fn into_field(self) -> Option<String> {
+        Some(self)
+    }
+}
+
+impl DiagnosticField<usize> for usize {
+    fn into_field(self) -> Option<usize> {
+        Some(self)
+    }
+}
+
+impl DiagnosticField<u8> for u8 {
+    fn into_field(self) -> Option<u8> {
+        Some(self)
+    }
+}
+
+// SGR parameters of the parts of colored diagnostics, like rustc uses them
+const STYLE_ERROR: &str = "1;31";
+const STYLE_WARNING: &str = "1;33";
+const STYLE_NOTE: &str = "1;32";
+const STYLE_INFO: &str = "1;36";
+const STYLE_DEBUG: &str = "2";
+const STYLE_GUTTER: &str = "1;34";
+const STYLE_BOLD: &str = "1";
+
+/// Where and how diagnostics are written
+struct DiagnosticsOutput {
+    format: DiagnosticsFormat,
+    file: Option<File>,
+    is_colored: bool,
+
+    /// Whether the last diagnostic was a warning or an error, or a note
+    /// following one, for notes to follow up on
+    follows_problem: bool,
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(LogLevel::Warning as u8);
+
+// Warning classes treated as errors, and the number of warnings turned into errors
+static STRICT_CLASSES: Mutex<Vec<StrictClass>> = Mutex::new(Vec::new());
+static STRICT_ERROR_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+static WARNING_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+static DIAGNOSTICS_OUTPUT: Mutex<DiagnosticsOutput> = Mutex::new(DiagnosticsOutput {
+    format: DiagnosticsFormat::Text,
+    file: None,
+    is_colored: false,
+    follows_problem: false,
+});
+
+/// Sets the most detailed level printed, from the `-q` and `-v` flags
+pub fn set_verbosity(quiet: bool, verbose_count: u8) {
+    let log_level = match (quiet, verbose_count) {
+        (true, _) => LogLevel::Error,
+        (false, 0) => LogLevel::Warning,
+        (false, 1) => LogLevel::Info,
+        (false, _) => LogLevel::Debug,
+    };
+
+    VERBOSITY.store(log_level as u8, Ordering::Relaxed);
+}
+
+/// Sets the format and the colors of the diagnostics, and the file they are
+/// written to instead of stderr
+pub fn set_diagnostics_output(
+    format: DiagnosticsFormat,
+    file_path: Option<&Path>,
+    color: ColorChoice,
+) -> io::Result<()> {
+    let file = file_path.map(File::create).transpose()?;
+
+    let is_colored = match color {
+        ColorChoice::Auto => {
+            file.is_none() && io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none()
+        }
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+    };
+
+    *DIAGNOSTICS_OUTPUT.lock().unwrap() = DiagnosticsOutput {
+        format,
+        file,
+        is_colored,
+        follows_problem: false,
+    };
+    Ok(())
+}
+
+/// Sets the warning classes treated as errors with `--strict`, and clears the
+/// count of warnings turned into errors, e.g. before converting a project
+pub fn set_strict_classes(strict_classes: &[StrictClass]) {
+    *STRICT_CLASSES.lock().unwrap() = strict_classes.to_vec();
+    STRICT_ERROR_COUNT.store(0, Ordering::Relaxed);
+}
+
+/// Number of warnings turned into errors since the strict classes were set
+pub fn strict_error_count() -> usize {
+    STRICT_ERROR_COUNT.load(Ordering::Relaxed)
+}
+
+/// Number of warnings emitted so far, printed or not
+pub fn warning_count() -> usize {
+    WARNING_COUNT.load(Ordering::Relaxed)
+}
+
+/// Whether messages of a level are printed
+pub fn log_enabled(log_level: LogLevel) -> bool {
+    log_level as u8 <= VERBOSITY.load(Ordering::Relaxed)
+}
+
+/// Writes a diagnostic to stderr or the diagnostics file
+pub fn emit(mut diagnostic: Diagnostic) {
+    if diagnostic.level == "warning" {
+        WARNING_COUNT.fetch_add(1, Ordering::Relaxed);
+    }
+
+    if diagnostic.level == "warning"
+        && STRICT_CLASSES
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|strict_class| strict_class.contains(diagnostic.code))
+    {
+        diagnostic.level = "error";
+        STRICT_ERROR_COUNT.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Warnings are emitted with `-q` too, to be counted and made strict
+    if diagnostic.level == "warning" && !log_enabled(LogLevel::Warning) {
+        return;
+    }
+
+    let mut diagnostics_output = DIAGNOSTICS_OUTPUT.lock().unwrap();
+
+    let is_follow_up = diagnostic.level == "note" && diagnostics_output.follows_problem;
+    diagnostics_output.follows_problem =
+        is_follow_up || matches!(diagnostic.level, "error" | "warning");
+
+    let line = match diagnostics_output.format {
+        DiagnosticsFormat::Text => diagnostic.to_text(is_follow_up, diagnostics_output.is_colored),
+        DiagnosticsFormat::Json => diagnostic.to_json(),
+    };
+
+    // Diagnostics are best effort, failing to write them fails no conversion
+    match diagnostics_output.file {
+        Some(ref mut file) => {
+            let _ = writeln!(file, "{line}");
+        }
+        None => {
+            progress::clear();
+            let _ = writeln!(io::stderr(), "{line}");
+        }
+    }
+}
+
+/// Emits a diagnostic of a level, with optional `code`, `tick`, `position`,
+/// `track`, `pattern` and `key` fields before a `;` and the message:
+///
+/// ```ignore
+/// warning!(code: "note-overlap", tick: event.ticks; "note overlap at {}", event.ticks);
+/// ```
+#[doc(hidden)]
+#[macro_export]
+macro_rules! diagnostic {
+    ($log_level:ident, $level:literal, $($field:ident: $value:expr),+; $($arg:tt)*) => {
+        if $crate::log::log_enabled($crate::log::LogLevel::$log_level) || $level == "warning" {
+            $crate::log::emit($crate::log::Diagnostic {
+                $($field: $crate::log::DiagnosticField::into_field($value),)+
+                ..$crate::log::Diagnostic::new($level, format!($($arg)*))
+            });
+        }
+    };
+    ($log_level:ident, $level:literal, $($arg:tt)*) => {
+        if $crate::log::log_enabled($crate::log::LogLevel::$log_level) || $level == "warning" {
+            $crate::log::emit($crate::log::Diagnostic::new($level, format!($($arg)*)));
+        }
+    };
+}
+
+/// Prints an error, regardless of the verbosity
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => {
+        $crate::diagnostic!(Error, "error", $($arg)*)
+    };
+}
+
+/// Prints a warning, unless `-q` is given
+#[macro_export]
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        $crate::diagnostic!(Warning, "warning", $($arg)*)
+    };
+}
+
+/// Prints a note following a warning or summarizing a repair, unless `-q` is
+/// given
+#[macro_export]
+macro_rules! note {
+    ($($arg:tt)*) => {
+        $crate::diagnostic!(Warning, "note", $($arg)*)
+    };
+}
+
+/// Prints a step of the conversion with `-v`
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => {
+        $crate::diagnostic!(Info, "info", $($arg)*)
+    };
+}
+
+/// Prints a trace of the conversion with `-vv`
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        $crate::diagnostic!(Debug, "debug", $($arg)*)
+    };
+}
@@ -0,0 +1,188 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+// Timed lyric lines of an LRC file, other ID tags than the offset, title and
+// artist are ignored. Words can be hyphenated into syllables for karaoke.
+//
+//     [ti:Title]
+//     [ar:Artist]
+//     [offset:+250]
+//     [00:12.00]First line
+//     [00:17.20][01:05.40]Repeated line
+
+#[derive(Debug, Clone, Default)]
+pub struct Lyrics {
+    /// Lines and their start in seconds
+    lines: Vec<(f64, String)>,
+
+    pub title: Option<String>,
+    pub artist: Option<String>,
+}
+
+/// Text event at the start of the first track identifying Soft Karaoke files
+pub const KARAOKE_FILE_ID: &[u8] = b"@KMIDI KARAOKE FILE";
+
+// Longest time a karaoke syllable is highlighted before the next one
+const KARAOKE_MAX_SYLLABLE_SECONDS: f64 = 0.5;
+
+/// Text events of a Soft Karaoke (.kar) file
+#[derive(Debug, Clone, Default)]
+pub struct Karaoke {
+    /// "@" header texts of the words track
+    pub headers: Vec<String>,
+
+    /// Syllables and their positions in LMMS ticks, "\\" starts a paragraph,
+    /// "/" a line and " " a word
+    pub syllables: Vec<(usize, String)>,
+}
+
+impl Lyrics {
+    pub fn load_from_path(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Lyrics::from_lrc(&fs::read_to_string(path)?)
+    }
+
+    pub fn from_lrc(contents: &str) -> Result<Self, Box<dyn Error>> {
+        let mut lyrics = Lyrics::default();
+        let mut offset_seconds = 0.0;
+
+        for (line_index, line) in contents.lines().enumerate() {
+            let mut rest = line.trim();
+            let mut timestamps = Vec::new();
+
+            while let Some(tag) = rest.strip_prefix('[') {
+                let (tag, after_tag) = tag
+                    .split_once(']')
+                    .ok_or_else(|| format!("Unclosed tag in lyrics line {}", line_index + 1))?;
+
+                if let Some(offset) = tag.strip_prefix("offset:") {
+                    let offset_milliseconds = offset
+                        .trim()
+                        .parse::<f64>()
+                        .map_err(|_| format!("Invalid offset in lyrics line {}", line_index + 1))?;
+
+                    // Positive offsets make the lyrics appear sooner
+                    offset_seconds = -offset_milliseconds / 1000.0;
+                } else if let Some(title) = tag.strip_prefix("ti:") {
+                    lyrics.title = Some(title.trim().to_owned());
+                } else if let Some(artist) = tag.strip_prefix("ar:") {
+                    lyrics.artist = Some(artist.trim().to_owned());
+                } else if let Some(seconds) = parse_timestamp(tag) {
+                    timestamps.push(seconds);
+                }
+
+                rest = after_tag;
+            }
+
+            let text = rest.trim();
+
+            if text.is_empty() {
+                continue;
+            }
+
+            for seconds in timestamps {
+                lyrics.lines.push((seconds, text.to_owned()));
+            }
+        }
+
+        for (seconds, _) in &mut lyrics.lines {
+            *seconds = (*seconds + offset_seconds).max(0.0);
+        }
+
+        lyrics.lines.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+        Ok(lyrics)
+    }
+
+    /// Lines and their positions in LMMS ticks at the given tempo, the
+    /// hyphens of syllables are removed
+    pub fn to_lmms_ticks(&self, bpm: f32, lmms_ticks_per_beat: usize) -> Vec<(usize, String)> {
+        self.lines
+            .iter()
+            .map(|(seconds, text)| {
+                (
+                    seconds_to_lmms_ticks(*seconds, bpm, lmms_ticks_per_beat),
+                    text.replace('-', ""),
+                )
+            })
+            .collect()
+    }
+
+    /// Splits the lines into syllables spread over the time until the next line
+    pub fn to_karaoke(&self, bpm: f32, lmms_ticks_per_beat: usize) -> Karaoke {
+        let mut karaoke = Karaoke {
+            headers: vec!["@LENGL".to_owned()],
+            syllables: Vec::new(),
+        };
+
+        karaoke
+            .headers
+            .extend(self.title.iter().map(|title| format!("@T{title}")));
+        karaoke
+            .headers
+            .extend(self.artist.iter().map(|artist| format!("@T{artist}")));
+
+        for (line_index, (seconds, text)) in self.lines.iter().enumerate() {
+            let syllables = text
+                .split_whitespace()
+                .enumerate()
+                .flat_map(|(word_index, word)| {
+                    word.split('-')
+                        .filter(|syllable| !syllable.is_empty())
+                        .enumerate()
+                        .map(
+                            move |(syllable_index, syllable)| match (word_index, syllable_index) {
+                                (0, 0) if line_index == 0 => format!("\\{syllable}"),
+                                (0, 0) => format!("/{syllable}"),
+                                (_, 0) => format!(" {syllable}"),
+                                _ => syllable.to_owned(),
+                            },
+                        )
+                })
+                .collect::<Vec<_>>();
+
+            let seconds_end = self
+                .lines
+                .get(line_index + 1)
+                .map_or(f64::INFINITY, |(seconds_next, _)| *seconds_next);
+
+            let syllable_seconds = ((seconds_end - seconds) / syllables.len() as f64)
+                .min(KARAOKE_MAX_SYLLABLE_SECONDS);
+
+            for (syllable_index, syllable) in syllables.into_iter().enumerate() {
+                let seconds = seconds + syllable_index as f64 * syllable_seconds;
+
+                karaoke.syllables.push((
+                    seconds_to_lmms_ticks(seconds, bpm, lmms_ticks_per_beat),
+                    syllable,
+                ));
+            }
+        }
+
+        karaoke
+    }
+}
+
+fn seconds_to_lmms_ticks(seconds: f64, bpm: f32, lmms_ticks_per_beat: usize) -> usize {
+    (seconds * bpm as f64 / 60.0 * lmms_ticks_per_beat as f64).round() as usize
+}
+
+/// Parses "mm:ss", "mm:ss.xx" or "mm:ss:xx" timestamps into seconds
+fn parse_timestamp(s: &str) -> Option<f64> {
+    let (minutes, seconds) = s.split_once(':')?;
+    let minutes = minutes.trim().parse::<u32>().ok()?;
+
+    let seconds = match seconds.split_once(':') {
+        Some((seconds, hundredths)) => {
+            seconds.trim().parse::<u32>().ok()? as f64
+                + hundredths.trim().parse::<u32>().ok()? as f64 / 100.0
+        }
+        None => seconds.trim().parse::<f64>().ok()?,
+    };
+
+    Some(minutes as f64 * 60.0 + seconds)
+}
+
+/// Command line value parser for LRC lyrics files
+pub fn parse_lyrics(s: &str) -> Result<Lyrics, String> {
+    Lyrics::load_from_path(Path::new(s)).map_err(|err| err.to_string())
+}
@@ -1,569 +1,1240 @@
-use std::collections::HashMap;
-use std::ops::RangeInclusive;
-use std::path::PathBuf;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+use clap::error::ErrorKind;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+use clap_complete::Shell;
+use clap_mangen::Man;
+use lmms2mid::convert::{Conversion, ConvertOptions, OutputFormat};
+use lmms2mid::dump::dump;
+use lmms2mid::error::ConvertError;
+use lmms2mid::exit_code::{exit_status_help, ExitCode};
+use lmms2mid::fixture::{generate_fixture, FixtureOptions};
+use lmms2mid::lmms_model::{compress_project, decompress_project, LmmsProject};
+use lmms2mid::log::{ColorChoice, DiagnosticsFormat};
+use lmms2mid::progress::Phase;
+use lmms2mid::{
+    bundle, config, debug, diff, dls, doctor, error, flac, import, info, inspect, log, note, play,
+    preview, progress, render, rmid, tracks, warning,
+};
+use midly::Smf;
 
-mod lmms_model;
-use lmms_model::{LmmsProject, LMMS_TICKS_PER_BAR};
+// How often `--watch` checks the project for changes
+const WATCH_POLL_INTERVAL: Duration = Duration::from_millis(500);
 
-use clap::{Parser, ValueEnum};
-use midly::num::{u15, u24, u28, u4, u7};
-use midly::{
-    Format, Header, MetaMessage, MidiMessage, Smf, Timing, Track, TrackEvent, TrackEventKind,
-};
+// Time given to LMMS to finish saving before converting
+const WATCH_SETTLE_TIME: Duration = Duration::from_millis(200);
 
-const MIDI_CC_BANK_SELECT_COARSE: u8 = 0;
-const MIDI_CC_BANK_SELECT_FINE: u8 = 32;
-const MIDI_CC_VOLUME: u8 = 7;
-const MIDI_CC_PANNING: u8 = 10;
+/// A less broken MIDI-exporter for LMMS
+#[derive(Debug, Parser)]
+#[clap(author, version)]
+#[command(
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
+    after_long_help = exit_status_help()
+)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
 
-const MIDI_CC_EMIDI_LOCAL_LOOP_START: u8 = 116;
-const MIDI_CC_EMIDI_LOCAL_LOOP_END: u8 = 117;
+    /// Print only errors, no warnings or notes
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
 
-const MIDI_CC_EMIDI_GLOBAL_LOOP_START: u8 = 118;
-const MIDI_CC_EMIDI_GLOBAL_LOOP_END: u8 = 119;
+    /// Print the steps of the conversion (`-v`) and the decisions made for
+    /// every track (`-vv`)
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
 
-const MIDI_CC_RPG_LOOP_START: u8 = 111;
+    /// Format of the warnings and errors, `json` for build pipelines
+    #[arg(long, global = true, value_enum, default_value_t)]
+    diagnostics_format: DiagnosticsFormat,
 
-const MIDI_MAX_POLYPHONY: usize = 24;
+    /// Write the warnings and errors to a file instead of stderr
+    #[arg(long, global = true)]
+    diagnostics_file: Option<PathBuf>,
 
-#[derive(Debug, Copy, Clone, ValueEnum)]
-enum MidiLoopStyle {
-    ///RPG Maker style loops with MIDI CC#111 events
-    RpgMaker,
+    /// When to color the warnings and errors
+    #[arg(long, global = true, value_enum, default_value_t)]
+    color: ColorChoice,
 
-    /// EMIDI style loops with MIDI CC#116/117 events
-    EmidiLocal,
+    /// Input LMMS project file (.mmp, .mmpz), `-` reads it from stdin
+    #[arg(required = true)]
+    input_path: Option<PathBuf>,
 
-    /// EMIDI style loops with MIDI CC#118/119 events
-    EmidiGlobal,
+    /// Output MIDI file (.mid), `-` writes it to stdout. Named after the
+    /// input file with the extension of the output format if not given.
+    output_path: Option<PathBuf>,
 
-    /// Final Fantasy style loops with "loopstart"/"loopend" MIDI marker events
-    FinalFantasy,
-}
+    /// Directory the output file named after the input file is saved to
+    #[arg(long, conflicts_with = "output_path")]
+    out_dir: Option<PathBuf>,
 
-/// A less broken MIDI-exporter for LMMS
-#[derive(Debug, Parser)]
-#[clap(author, version)]
-struct Args {
-    /// Input LMMS project file (.mmp, .mmpz)
-    input_path: PathBuf,
+    /// Overwrite the output file if it already exists
+    #[arg(long)]
+    force: bool,
 
-    /// Output MIDI file (.mid)
-    output_path: PathBuf,
+    #[command(flatten)]
+    convert_options: ConvertOptions,
 
-    /// Loop style
+    /// Embed the project's SF2 presets, converted to a DLS collection, in the
+    /// output file (requires `--output-format rmid`)
     #[arg(long)]
-    loop_style: Vec<MidiLoopStyle>,
+    embed_dls: bool,
 
-    /// Track name
+    /// Render the converted song with the project's SF2 presets to a WAV or
+    /// FLAC file (by extension), as an audio preview. FLAC files carry the
+    /// loop points as LOOPSTART/LOOPLENGTH tags when loops are converted.
     #[arg(long)]
-    track_name: Option<String>,
+    render_audio: Option<PathBuf>,
 
-    /// Track copyright
+    /// Package the output MIDI file with its soundfonts into a folder or .zip archive
     #[arg(long)]
-    track_copyright: Option<String>,
+    bundle: Option<PathBuf>,
 
-    /// Track comment
+    /// Save the parsed LMMS project as JSON, for scripts reading project data
     #[arg(long)]
-    track_comment: Option<String>,
+    dump_model: Option<PathBuf>,
+
+    /// Report the progress of the conversion phases (parse, convert, sort,
+    /// validate, write), also when stderr is not a terminal
+    #[arg(long)]
+    progress: bool,
+
+    /// Watch the input project and convert it again whenever it is saved,
+    /// including the rendered audio and the bundle
+    #[arg(long)]
+    watch: bool,
+
+    /// Run the whole conversion and report its warnings without writing any
+    /// file, as a quick check of the project
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "output_path",
+            "out_dir",
+            "force",
+            "embed_dls",
+            "render_audio",
+            "bundle",
+            "dump_model",
+            "watch",
+        ]
+    )]
+    check: bool,
+
+    /// Print the manual page in roff format, e.g. for installing as lmms2mid.1
+    #[arg(long, exclusive = true)]
+    generate_man: bool,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Convert multiple projects at once, naming the output files after the
+    /// projects and reporting the result of each one
+    Convert {
+        /// Input LMMS project files (.mmp, .mmpz), directories of projects or
+        /// file name patterns with `*` and `?`
+        #[arg(required = true)]
+        input_paths: Vec<PathBuf>,
+
+        /// Directory the output files are saved to, next to the projects if not given
+        #[arg(long)]
+        out_dir: Option<PathBuf>,
+
+        /// Overwrite output files that already exist
+        #[arg(long)]
+        force: bool,
+
+        /// Report the progress of every project, also when stderr is not a
+        /// terminal
+        #[arg(long)]
+        progress: bool,
+
+        #[command(flatten)]
+        convert_options: Box<ConvertOptions>,
+    },
+
+    /// Print the completion script of a shell for the command line
+    Completions {
+        /// Shell the completion script is for
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+
+    /// Synthesize an LMMS project for testing and bug reports
+    GenFixture {
+        #[command(flatten)]
+        fixture_options: FixtureOptions,
+
+        /// Output LMMS project file (.mmp)
+        output_path: PathBuf,
+    },
+
+    /// Check the project and the environment for problems before converting or filing a bug report
+    Doctor {
+        /// Input LMMS project file (.mmp, .mmpz)
+        input_path: PathBuf,
+
+        /// Output MIDI file (.mid) to check for writability
+        output_path: Option<PathBuf>,
+
+        #[command(flatten)]
+        convert_options: Box<ConvertOptions>,
+    },
+
+    /// Play a project on a MIDI output port (ALSA raw MIDI device), for
+    /// auditioning on hardware synths before writing a file
+    Play {
+        /// Input LMMS project file (.mmp, .mmpz)
+        input_path: PathBuf,
+
+        /// MIDI output port (/dev/snd/midiC*D*), the first one if not given
+        #[arg(long)]
+        port: Option<PathBuf>,
+
+        #[command(flatten)]
+        convert_options: Box<ConvertOptions>,
+    },
+
+    /// Print a readable listing of the events of a MIDI file, or of the MIDI
+    /// events a project converts to
+    Dump {
+        /// Input MIDI file (.mid, .midi) or LMMS project file (.mmp, .mmpz)
+        input_path: PathBuf,
+
+        #[command(flatten)]
+        convert_options: Box<ConvertOptions>,
+    },
+
+    /// Compare two MIDI files note by note and event by event, exiting with
+    /// status 1 when they differ
+    Diff {
+        /// MIDI file before the change (.mid)
+        a_path: PathBuf,
+
+        /// MIDI file after the change (.mid)
+        b_path: PathBuf,
+    },
+
+    /// Print an overview of a project before converting it: tracks by type,
+    /// note counts, length, tempo, polyphony over time, MIDI channel usage and
+    /// the soundfonts it references
+    Inspect {
+        /// Input LMMS project file (.mmp, .mmpz)
+        input_path: PathBuf,
+
+        #[command(flatten)]
+        convert_options: Box<ConvertOptions>,
+    },
+
+    /// List which MIDI channel, bank and program every LMMS track is converted
+    /// to, and why the others are dropped
+    Tracks {
+        /// Input LMMS project file (.mmp, .mmpz)
+        input_path: PathBuf,
+
+        #[command(flatten)]
+        convert_options: Box<ConvertOptions>,
+    },
+
+    /// Print a text piano roll of the converted notes, a row per key and a
+    /// column per sixteenth note, to check note placement without a DAW
+    Preview {
+        /// Input LMMS project file (.mmp, .mmpz)
+        input_path: PathBuf,
+
+        /// Width of the piano roll in characters, bars wrap to fit
+        #[arg(long, default_value_t = 80)]
+        width: usize,
+
+        #[command(flatten)]
+        convert_options: Box<ConvertOptions>,
+    },
+
+    /// Extract the XML of a compressed LMMS project (.mmpz) for inspecting or
+    /// patching it
+    Unpack {
+        /// Input compressed LMMS project file (.mmpz)
+        input_path: PathBuf,
+
+        /// Output uncompressed LMMS project file (.mmp)
+        output_path: PathBuf,
+    },
+
+    /// Compress the XML of an LMMS project (.mmp) into an .mmpz file LMMS opens
+    Pack {
+        /// Input uncompressed LMMS project file (.mmp)
+        input_path: PathBuf,
+
+        /// Output compressed LMMS project file (.mmpz)
+        output_path: PathBuf,
+    },
+
+    /// Convert a MIDI file into an LMMS project with an SF2 Player track per
+    /// MIDI channel of every MIDI track
+    Import {
+        /// Input MIDI file (.mid)
+        input_path: PathBuf,
+
+        /// Output LMMS project file (.mmp, .mmpz)
+        output_path: PathBuf,
+
+        /// SoundFont the SF2 Player tracks load
+        #[arg(long)]
+        soundfont: Option<PathBuf>,
+    },
 }
 
 // cargo run --release -- test/test.mmpz tmp/test.mid
 
-pub struct AbsoluteTrackEvent<'a> {
-    //// When this event occurs in absolute MIDI ticks
-    pub ticks: usize,
+fn main() {
+    let args = parse_args();
+
+    match args.command {
+        Some(Command::Convert {
+            input_paths,
+            out_dir,
+            force,
+            progress,
+            convert_options,
+        }) => {
+            progress::set_progress(progress, args.quiet);
+
+            let exit_code =
+                convert_batch(&input_paths, out_dir.as_deref(), force, &convert_options);
+
+            process::exit(exit_code.code());
+        }
+        Some(Command::Completions { shell }) => {
+            let mut command = Args::command();
+            let bin_name = command.get_name().to_owned();
+            clap_complete::generate(shell, &mut command, bin_name, &mut io::stdout());
+            return;
+        }
+        Some(Command::GenFixture {
+            fixture_options,
+            output_path,
+        }) => {
+            fs::write(output_path, generate_fixture(&fixture_options))
+                .or_exit("Failed to save output LMMS project file");
+            return;
+        }
+        Some(Command::Doctor {
+            input_path,
+            output_path,
+            convert_options,
+        }) => {
+            let findings = doctor::diagnose(&input_path, output_path.as_deref(), &convert_options);
+            doctor::print_findings(&findings);
+
+            if findings
+                .iter()
+                .any(|finding| finding.severity == doctor::Severity::Error)
+            {
+                process::exit(ExitCode::Failure.code());
+            }
+            return;
+        }
+        Some(Command::Play {
+            input_path,
+            port,
+            convert_options,
+        }) => {
+            let Some(port_path) = port.or_else(|| play::midi_output_ports().into_iter().next())
+            else {
+                error!("no MIDI output port found");
+                process::exit(ExitCode::Failure.code());
+            };
+
+            let lmms_project = LmmsProject::load_from_path(&input_path)
+                .or_exit("Failed to load LMMS project file");
+            let conversion = Conversion::new(&lmms_project, &convert_options)
+                .or_exit("Failed to convert LMMS project");
+
+            play::play(&port_path, &lmms_project, &conversion, &convert_options)
+                .or_exit("Failed to play on MIDI output port");
+            return;
+        }
+        Some(Command::Dump {
+            input_path,
+            convert_options,
+        }) => {
+            let is_midi_file = input_path.extension().is_some_and(|extension| {
+                extension.eq_ignore_ascii_case("mid") || extension.eq_ignore_ascii_case("midi")
+            });
 
-    /// When this event really started (NoteOn for NoteOff events)
-    pub ticks_event_start: usize,
+            if is_midi_file {
+                let midi_bytes = fs::read(&input_path).or_exit("Failed to read input MIDI file");
+                let midi_document =
+                    Smf::parse(&midi_bytes).or_exit("Failed to parse input MIDI file");
+
+                print!("{}", dump(&midi_document));
+            } else {
+                let lmms_project = LmmsProject::load_from_path(&input_path)
+                    .or_exit("Failed to load LMMS project file");
+                let conversion = Conversion::new(&lmms_project, &convert_options)
+                    .or_exit("Failed to convert LMMS project");
+
+                print!(
+                    "{}",
+                    dump(&conversion.to_smf(&lmms_project, &convert_options))
+                );
+            }
+            return;
+        }
+        Some(Command::Diff { a_path, b_path }) => {
+            let a_bytes = fs::read(&a_path).or_exit("Failed to read first MIDI file");
+            let a_document = Smf::parse(&a_bytes).or_exit("Failed to parse first MIDI file");
+            let b_bytes = fs::read(&b_path).or_exit("Failed to read second MIDI file");
+            let b_document = Smf::parse(&b_bytes).or_exit("Failed to parse second MIDI file");
 
-    /// MIDI event data
-    pub kind: TrackEventKind<'a>,
-}
+            let midi_diff = diff::diff(&a_document, &b_document);
+            print!("{}", midi_diff.report);
 
-pub trait TrackEventKindExt {
-    fn is_note_on(&self) -> bool;
-    fn is_note_off(&self) -> bool;
-    fn is_meta_event(&self) -> bool;
-    fn is_cc_event(&self) -> bool;
-}
+            if midi_diff.difference_count > 0 {
+                process::exit(ExitCode::Failure.code());
+            }
+            return;
+        }
+        Some(Command::Inspect {
+            input_path,
+            convert_options,
+        }) => {
+            let lmms_project = LmmsProject::load_from_path(&input_path)
+                .or_exit("Failed to load LMMS project file");
+            let conversion = Conversion::new(&lmms_project, &convert_options)
+                .or_exit("Failed to convert LMMS project");
+
+            print!(
+                "{}",
+                inspect::inspect(&input_path, &lmms_project, &conversion, &convert_options)
+            );
+            return;
+        }
+        Some(Command::Tracks {
+            input_path,
+            convert_options,
+        }) => {
+            let lmms_project = LmmsProject::load_from_path(&input_path)
+                .or_exit("Failed to load LMMS project file");
+
+            print!("{}", tracks::tracks(&lmms_project, &convert_options));
+            return;
+        }
+        Some(Command::Preview {
+            input_path,
+            width,
+            convert_options,
+        }) => {
+            let lmms_project = LmmsProject::load_from_path(&input_path)
+                .or_exit("Failed to load LMMS project file");
+            let conversion = Conversion::new(&lmms_project, &convert_options)
+                .or_exit("Failed to convert LMMS project");
+
+            print!("{}", preview::preview(&lmms_project, &conversion, width));
+            return;
+        }
+        Some(Command::Unpack {
+            input_path,
+            output_path,
+        }) => {
+            let compressed_bin =
+                fs::read(&input_path).or_exit("Failed to read input LMMS project file");
+            let uncompressed_bin = decompress_project(&compressed_bin)
+                .or_exit("Failed to decompress LMMS project file");
+
+            fs::write(output_path, uncompressed_bin)
+                .or_exit("Failed to save output LMMS project file");
+            return;
+        }
+        Some(Command::Pack {
+            input_path,
+            output_path,
+        }) => {
+            let uncompressed_xml =
+                fs::read_to_string(&input_path).or_exit("Failed to read input LMMS project file");
+
+            // Patched XML is packed even when it no longer parses, LMMS may still open it
+            if let Err(err) = LmmsProject::from_str_recovering(&uncompressed_xml) {
+                warning!(
+                    code: "unreadable-project";
+                    "'{}' is not a readable LMMS project ({err})",
+                    input_path.display()
+                );
+                note!("it is packed as is, LMMS may fail to open it");
+            }
+
+            fs::write(output_path, compress_project(uncompressed_xml.as_bytes()))
+                .or_exit("Failed to save output LMMS project file");
+            return;
+        }
+        Some(Command::Import {
+            input_path,
+            output_path,
+            soundfont,
+        }) => {
+            let midi_bytes = fs::read(&input_path).or_exit("Failed to read input MIDI file");
+            let midi_document = Smf::parse(&midi_bytes).or_exit("Failed to parse input MIDI file");
+
+            let soundfont_path = soundfont.map_or(String::new(), |soundfont| {
+                soundfont.to_string_lossy().into_owned()
+            });
 
-impl TrackEventKindExt for TrackEventKind<'_> {
-    fn is_note_on(&self) -> bool {
-        matches!(
-            self,
-            TrackEventKind::Midi {
-                message: MidiMessage::NoteOn { .. },
-                ..
+            if soundfont_path.is_empty() {
+                note!(
+                    "no SoundFont given, select one in the SF2 Player tracks or pass `--soundfont`"
+                );
             }
-        )
+
+            let xml = import::import_midi(&midi_document, &soundfont_path);
+
+            let is_compressed = output_path
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("mmpz"));
+
+            let project_bytes = if is_compressed {
+                compress_project(xml.as_bytes())
+            } else {
+                xml.into_bytes()
+            };
+
+            fs::write(output_path, project_bytes)
+                .or_exit("Failed to save output LMMS project file");
+            return;
+        }
+        None => {}
+    }
+
+    if args.generate_man {
+        Man::new(Args::command())
+            .render(&mut io::stdout())
+            .or_exit("Failed to write the manual page");
+        return;
     }
 
-    fn is_note_off(&self) -> bool {
-        matches!(
-            self,
-            TrackEventKind::Midi {
-                message: MidiMessage::NoteOff { .. },
-                ..
+    progress::set_progress(args.progress, args.quiet);
+
+    let input_path = args.input_path.clone().unwrap_or_else(|| {
+        Args::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "an input LMMS project file is required",
+            )
+            .exit()
+    });
+
+    if args.check {
+        match check(&args, &input_path) {
+            Ok(0) => println!("'{}': no problems found", input_path.display()),
+            Ok(1) => println!("'{}': 1 warning", input_path.display()),
+            Ok(warning_count) => {
+                println!("'{}': {warning_count} warnings", input_path.display())
             }
-        )
+            Err(failure) => {
+                error!("{failure}");
+                process::exit(failure.exit_code.code());
+            }
+        }
+
+        return;
     }
 
-    fn is_meta_event(&self) -> bool {
-        matches!(self, TrackEventKind::Meta { .. })
+    if args.embed_dls && args.convert_options.output_format != OutputFormat::Rmid {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "`--embed-dls` requires `--output-format rmid`",
+            )
+            .exit();
     }
 
-    fn is_cc_event(&self) -> bool {
-        matches!(
-            self,
-            TrackEventKind::Midi {
-                message: MidiMessage::Controller { .. },
-                ..
-            }
-        )
+    let render_format = args.render_audio.as_ref().map(|render_path| {
+        render_path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase)
+            .unwrap_or_default()
+    });
+
+    if let Some("ogg" | "oga") = render_format.as_deref() {
+        Args::command()
+            .error(
+                ErrorKind::InvalidValue,
+                "`--render-audio` has no OGG Vorbis encoder, render to .flac and encode that instead",
+            )
+            .exit();
+    }
+
+    let output_path = match args.output_path.clone() {
+        Some(output_path) => output_path,
+        None if is_stdio(&input_path) => Args::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "an output path is required when reading the project from stdin",
+            )
+            .exit(),
+        None => derived_output_path(
+            &input_path,
+            args.out_dir.as_deref(),
+            args.convert_options.output_format,
+        ),
+    };
+
+    if let Some(ref out_dir) = args.out_dir {
+        if let Err(err) = fs::create_dir_all(out_dir) {
+            error!(
+                "Failed to create output directory '{}': {err}",
+                out_dir.display()
+            );
+            process::exit(ExitCode::Io.code());
+        }
+    }
+
+    // Later conversions of `--watch` overwrite their own output
+    if !args.force && !is_stdio(&output_path) && output_path.exists() {
+        error!(
+            "'{}' already exists, use `--force` to overwrite it",
+            output_path.display()
+        );
+        process::exit(ExitCode::Io.code());
+    }
+
+    if args.watch && is_stdio(&input_path) {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "`--watch` needs an input file, stdin cannot be watched",
+            )
+            .exit();
+    }
+
+    // Both rewrite or read back the saved output file
+    if (args.embed_dls || args.bundle.is_some()) && is_stdio(&output_path) {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "`--embed-dls` and `--bundle` need an output file, not stdout",
+            )
+            .exit();
+    }
+
+    if let Err(failure) = convert(&args, &input_path, &output_path, render_format.as_deref()) {
+        error!("{failure}");
+
+        if !args.watch {
+            process::exit(failure.exit_code.code());
+        }
+    }
+
+    if args.watch {
+        watch(&args, &input_path, &output_path, render_format.as_deref());
     }
 }
 
-pub fn remap_clamp_range(
-    value: f32,
-    range_from: RangeInclusive<f32>,
-    range_to: RangeInclusive<f32>,
-    transfer_fn: fn(f32) -> f32,
-) -> f32 {
-    let t = (value - range_from.start()) / (range_from.end() - range_from.start());
-    range_to.start() + transfer_fn(t.clamp(0.0, 1.0)) * (range_to.end() - range_to.start())
+/// Errors of the subcommands, with the exit code they fail with
+trait ExitStatus: fmt::Display {
+    fn exit_code(&self) -> ExitCode;
 }
 
-fn main() {
-    let args = Args::parse();
-    let lmms_project =
-        LmmsProject::load_from_path(&args.input_path).expect("Failed to load LMMS project file");
-
-    // Sanity check for LMMS instrument/percussion track counts
-    {
-        let lmms_sf2_instrument_track_count = lmms_project
-            .sf2_tracks()
-            .filter(|lmms_track| lmms_track.is_instrument_track())
-            .count();
-
-        if lmms_sf2_instrument_track_count > 15 {
-            eprintln!("warning: LMMS project has more SF2 instrument tracks than available MIDI channels ({lmms_sf2_instrument_track_count}/15)");
-            eprintln!("note: unassignable instrument tracks will be dropped");
-        }
+impl ExitStatus for ConvertError {
+    fn exit_code(&self) -> ExitCode {
+        ConvertError::exit_code(self)
+    }
+}
 
-        let lmms_sf2_percussion_track_count = lmms_project
-            .sf2_tracks()
-            .filter(|lmms_track| lmms_track.is_precussion_track())
-            .count();
+impl ExitStatus for io::Error {
+    fn exit_code(&self) -> ExitCode {
+        ExitCode::Io
+    }
+}
 
-        if lmms_sf2_percussion_track_count > 1 {
-            eprintln!("warning: LMMS project should only have at most one SF2 percussion track (found {lmms_sf2_percussion_track_count} tracks)");
-            eprintln!("note: unassignable percussion tracks will be dropped");
+impl ExitStatus for midly::Error {
+    fn exit_code(&self) -> ExitCode {
+        ExitCode::Parse
+    }
+}
+
+impl ExitStatus for Box<dyn Error> {
+    fn exit_code(&self) -> ExitCode {
+        if let Some(err) = self.downcast_ref::<ConvertError>() {
+            err.exit_code()
+        } else if self.is::<io::Error>() {
+            ExitCode::Io
+        } else {
+            ExitCode::Failure
         }
     }
+}
 
-    // LMMS track -> MIDI channel assignment
-    let lmms_track_midi_channel = {
-        let mut results = Vec::new();
+/// Exiting with the error and its exit code, where the subcommands cannot go
+/// on without the result
+trait OrExit<T> {
+    fn or_exit(self, context: &str) -> T;
+}
 
-        // Instrument tracks
-        results.extend(
-            [0, 1, 2, 3, 4, 5, 6, 7, 8, 10, 11, 12, 13, 14, 15]
-                .into_iter()
-                .map(u4::from)
-                .zip(
-                    lmms_project
-                        .sf2_tracks()
-                        .filter(|lmms_track| lmms_track.is_instrument_track()),
-                ),
-        );
+impl<T, E: ExitStatus> OrExit<T> for Result<T, E> {
+    fn or_exit(self, context: &str) -> T {
+        self.unwrap_or_else(|err| {
+            error!("{context}: {err}");
+            process::exit(err.exit_code().code());
+        })
+    }
+}
 
-        // Percussion track
-        results.extend(
-            [9].into_iter().map(u4::from).zip(
-                lmms_project
-                    .sf2_tracks()
-                    .filter(|lmms_track| lmms_track.is_precussion_track()),
-            ),
-        );
+/// A failed conversion, with the exit code telling scripts what failed
+#[derive(Debug)]
+struct Failure {
+    exit_code: ExitCode,
+    message: String,
+}
+
+impl Failure {
+    fn new(exit_code: ExitCode, message: String) -> Self {
+        Failure { exit_code, message }
+    }
+
+    /// Failure of a step of the conversion, with the exit code of the error
+    fn convert_error(context: &str, err: ConvertError) -> Self {
+        Failure::new(err.exit_code(), format!("{context}: {err}"))
+    }
+}
 
-        results.sort_by_key(|(midi_channel, _lmms_track)| *midi_channel);
-        results
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Loads the input project from a file or from stdin for `-`
+fn load_project(input_path: &Path) -> Result<LmmsProject, Failure> {
+    progress::start_phase(Phase::Parse, None);
+
+    let lmms_project = if is_stdio(input_path) {
+        let mut project_bin = Vec::new();
+        io::stdin().read_to_end(&mut project_bin).map_err(|err| {
+            Failure::new(
+                ExitCode::Io,
+                format!("Failed to read LMMS project from stdin: {err}"),
+            )
+        })?;
+
+        LmmsProject::from_bytes(&project_bin)
+    } else {
+        LmmsProject::load_from_path(input_path)
     };
 
-    let mut midi_document = Smf::new(Header::new(
-        Format::SingleTrack,
-        Timing::Metrical(u15::from((LMMS_TICKS_PER_BAR / 4) as u16)),
-    ));
+    lmms_project.map_err(|err| Failure::convert_error("Failed to load LMMS project file", err))
+}
+
+/// Runs the conversion of the project into the output format without saving
+/// it. Returns the number of warnings.
+fn check(args: &Args, input_path: &Path) -> Result<usize, Failure> {
+    let lmms_project = load_project(input_path)?;
+
+    log::set_strict_classes(&args.convert_options.strict);
+
+    let conversion = Conversion::new(&lmms_project, &args.convert_options)
+        .map_err(|err| Failure::convert_error("Failed to convert LMMS project", err))?;
+
+    // The output is built for the checks of the output format, then dropped
+    conversion
+        .to_bytes(&lmms_project, &args.convert_options)
+        .map_err(|err| Failure::convert_error("Failed to convert LMMS project", err))?;
+
+    progress::finish();
+    check_strict_errors()?;
+
+    Ok(log::warning_count())
+}
+
+/// Converts the project and writes the output file along with the DLS
+/// collection, rendered audio, channel map and bundle requested
+fn convert(
+    args: &Args,
+    input_path: &Path,
+    output_path: &Path,
+    render_format: Option<&str>,
+) -> Result<(), Failure> {
+    info!("loading LMMS project '{}'", input_path.display());
+    let lmms_project = load_project(input_path)?;
+
+    debug!(
+        "LMMS project has {} tracks at {} BPM, {} ticks per bar",
+        lmms_project.song.track_container.tracks.len(),
+        lmms_project.head.bpm,
+        lmms_project.lmms_ticks_per_bar()
+    );
 
-    let mut midi_track = Track::new();
+    if let Some(ref model_path) = args.dump_model {
+        // Objects are named after the model's fields, positions are in the
+        // project's own timebase as stored
+        let save_model = || -> io::Result<()> {
+            let mut model_file = BufWriter::new(File::create(model_path)?);
+            serde_json::to_writer_pretty(&mut model_file, &lmms_project)?;
+            writeln!(model_file)?;
+            model_file.flush()
+        };
 
-    if let Some(ref track_name) = args.track_name {
-        midi_track.push(TrackEvent {
-            delta: u28::from(0),
-            kind: TrackEventKind::Meta(MetaMessage::TrackName(track_name.as_bytes())),
-        });
+        save_model().map_err(|err| {
+            Failure::new(
+                ExitCode::Io,
+                format!("Failed to save project model file: {err}"),
+            )
+        })?;
     }
 
-    if let Some(ref track_copyright) = args.track_copyright {
-        midi_track.push(TrackEvent {
-            delta: u28::from(0),
-            kind: TrackEventKind::Meta(MetaMessage::Copyright(track_copyright.as_bytes())),
-        });
+    log::set_strict_classes(&args.convert_options.strict);
+
+    let conversion = Conversion::new(&lmms_project, &args.convert_options)
+        .map_err(|err| Failure::convert_error("Failed to convert LMMS project", err))?;
+
+    info!("saving the output to '{}'", output_path.display());
+
+    let output_bytes = conversion
+        .to_bytes(&lmms_project, &args.convert_options)
+        .map_err(|err| Failure::convert_error("Failed to convert LMMS project", err))?;
+
+    check_strict_errors()?;
+
+    progress::start_phase(Phase::Write, None);
+    if is_stdio(output_path) {
+        let mut stdout = io::stdout().lock();
+        stdout
+            .write_all(&output_bytes)
+            .and_then(|()| stdout.flush())
+            .map_err(|err| {
+                Failure::new(
+                    ExitCode::Io,
+                    format!("Failed to write output to stdout: {err}"),
+                )
+            })?;
+    } else {
+        fs::write(output_path, output_bytes).map_err(|err| {
+            Failure::new(
+                ExitCode::Io,
+                format!(
+                    "Failed to save output file '{}': {err}",
+                    output_path.display()
+                ),
+            )
+        })?;
     }
+    progress::finish();
+
+    if args.embed_dls {
+        info!("embedding the SF2 presets as a DLS collection");
+        let dls_bytes = dls::soundfont_dls(
+            input_path,
+            &lmms_project,
+            conversion.channel_assignments(),
+            &args.convert_options,
+        )
+        .map_err(|err| {
+            Failure::new(
+                ExitCode::of_load_error(&*err),
+                format!("Failed to convert the SF2 presets to DLS: {err}"),
+            )
+        })?;
 
-    if let Some(ref track_comment) = args.track_comment {
-        midi_track.push(TrackEvent {
-            delta: u28::from(0),
-            kind: TrackEventKind::Meta(MetaMessage::Text(track_comment.as_bytes())),
-        });
+        rmid::embed_riff(output_path, &dls_bytes).map_err(|err| {
+            Failure::new(
+                ExitCode::of_load_error(&*err),
+                format!("Failed to embed DLS collection: {err}"),
+            )
+        })?;
     }
 
-    midi_track.push(TrackEvent {
-        delta: u28::from(0),
-        kind: TrackEventKind::Meta(MetaMessage::Tempo(u24::from(
-            (60_000_000.0 / lmms_project.head.bpm as f32) as u32,
-        ))),
-    });
+    if let Some(ref render_path) = args.render_audio {
+        info!("rendering audio to '{}'", render_path.display());
+        let frames = render::render(
+            input_path,
+            &lmms_project,
+            &conversion,
+            &args.convert_options,
+        )
+        .map_err(|err| {
+            Failure::new(
+                ExitCode::of_load_error(&*err),
+                format!("Failed to render audio: {err}"),
+            )
+        })?;
+
+        let audio_bytes = match render_format {
+            Some("flac") => flac::flac(
+                &frames,
+                render::RENDER_SAMPLE_RATE,
+                &render::loop_tags(&lmms_project, &args.convert_options),
+            ),
+            _ => render::wav(&frames),
+        };
 
-    // MIDI channel initialization
+        fs::write(render_path, audio_bytes).map_err(|err| {
+            Failure::new(
+                ExitCode::Io,
+                format!("Failed to save rendered audio file: {err}"),
+            )
+        })?;
+    }
 
-    for (midi_channel, lmms_track) in &lmms_track_midi_channel {
-        midi_track.push(TrackEvent {
-            delta: u28::from(0),
-            kind: TrackEventKind::Meta(MetaMessage::MidiChannel(*midi_channel)),
-        });
+    if let Some(ref channel_map) = args.convert_options.channel_map_file {
+        channel_map
+            .save(&lmms_project, conversion.channel_assignments())
+            .map_err(|err| {
+                Failure::new(
+                    ExitCode::Io,
+                    format!("Failed to save channel map file: {err}"),
+                )
+            })?;
+    }
 
-        if !lmms_track.name.is_empty() {
-            if !lmms_track.name.is_ascii() {
-                eprintln!(
-                    "warning: non-ASCII LMMS track name '{}'",
-                    lmms_track.name.escape_default(),
-                );
-                eprintln!("note: these track names may be mishandled by other music software");
-            }
+    if let Some(ref bundle_path) = args.bundle {
+        info!("creating bundle '{}'", bundle_path.display());
+        bundle::create_bundle(bundle_path, input_path, output_path, &lmms_project)
+            .map_err(|err| Failure::new(ExitCode::Io, format!("Failed to create bundle: {err}")))?;
+    }
 
-            midi_track.push(TrackEvent {
-                delta: u28::from(0),
-                kind: TrackEventKind::Meta(MetaMessage::InstrumentName(lmms_track.name.as_bytes())),
-            });
-        }
+    Ok(())
+}
 
-        // Bank and preset selection
-        {
-            let bank = lmms_track.sf2_player().bank;
-            let bank_coarse = u7::from((bank >> 7) as u8);
-            let bank_fine = u7::from((bank & 0x7F) as u8);
-
-            midi_track.push(TrackEvent {
-                delta: u28::from(0),
-                kind: TrackEventKind::Midi {
-                    channel: *midi_channel,
-                    message: MidiMessage::Controller {
-                        controller: u7::from(MIDI_CC_BANK_SELECT_COARSE),
-                        value: bank_coarse,
-                    },
-                },
-            });
+/// Parses the command line, with the conversion options of the configuration
+/// files applying to the project filled in where the command line leaves them
+fn parse_args() -> Args {
+    let argv = env::args_os().collect::<Vec<_>>();
+    let matches = Args::command().get_matches_from(&argv);
+
+    log::set_verbosity(matches.get_flag("quiet"), matches.get_count("verbose"));
+
+    // Both have default values, clap fills them in
+    let diagnostics_format = matches
+        .get_one::<DiagnosticsFormat>("diagnostics_format")
+        .copied()
+        .unwrap_or_default();
+    let diagnostics_path = matches.get_one::<PathBuf>("diagnostics_file");
+    let color = matches
+        .get_one::<ColorChoice>("color")
+        .copied()
+        .unwrap_or_default();
+
+    if let Err(err) = log::set_diagnostics_output(
+        diagnostics_format,
+        diagnostics_path.map(PathBuf::as_path),
+        color,
+    ) {
+        error!("Failed to create diagnostics file: {err}");
+        process::exit(ExitCode::Io.code());
+    }
 
-            midi_track.push(TrackEvent {
-                delta: u28::from(0),
-                kind: TrackEventKind::Midi {
-                    channel: *midi_channel,
-                    message: MidiMessage::Controller {
-                        controller: u7::from(MIDI_CC_BANK_SELECT_FINE),
-                        value: bank_fine,
-                    },
-                },
-            });
+    // Options of subcommands follow the subcommand name
+    let (convert_matches, insert_index) = match matches.subcommand() {
+        Some((
+            "convert" | "doctor" | "play" | "dump" | "inspect" | "tracks" | "preview",
+            subcommand_matches,
+        )) => (subcommand_matches, 2),
+        Some(_) => (&matches, 0),
+        None => (&matches, 1),
+    };
 
-            midi_track.push(TrackEvent {
-                delta: u28::from(0),
-                kind: TrackEventKind::Midi {
-                    channel: *midi_channel,
-                    message: MidiMessage::ProgramChange {
-                        program: u7::from(lmms_track.sf2_player().patch as u8),
-                    },
-                },
-            });
-        }
+    if insert_index == 0 {
+        return Args::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    }
 
-        {
-            let channel_volume = remap_clamp_range(
-                lmms_track.instrument_track.volume,
-                0.0..=100.0,
-                0.0..=127.0,
-                |volume| volume.sqrt(),
-            );
+    let project_dir = config_project_dir(convert_matches);
+    let config_paths = config::config_paths(project_dir.as_deref());
 
-            midi_track.push(TrackEvent {
-                delta: u28::from(0),
-                kind: TrackEventKind::Midi {
-                    channel: *midi_channel,
-                    message: MidiMessage::Controller {
-                        controller: u7::from(MIDI_CC_VOLUME),
-                        value: u7::from(channel_volume as u8),
-                    },
-                },
-            });
-        }
+    let config_args = config::config_args(&config_paths, convert_matches).unwrap_or_else(|err| {
+        error!("{err}");
+        process::exit(ExitCode::of_load_error(&*err).code());
+    });
 
-        {
-            let channel_panning = remap_clamp_range(
-                lmms_track.instrument_track.panning,
-                -100.0..=100.0,
-                0.0..=127.0,
-                |panning| panning,
-            );
+    if config_args.is_empty() {
+        return Args::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    }
 
-            midi_track.push(TrackEvent {
-                delta: u28::from(0),
-                kind: TrackEventKind::Midi {
-                    channel: *midi_channel,
-                    message: MidiMessage::Controller {
-                        controller: u7::from(MIDI_CC_PANNING),
-                        value: u7::from(channel_panning as u8),
-                    },
-                },
-            });
-        }
+    for config_path in &config_paths {
+        note!("using defaults from '{}'", config_path.display());
     }
 
-    let mut midi_track_events = Vec::new();
+    let mut argv = argv;
+    argv.splice(insert_index..insert_index, config_args);
+    Args::parse_from(argv)
+}
+
+/// Directory whose configuration file applies to the projects of a command
+fn config_project_dir(matches: &ArgMatches) -> Option<PathBuf> {
+    let input_path = if matches.try_contains_id("input_paths").unwrap_or(false) {
+        matches.get_many::<PathBuf>("input_paths")?.next()?
+    } else {
+        matches.get_one::<PathBuf>("input_path")?
+    };
+
+    if is_stdio(input_path) {
+        None
+    } else if input_path.is_dir() {
+        Some(input_path.to_owned())
+    } else {
+        match input_path.parent() {
+            Some(dir_path) if !dir_path.as_os_str().is_empty() => Some(dir_path.to_owned()),
+            _ => Some(PathBuf::from(".")),
+        }
+    }
+}
 
-    for (midi_channel, lmms_track) in &lmms_track_midi_channel {
-        for lmms_pattern in &lmms_track.patterns {
-            for lmms_note in &lmms_pattern.notes {
-                let ticks_start = lmms_pattern.position + lmms_note.position;
-                let ticks_end = ticks_start + lmms_note.length;
+/// Fails the conversion if `--strict` turned warnings into errors
+fn check_strict_errors() -> Result<(), Failure> {
+    let message = match log::strict_error_count() {
+        0 => return Ok(()),
+        1 => "1 warning is treated as an error (`--strict`)".to_owned(),
+        strict_error_count => {
+            format!("{strict_error_count} warnings are treated as errors (`--strict`)")
+        }
+    };
 
-                let mut note_key = lmms_note.key as isize;
-                note_key += 69 - lmms_track.instrument_track.base_note as isize;
+    Err(Failure::new(ExitCode::Strict, message))
+}
 
-                if lmms_track.instrument_track.use_master_pitch == 1 {
-                    note_key += lmms_project.head.master_pitch;
-                };
+/// Output file named after a project, with the extension of the output
+/// format, in the output directory or next to the project
+fn derived_output_path(
+    project_path: &Path,
+    out_dir: Option<&Path>,
+    output_format: OutputFormat,
+) -> PathBuf {
+    let file_name = project_path
+        .with_extension(output_format.extension())
+        .file_name()
+        .unwrap_or_default()
+        .to_owned();
+
+    match out_dir {
+        Some(out_dir) => out_dir.join(file_name),
+        None => project_path.with_file_name(file_name),
+    }
+}
 
-                let note_velocity = remap_clamp_range(
-                    lmms_note.volume as f32,
-                    0.0..=200.0,
-                    0.0..=127.0,
-                    |velocity| velocity,
-                );
+/// Whether a path stands for stdin or stdout
+fn is_stdio(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
 
-                midi_track_events.push(AbsoluteTrackEvent {
-                    ticks: ticks_start,
-                    ticks_event_start: ticks_start,
-                    kind: TrackEventKind::Midi {
-                        channel: *midi_channel,
-                        message: MidiMessage::NoteOn {
-                            key: u7::from(note_key as u8),
-                            vel: u7::from(note_velocity as u8),
-                        },
-                    },
-                });
-
-                midi_track_events.push(AbsoluteTrackEvent {
-                    ticks: ticks_end,
-                    ticks_event_start: ticks_start,
-                    kind: TrackEventKind::Midi {
-                        channel: *midi_channel,
-                        message: MidiMessage::NoteOff {
-                            key: u7::from(note_key as u8),
-                            vel: u7::from(note_velocity as u8),
-                        },
-                    },
-                });
-            }
+/// Whether a file name matches a pattern of `*` (any characters) and `?` (a
+/// single character)
+fn matches_pattern(pattern: &[char], file_name: &[char]) -> bool {
+    match (pattern.first(), file_name.first()) {
+        (None, None) => true,
+        (Some('*'), _) => {
+            matches_pattern(&pattern[1..], file_name)
+                || (!file_name.is_empty() && matches_pattern(pattern, &file_name[1..]))
+        }
+        (Some('?'), Some(_)) => matches_pattern(&pattern[1..], &file_name[1..]),
+        (Some(pattern_char), Some(file_name_char)) if pattern_char == file_name_char => {
+            matches_pattern(&pattern[1..], &file_name[1..])
         }
+        _ => false,
     }
+}
 
-    for loop_style in &args.loop_style {
-        let loop_start = lmms_project.song.timeline.loop_start;
-        let loop_end = lmms_project.song.timeline.loop_end;
-
-        match loop_style {
-            MidiLoopStyle::RpgMaker => {
-                midi_track_events.push(AbsoluteTrackEvent {
-                    ticks: loop_start,
-                    ticks_event_start: loop_start,
-                    kind: TrackEventKind::Midi {
-                        channel: u4::from(0),
-                        message: MidiMessage::Controller {
-                            controller: u7::from(MIDI_CC_RPG_LOOP_START),
-                            value: u7::from(0),
-                        },
-                    },
-                });
-            }
-            MidiLoopStyle::EmidiLocal => {
-                midi_track_events.push(AbsoluteTrackEvent {
-                    ticks: loop_start,
-                    ticks_event_start: loop_start,
-                    kind: TrackEventKind::Midi {
-                        channel: u4::from(0),
-                        message: MidiMessage::Controller {
-                            controller: u7::from(MIDI_CC_EMIDI_LOCAL_LOOP_START),
-                            value: u7::from(0),
-                        },
-                    },
-                });
-
-                midi_track_events.push(AbsoluteTrackEvent {
-                    ticks: loop_end,
-                    ticks_event_start: loop_end,
-                    kind: TrackEventKind::Midi {
-                        channel: u4::from(0),
-                        message: MidiMessage::Controller {
-                            controller: u7::from(MIDI_CC_EMIDI_LOCAL_LOOP_END),
-                            value: u7::from(0),
-                        },
-                    },
-                });
-            }
-            MidiLoopStyle::EmidiGlobal => {
-                midi_track_events.push(AbsoluteTrackEvent {
-                    ticks: loop_start,
-                    ticks_event_start: loop_start,
-                    kind: TrackEventKind::Midi {
-                        channel: u4::from(0),
-                        message: MidiMessage::Controller {
-                            controller: u7::from(MIDI_CC_EMIDI_GLOBAL_LOOP_START),
-                            value: u7::from(0),
-                        },
-                    },
-                });
-
-                midi_track_events.push(AbsoluteTrackEvent {
-                    ticks: loop_end,
-                    ticks_event_start: loop_end,
-                    kind: TrackEventKind::Midi {
-                        channel: u4::from(0),
-                        message: MidiMessage::Controller {
-                            controller: u7::from(MIDI_CC_EMIDI_GLOBAL_LOOP_END),
-                            value: u7::from(0),
-                        },
-                    },
-                });
+/// The projects an input path stands for: the path itself, the projects of
+/// a directory, or the files matching a file name pattern the shell did not
+/// expand, sorted by name
+fn expand_input_path(input_path: &Path) -> Result<Vec<PathBuf>, String> {
+    let is_project = |path: &Path| {
+        path.is_file()
+            && path.extension().is_some_and(|extension| {
+                extension.eq_ignore_ascii_case("mmp") || extension.eq_ignore_ascii_case("mmpz")
+            })
+    };
+
+    let file_name = input_path
+        .file_name()
+        .map(|file_name| file_name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let (dir_path, pattern) = if input_path.is_dir() {
+        (input_path.to_owned(), None)
+    } else if !input_path.exists() && file_name.contains(['*', '?']) {
+        let dir_path = match input_path.parent() {
+            Some(dir_path) if !dir_path.as_os_str().is_empty() => dir_path.to_owned(),
+            _ => PathBuf::from("."),
+        };
+
+        (dir_path, Some(file_name.chars().collect::<Vec<_>>()))
+    } else {
+        return Ok(vec![input_path.to_owned()]);
+    };
+
+    let mut project_paths = fs::read_dir(&dir_path)
+        .map_err(|err| format!("Failed to read directory '{}': {err}", dir_path.display()))?
+        .filter_map(|dir_entry| dir_entry.ok().map(|dir_entry| dir_entry.path()))
+        .filter(|path| is_project(path))
+        .filter(|path| {
+            pattern.as_ref().is_none_or(|pattern| {
+                let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+                matches_pattern(pattern, &file_name.chars().collect::<Vec<_>>())
+            })
+        })
+        .collect::<Vec<_>>();
+
+    project_paths.sort();
+    Ok(project_paths)
+}
+
+/// Converts every project of the input paths into a file named after it,
+/// printing the result of each. Returns the exit code of the failures.
+fn convert_batch(
+    input_paths: &[PathBuf],
+    out_dir: Option<&Path>,
+    force: bool,
+    convert_options: &ConvertOptions,
+) -> ExitCode {
+    let mut project_paths = Vec::new();
+    let mut failure_exit_codes = Vec::new();
+
+    for input_path in input_paths {
+        match expand_input_path(input_path) {
+            Ok(expanded_paths) if expanded_paths.is_empty() => {
+                warning!(code: "no-projects"; "no LMMS projects found at '{}'", input_path.display());
             }
-            MidiLoopStyle::FinalFantasy => {
-                midi_track_events.push(AbsoluteTrackEvent {
-                    ticks: loop_start,
-                    ticks_event_start: loop_start,
-                    kind: TrackEventKind::Meta(MetaMessage::Marker(b"loopstart")),
-                });
-
-                midi_track_events.push(AbsoluteTrackEvent {
-                    ticks: loop_end,
-                    ticks_event_start: loop_end,
-                    kind: TrackEventKind::Meta(MetaMessage::Marker(b"loopend")),
-                });
+            Ok(expanded_paths) => project_paths.extend(expanded_paths),
+            Err(err) => {
+                println!("failed  {}: {err}", input_path.display());
+                failure_exit_codes.push(ExitCode::Io);
             }
         }
     }
 
-    midi_track_events.sort_by_key(
-        |&AbsoluteTrackEvent {
-             ticks,
-             ticks_event_start,
-             kind,
-             ..
-         }| {
-            (
-                ticks,
-                ticks_event_start,
-                !kind.is_meta_event(),
-                !kind.is_cc_event(),
-                !kind.is_note_on(),
-                !kind.is_note_off(),
-            )
-        },
-    );
+    if let Some(out_dir) = out_dir {
+        if let Err(err) = fs::create_dir_all(out_dir) {
+            error!(
+                "Failed to create output directory '{}': {err}",
+                out_dir.display()
+            );
+            return ExitCode::Io;
+        }
+    }
 
-    {
-        let mut current_polyphony = 0;
-        let mut already_warned = false;
+    let mut output_paths = Vec::new();
+    let mut converted_count = 0;
 
-        for event in midi_track_events.iter() {
-            if event.kind.is_note_on() {
-                current_polyphony += 1;
+    for project_path in &project_paths {
+        let output_path = derived_output_path(project_path, out_dir, convert_options.output_format);
 
-                if (current_polyphony > MIDI_MAX_POLYPHONY) && !already_warned {
-                    eprintln!("warning: excessive polyphony at {}", event.ticks);
-                    already_warned = true;
-                }
-            }
+        // Projects of the same name in different directories
+        if output_paths.contains(&output_path) {
+            println!(
+                "failed  {}: another project is already saved to '{}'",
+                project_path.display(),
+                output_path.display()
+            );
+            failure_exit_codes.push(ExitCode::Failure);
+            continue;
+        }
+
+        if !force && output_path.exists() {
+            println!(
+                "failed  {}: '{}' already exists, use `--force` to overwrite it",
+                project_path.display(),
+                output_path.display()
+            );
+            failure_exit_codes.push(ExitCode::Io);
+            continue;
+        }
 
-            if event.kind.is_note_off() {
-                assert!(current_polyphony > 0);
-                current_polyphony -= 1;
+        progress::start_phase(Phase::Parse, None);
+        let result = LmmsProject::load_from_path(project_path)
+            .map_err(|err| Failure::convert_error("Failed to load LMMS project file", err))
+            .and_then(|lmms_project| {
+                log::set_strict_classes(&convert_options.strict);
+
+                let conversion = Conversion::new(&lmms_project, convert_options)
+                    .map_err(|err| Failure::convert_error("Failed to convert LMMS project", err))?;
+
+                let output_bytes = conversion
+                    .to_bytes(&lmms_project, convert_options)
+                    .map_err(|err| Failure::convert_error("Failed to convert LMMS project", err))?;
+
+                check_strict_errors()?;
+
+                fs::write(&output_path, output_bytes).map_err(|err| {
+                    Failure::new(
+                        ExitCode::Io,
+                        format!(
+                            "Failed to save output file '{}': {err}",
+                            output_path.display()
+                        ),
+                    )
+                })
+            });
+
+        progress::finish();
 
-                if (current_polyphony <= MIDI_MAX_POLYPHONY) && already_warned {
-                    already_warned = false;
-                }
+        match result {
+            Ok(()) => {
+                println!(
+                    "ok      {} -> {}",
+                    project_path.display(),
+                    output_path.display()
+                );
+                converted_count += 1;
+            }
+            Err(failure) => {
+                println!("failed  {}: {failure}", project_path.display());
+                failure_exit_codes.push(failure.exit_code);
             }
         }
+
+        output_paths.push(output_path);
     }
 
-    {
-        let mut current_note_counts = HashMap::new();
+    println!();
+    println!(
+        "{converted_count} converted, {} failed",
+        failure_exit_codes.len()
+    );
 
-        for event in midi_track_events.iter() {
-            if let TrackEventKind::Midi {
-                channel,
-                message: MidiMessage::NoteOn { key, .. },
-            } = event.kind
-            {
-                let note_count = current_note_counts.entry((channel, key)).or_insert(0);
-                *note_count += 1;
+    ExitCode::of_batch(&failure_exit_codes)
+}
 
-                if *note_count >= 2 {
-                    eprintln!("warning: note overlap at {}", event.ticks);
-                }
-            }
+/// Converts the project again whenever it is saved, until interrupted.
+/// Conversion errors are reported without ending the watch, as LMMS may be
+/// caught in the middle of writing the file.
+fn watch(args: &Args, input_path: &Path, output_path: &Path, render_format: Option<&str>) {
+    let modified_time = || {
+        fs::metadata(input_path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    };
+    let mut last_modified_time = modified_time();
 
-            if let TrackEventKind::Midi {
-                channel,
-                message: MidiMessage::NoteOff { key, .. },
-            } = event.kind
-            {
-                let note_count = current_note_counts
-                    .get_mut(&(channel, key))
-                    .expect("failed to get note count");
+    note!(
+        "watching '{}' for changes, press Ctrl+C to stop",
+        input_path.display()
+    );
 
-                assert!(*note_count > 0);
-                *note_count -= 1;
+    loop {
+        thread::sleep(WATCH_POLL_INTERVAL);
 
-                if *note_count == 0 {
-                    current_note_counts.remove(&(channel, key));
-                }
-            }
+        let current_modified_time = modified_time();
+        if current_modified_time == last_modified_time {
+            continue;
         }
-    }
 
-    for (event_index, event) in midi_track_events.iter().enumerate() {
-        let delta_time = if event_index == 0 {
-            event.ticks
-        } else {
-            let ticks_before = midi_track_events[event_index - 1].ticks;
-            let ticks_current = midi_track_events[event_index].ticks;
-            assert!(ticks_before <= ticks_current);
-            ticks_current - ticks_before
-        };
+        // Wait for the save to finish
+        thread::sleep(WATCH_SETTLE_TIME);
+        last_modified_time = modified_time();
 
-        midi_track.push(TrackEvent {
-            delta: u28::from(delta_time as u32),
-            kind: event.kind,
-        });
-    }
+        note!("'{}' changed, converting", input_path.display());
 
-    midi_track.push(TrackEvent {
-        delta: u28::from(0),
-        kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
-    });
-
-    midi_document.tracks.push(midi_track);
-    midi_document
-        .save(args.output_path)
-        .expect("Failed to save output MIDI file");
+        match convert(args, input_path, output_path, render_format) {
+            Ok(()) => note!("saved '{}'", output_path.display()),
+            Err(failure) => error!("{failure}"),
+        }
+    }
 }
@@ -0,0 +1,197 @@
+use std::ops::RangeInclusive;
+
+use midly::num::{u4, u7};
+use midly::{MidiMessage, TrackEventKind};
+
+pub const MIDI_CC_BANK_SELECT_COARSE: u8 = 0;
+pub const MIDI_CC_BANK_SELECT_FINE: u8 = 32;
+pub const MIDI_CC_MODULATION: u8 = 1;
+pub const MIDI_CC_VOLUME: u8 = 7;
+pub const MIDI_CC_PANNING: u8 = 10;
+pub const MIDI_CC_EXPRESSION: u8 = 11;
+pub const MIDI_CC_PORTAMENTO_TIME: u8 = 5;
+pub const MIDI_CC_SUSTAIN: u8 = 64;
+pub const MIDI_CC_PORTAMENTO: u8 = 65;
+
+pub const MIDI_CC_EMIDI_LOCAL_LOOP_START: u8 = 116;
+pub const MIDI_CC_EMIDI_LOCAL_LOOP_END: u8 = 117;
+
+pub const MIDI_CC_EMIDI_GLOBAL_LOOP_START: u8 = 118;
+pub const MIDI_CC_EMIDI_GLOBAL_LOOP_END: u8 = 119;
+
+pub const MIDI_CC_RPG_LOOP_START: u8 = 111;
+
+// System reset SysEx messages, without the leading 0xF0
+pub const MIDI_SYSEX_GM_SYSTEM_ON: &[u8] = &[0x7E, 0x7F, 0x09, 0x01, 0xF7];
+pub const MIDI_SYSEX_GM2_SYSTEM_ON: &[u8] = &[0x7E, 0x7F, 0x09, 0x03, 0xF7];
+pub const MIDI_SYSEX_GS_RESET: &[u8] =
+    &[0x41, 0x10, 0x42, 0x12, 0x40, 0x00, 0x7F, 0x00, 0x41, 0xF7];
+// GS "Use for Rhythm Part" of part 10 set to off, making channel 10 melodic
+pub const MIDI_SYSEX_GS_PART10_MELODIC: &[u8] =
+    &[0x41, 0x10, 0x42, 0x12, 0x40, 0x10, 0x15, 0x00, 0x1B, 0xF7];
+pub const MIDI_SYSEX_XG_SYSTEM_ON: &[u8] = &[0x43, 0x10, 0x4C, 0x00, 0x00, 0x7E, 0x00, 0xF7];
+
+pub const MIDI_MAX_POLYPHONY: usize = 24;
+
+// Port numbers of the MIDI Port meta event are 7-bit, but few setups go beyond 16
+pub const MIDI_MAX_PORTS: usize = 16;
+
+#[derive(Debug, Clone, Copy)]
+pub struct AbsoluteTrackEvent<'a> {
+    //// When this event occurs in absolute MIDI ticks
+    pub ticks: usize,
+
+    /// When this event really started (NoteOn for NoteOff events)
+    pub ticks_event_start: usize,
+
+    /// MIDI event data
+    pub kind: TrackEventKind<'a>,
+
+    /// Velocity or controller value before rounding to 7 bits, scaled to the
+    /// full 32-bit range, for MIDI 2.0 output
+    pub precise_value: Option<u32>,
+}
+
+/// A note before it is split into Note On and Note Off events
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MidiNote {
+    pub ticks_start: usize,
+    pub ticks_end: usize,
+    pub channel: u4,
+    pub key: u7,
+    pub velocity: u7,
+
+    /// Velocity before rounding to 7 bits, see `precise_value`
+    pub precise_velocity: u32,
+}
+
+impl MidiNote {
+    pub fn events(&self) -> [AbsoluteTrackEvent<'static>; 2] {
+        [
+            AbsoluteTrackEvent {
+                ticks: self.ticks_start,
+                ticks_event_start: self.ticks_start,
+                kind: TrackEventKind::Midi {
+                    channel: self.channel,
+                    message: MidiMessage::NoteOn {
+                        key: self.key,
+                        vel: self.velocity,
+                    },
+                },
+                precise_value: Some(self.precise_velocity),
+            },
+            AbsoluteTrackEvent {
+                ticks: self.ticks_end,
+                ticks_event_start: self.ticks_start,
+                kind: TrackEventKind::Midi {
+                    channel: self.channel,
+                    message: MidiMessage::NoteOff {
+                        key: self.key,
+                        vel: self.velocity,
+                    },
+                },
+                precise_value: Some(self.precise_velocity),
+            },
+        ]
+    }
+}
+
+pub trait TrackEventKindExt {
+    fn is_note_on(&self) -> bool;
+    fn is_note_off(&self) -> bool;
+    fn is_meta_event(&self) -> bool;
+    fn is_cc_event(&self) -> bool;
+    fn is_program_change(&self) -> bool;
+}
+
+impl TrackEventKindExt for TrackEventKind<'_> {
+    fn is_note_on(&self) -> bool {
+        matches!(
+            self,
+            TrackEventKind::Midi {
+                message: MidiMessage::NoteOn { .. },
+                ..
+            }
+        )
+    }
+
+    fn is_note_off(&self) -> bool {
+        matches!(
+            self,
+            TrackEventKind::Midi {
+                message: MidiMessage::NoteOff { .. },
+                ..
+            }
+        )
+    }
+
+    fn is_meta_event(&self) -> bool {
+        matches!(self, TrackEventKind::Meta { .. })
+    }
+
+    fn is_cc_event(&self) -> bool {
+        matches!(
+            self,
+            TrackEventKind::Midi {
+                message: MidiMessage::Controller { .. },
+                ..
+            }
+        )
+    }
+
+    fn is_program_change(&self) -> bool {
+        matches!(
+            self,
+            TrackEventKind::Midi {
+                message: MidiMessage::ProgramChange { .. },
+                ..
+            }
+        )
+    }
+}
+
+/// Whether the controller simply holds its last value, so repeating that value
+/// has no effect. Bank select, data entry, (N)RPN selection, loop marker and
+/// channel mode controllers act on every message and are never stateful.
+pub fn is_stateful_controller(controller: u8) -> bool {
+    !matches!(
+        controller,
+        MIDI_CC_BANK_SELECT_COARSE
+            | MIDI_CC_BANK_SELECT_FINE
+            | 6
+            | 38
+            | 96..=101
+            | MIDI_CC_RPG_LOOP_START
+            | MIDI_CC_EMIDI_LOCAL_LOOP_START..=MIDI_CC_EMIDI_GLOBAL_LOOP_END
+            | 120..
+    )
+}
+
+/// Variable-length quantity of Standard MIDI Files, also used by the event
+/// streams of XMIDI, MUS and HMP
+pub fn write_vlq(bytes: &mut Vec<u8>, value: usize) {
+    let mut groups = vec![(value & 0x7F) as u8];
+    let mut value = value >> 7;
+
+    while value > 0 {
+        groups.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+
+    bytes.extend(groups.iter().rev());
+}
+
+/// A 0.0-127.0 MIDI value scaled to the full 32-bit range
+pub fn precise_value(value: f32) -> u32 {
+    (value.clamp(0.0, 127.0) as f64 / 127.0 * u32::MAX as f64).round() as u32
+}
+
+pub fn remap_clamp_range(
+    value: f32,
+    range_from: RangeInclusive<f32>,
+    range_to: RangeInclusive<f32>,
+    transfer_fn: fn(f32) -> f32,
+) -> f32 {
+    let t = (value - range_from.start()) / (range_from.end() - range_from.start());
+    range_to.start() + transfer_fn(t.clamp(0.0, 1.0)) * (range_to.end() - range_to.start())
+}
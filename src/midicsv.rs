@@ -0,0 +1,210 @@
+use std::fmt::Write;
+
+use midly::{Format, Fps, MetaMessage, MidiMessage, Smf, Timing, TrackEventKind};
+
+/// Text of a meta event as a midicsv string: quoted, with quotes doubled,
+/// backslashes doubled and the bytes outside of printable ASCII written as
+/// backslash escapes of their octal codes, which csvmidi reads back unchanged
+fn csv_string(text: &[u8]) -> String {
+    let mut csv_string = String::from("\"");
+
+    for byte in text {
+        match byte {
+            b'"' => csv_string.push_str("\"\""),
+            b'\\' => csv_string.push_str("\\\\"),
+            0x00..=0x1F | 0x7F.. => write!(csv_string, "\\{byte:03o}").unwrap(),
+            _ => csv_string.push(*byte as char),
+        }
+    }
+
+    csv_string.push('"');
+    csv_string
+}
+
+/// Length and bytes of a midicsv data field
+fn csv_data(data: &[u8]) -> String {
+    let mut fields = vec![data.len().to_string()];
+    fields.extend(data.iter().map(u8::to_string));
+    fields.join(", ")
+}
+
+/// Record type and fields of an event
+fn csv_record(kind: &TrackEventKind) -> String {
+    match kind {
+        TrackEventKind::Midi { channel, message } => {
+            let channel = channel.as_int();
+
+            match message {
+                MidiMessage::NoteOff { key, vel } => format!("Note_off_c, {channel}, {key}, {vel}"),
+                MidiMessage::NoteOn { key, vel } => format!("Note_on_c, {channel}, {key}, {vel}"),
+                MidiMessage::Aftertouch { key, vel } => {
+                    format!("Poly_aftertouch_c, {channel}, {key}, {vel}")
+                }
+                MidiMessage::Controller { controller, value } => {
+                    format!("Control_c, {channel}, {controller}, {value}")
+                }
+                MidiMessage::ProgramChange { program } => {
+                    format!("Program_c, {channel}, {program}")
+                }
+                MidiMessage::ChannelAftertouch { vel } => {
+                    format!("Channel_aftertouch_c, {channel}, {vel}")
+                }
+                MidiMessage::PitchBend { bend } => format!("Pitch_bend_c, {channel}, {}", bend.0),
+            }
+        }
+        TrackEventKind::SysEx(data) => format!("System_exclusive, {}", csv_data(data)),
+        TrackEventKind::Escape(data) => format!("System_exclusive_packet, {}", csv_data(data)),
+        TrackEventKind::Meta(meta_message) => match meta_message {
+            MetaMessage::TrackNumber(Some(number)) => format!("Sequence_number, {number}"),
+            MetaMessage::TrackNumber(None) => "Unknown_meta_event, 0, 0".to_string(),
+            MetaMessage::Text(text) => format!("Text_t, {}", csv_string(text)),
+            MetaMessage::Copyright(text) => format!("Copyright_t, {}", csv_string(text)),
+            MetaMessage::TrackName(text) => format!("Title_t, {}", csv_string(text)),
+            MetaMessage::InstrumentName(text) => format!("Instrument_name_t, {}", csv_string(text)),
+            MetaMessage::Lyric(text) => format!("Lyric_t, {}", csv_string(text)),
+            MetaMessage::Marker(text) => format!("Marker_t, {}", csv_string(text)),
+            MetaMessage::CuePoint(text) => format!("Cue_point_t, {}", csv_string(text)),
+            // midicsv predates the program and device name meta events
+            MetaMessage::ProgramName(text) => format!("Unknown_meta_event, 8, {}", csv_data(text)),
+            MetaMessage::DeviceName(text) => format!("Unknown_meta_event, 9, {}", csv_data(text)),
+            MetaMessage::MidiChannel(channel) => format!("Channel_prefix, {channel}"),
+            MetaMessage::MidiPort(port) => format!("MIDI_port, {port}"),
+            MetaMessage::EndOfTrack => "End_track".to_string(),
+            MetaMessage::Tempo(tempo) => format!("Tempo, {tempo}"),
+            MetaMessage::SmpteOffset(smpte_time) => {
+                // The frame rate is in the upper bits of the hour byte
+                let fps_code = match smpte_time.fps() {
+                    Fps::Fps24 => 0,
+                    Fps::Fps25 => 1,
+                    Fps::Fps29 => 2,
+                    Fps::Fps30 => 3,
+                };
+
+                format!(
+                    "SMPTE_offset, {}, {}, {}, {}, {}",
+                    fps_code << 5 | smpte_time.hour(),
+                    smpte_time.minute(),
+                    smpte_time.second(),
+                    smpte_time.frame(),
+                    smpte_time.subframe(),
+                )
+            }
+            MetaMessage::TimeSignature(numerator, denominator, clocks, notes) => {
+                format!("Time_signature, {numerator}, {denominator}, {clocks}, {notes}")
+            }
+            MetaMessage::KeySignature(key, minor) => format!(
+                "Key_signature, {key}, \"{}\"",
+                if *minor { "minor" } else { "major" }
+            ),
+            MetaMessage::SequencerSpecific(data) => {
+                format!("Sequencer_specific, {}", csv_data(data))
+            }
+            MetaMessage::Unknown(meta_type, data) => {
+                format!("Unknown_meta_event, {meta_type}, {}", csv_data(data))
+            }
+        },
+    }
+}
+
+/// Standard MIDI File in the CSV format of midicsv, one record per line: the
+/// track (0 for the file records), the absolute time in ticks, the record type
+/// and its fields. Tracks are framed by Start_track and End_track records.
+pub fn midicsv(midi_document: &Smf) -> String {
+    let format = match midi_document.header.format {
+        Format::SingleTrack => 0,
+        Format::Parallel => 1,
+        Format::Sequential => 2,
+    };
+
+    // The raw division field of the header
+    let division = match midi_document.header.timing {
+        Timing::Metrical(ticks_per_beat) => ticks_per_beat.as_int(),
+        Timing::Timecode(fps, subframes) => {
+            ((-(fps.as_int() as i8)) as u8 as u16) << 8 | subframes as u16
+        }
+    };
+
+    let mut csv = format!(
+        "0, 0, Header, {format}, {}, {division}\n",
+        midi_document.tracks.len()
+    );
+
+    for (track_index, midi_track) in midi_document.tracks.iter().enumerate() {
+        let track_number = track_index + 1;
+        let mut ticks = 0;
+
+        writeln!(csv, "{track_number}, 0, Start_track").unwrap();
+
+        for event in midi_track {
+            ticks += event.delta.as_int();
+            writeln!(csv, "{track_number}, {ticks}, {}", csv_record(&event.kind)).unwrap();
+        }
+    }
+
+    csv.push_str("0, 0, End_of_file\n");
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use midly::num::{u4, u7};
+    use midly::{Header, TrackEvent};
+
+    use super::*;
+
+    #[test]
+    fn records_have_track_and_absolute_time() {
+        let midi_document = Smf {
+            header: Header::new(Format::SingleTrack, Timing::Metrical(96.into())),
+            tracks: vec![vec![
+                TrackEvent {
+                    delta: 0.into(),
+                    kind: TrackEventKind::Meta(MetaMessage::TrackName(b"Lead")),
+                },
+                TrackEvent {
+                    delta: 0.into(),
+                    kind: TrackEventKind::Midi {
+                        channel: u4::from(1),
+                        message: MidiMessage::NoteOn {
+                            key: u7::from(60),
+                            vel: u7::from(100),
+                        },
+                    },
+                },
+                TrackEvent {
+                    delta: 48.into(),
+                    kind: TrackEventKind::Midi {
+                        channel: u4::from(1),
+                        message: MidiMessage::NoteOff {
+                            key: u7::from(60),
+                            vel: u7::from(0),
+                        },
+                    },
+                },
+                TrackEvent {
+                    delta: 0.into(),
+                    kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+                },
+            ]],
+        };
+
+        assert_eq!(
+            midicsv(&midi_document),
+            "0, 0, Header, 0, 1, 96\n\
+             1, 0, Start_track\n\
+             1, 0, Title_t, \"Lead\"\n\
+             1, 0, Note_on_c, 1, 60, 100\n\
+             1, 48, Note_off_c, 1, 60, 0\n\
+             1, 48, End_track\n\
+             0, 0, End_of_file\n"
+        );
+    }
+
+    #[test]
+    fn strings_escape_quotes_backslashes_and_control_bytes() {
+        assert_eq!(
+            csv_string(b"say \"hi\"\\\n\xE9"),
+            "\"say \"\"hi\"\"\\\\\\012\\351\""
+        );
+    }
+}
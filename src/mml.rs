@@ -0,0 +1,249 @@
+use std::cmp::Reverse;
+use std::fmt::Write;
+
+use clap::ValueEnum;
+
+use crate::lmms_model::LMMS_TICKS_PER_BAR;
+use crate::notation::{note_value, Chord, NotationNote, NotationVoice, Score};
+use crate::warning;
+
+const MML_NOTE_NAMES: [&str; 12] = [
+    "c", "c+", "d", "d+", "e", "f", "f+", "g", "g+", "a", "a+", "b",
+];
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum MmlDialect {
+    /// ppmck for the NES: pulse channels A and B, triangle channel C
+    #[default]
+    Ppmck,
+
+    /// PMD for the PC-98: FM channels A-F, SSG channels G-I
+    Pmd,
+
+    /// ctrmml for the Mega Drive: FM channels A-F, PSG channels G-I
+    Ctrmml,
+}
+
+impl MmlDialect {
+    /// Names of the melodic channels, in the order note lines are assigned
+    fn channel_names(&self) -> &'static [&'static str] {
+        match self {
+            MmlDialect::Ppmck => &["A", "B", "C"],
+            MmlDialect::Pmd | MmlDialect::Ctrmml => &["A", "B", "C", "D", "E", "F", "G", "H", "I"],
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            MmlDialect::Ppmck => "ppmck",
+            MmlDialect::Pmd => "PMD",
+            MmlDialect::Ctrmml => "ctrmml",
+        }
+    }
+
+    /// Header commands before the channel lines
+    fn header(&self, title: Option<&str>) -> String {
+        let mut header = String::new();
+
+        match self {
+            MmlDialect::Ppmck => {
+                if let Some(title) = title {
+                    writeln!(header, "#TITLE {title}").unwrap();
+                }
+            }
+            MmlDialect::Pmd => {
+                if let Some(title) = title {
+                    writeln!(header, "#Title\t{title}").unwrap();
+                }
+            }
+            MmlDialect::Ctrmml => {
+                writeln!(header, "#platform megadrive").unwrap();
+
+                if let Some(title) = title {
+                    writeln!(header, "#title {title}").unwrap();
+                }
+            }
+        }
+
+        header
+    }
+}
+
+/// Splits the notes of a voice into lines without overlapping notes, the
+/// highest notes of chords going to the first lines
+fn monophonic_lines(voice: &NotationVoice) -> Vec<Vec<NotationNote>> {
+    let mut notes = voice.notes.clone();
+    notes.sort_by_key(|note| (note.start, Reverse(note.key)));
+
+    let mut lines: Vec<Vec<NotationNote>> = Vec::new();
+
+    for note in notes {
+        let free_line = lines.iter_mut().find(|line| {
+            line.last()
+                .is_none_or(|last_note| last_note.end <= note.start)
+        });
+
+        match free_line {
+            Some(line) => line.push(note),
+            None => lines.push(vec![note]),
+        }
+    }
+
+    lines
+}
+
+/// Length of a note value ("4."), lengths off the note values approximated
+fn mml_length(duration: usize) -> String {
+    match note_value(duration) {
+        Some((denominator, true)) => format!("{denominator}."),
+        Some((denominator, false)) => denominator.to_string(),
+        None => (LMMS_TICKS_PER_BAR / duration.max(1)).to_string(),
+    }
+}
+
+/// The notes and rests of a bar on one channel. Ties extend the previous note
+/// with "^", octaves change relative to the previous note.
+fn mml_bar(bar: &[Chord], octave: &mut i32, is_tied: &mut bool) -> String {
+    let mut mml = String::new();
+
+    for chord in bar {
+        let length = mml_length(chord.duration);
+
+        match chord.keys.first() {
+            None => {
+                write!(mml, "r{length}").unwrap();
+                *is_tied = false;
+            }
+            Some(chord_key) => {
+                if *is_tied {
+                    write!(mml, "^{length}").unwrap();
+                } else {
+                    let note_octave = chord_key.key as i32 / 12 - 1;
+
+                    match note_octave - *octave {
+                        0 => {}
+                        octaves @ 1..=2 => mml.push_str(&">".repeat(octaves as usize)),
+                        octaves @ -2..=-1 => mml.push_str(&"<".repeat(-octaves as usize)),
+                        _ => write!(mml, "o{note_octave}").unwrap(),
+                    }
+
+                    *octave = note_octave;
+                    write!(
+                        mml,
+                        "{}{length}",
+                        MML_NOTE_NAMES[chord_key.key as usize % 12]
+                    )
+                    .unwrap();
+                }
+
+                *is_tied = chord_key.tied;
+            }
+        }
+    }
+
+    mml
+}
+
+/// The score as MML for the sound driver of the dialect. Chords are split
+/// into lines of single notes and every line takes a channel of its own, in
+/// track order. Percussion tracks are left out, as drivers have no common
+/// drum sounds.
+pub fn mml(score: &Score, dialect: MmlDialect) -> String {
+    let channel_names = dialect.channel_names();
+
+    if score.voices.iter().any(NotationVoice::is_percussion) {
+        warning!(code: "mml-percussion"; "percussion tracks are not converted to MML");
+    }
+
+    let lines = score
+        .voices
+        .iter()
+        .filter(|voice| !voice.is_percussion())
+        .flat_map(|voice| {
+            monophonic_lines(voice).into_iter().map(|notes| {
+                NotationVoice::new(&voice.name, voice.midi_channel, voice.midi_preset, &notes)
+            })
+        })
+        .collect::<Vec<_>>();
+
+    if lines.len() > channel_names.len() {
+        warning!(
+            code: "mml-channels";
+            "{} has {} channels, {} of the {} note lines (chord notes count separately) are dropped",
+            dialect.name(),
+            channel_names.len(),
+            lines.len() - channel_names.len(),
+            lines.len()
+        );
+    }
+
+    let mut mml = dialect.header(score.title.as_deref());
+
+    for (line_index, (line, channel_name)) in lines.iter().zip(channel_names).enumerate() {
+        writeln!(mml).unwrap();
+        writeln!(mml, "; {}", line.name.replace(['\r', '\n'], " ")).unwrap();
+
+        let mut octave = 4;
+        let mut is_tied = false;
+
+        if line_index == 0 {
+            writeln!(mml, "{channel_name} t{}", score.bpm.round()).unwrap();
+        }
+
+        writeln!(mml, "{channel_name} o{octave}").unwrap();
+
+        for bar in score.bars(line) {
+            writeln!(
+                mml,
+                "{channel_name} {}",
+                mml_bar(&bar, &mut octave, &mut is_tied)
+            )
+            .unwrap();
+        }
+    }
+
+    mml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert::MidiPreset;
+    use crate::key_signature::KeySignature;
+
+    #[test]
+    fn chords_are_split_over_channels() {
+        let note = |start, end, key| NotationNote { start, end, key };
+
+        let score = Score {
+            title: Some("Demo".to_string()),
+            bpm: 120.0,
+            time_signature: (4, 4),
+            key_signature: KeySignature {
+                sharps: 0,
+                minor: false,
+            },
+            voices: vec![NotationVoice::new(
+                "Lead",
+                0,
+                MidiPreset::default(),
+                &[note(0, 96, 60), note(0, 96, 64), note(96, 240, 72)],
+            )],
+        };
+
+        assert_eq!(
+            mml(&score, MmlDialect::Ppmck),
+            "#TITLE Demo\n\
+             \n\
+             ; Lead\nA t120\nA o4\nA e2>c2\nA ^4r2.\n\
+             \n\
+             ; Lead\nB o4\nB c2r2\nB r1\n"
+        );
+    }
+
+    #[test]
+    fn headers_follow_the_dialect() {
+        assert_eq!(MmlDialect::Pmd.header(Some("Demo")), "#Title\tDemo\n");
+        assert_eq!(MmlDialect::Ctrmml.header(None), "#platform megadrive\n");
+    }
+}
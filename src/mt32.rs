@@ -0,0 +1,136 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+// GM programs and their closest MT-32 factory timbres, 128 where the MT-32
+// has no counterpart and the program is kept
+#[rustfmt::skip]
+const GM_TO_MT32_PROGRAMS: [u8; 128] = [
+    //  0    1    2    3    4    5    6    7    8    9   10   11   12   13   14   15
+        0,   1,   2,   7,   3,   5,  16,  21,  22, 101, 101,  97, 104, 103, 102,  20,
+        8,   9,  11,  12,  14,  15,  87,  15,  59,  60,  61,  62,  67,  44,  79,  23,
+       64,  67,  66,  70,  68,  69,  28,  31,  52,  54,  55,  56,  49,  51,  57, 112,
+       48,  50,  45,  26,  34,  35,  45, 122,  89,  90,  94,  81,  92,  95,  24,  25,
+       80,  78,  79,  78,  84,  85,  86,  82,  74,  72,  76,  77, 110, 107, 108,  76,
+       47,  44, 111,  45,  44,  34,  44,  30,  32,  33,  88,  34,  35,  35,  38,  33,
+       41,  36, 100,  37,  40,  34,  43,  40,  63,  21,  99, 105, 103,  86,  55,  84,
+      101, 103, 100, 120, 117, 113,  99, 128, 128, 128, 128, 124, 123, 128, 128, 128,
+];
+
+// GM percussion keys outside the MT-32 rhythm setup (keys 35-76) and the
+// closest sounds it has
+const GM_TO_MT32_DRUM_KEYS: &[(u8, u8)] = &[
+    (27, 75), // High Q -> Claves
+    (28, 39), // Slap -> Hand Clap
+    (29, 39), // Scratch Push -> Hand Clap
+    (30, 39), // Scratch Pull -> Hand Clap
+    (31, 37), // Sticks -> Side Stick
+    (32, 37), // Square Click -> Side Stick
+    (33, 37), // Metronome Click -> Side Stick
+    (34, 56), // Metronome Bell -> Cowbell
+    (52, 49), // Chinese Cymbal -> Crash Cymbal
+    (53, 51), // Ride Bell -> Ride Cymbal
+    (55, 49), // Splash Cymbal -> Crash Cymbal
+    (57, 49), // Crash Cymbal 2 -> Crash Cymbal
+    (58, 69), // Vibraslap -> Cabasa
+    (59, 51), // Ride Cymbal 2 -> Ride Cymbal
+    (77, 76), // Low Wood Block -> High Wood Block
+    (78, 62), // Mute Cuica -> Mute High Conga
+    (79, 63), // Open Cuica -> Open High Conga
+    (80, 75), // Mute Triangle -> Claves
+    (81, 54), // Open Triangle -> Tambourine
+    (82, 70), // Shaker -> Maracas
+    (83, 54), // Jingle Bell -> Tambourine
+    (84, 53), // Bell Tree -> Ride Bell
+    (85, 75), // Castanets -> Claves
+    (86, 64), // Mute Surdo -> Low Conga
+    (87, 64), // Open Surdo -> Low Conga
+];
+
+// Overrides of the built-in GM to MT-32 remapping, GM numbers to MT-32 numbers.
+//
+//     [programs]
+//     0 = 2
+//     48 = 52
+//
+//     [drums]
+//     49 = 57
+
+#[derive(Debug, Clone)]
+pub struct Mt32Map {
+    programs: [u8; 128],
+    drum_keys: [u8; 128],
+}
+
+impl Default for Mt32Map {
+    fn default() -> Self {
+        let mut programs = GM_TO_MT32_PROGRAMS;
+
+        for (gm_program, mt32_program) in programs.iter_mut().enumerate() {
+            if *mt32_program >= 128 {
+                *mt32_program = gm_program as u8;
+            }
+        }
+
+        let mut drum_keys = std::array::from_fn(|key| key as u8);
+
+        for (gm_key, mt32_key) in GM_TO_MT32_DRUM_KEYS {
+            drum_keys[*gm_key as usize] = *mt32_key;
+        }
+
+        Mt32Map {
+            programs,
+            drum_keys,
+        }
+    }
+}
+
+impl Mt32Map {
+    /// Loads the overrides on top of the built-in remapping
+    pub fn load_from_path(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let table = fs::read_to_string(path)?.parse::<toml::Table>()?;
+        let mut mt32_map = Mt32Map::default();
+
+        for (section, value) in table {
+            let entries = match section.as_str() {
+                "programs" => &mut mt32_map.programs,
+                "drums" => &mut mt32_map.drum_keys,
+                _ => return Err(format!("Unknown MT-32 map section '{section}'").into()),
+            };
+
+            let value = value
+                .as_table()
+                .ok_or_else(|| format!("Invalid MT-32 map section '{section}'"))?;
+
+            for (from, to) in value {
+                let from = from
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|from| *from < 128)
+                    .ok_or_else(|| format!("Invalid MT-32 map entry '{from}' in [{section}]"))?;
+
+                let to = to
+                    .as_integer()
+                    .filter(|to| (0..128).contains(to))
+                    .ok_or_else(|| format!("Invalid MT-32 map entry '{from}' in [{section}]"))?;
+
+                entries[from as usize] = to as u8;
+            }
+        }
+
+        Ok(mt32_map)
+    }
+
+    pub fn program(&self, gm_program: u8) -> u8 {
+        self.programs[gm_program as usize]
+    }
+
+    pub fn drum_key(&self, gm_key: u8) -> u8 {
+        self.drum_keys[gm_key as usize]
+    }
+}
+
+/// Command line value parser for MT-32 map files
+pub fn parse_mt32_map(s: &str) -> Result<Mt32Map, String> {
+    Mt32Map::load_from_path(Path::new(s)).map_err(|err| err.to_string())
+}
@@ -0,0 +1,342 @@
+use std::collections::BTreeSet;
+use std::ops::RangeInclusive;
+
+use midly::num::u4;
+use midly::{MidiMessage, TrackEventKind};
+
+use crate::midi::*;
+use crate::warning;
+
+// MUS scores play at a fixed rate of 140 ticks per second
+pub const MUS_TICKS_PER_SECOND: f32 = 140.0;
+
+// The score length is a 16-bit field of the header
+const MUS_MAX_SCORE_LENGTH: usize = 0xFFFF;
+
+// MUS channel of the percussion, MIDI channel 10
+const MUS_PERCUSSION_CHANNEL: u8 = 15;
+
+// DMX plays the General MIDI percussion keys, listed as instruments 135-181
+const MUS_PERCUSSION_KEYS: RangeInclusive<u8> = 35..=81;
+
+// Event types, in bits 4-6 of the event descriptor
+const MUS_EVENT_RELEASE_NOTE: u8 = 0;
+const MUS_EVENT_PLAY_NOTE: u8 = 1;
+const MUS_EVENT_PITCH_BEND: u8 = 2;
+const MUS_EVENT_SYSTEM: u8 = 3;
+const MUS_EVENT_CONTROLLER: u8 = 4;
+const MUS_EVENT_SCORE_END: u8 = 6;
+
+/// MUS controller numbers of the MIDI controllers, the instrument (Program
+/// Change) being controller 0
+fn mus_controller(controller: u8) -> Option<u8> {
+    match controller {
+        MIDI_CC_BANK_SELECT_COARSE => Some(1),
+        MIDI_CC_MODULATION => Some(2),
+        MIDI_CC_VOLUME => Some(3),
+        MIDI_CC_PANNING => Some(4),
+        MIDI_CC_EXPRESSION => Some(5),
+        91 => Some(6),
+        93 => Some(7),
+        MIDI_CC_SUSTAIN => Some(8),
+        67 => Some(9),
+        _ => None,
+    }
+}
+
+/// MUS system events of the MIDI channel mode messages
+fn mus_system_event(controller: u8) -> Option<u8> {
+    match controller {
+        120 => Some(10),
+        123 => Some(11),
+        126 => Some(12),
+        127 => Some(13),
+        121 => Some(14),
+        _ => None,
+    }
+}
+
+/// MUS channels 0-14 are melodic and 15 is the percussion channel
+fn mus_channel(midi_channel: u4) -> u8 {
+    match midi_channel.as_int() {
+        9 => MUS_PERCUSSION_CHANNEL,
+        midi_channel @ 10.. => midi_channel - 1,
+        midi_channel => midi_channel,
+    }
+}
+
+/// DMX MUS file of the events, sorted and timed in MUS ticks. Notes carry
+/// their volume only when it changes from the last note of the channel, and
+/// the last event before a delay is flagged and followed by the delay.
+/// Events without a MUS equivalent are dropped and reported, scores too long
+/// for the header are an error.
+pub fn mus(midi_track_events: &[AbsoluteTrackEvent]) -> Result<Vec<u8>, String> {
+    // (ticks, event bytes)
+    let mut mus_events: Vec<(usize, Vec<u8>)> = Vec::new();
+    let mut note_volumes = [None; 16];
+    let mut instruments = BTreeSet::new();
+
+    let mut dropped_sysex = 0;
+    let mut dropped_aftertouch = 0;
+    let mut dropped_controllers = BTreeSet::new();
+    let mut dropped_controller_events = 0;
+    let mut percussion_keys_out_of_range = 0;
+
+    for event in midi_track_events {
+        let TrackEventKind::Midi { channel, message } = event.kind else {
+            if let TrackEventKind::SysEx(_) = event.kind {
+                dropped_sysex += 1;
+            }
+            continue;
+        };
+
+        let mus_channel = mus_channel(channel);
+        let descriptor = |event_type: u8| event_type << 4 | mus_channel;
+
+        let bytes = match message {
+            MidiMessage::NoteOff { key, .. } => {
+                vec![descriptor(MUS_EVENT_RELEASE_NOTE), key.as_int()]
+            }
+            MidiMessage::NoteOn { key, vel } => {
+                if mus_channel == MUS_PERCUSSION_CHANNEL {
+                    if MUS_PERCUSSION_KEYS.contains(&key.as_int()) {
+                        instruments.insert(key.as_int() as u16 + 100);
+                    } else {
+                        percussion_keys_out_of_range += 1;
+                    }
+                }
+
+                let note_volume = &mut note_volumes[mus_channel as usize];
+
+                if *note_volume == Some(vel.as_int()) {
+                    vec![descriptor(MUS_EVENT_PLAY_NOTE), key.as_int()]
+                } else {
+                    *note_volume = Some(vel.as_int());
+                    vec![
+                        descriptor(MUS_EVENT_PLAY_NOTE),
+                        0x80 | key.as_int(),
+                        vel.as_int(),
+                    ]
+                }
+            }
+            MidiMessage::PitchBend { bend } => {
+                // 8 bits, centered at 128
+                vec![
+                    descriptor(MUS_EVENT_PITCH_BEND),
+                    (bend.0.as_int() >> 6) as u8,
+                ]
+            }
+            MidiMessage::ProgramChange { program } => {
+                if mus_channel != MUS_PERCUSSION_CHANNEL {
+                    instruments.insert(program.as_int() as u16);
+                }
+
+                vec![descriptor(MUS_EVENT_CONTROLLER), 0, program.as_int()]
+            }
+            MidiMessage::Controller { controller, value } => {
+                if let Some(mus_controller) = mus_controller(controller.as_int()) {
+                    vec![
+                        descriptor(MUS_EVENT_CONTROLLER),
+                        mus_controller,
+                        value.as_int(),
+                    ]
+                } else if let Some(system_event) = mus_system_event(controller.as_int()) {
+                    vec![descriptor(MUS_EVENT_SYSTEM), system_event]
+                } else if controller.as_int() == MIDI_CC_BANK_SELECT_FINE {
+                    // MUS banks are a single controller
+                    continue;
+                } else {
+                    dropped_controllers.insert(controller.as_int());
+                    dropped_controller_events += 1;
+                    continue;
+                }
+            }
+            MidiMessage::Aftertouch { .. } | MidiMessage::ChannelAftertouch { .. } => {
+                dropped_aftertouch += 1;
+                continue;
+            }
+        };
+
+        mus_events.push((event.ticks, bytes));
+    }
+
+    if dropped_sysex > 0 {
+        warning!(code: "mus-dropped-events"; "{dropped_sysex} SysEx messages were dropped, MUS has none");
+    }
+
+    if dropped_aftertouch > 0 {
+        warning!(code: "mus-dropped-events"; "{dropped_aftertouch} aftertouch events were dropped, MUS has none");
+    }
+
+    if dropped_controller_events > 0 {
+        warning!(
+            code: "mus-dropped-events";
+            "{} events of controllers {} were dropped, MUS has no equivalent",
+            dropped_controller_events,
+            dropped_controllers
+                .iter()
+                .map(u8::to_string)
+                .collect::<Vec<_>>()
+                .join(", "),
+        );
+    }
+
+    if percussion_keys_out_of_range > 0 {
+        warning!(
+            code: "mus-percussion-keys";
+            "{} percussion notes are outside the General MIDI percussion keys {}-{} that DMX plays",
+            percussion_keys_out_of_range,
+            MUS_PERCUSSION_KEYS.start(),
+            MUS_PERCUSSION_KEYS.end(),
+        );
+    }
+
+    let mut score = Vec::new();
+
+    for (event_index, (ticks, bytes)) in mus_events.iter().enumerate() {
+        let delay = mus_events
+            .get(event_index + 1)
+            .map_or(0, |(next_ticks, _)| next_ticks - ticks);
+
+        if delay > 0 {
+            score.push(bytes[0] | 0x80);
+            score.extend(&bytes[1..]);
+            write_vlq(&mut score, delay);
+        } else {
+            score.extend(bytes);
+        }
+    }
+
+    score.push(MUS_EVENT_SCORE_END << 4);
+
+    if score.len() > MUS_MAX_SCORE_LENGTH {
+        return Err(format!(
+            "The MUS score is {} bytes, over the {} byte limit of the format",
+            score.len(),
+            MUS_MAX_SCORE_LENGTH,
+        ));
+    }
+
+    // Melodic channels in use, the percussion channel is not counted
+    let primary_channels = mus_events
+        .iter()
+        .map(|(_, bytes)| bytes[0] & 0x0F)
+        .filter(|mus_channel| *mus_channel != MUS_PERCUSSION_CHANNEL)
+        .max()
+        .map_or(0, |mus_channel| mus_channel as u16 + 1);
+
+    let mut mus_bytes = b"MUS\x1A".to_vec();
+
+    for value in [
+        score.len() as u16,
+        16 + 2 * instruments.len() as u16,
+        primary_channels,
+        0,
+        instruments.len() as u16,
+        0,
+    ] {
+        mus_bytes.extend(value.to_le_bytes());
+    }
+
+    for instrument in instruments {
+        mus_bytes.extend(instrument.to_le_bytes());
+    }
+
+    mus_bytes.extend(score);
+    Ok(mus_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use midly::num::u7;
+
+    use super::*;
+
+    fn event(ticks: usize, channel: u8, message: MidiMessage) -> AbsoluteTrackEvent<'static> {
+        AbsoluteTrackEvent {
+            ticks,
+            ticks_event_start: ticks,
+            kind: TrackEventKind::Midi {
+                channel: u4::from(channel),
+                message,
+            },
+            precise_value: None,
+        }
+    }
+
+    fn note_on(ticks: usize, channel: u8, key: u8) -> AbsoluteTrackEvent<'static> {
+        let (key, vel) = (u7::from(key), u7::from(100));
+        event(ticks, channel, MidiMessage::NoteOn { key, vel })
+    }
+
+    fn note_off(ticks: usize, channel: u8, key: u8) -> AbsoluteTrackEvent<'static> {
+        let (key, vel) = (u7::from(key), u7::from(100));
+        event(ticks, channel, MidiMessage::NoteOff { key, vel })
+    }
+
+    #[test]
+    fn header_points_past_the_instruments_to_the_score() {
+        let mus_bytes = mus(&[
+            event(
+                0,
+                0,
+                MidiMessage::ProgramChange {
+                    program: u7::from(5),
+                },
+            ),
+            note_on(0, 0, 60),
+            note_on(0, 9, 36),
+            note_off(140, 0, 60),
+            note_off(140, 9, 36),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            mus_bytes,
+            [
+                b"MUS\x1A" as &[u8],
+                // Score length and offset, primary and secondary channels
+                &[16, 0, 20, 0, 1, 0, 0, 0],
+                // Instruments: program 5 and the bass drum
+                &[2, 0, 0, 0, 5, 0, 136, 0],
+                &[0x40, 0, 5],
+                &[0x10, 0x80 | 60, 100],
+                &[0x9F, 0x80 | 36, 100, 0x81, 0x0C],
+                &[0x00, 60],
+                &[0x0F, 36],
+                &[0x60],
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn notes_repeat_the_volume_only_when_it_changes() {
+        let mus_bytes = mus(&[
+            note_on(0, 1, 60),
+            note_off(1, 1, 60),
+            note_on(2, 1, 62),
+            note_off(3, 1, 62),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            mus_bytes[16..],
+            [
+                0x91,
+                0x80 | 60,
+                100,
+                1,
+                0x81,
+                60,
+                1,
+                0x91,
+                62,
+                1,
+                0x01,
+                62,
+                0x60
+            ]
+        );
+    }
+}
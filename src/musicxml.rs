@@ -0,0 +1,365 @@
+use std::fmt::Write;
+
+use crate::lmms_model::LMMS_TICKS_PER_BAR;
+use crate::notation::{
+    note_value, Chord, NotationVoice, Pitch, Score, LETTER_NAMES, NOTATION_GRID,
+};
+
+// Divisions of a quarter note, the notation grid being a single division
+const MUSICXML_DIVISIONS: usize = LMMS_TICKS_PER_BAR / 4 / NOTATION_GRID;
+
+/// Text escaped for element content and attribute values
+fn xml_text(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// MusicXML note type of a note value denominator
+fn note_type(denominator: usize) -> &'static str {
+    match denominator {
+        1 => "whole",
+        2 => "half",
+        4 => "quarter",
+        8 => "eighth",
+        16 => "16th",
+        _ => "32nd",
+    }
+}
+
+/// The attributes opening the first measure of a part
+fn write_attributes(musicxml: &mut String, score: &Score, voice: &NotationVoice) {
+    let (numerator, denominator) = score.time_signature;
+
+    let (clef_sign, clef_line) = if voice.is_percussion() {
+        ("percussion", 2)
+    } else if voice.is_low() {
+        ("F", 4)
+    } else {
+        ("G", 2)
+    };
+
+    writeln!(musicxml, "      <attributes>").unwrap();
+    writeln!(
+        musicxml,
+        "        <divisions>{MUSICXML_DIVISIONS}</divisions>"
+    )
+    .unwrap();
+    writeln!(musicxml, "        <key>").unwrap();
+    writeln!(
+        musicxml,
+        "          <fifths>{}</fifths>",
+        score.key_signature.sharps
+    )
+    .unwrap();
+    writeln!(
+        musicxml,
+        "          <mode>{}</mode>",
+        if score.key_signature.minor {
+            "minor"
+        } else {
+            "major"
+        }
+    )
+    .unwrap();
+    writeln!(musicxml, "        </key>").unwrap();
+    writeln!(musicxml, "        <time>").unwrap();
+    writeln!(musicxml, "          <beats>{numerator}</beats>").unwrap();
+    writeln!(musicxml, "          <beat-type>{denominator}</beat-type>").unwrap();
+    writeln!(musicxml, "        </time>").unwrap();
+    writeln!(musicxml, "        <clef>").unwrap();
+    writeln!(musicxml, "          <sign>{clef_sign}</sign>").unwrap();
+    writeln!(musicxml, "          <line>{clef_line}</line>").unwrap();
+    writeln!(musicxml, "        </clef>").unwrap();
+    writeln!(musicxml, "      </attributes>").unwrap();
+}
+
+/// Metronome mark and playback tempo
+fn write_tempo(musicxml: &mut String, bpm: f32) {
+    writeln!(musicxml, "      <direction placement=\"above\">").unwrap();
+    writeln!(musicxml, "        <direction-type>").unwrap();
+    writeln!(musicxml, "          <metronome>").unwrap();
+    writeln!(musicxml, "            <beat-unit>quarter</beat-unit>").unwrap();
+    writeln!(
+        musicxml,
+        "            <per-minute>{}</per-minute>",
+        bpm.round()
+    )
+    .unwrap();
+    writeln!(musicxml, "          </metronome>").unwrap();
+    writeln!(musicxml, "        </direction-type>").unwrap();
+    writeln!(musicxml, "        <sound tempo=\"{bpm}\"/>").unwrap();
+    writeln!(musicxml, "      </direction>").unwrap();
+}
+
+/// The notes of a chord, or a rest. Whole-bar rests are measure rests.
+fn write_chord(
+    musicxml: &mut String,
+    score: &Score,
+    chord: &Chord,
+    is_whole_bar: bool,
+    tied_keys: &mut Vec<u8>,
+) {
+    let duration = chord.duration / NOTATION_GRID;
+    let value = note_value(chord.duration);
+
+    let write_value = |musicxml: &mut String| {
+        if let Some((denominator, is_dotted)) = value {
+            writeln!(musicxml, "        <type>{}</type>", note_type(denominator)).unwrap();
+
+            if is_dotted {
+                writeln!(musicxml, "        <dot/>").unwrap();
+            }
+        }
+    };
+
+    if chord.is_rest() {
+        writeln!(musicxml, "      <note>").unwrap();
+
+        if is_whole_bar {
+            writeln!(musicxml, "        <rest measure=\"yes\"/>").unwrap();
+            writeln!(musicxml, "        <duration>{duration}</duration>").unwrap();
+            writeln!(musicxml, "        <voice>1</voice>").unwrap();
+        } else {
+            writeln!(musicxml, "        <rest/>").unwrap();
+            writeln!(musicxml, "        <duration>{duration}</duration>").unwrap();
+            writeln!(musicxml, "        <voice>1</voice>").unwrap();
+            write_value(musicxml);
+        }
+
+        writeln!(musicxml, "      </note>").unwrap();
+
+        tied_keys.clear();
+        return;
+    }
+
+    for (key_index, chord_key) in chord.keys.iter().enumerate() {
+        let pitch = Pitch::spell(chord_key.key, score.key_signature);
+        let tie_stop = tied_keys.contains(&chord_key.key);
+
+        writeln!(musicxml, "      <note>").unwrap();
+
+        if key_index > 0 {
+            writeln!(musicxml, "        <chord/>").unwrap();
+        }
+
+        writeln!(musicxml, "        <pitch>").unwrap();
+        writeln!(
+            musicxml,
+            "          <step>{}</step>",
+            LETTER_NAMES[pitch.letter]
+        )
+        .unwrap();
+
+        if pitch.alteration != 0 {
+            writeln!(musicxml, "          <alter>{}</alter>", pitch.alteration).unwrap();
+        }
+
+        writeln!(musicxml, "          <octave>{}</octave>", pitch.octave).unwrap();
+        writeln!(musicxml, "        </pitch>").unwrap();
+        writeln!(musicxml, "        <duration>{duration}</duration>").unwrap();
+
+        if tie_stop {
+            writeln!(musicxml, "        <tie type=\"stop\"/>").unwrap();
+        }
+
+        if chord_key.tied {
+            writeln!(musicxml, "        <tie type=\"start\"/>").unwrap();
+        }
+
+        writeln!(musicxml, "        <voice>1</voice>").unwrap();
+        write_value(musicxml);
+
+        if tie_stop || chord_key.tied {
+            writeln!(musicxml, "        <notations>").unwrap();
+
+            if tie_stop {
+                writeln!(musicxml, "          <tied type=\"stop\"/>").unwrap();
+            }
+
+            if chord_key.tied {
+                writeln!(musicxml, "          <tied type=\"start\"/>").unwrap();
+            }
+
+            writeln!(musicxml, "        </notations>").unwrap();
+        }
+
+        writeln!(musicxml, "      </note>").unwrap();
+    }
+
+    *tied_keys = chord
+        .keys
+        .iter()
+        .filter(|chord_key| chord_key.tied)
+        .map(|chord_key| chord_key.key)
+        .collect();
+}
+
+/// The score as an uncompressed MusicXML partwise document, a part per track
+/// playing on the track's MIDI channel and program
+pub fn musicxml(score: &Score) -> String {
+    let mut musicxml = String::new();
+
+    writeln!(
+        musicxml,
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>"
+    )
+    .unwrap();
+    writeln!(
+        musicxml,
+        "<!DOCTYPE score-partwise PUBLIC \"-//Recordare//DTD MusicXML 4.0 Partwise//EN\" \"http://www.musicxml.org/dtds/partwise.dtd\">"
+    )
+    .unwrap();
+    writeln!(musicxml, "<score-partwise version=\"4.0\">").unwrap();
+
+    if let Some(ref title) = score.title {
+        writeln!(musicxml, "  <work>").unwrap();
+        writeln!(musicxml, "    <work-title>{}</work-title>", xml_text(title)).unwrap();
+        writeln!(musicxml, "  </work>").unwrap();
+    }
+
+    writeln!(musicxml, "  <identification>").unwrap();
+    writeln!(musicxml, "    <encoding>").unwrap();
+    writeln!(
+        musicxml,
+        "      <software>lmms2mid {}</software>",
+        env!("CARGO_PKG_VERSION")
+    )
+    .unwrap();
+    writeln!(musicxml, "    </encoding>").unwrap();
+    writeln!(musicxml, "  </identification>").unwrap();
+
+    writeln!(musicxml, "  <part-list>").unwrap();
+
+    for (voice_index, voice) in score.voices.iter().enumerate() {
+        let part_id = format!("P{}", voice_index + 1);
+
+        writeln!(musicxml, "    <score-part id=\"{part_id}\">").unwrap();
+        writeln!(
+            musicxml,
+            "      <part-name>{}</part-name>",
+            xml_text(&voice.name)
+        )
+        .unwrap();
+        writeln!(musicxml, "      <score-instrument id=\"{part_id}-I1\">").unwrap();
+        writeln!(
+            musicxml,
+            "        <instrument-name>{}</instrument-name>",
+            xml_text(&voice.name)
+        )
+        .unwrap();
+        writeln!(musicxml, "      </score-instrument>").unwrap();
+        writeln!(musicxml, "      <midi-instrument id=\"{part_id}-I1\">").unwrap();
+        writeln!(
+            musicxml,
+            "        <midi-channel>{}</midi-channel>",
+            voice.midi_channel + 1
+        )
+        .unwrap();
+
+        if !voice.is_percussion() {
+            writeln!(
+                musicxml,
+                "        <midi-program>{}</midi-program>",
+                voice.midi_preset.program + 1
+            )
+            .unwrap();
+        }
+
+        writeln!(musicxml, "      </midi-instrument>").unwrap();
+        writeln!(musicxml, "    </score-part>").unwrap();
+    }
+
+    writeln!(musicxml, "  </part-list>").unwrap();
+
+    for (voice_index, voice) in score.voices.iter().enumerate() {
+        writeln!(musicxml, "  <part id=\"P{}\">", voice_index + 1).unwrap();
+
+        let mut tied_keys = Vec::new();
+
+        for (bar_index, bar) in score.bars(voice).iter().enumerate() {
+            writeln!(musicxml, "    <measure number=\"{}\">", bar_index + 1).unwrap();
+
+            if bar_index == 0 {
+                write_attributes(&mut musicxml, score, voice);
+
+                if voice_index == 0 {
+                    write_tempo(&mut musicxml, score.bpm);
+                }
+            }
+
+            let is_whole_bar_rest = bar.iter().all(Chord::is_rest);
+
+            if is_whole_bar_rest {
+                let whole_bar_rest = Chord {
+                    start: bar_index * score.ticks_per_bar(),
+                    duration: score.ticks_per_bar(),
+                    keys: Vec::new(),
+                };
+
+                write_chord(&mut musicxml, score, &whole_bar_rest, true, &mut tied_keys);
+            } else {
+                for chord in bar {
+                    write_chord(&mut musicxml, score, chord, false, &mut tied_keys);
+                }
+            }
+
+            writeln!(musicxml, "    </measure>").unwrap();
+        }
+
+        writeln!(musicxml, "  </part>").unwrap();
+    }
+
+    writeln!(musicxml, "</score-partwise>").unwrap();
+    musicxml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert::MidiPreset;
+    use crate::key_signature::KeySignature;
+    use crate::notation::NotationNote;
+
+    #[test]
+    fn notes_over_the_bar_line_are_tied() {
+        let score = Score {
+            title: None,
+            bpm: 120.0,
+            time_signature: (4, 4),
+            key_signature: KeySignature {
+                sharps: 0,
+                minor: false,
+            },
+            voices: vec![NotationVoice::new(
+                "Bass & Keys",
+                1,
+                MidiPreset {
+                    bank: 0,
+                    program: 32,
+                },
+                &[NotationNote {
+                    start: 144,
+                    end: 240,
+                    key: 48,
+                }],
+            )],
+        };
+
+        let musicxml = musicxml(&score);
+
+        assert!(musicxml.contains("<part-name>Bass &amp; Keys</part-name>"));
+        assert!(musicxml.contains("<midi-channel>2</midi-channel>"));
+        assert!(musicxml.contains("<midi-program>33</midi-program>"));
+        assert!(musicxml.contains("<divisions>8</divisions>"));
+        assert!(musicxml.contains("<sign>F</sign>"));
+        assert!(musicxml.contains("<measure number=\"2\">"));
+        assert!(!musicxml.contains("<measure number=\"3\">"));
+        assert_eq!(musicxml.matches("<step>C</step>").count(), 2);
+        assert_eq!(musicxml.matches("<octave>3</octave>").count(), 2);
+        assert_eq!(musicxml.matches("<tie type=\"start\"/>").count(), 1);
+        assert_eq!(musicxml.matches("<tie type=\"stop\"/>").count(), 1);
+        assert!(musicxml.ends_with("</score-partwise>\n"));
+    }
+}
@@ -0,0 +1,321 @@
+use std::collections::BTreeSet;
+
+use crate::convert::MidiPreset;
+use crate::key_signature::KeySignature;
+use crate::lmms_model::LMMS_TICKS_PER_BAR;
+
+// Note positions and lengths of sheet music output are rounded to 1/32 notes
+pub const NOTATION_GRID: usize = LMMS_TICKS_PER_BAR / 32;
+
+// Note values written without ties, longest first: whole to 1/32 notes with
+// their dotted values
+const NOTE_VALUES: [usize; 10] = [192, 144, 96, 72, 48, 36, 24, 18, 12, 6];
+
+// Letters of the key signature accidentals, in the order they are added
+const SHARP_LETTERS: [usize; 7] = [3, 0, 4, 1, 5, 2, 6];
+const FLAT_LETTERS: [usize; 7] = [6, 2, 5, 1, 4, 0, 3];
+
+// Letter (0 being C) and alteration of each pitch class, spelled with sharps
+// or with flats
+const SHARP_SPELLINGS: [(usize, i8); 12] = [
+    (0, 0),
+    (0, 1),
+    (1, 0),
+    (1, 1),
+    (2, 0),
+    (3, 0),
+    (3, 1),
+    (4, 0),
+    (4, 1),
+    (5, 0),
+    (5, 1),
+    (6, 0),
+];
+const FLAT_SPELLINGS: [(usize, i8); 12] = [
+    (0, 0),
+    (1, -1),
+    (1, 0),
+    (2, -1),
+    (2, 0),
+    (3, 0),
+    (4, -1),
+    (4, 0),
+    (5, -1),
+    (5, 0),
+    (6, -1),
+    (6, 0),
+];
+
+pub const LETTER_NAMES: [char; 7] = ['C', 'D', 'E', 'F', 'G', 'A', 'B'];
+
+/// A note of a voice in LMMS ticks, on the notation grid
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct NotationNote {
+    pub start: usize,
+    pub end: usize,
+    pub key: u8,
+}
+
+/// The notes of an LMMS track, a staff of the sheet music
+#[derive(Debug, Clone)]
+pub struct NotationVoice {
+    pub name: String,
+
+    /// First MIDI channel of the track, 0-15
+    pub midi_channel: u8,
+
+    pub midi_preset: MidiPreset,
+    pub notes: Vec<NotationNote>,
+}
+
+impl NotationVoice {
+    /// Voice of the notes rounded to the notation grid, notes shorter than the
+    /// grid are lengthened to a grid step
+    pub fn new(
+        name: &str,
+        midi_channel: u8,
+        midi_preset: MidiPreset,
+        notes: &[NotationNote],
+    ) -> Self {
+        let snap = |ticks: usize| (ticks + NOTATION_GRID / 2) / NOTATION_GRID * NOTATION_GRID;
+
+        let mut notes = notes
+            .iter()
+            .map(|note| {
+                let start = snap(note.start);
+                let end = snap(note.end).max(start + NOTATION_GRID);
+
+                NotationNote {
+                    start,
+                    end,
+                    ..*note
+                }
+            })
+            .collect::<Vec<_>>();
+
+        notes.sort_by_key(|note| (note.start, note.key));
+
+        NotationVoice {
+            name: name.to_string(),
+            midi_channel,
+            midi_preset,
+            notes,
+        }
+    }
+
+    pub fn is_percussion(&self) -> bool {
+        self.midi_preset.is_percussion()
+    }
+
+    /// Whether the notes sit mostly below middle C, calling for a bass clef
+    pub fn is_low(&self) -> bool {
+        !self.notes.is_empty()
+            && self
+                .notes
+                .iter()
+                .map(|note| note.key as usize)
+                .sum::<usize>()
+                < self.notes.len() * 60
+    }
+}
+
+/// A key of a chord, tied to the same key of the following chord
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ChordKey {
+    pub key: u8,
+    pub tied: bool,
+}
+
+/// Keys sounding together for one of the note values, a rest without keys
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chord {
+    pub start: usize,
+    pub duration: usize,
+    pub keys: Vec<ChordKey>,
+}
+
+impl Chord {
+    pub fn is_rest(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+/// Spelled pitch of a MIDI key
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Pitch {
+    /// Index of the letter, 0 being C
+    pub letter: usize,
+
+    /// Sharps (positive) or flats (negative)
+    pub alteration: i8,
+
+    /// Octave number, middle C being in octave 4
+    pub octave: i32,
+}
+
+impl Pitch {
+    /// Spells a key with sharps in sharp keys and with flats in flat keys
+    pub fn spell(key: u8, key_signature: KeySignature) -> Self {
+        let spellings = if key_signature.sharps >= 0 {
+            &SHARP_SPELLINGS
+        } else {
+            &FLAT_SPELLINGS
+        };
+
+        let (letter, alteration) = spellings[key as usize % 12];
+
+        Pitch {
+            letter,
+            alteration,
+            octave: key as i32 / 12 - 1,
+        }
+    }
+}
+
+/// Alteration the key signature applies to a letter
+pub fn key_signature_alteration(letter: usize, key_signature: KeySignature) -> i8 {
+    let count = key_signature.sharps.unsigned_abs() as usize;
+
+    if key_signature.sharps > 0 && SHARP_LETTERS[..count].contains(&letter) {
+        1
+    } else if key_signature.sharps < 0 && FLAT_LETTERS[..count].contains(&letter) {
+        -1
+    } else {
+        0
+    }
+}
+
+/// Splits a length into note values that need no dots beyond one, longest
+/// first, the remainder below the grid kept as is
+pub fn note_values(mut duration: usize) -> Vec<usize> {
+    let mut values = Vec::new();
+
+    while duration > 0 {
+        let value = NOTE_VALUES
+            .iter()
+            .copied()
+            .find(|value| *value <= duration)
+            .unwrap_or(duration);
+
+        values.push(value);
+        duration -= value;
+    }
+
+    values
+}
+
+/// Note value of a length as the denominator of the undotted value and
+/// whether it is dotted, e.g. (4, true) for a dotted quarter note
+pub fn note_value(duration: usize) -> Option<(usize, bool)> {
+    [1, 2, 4, 8, 16, 32].into_iter().find_map(|denominator| {
+        let undotted_duration = LMMS_TICKS_PER_BAR / denominator;
+
+        if duration == undotted_duration {
+            Some((denominator, false))
+        } else if duration == undotted_duration * 3 / 2 {
+            Some((denominator, true))
+        } else {
+            None
+        }
+    })
+}
+
+/// The song as sheet music: one voice per LMMS track in a single meter, key
+/// and tempo
+#[derive(Debug, Clone)]
+pub struct Score {
+    pub title: Option<String>,
+    pub bpm: f32,
+    pub time_signature: (usize, usize),
+    pub key_signature: KeySignature,
+    pub voices: Vec<NotationVoice>,
+}
+
+impl Score {
+    pub fn ticks_per_bar(&self) -> usize {
+        let (numerator, denominator) = self.time_signature;
+        (LMMS_TICKS_PER_BAR * numerator / denominator).max(1)
+    }
+
+    /// Number of bars up to the end of the last note, at least one
+    pub fn bar_count(&self) -> usize {
+        self.voices
+            .iter()
+            .flat_map(|voice| &voice.notes)
+            .map(|note| note.end.div_ceil(self.ticks_per_bar()))
+            .max()
+            .unwrap_or(0)
+            .max(1)
+    }
+
+    /// The chords and rests of a voice bar by bar. Notes sounding over the
+    /// start or end of another note and over bar lines are split with ties,
+    /// and every length is split into note values.
+    pub fn bars(&self, voice: &NotationVoice) -> Vec<Vec<Chord>> {
+        let ticks_per_bar = self.ticks_per_bar();
+        let bar_count = self.bar_count();
+
+        let mut boundaries = (0..=bar_count)
+            .map(|bar| bar * ticks_per_bar)
+            .collect::<BTreeSet<_>>();
+
+        for note in &voice.notes {
+            boundaries.insert(note.start);
+            boundaries.insert(note.end);
+        }
+
+        let boundaries = boundaries
+            .into_iter()
+            .filter(|ticks| *ticks <= bar_count * ticks_per_bar)
+            .collect::<Vec<_>>();
+
+        let mut bars = vec![Vec::new(); bar_count];
+
+        for segment in boundaries.windows(2) {
+            let (start, end) = (segment[0], segment[1]);
+
+            let mut keys: Vec<ChordKey> = Vec::new();
+
+            for note in &voice.notes {
+                if note.start > start || note.end <= start {
+                    continue;
+                }
+
+                let tied = note.end > end;
+
+                match keys.iter_mut().find(|chord_key| chord_key.key == note.key) {
+                    Some(chord_key) => chord_key.tied |= tied,
+                    None => keys.push(ChordKey {
+                        key: note.key,
+                        tied,
+                    }),
+                }
+            }
+
+            keys.sort_by_key(|chord_key| chord_key.key);
+
+            let values = note_values(end - start);
+            let mut value_start = start;
+
+            for (value_index, value) in values.iter().enumerate() {
+                let is_last_value = value_index == values.len() - 1;
+
+                bars[start / ticks_per_bar].push(Chord {
+                    start: value_start,
+                    duration: *value,
+                    keys: keys
+                        .iter()
+                        .map(|chord_key| ChordKey {
+                            tied: chord_key.tied || !is_last_value,
+                            ..*chord_key
+                        })
+                        .collect(),
+                });
+
+                value_start += value;
+            }
+        }
+
+        bars
+    }
+}
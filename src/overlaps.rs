@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+
+use clap::ValueEnum;
+use midly::num::u4;
+
+use crate::midi::MidiNote;
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum NoteOverlap {
+    /// Keep the overlapping notes and report them
+    #[default]
+    Keep,
+
+    /// End the earlier note where the later one starts
+    Truncate,
+
+    /// Merge the overlapping notes into one long note
+    Merge,
+
+    /// Move the later note onto another channel of the track (see `--spread`),
+    /// truncating when none is free
+    SplitChannel,
+}
+
+/// Resolves overlapping notes of the same key on the same channel, returns the
+/// number of overlaps resolved
+pub fn resolve_note_overlaps(
+    midi_notes: &mut Vec<MidiNote>,
+    note_overlap: NoteOverlap,
+    midi_channels: &[u4],
+) -> usize {
+    if note_overlap == NoteOverlap::Keep {
+        return 0;
+    }
+
+    midi_notes.sort_by_key(|midi_note| midi_note.ticks_start);
+
+    // Index of the last kept note for each channel and key
+    let mut last_notes: HashMap<_, usize> = HashMap::new();
+    let mut dropped_notes = vec![false; midi_notes.len()];
+    let mut resolved_overlaps = 0;
+
+    for note_index in 0..midi_notes.len() {
+        let midi_note = midi_notes[note_index];
+
+        let is_free = |midi_channel: u4| {
+            last_notes
+                .get(&(midi_channel, midi_note.key))
+                .is_none_or(|last_index| midi_notes[*last_index].ticks_end <= midi_note.ticks_start)
+        };
+
+        if is_free(midi_note.channel) {
+            last_notes.insert((midi_note.channel, midi_note.key), note_index);
+            continue;
+        }
+
+        resolved_overlaps += 1;
+        let last_index = last_notes[&(midi_note.channel, midi_note.key)];
+
+        let free_channel = midi_channels
+            .iter()
+            .copied()
+            .find(|midi_channel| is_free(*midi_channel));
+
+        match (note_overlap, free_channel) {
+            (NoteOverlap::Merge, _) => {
+                let last_note = &mut midi_notes[last_index];
+                last_note.ticks_end = last_note.ticks_end.max(midi_note.ticks_end);
+                dropped_notes[note_index] = true;
+            }
+            (NoteOverlap::SplitChannel, Some(free_channel)) => {
+                midi_notes[note_index].channel = free_channel;
+                last_notes.insert((free_channel, midi_note.key), note_index);
+            }
+            _ => {
+                let last_note = &mut midi_notes[last_index];
+                last_note.ticks_end = midi_note.ticks_start;
+
+                // Notes starting together would leave a zero length note behind
+                if last_note.ticks_end == last_note.ticks_start {
+                    dropped_notes[last_index] = true;
+                }
+
+                last_notes.insert((midi_note.channel, midi_note.key), note_index);
+            }
+        }
+    }
+
+    let mut dropped_notes = dropped_notes.into_iter();
+    midi_notes.retain(|_| !dropped_notes.next().unwrap());
+
+    resolved_overlaps
+}
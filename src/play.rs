@@ -0,0 +1,96 @@
+use std::error::Error;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::convert::{Conversion, ConvertOptions};
+use crate::lmms_model::LmmsProject;
+use crate::{note, warning};
+
+// Events are timed in tenths of milliseconds
+const PLAY_TICKS_PER_SECOND: f32 = 10000.0;
+
+// ALSA raw MIDI devices, one per port of a sound card or MIDI interface
+const RAW_MIDI_DEVICE_DIR: &str = "/dev/snd";
+const RAW_MIDI_DEVICE_PREFIX: &str = "midiC";
+
+/// Raw MIDI devices of the system, in card and device order
+pub fn midi_output_ports() -> Vec<PathBuf> {
+    let mut ports = fs::read_dir(RAW_MIDI_DEVICE_DIR)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|file_name| file_name.to_str())
+                .is_some_and(|file_name| file_name.starts_with(RAW_MIDI_DEVICE_PREFIX))
+        })
+        .collect::<Vec<_>>();
+
+    ports.sort();
+    ports
+}
+
+/// Streams the events of the single-track output to a raw MIDI device as they
+/// fall due, then silences every channel. Meta events are not sent.
+pub fn play(
+    port_path: &Path,
+    lmms_project: &LmmsProject,
+    conversion: &Conversion,
+    options: &ConvertOptions,
+) -> Result<(), Box<dyn Error>> {
+    if options.midi_port_count() > 1 {
+        warning!(code: "midi-ports-merged"; "playback uses a single MIDI port, the tracks of all MIDI ports are merged");
+    }
+
+    let mut port = OpenOptions::new()
+        .write(true)
+        .open(port_path)
+        .map_err(|err| format!("{}: {err}", port_path.display()))?;
+
+    let midi_track_events = conversion.fixed_rate_events(
+        lmms_project,
+        options,
+        conversion.events(),
+        PLAY_TICKS_PER_SECOND,
+    );
+
+    let duration = midi_track_events
+        .last()
+        .map_or(0.0, |event| event.ticks as f32 / PLAY_TICKS_PER_SECOND);
+
+    note!(
+        "playing {} events ({}:{:02}) on {}",
+        midi_track_events.len(),
+        duration as u32 / 60,
+        duration as u32 % 60,
+        port_path.display(),
+    );
+
+    let start = Instant::now();
+
+    for event in &midi_track_events {
+        let Some(live_event) = event.kind.as_live_event() else {
+            continue;
+        };
+
+        let due =
+            start + Duration::from_secs_f64(event.ticks as f64 / PLAY_TICKS_PER_SECOND as f64);
+        thread::sleep(due.saturating_duration_since(Instant::now()));
+
+        let mut bytes = Vec::new();
+        live_event.write_std(&mut bytes)?;
+        port.write_all(&bytes)?;
+    }
+
+    // All Sound Off and Reset All Controllers, for notes cut off by the end
+    for channel in 0..16 {
+        port.write_all(&[0xB0 | channel, 120, 0, 0xB0 | channel, 121, 0])?;
+    }
+
+    Ok(())
+}
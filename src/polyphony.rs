@@ -0,0 +1,214 @@
+use std::collections::{HashMap, VecDeque};
+
+use clap::ValueEnum;
+use midly::num::u4;
+use midly::{MidiMessage, TrackEventKind};
+
+use crate::midi::{AbsoluteTrackEvent, MIDI_MAX_POLYPHONY};
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum PolyphonyPolicy {
+    /// Cut the quietest sounding note, or skip the new one if it is the quietest
+    DropQuietest,
+
+    /// Cut the longest sounding note, like the voice stealing of most synths
+    #[default]
+    DropOldest,
+
+    /// Skip the notes starting while all voices are in use
+    DropNewest,
+}
+
+/// Voice budgets of a MIDI port, as a whole and for single channels
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct PolyphonyLimits {
+    /// Voices of the port, enforced when set
+    pub voices: Option<usize>,
+
+    /// Voices of single channels, e.g. fewer for the drum channel
+    pub channel_voices: [Option<usize>; 16],
+}
+
+impl PolyphonyLimits {
+    /// Whether notes have to be dropped or shortened to fit the limits
+    pub fn is_enforced(&self) -> bool {
+        self.voices.is_some() || self.channel_voices.iter().any(Option::is_some)
+    }
+
+    /// Voices of the port diagnostics are reported against
+    pub fn port_voices(&self) -> usize {
+        self.voices.unwrap_or(MIDI_MAX_POLYPHONY)
+    }
+
+    pub fn channel_voices(&self, midi_channel: u4) -> Option<usize> {
+        self.channel_voices[midi_channel.as_int() as usize]
+    }
+}
+
+/// Command line value parser for channel voice limits ("10=8"), channels are
+/// numbered from 1
+pub fn parse_channel_polyphony(s: &str) -> Result<(u4, usize), String> {
+    let (midi_channel, voices) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected '<channel>=<voices>', found '{s}'"))?;
+
+    let midi_channel = midi_channel
+        .trim()
+        .parse::<u8>()
+        .ok()
+        .filter(|midi_channel| (1..=16).contains(midi_channel))
+        .ok_or_else(|| format!("invalid MIDI channel '{midi_channel}', expected 1-16"))?;
+
+    let voices = voices
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .filter(|voices| *voices > 0)
+        .ok_or_else(|| format!("invalid voice count '{voices}'"))?;
+
+    Ok((u4::from(midi_channel - 1), voices))
+}
+
+/// A Note On/Note Off event pair within the event lists of a port
+#[derive(Debug, Copy, Clone)]
+struct PolyphonyNote {
+    track: usize,
+    channel: u4,
+    note_on: usize,
+    note_off: usize,
+    ticks_start: usize,
+    ticks_end: usize,
+    velocity: u8,
+}
+
+/// Drops or shortens notes so that no more of them sound at once across the
+/// event lists of a MIDI port, or on one of its channels, than the limits allow.
+/// Returns the number of notes changed, the event lists need to be sorted again
+/// afterwards.
+pub fn limit_polyphony(
+    track_events: &mut [&mut Vec<AbsoluteTrackEvent<'static>>],
+    polyphony_limits: &PolyphonyLimits,
+    polyphony_policy: PolyphonyPolicy,
+) -> usize {
+    let mut notes = Vec::new();
+
+    for (track, midi_track_events) in track_events.iter().enumerate() {
+        let mut pending_notes: HashMap<_, VecDeque<_>> = HashMap::new();
+
+        for (event_index, event) in midi_track_events.iter().enumerate() {
+            match event.kind {
+                TrackEventKind::Midi {
+                    channel,
+                    message: MidiMessage::NoteOn { key, vel },
+                } => {
+                    pending_notes
+                        .entry((channel, key, event.ticks))
+                        .or_default()
+                        .push_back((event_index, vel.as_int()));
+                }
+                TrackEventKind::Midi {
+                    channel,
+                    message: MidiMessage::NoteOff { key, .. },
+                } => {
+                    let (note_on, velocity) = pending_notes
+                        .get_mut(&(channel, key, event.ticks_event_start))
+                        .and_then(VecDeque::pop_front)
+                        .expect("failed to find Note On event");
+
+                    notes.push(PolyphonyNote {
+                        track,
+                        channel,
+                        note_on,
+                        note_off: event_index,
+                        ticks_start: event.ticks_event_start,
+                        ticks_end: event.ticks,
+                        velocity,
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+
+    notes.sort_by_key(|note| (note.ticks_start, note.ticks_end));
+
+    let mut dropped_notes = vec![false; notes.len()];
+    let mut changed_notes = 0;
+    let mut active_notes: Vec<usize> = Vec::new();
+
+    for note_index in 0..notes.len() {
+        let note = notes[note_index];
+
+        // Zero length notes never take a voice
+        if note.ticks_start == note.ticks_end {
+            continue;
+        }
+
+        active_notes.retain(|active_index| notes[*active_index].ticks_end > note.ticks_start);
+
+        let channel_notes = active_notes
+            .iter()
+            .copied()
+            .filter(|active_index| notes[*active_index].channel == note.channel)
+            .collect::<Vec<_>>();
+
+        // A full channel gives way within itself, a full port anywhere
+        let candidates = match polyphony_limits.channel_voices(note.channel) {
+            Some(channel_voices) if channel_notes.len() >= channel_voices => channel_notes,
+            _ if active_notes.len() >= polyphony_limits.voices.unwrap_or(usize::MAX) => {
+                active_notes.clone()
+            }
+            _ => {
+                active_notes.push(note_index);
+                continue;
+            }
+        };
+
+        changed_notes += 1;
+
+        let victim = match polyphony_policy {
+            PolyphonyPolicy::DropQuietest => candidates
+                .iter()
+                .copied()
+                .min_by_key(|active_index| notes[*active_index].velocity)
+                .filter(|active_index| notes[*active_index].velocity < note.velocity),
+            PolyphonyPolicy::DropOldest => candidates.first().copied(),
+            PolyphonyPolicy::DropNewest => None,
+        };
+
+        let Some(victim) = victim else {
+            dropped_notes[note_index] = true;
+            continue;
+        };
+
+        if notes[victim].ticks_start == note.ticks_start {
+            dropped_notes[victim] = true;
+        } else {
+            notes[victim].ticks_end = note.ticks_start;
+        }
+
+        active_notes.retain(|active_index| *active_index != victim);
+        active_notes.push(note_index);
+    }
+
+    let mut removed_events = track_events
+        .iter()
+        .map(|midi_track_events| vec![false; midi_track_events.len()])
+        .collect::<Vec<_>>();
+
+    for (note, dropped) in notes.iter().zip(dropped_notes) {
+        if dropped {
+            removed_events[note.track][note.note_on] = true;
+            removed_events[note.track][note.note_off] = true;
+        } else {
+            track_events[note.track][note.note_off].ticks = note.ticks_end;
+        }
+    }
+
+    for (midi_track_events, removed_events) in track_events.iter_mut().zip(removed_events) {
+        let mut removed_events = removed_events.into_iter();
+        midi_track_events.retain(|_| !removed_events.next().unwrap());
+    }
+
+    changed_notes
+}
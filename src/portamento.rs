@@ -0,0 +1,101 @@
+use midly::num::{u4, u7};
+use midly::{MidiMessage, TrackEventKind};
+
+use crate::lmms_model::LMMS_TICKS_PER_BAR;
+use crate::midi::{AbsoluteTrackEvent, MidiNote, MIDI_CC_PORTAMENTO, MIDI_CC_PORTAMENTO_TIME};
+
+// Largest interval in semitones glided over
+const PORTAMENTO_MAX_INTERVAL: u8 = 2;
+
+/// Portamento events around legato note pairs (a note starting before the
+/// previous one ends, a step apart) of monophonic tracks. Portamento is switched
+/// on at the start of the second note and off once the first one is released,
+/// with the portamento time following the length of the overlap.
+pub fn portamento_events(
+    midi_notes: &[MidiNote],
+    is_percussion_channel: impl Fn(u4) -> bool,
+) -> Vec<AbsoluteTrackEvent<'static>> {
+    let mut midi_notes = midi_notes
+        .iter()
+        .filter(|midi_note| !is_percussion_channel(midi_note.channel))
+        .copied()
+        .collect::<Vec<_>>();
+
+    midi_notes.sort_by_key(|midi_note| (midi_note.ticks_start, midi_note.ticks_end));
+
+    // Chords are not slides
+    let is_monophonic = midi_notes
+        .windows(3)
+        .all(|window| window[0].ticks_end <= window[2].ticks_start);
+
+    if !is_monophonic {
+        return Vec::new();
+    }
+
+    let mut midi_track_events = Vec::new();
+
+    for window in midi_notes.windows(2) {
+        let (first_note, second_note) = (window[0], window[1]);
+
+        if first_note.channel != second_note.channel
+            || second_note.ticks_start <= first_note.ticks_start
+            || second_note.ticks_start >= first_note.ticks_end
+            || second_note.ticks_end <= first_note.ticks_end
+            || first_note.key.as_int().abs_diff(second_note.key.as_int()) > PORTAMENTO_MAX_INTERVAL
+        {
+            continue;
+        }
+
+        // A beat of overlap is a medium portamento time
+        let overlap = first_note.ticks_end - second_note.ticks_start;
+        let portamento_time = (overlap * 64 / (LMMS_TICKS_PER_BAR / 4)).clamp(1, 127) as u8;
+
+        midi_track_events.extend([
+            controller_event(
+                second_note.ticks_start,
+                second_note.ticks_start,
+                second_note.channel,
+                MIDI_CC_PORTAMENTO_TIME,
+                portamento_time,
+            ),
+            controller_event(
+                second_note.ticks_start,
+                second_note.ticks_start,
+                second_note.channel,
+                MIDI_CC_PORTAMENTO,
+                127,
+            ),
+            // Sorted after the Note Off of the first note
+            controller_event(
+                first_note.ticks_end,
+                second_note.ticks_start,
+                second_note.channel,
+                MIDI_CC_PORTAMENTO,
+                0,
+            ),
+        ]);
+    }
+
+    midi_track_events
+}
+
+fn controller_event(
+    ticks: usize,
+    ticks_event_start: usize,
+    midi_channel: u4,
+    controller: u8,
+    value: u8,
+) -> AbsoluteTrackEvent<'static> {
+    AbsoluteTrackEvent {
+        ticks,
+        ticks_event_start,
+        kind: TrackEventKind::Midi {
+            channel: midi_channel,
+            message: MidiMessage::Controller {
+                controller: u7::from(controller),
+                value: u7::from(value),
+            },
+        },
+        precise_value: None,
+    }
+}
@@ -0,0 +1,133 @@
+use std::fmt::Write;
+
+use midly::{MidiMessage, TrackEventKind};
+
+use crate::convert::Conversion;
+use crate::dump::note_name;
+use crate::lmms_model::{LmmsProject, LMMS_TICKS_PER_BAR};
+
+// A column per sixteenth note
+const PREVIEW_TICKS_PER_COLUMN: usize = LMMS_TICKS_PER_BAR / 16;
+
+// Width of the note name column, e.g. "C#-1 "
+const PREVIEW_LABEL_WIDTH: usize = 5;
+
+/// Symbol of a MIDI channel 0-15 at the start of its notes, 1-9 then A-G
+fn channel_symbol(midi_channel: u8) -> char {
+    char::from_digit(midi_channel as u32 + 1, 17)
+        .unwrap_or('?')
+        .to_ascii_uppercase()
+}
+
+/// A text piano roll of the converted notes: a row per key, highest first,
+/// and a column per sixteenth note. Notes start with the symbol of their MIDI
+/// channel (1-9, A-G) and are held with "=". Bars are stacked in systems
+/// fitting the given width, each system covering only the keys it plays.
+pub fn preview(lmms_project: &LmmsProject, conversion: &Conversion, width: usize) -> String {
+    let mut preview = String::new();
+
+    let notes = conversion
+        .events()
+        .iter()
+        .filter_map(|event| match event.kind {
+            TrackEventKind::Midi {
+                channel,
+                message: MidiMessage::NoteOff { key, .. },
+            } => Some((
+                event.ticks_event_start,
+                event.ticks,
+                key.as_int(),
+                channel.as_int(),
+            )),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let Some(song_end) = notes.iter().map(|(_, end, _, _)| *end).max() else {
+        writeln!(preview, "no notes").unwrap();
+        return preview;
+    };
+
+    let columns_per_bar = lmms_project
+        .lmms_ticks_per_bar()
+        .div_ceil(PREVIEW_TICKS_PER_COLUMN);
+    let ticks_per_bar = columns_per_bar * PREVIEW_TICKS_PER_COLUMN;
+    let bar_count = song_end.div_ceil(ticks_per_bar).max(1);
+
+    // Bar lines take a column of their own
+    let bars_per_system =
+        (width.saturating_sub(PREVIEW_LABEL_WIDTH + 1) / (columns_per_bar + 1)).max(1);
+
+    for first_bar in (0..bar_count).step_by(bars_per_system) {
+        let last_bar = (first_bar + bars_per_system).min(bar_count);
+        let system_start = first_bar * ticks_per_bar;
+        let system_end = last_bar * ticks_per_bar;
+        let column_count = (last_bar - first_bar) * columns_per_bar;
+
+        let system_notes = notes
+            .iter()
+            .filter(|(start, end, _, _)| *start < system_end && *end > system_start)
+            .collect::<Vec<_>>();
+
+        if !preview.is_empty() {
+            writeln!(preview).unwrap();
+        }
+
+        // Bar numbers above their bar lines
+        let mut header = " ".repeat(PREVIEW_LABEL_WIDTH);
+        for bar in first_bar..last_bar {
+            write!(header, "{:<width$}", bar + 1, width = columns_per_bar + 1).unwrap();
+        }
+        writeln!(preview, "{}", header.trim_end()).unwrap();
+
+        let (Some(lowest_key), Some(highest_key)) = (
+            system_notes.iter().map(|(_, _, key, _)| *key).min(),
+            system_notes.iter().map(|(_, _, key, _)| *key).max(),
+        ) else {
+            writeln!(preview, "{:PREVIEW_LABEL_WIDTH$}(rest)", "").unwrap();
+            continue;
+        };
+
+        for key in (lowest_key..=highest_key).rev() {
+            let mut cells = vec!['.'; column_count];
+
+            for (start, end, _, midi_channel) in system_notes
+                .iter()
+                .filter(|(_, _, note_key, _)| *note_key == key)
+            {
+                let first_column = start.saturating_sub(system_start) / PREVIEW_TICKS_PER_COLUMN;
+                let last_column = (end.saturating_sub(system_start))
+                    .div_ceil(PREVIEW_TICKS_PER_COLUMN)
+                    .max(first_column + 1)
+                    .min(column_count);
+
+                for (column, cell) in cells
+                    .iter_mut()
+                    .enumerate()
+                    .take(last_column)
+                    .skip(first_column)
+                {
+                    // Later notes do not hide the start of an earlier one
+                    if *cell == '.' || *cell == '=' {
+                        *cell = if column == first_column && *start >= system_start {
+                            channel_symbol(*midi_channel)
+                        } else {
+                            '='
+                        };
+                    }
+                }
+            }
+
+            let mut row = format!("{:<PREVIEW_LABEL_WIDTH$}", note_name(key));
+
+            for bar_cells in cells.chunks(columns_per_bar) {
+                row.push('|');
+                row.extend(bar_cells);
+            }
+
+            writeln!(preview, "{row}|").unwrap();
+        }
+    }
+
+    preview
+}
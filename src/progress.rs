@@ -0,0 +1,193 @@
+use std::io::{self, IsTerminal, Write};
+use std::sync::Mutex;
+
+/// Phase of the conversion pipeline, reported in order
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Phase {
+    Parse,
+    Convert,
+    Sort,
+    Validate,
+    Write,
+}
+
+impl Phase {
+    fn name(self) -> &'static str {
+        match self {
+            Phase::Parse => "parse",
+            Phase::Convert => "convert",
+            Phase::Sort => "sort",
+            Phase::Validate => "validate",
+            Phase::Write => "write",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ProgressMode {
+    Off,
+
+    /// A bar redrawn in place on the terminal
+    Bar,
+
+    /// A line per tenth of a phase, for logs
+    Lines,
+}
+
+struct ProgressState {
+    mode: ProgressMode,
+    phase: Option<Phase>,
+    total: Option<usize>,
+    done: usize,
+
+    /// Percentage last reported, to skip redundant redraws
+    reported_percent: Option<usize>,
+
+    /// Whether the bar is on the terminal and has to be erased before
+    /// printing anything else
+    bar_drawn: bool,
+}
+
+const BAR_WIDTH: usize = 30;
+
+static PROGRESS: Mutex<ProgressState> = Mutex::new(ProgressState {
+    mode: ProgressMode::Off,
+    phase: None,
+    total: None,
+    done: 0,
+    reported_percent: None,
+    bar_drawn: false,
+});
+
+/// Enables progress reporting, as a bar when stderr is a terminal or as lines
+/// of text when `--progress` is given for a log. `-q` keeps the terminal quiet.
+pub fn set_progress(forced: bool, quiet: bool) {
+    let mode = match (forced, io::stderr().is_terminal()) {
+        (_, true) if forced || !quiet => ProgressMode::Bar,
+        (true, false) => ProgressMode::Lines,
+        _ => ProgressMode::Off,
+    };
+
+    PROGRESS.lock().unwrap().mode = mode;
+}
+
+/// Starts a phase of `total` steps, or of unknown length, finishing the
+/// previous one
+pub fn start_phase(phase: Phase, total: Option<usize>) {
+    let mut progress = PROGRESS.lock().unwrap();
+
+    // The phase goes on, e.g. writing the file after encoding the output
+    if progress.mode == ProgressMode::Off || (progress.phase == Some(phase) && total.is_none()) {
+        return;
+    }
+
+    progress.phase = Some(phase);
+    progress.total = total;
+    progress.done = 0;
+    progress.reported_percent = None;
+    progress.report();
+}
+
+/// Counts a step of the current phase as done
+pub fn advance() {
+    let mut progress = PROGRESS.lock().unwrap();
+
+    if progress.mode == ProgressMode::Off || progress.phase.is_none() {
+        return;
+    }
+
+    progress.done += 1;
+    progress.report();
+}
+
+/// Ends the last phase, erasing the bar
+pub fn finish() {
+    let mut progress = PROGRESS.lock().unwrap();
+
+    progress.erase();
+    progress.phase = None;
+}
+
+/// Erases the bar so that a diagnostic can be printed, it is drawn again on
+/// the next step
+pub fn clear() {
+    PROGRESS.lock().unwrap().erase();
+}
+
+impl ProgressState {
+    fn percent(&self) -> Option<usize> {
+        self.total
+            .map(|total| (self.done * 100).checked_div(total).unwrap_or(100).min(100))
+    }
+
+    fn report(&mut self) {
+        let Some(phase) = self.phase else {
+            return;
+        };
+
+        let percent = self.percent();
+
+        // Lines are only written every 10 percent
+        let reported_percent = match self.mode {
+            ProgressMode::Lines => percent.map(|percent| percent / 10 * 10),
+            _ => percent,
+        };
+
+        if self.bar_drawn && reported_percent == self.reported_percent {
+            return;
+        }
+
+        if self.mode == ProgressMode::Lines
+            && self.reported_percent.is_some()
+            && reported_percent == self.reported_percent
+        {
+            return;
+        }
+
+        self.reported_percent = reported_percent;
+
+        // Progress is best effort, failing to write it fails no conversion
+        let mut stderr = io::stderr().lock();
+
+        match (self.mode, percent) {
+            (ProgressMode::Bar, Some(percent)) => {
+                let filled = percent * BAR_WIDTH / 100;
+                let _ = write!(
+                    stderr,
+                    "\r\x1b[K{:<8} [{}{}] {percent:>3}% ({}/{})",
+                    phase.name(),
+                    "=".repeat(filled),
+                    " ".repeat(BAR_WIDTH - filled),
+                    self.done,
+                    self.total.unwrap_or_default()
+                );
+                self.bar_drawn = true;
+            }
+            (ProgressMode::Bar, None) => {
+                let _ = write!(stderr, "\r\x1b[K{:<8} ...", phase.name());
+                self.bar_drawn = true;
+            }
+            (ProgressMode::Lines, Some(_)) => {
+                let _ = writeln!(
+                    stderr,
+                    "progress: {} {}%",
+                    phase.name(),
+                    reported_percent.unwrap_or_default()
+                );
+            }
+            (ProgressMode::Lines, None) => {
+                let _ = writeln!(stderr, "progress: {}", phase.name());
+            }
+            (ProgressMode::Off, _) => {}
+        }
+
+        let _ = stderr.flush();
+    }
+
+    fn erase(&mut self) {
+        if self.bar_drawn {
+            let _ = write!(io::stderr(), "\r\x1b[K");
+            self.bar_drawn = false;
+        }
+    }
+}
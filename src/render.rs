@@ -0,0 +1,415 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+
+use midly::num::u4;
+use midly::{MidiMessage, TrackEventKind};
+
+use crate::convert::{fixed_rate_ticks, Conversion, ConvertOptions};
+use crate::dls::{project_presets, ProjectPreset, SoundFont, DLS_F_INSTRUMENT_DRUMS};
+use crate::lmms_model::LmmsProject;
+use crate::midi::*;
+use crate::rmid::riff_chunk;
+use crate::{note, warning};
+
+pub const RENDER_SAMPLE_RATE: u32 = 44100;
+
+// Seconds rendered after the last event at most, for the notes to fade out
+const RENDER_MAX_TAIL_SECONDS: u32 = 10;
+
+// Semitones of the full pitch bend, the General MIDI default
+const RENDER_PITCH_BEND_RANGE: f32 = 2.0;
+
+// Fastest release of a voice, avoiding clicks on note ends
+const RENDER_MIN_RELEASE_TIME: f32 = 0.005;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum VoiceState {
+    Held,
+
+    /// Released while the sustain pedal is down
+    Sustained,
+
+    Released,
+}
+
+/// A sample playing on a channel
+struct Voice<'a> {
+    channel: u4,
+    key: u8,
+    state: VoiceState,
+
+    soundfont: &'a SoundFont,
+    sample_index: usize,
+    loop_range: Option<(usize, usize)>,
+
+    /// Position in the soundfont's samples
+    position: f64,
+
+    /// Samples advanced per frame, without the pitch bend
+    step: f64,
+
+    gain: f32,
+
+    /// Release envelope, falling from 1 to 0
+    envelope: f32,
+    envelope_step: f32,
+}
+
+impl Voice<'_> {
+    /// Next sample at the given pitch bend factor, or None when the sample ended
+    fn next_sample(&mut self, bend_factor: f64) -> Option<f32> {
+        let sample_header = &self.soundfont.sample_headers[self.sample_index];
+        let sample_at = |index: usize| {
+            let offset = index * 2;
+            i16::from_le_bytes([
+                self.soundfont.sample_data[offset],
+                self.soundfont.sample_data[offset + 1],
+            ]) as f32
+                / 32768.0
+        };
+
+        let index = self.position as usize;
+        let next_index = match self.loop_range {
+            Some((loop_start, loop_end)) if index + 1 >= loop_end => loop_start,
+            _ => index + 1,
+        };
+
+        if next_index >= sample_header.end {
+            return None;
+        }
+
+        let fraction = (self.position - index as f64) as f32;
+        let sample = sample_at(index) * (1.0 - fraction) + sample_at(next_index) * fraction;
+
+        self.position += self.step * bend_factor;
+
+        if let Some((loop_start, loop_end)) = self.loop_range {
+            while self.position >= loop_end as f64 {
+                self.position -= (loop_end - loop_start) as f64;
+            }
+        }
+
+        if self.state == VoiceState::Released {
+            self.envelope -= self.envelope_step;
+
+            if self.envelope <= 0.0 {
+                return None;
+            }
+        }
+
+        Some(sample * self.gain * self.envelope)
+    }
+}
+
+/// Controller state of a MIDI channel
+struct ChannelState {
+    bank_coarse: u8,
+    bank_fine: u8,
+    program: u8,
+    volume: u8,
+    expression: u8,
+    panning: u8,
+    pitch_bend: f32,
+    sustain: bool,
+}
+
+impl Default for ChannelState {
+    fn default() -> Self {
+        ChannelState {
+            bank_coarse: 0,
+            bank_fine: 0,
+            program: 0,
+            volume: 100,
+            expression: 127,
+            panning: 64,
+            pitch_bend: 0.0,
+            sustain: false,
+        }
+    }
+}
+
+impl ChannelState {
+    /// Left and right gain of the channel, with a constant power pan law
+    fn gains(&self) -> (f32, f32) {
+        let gain = (self.volume as f32 / 127.0).powi(2) * (self.expression as f32 / 127.0).powi(2);
+        let pan_angle =
+            (self.panning.saturating_sub(1) as f32 / 126.0) * std::f32::consts::FRAC_PI_2;
+
+        (gain * pan_angle.cos(), gain * pan_angle.sin())
+    }
+
+    fn bend_factor(&self) -> f64 {
+        2f64.powf((self.pitch_bend * RENDER_PITCH_BEND_RANGE) as f64 / 12.0)
+    }
+}
+
+/// Renders the conversion with the project's SF2 presets into 16-bit stereo
+/// frames, as a preview of how the MIDI file sounds. Regions play their
+/// samples with the tuning, attenuation, loop and volume envelope release of
+/// the presets; other envelope stages, filters, modulators and effects are
+/// not rendered. Songs louder than full scale are scaled down.
+pub fn render(
+    project_path: &Path,
+    lmms_project: &LmmsProject,
+    conversion: &Conversion,
+    options: &ConvertOptions,
+) -> Result<Vec<(i16, i16)>, Box<dyn Error>> {
+    let (soundfonts, presets) = project_presets(
+        project_path,
+        lmms_project,
+        conversion.channel_assignments(),
+        options,
+    )?;
+
+    if options.midi_port_count() > 1 {
+        warning!(code: "midi-ports-merged"; "the rendering has a single set of 16 channels, the tracks of all MIDI ports are merged");
+    }
+
+    let presets_by_locale = presets
+        .iter()
+        .map(|preset| ((preset.midi_bank, preset.midi_program), preset))
+        .collect::<HashMap<_, &ProjectPreset>>();
+
+    let percussion_channel = (!options.is_channel_10_melodic(lmms_project)).then_some(u4::from(9));
+
+    let midi_track_events = conversion.fixed_rate_events(
+        lmms_project,
+        options,
+        conversion.events(),
+        RENDER_SAMPLE_RATE as f32,
+    );
+
+    let mut channels: [ChannelState; 16] = Default::default();
+    let mut voices: Vec<Voice> = Vec::new();
+    let mut frames: Vec<(f32, f32)> = Vec::new();
+    let mut silent_notes = 0;
+
+    for event in &midi_track_events {
+        render_frames(&mut frames, event.ticks, &mut voices, &channels);
+
+        let TrackEventKind::Midi { channel, message } = event.kind else {
+            continue;
+        };
+        let channel_state = &mut channels[channel.as_int() as usize];
+
+        match message {
+            MidiMessage::NoteOn { key, vel } if vel > 0 => {
+                let mut midi_bank =
+                    (channel_state.bank_coarse as u32) << 8 | channel_state.bank_fine as u32;
+
+                if Some(channel) == percussion_channel {
+                    midi_bank |= DLS_F_INSTRUMENT_DRUMS;
+                }
+
+                let Some(preset) =
+                    presets_by_locale.get(&(midi_bank, channel_state.program as u32))
+                else {
+                    silent_notes += 1;
+                    continue;
+                };
+
+                let soundfont = &soundfonts[&preset.soundfont_path];
+
+                for region in &preset.regions {
+                    let (key, vel) = (key.as_int(), vel.as_int());
+
+                    if !(region.key_range.0..=region.key_range.1).contains(&key)
+                        || !(region.velocity_range.0..=region.velocity_range.1).contains(&vel)
+                    {
+                        continue;
+                    }
+
+                    let sample_header = &soundfont.sample_headers[region.sample_index];
+                    let semitones =
+                        key as f64 - region.unity_note as f64 + region.fine_tune as f64 / 100.0;
+
+                    voices.push(Voice {
+                        channel,
+                        key,
+                        state: VoiceState::Held,
+                        soundfont,
+                        sample_index: region.sample_index,
+                        loop_range: (region.is_looped && sample_header.has_valid_loop())
+                            .then_some((sample_header.loop_start, sample_header.loop_end)),
+                        position: sample_header.start as f64,
+                        step: sample_header.sample_rate as f64 / RENDER_SAMPLE_RATE as f64
+                            * 2f64.powf(semitones / 12.0),
+                        gain: (vel as f32 / 127.0).powi(2)
+                            * 10f32.powf(-region.attenuation as f32 / 200.0),
+                        envelope: 1.0,
+                        envelope_step: 1.0
+                            / (region.release_time.max(RENDER_MIN_RELEASE_TIME)
+                                * RENDER_SAMPLE_RATE as f32),
+                    });
+                }
+            }
+            MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                for voice in &mut voices {
+                    if voice.channel == channel
+                        && voice.key == key.as_int()
+                        && voice.state == VoiceState::Held
+                    {
+                        voice.state = if channel_state.sustain {
+                            VoiceState::Sustained
+                        } else {
+                            VoiceState::Released
+                        };
+                    }
+                }
+            }
+            MidiMessage::Controller { controller, value } => {
+                let value = value.as_int();
+
+                match controller.as_int() {
+                    MIDI_CC_BANK_SELECT_COARSE => channel_state.bank_coarse = value,
+                    MIDI_CC_BANK_SELECT_FINE => channel_state.bank_fine = value,
+                    MIDI_CC_VOLUME => channel_state.volume = value,
+                    MIDI_CC_EXPRESSION => channel_state.expression = value,
+                    MIDI_CC_PANNING => channel_state.panning = value,
+                    MIDI_CC_SUSTAIN => {
+                        channel_state.sustain = value >= 64;
+
+                        if !channel_state.sustain {
+                            for voice in &mut voices {
+                                if voice.channel == channel && voice.state == VoiceState::Sustained
+                                {
+                                    voice.state = VoiceState::Released;
+                                }
+                            }
+                        }
+                    }
+                    // All Sound Off
+                    120 => voices.retain(|voice| voice.channel != channel),
+                    // All Notes Off
+                    123 => {
+                        for voice in &mut voices {
+                            if voice.channel == channel && voice.state == VoiceState::Held {
+                                voice.state = VoiceState::Released;
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            MidiMessage::ProgramChange { program } => channel_state.program = program.as_int(),
+            MidiMessage::PitchBend { bend } => channel_state.pitch_bend = bend.as_f32(),
+            MidiMessage::Aftertouch { .. } | MidiMessage::ChannelAftertouch { .. } => {}
+        }
+    }
+
+    // Let the last notes fade out
+    let ticks_end = frames.len() + (RENDER_MAX_TAIL_SECONDS * RENDER_SAMPLE_RATE) as usize;
+    for voice in &mut voices {
+        voice.state = VoiceState::Released;
+    }
+
+    while !voices.is_empty() && frames.len() < ticks_end {
+        let ticks = (frames.len() + RENDER_SAMPLE_RATE as usize / 10).min(ticks_end);
+        render_frames(&mut frames, ticks, &mut voices, &channels);
+    }
+
+    if silent_notes > 0 {
+        warning!(
+            code: "silent-notes";
+            "{silent_notes} notes were not rendered, their channels select no SF2 preset of the project"
+        );
+    }
+
+    let peak = frames
+        .iter()
+        .map(|(left, right)| left.abs().max(right.abs()))
+        .fold(0.0, f32::max);
+
+    let scale = if peak > 1.0 {
+        note!(
+            "the rendering was scaled by {:.1} dB to avoid clipping",
+            -20.0 * peak.log10()
+        );
+        1.0 / peak
+    } else {
+        1.0
+    };
+
+    let to_i16 = |sample: f32| (sample * scale * 32767.0).round() as i16;
+
+    Ok(frames
+        .into_iter()
+        .map(|(left, right)| (to_i16(left), to_i16(right)))
+        .collect())
+}
+
+/// Song loop points in rendered frames as the LOOPSTART and LOOPLENGTH tags of
+/// RPG Maker MV/MZ, if loops are converted
+pub fn loop_tags(lmms_project: &LmmsProject, options: &ConvertOptions) -> Vec<String> {
+    let loop_points = lmms_project.loop_points();
+
+    if options.loop_style.is_empty() || loop_points.end <= loop_points.start {
+        return Vec::new();
+    }
+
+    let render_ticks =
+        |ticks| fixed_rate_ticks(ticks, lmms_project, options, RENDER_SAMPLE_RATE as f32);
+    let loop_start = render_ticks(loop_points.start);
+    let loop_end = render_ticks(loop_points.end);
+
+    vec![
+        format!("LOOPSTART={loop_start}"),
+        format!("LOOPLENGTH={}", loop_end - loop_start),
+    ]
+}
+
+/// WAV file of 16-bit stereo frames
+pub fn wav(frames: &[(i16, i16)]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(frames.len() * 4);
+    for (left, right) in frames {
+        data.extend(left.to_le_bytes());
+        data.extend(right.to_le_bytes());
+    }
+
+    // 16-bit stereo PCM
+    let mut fmt = Vec::new();
+    fmt.extend(1u16.to_le_bytes());
+    fmt.extend(2u16.to_le_bytes());
+    fmt.extend(RENDER_SAMPLE_RATE.to_le_bytes());
+    fmt.extend((RENDER_SAMPLE_RATE * 4).to_le_bytes());
+    fmt.extend(4u16.to_le_bytes());
+    fmt.extend(16u16.to_le_bytes());
+
+    let mut wave_data = b"WAVE".to_vec();
+    wave_data.extend(riff_chunk(b"fmt ", &fmt));
+    wave_data.extend(riff_chunk(b"data", &data));
+
+    riff_chunk(b"RIFF", &wave_data)
+}
+
+/// Mixes the voices into frames up to the given time, dropping the voices that
+/// ended
+fn render_frames(
+    frames: &mut Vec<(f32, f32)>,
+    ticks: usize,
+    voices: &mut Vec<Voice>,
+    channels: &[ChannelState; 16],
+) {
+    let channel_gains = channels.each_ref().map(ChannelState::gains);
+    let bend_factors = channels.each_ref().map(ChannelState::bend_factor);
+
+    while frames.len() < ticks {
+        let mut frame = (0.0, 0.0);
+
+        voices.retain_mut(|voice| {
+            let channel = voice.channel.as_int() as usize;
+
+            match voice.next_sample(bend_factors[channel]) {
+                Some(sample) => {
+                    frame.0 += sample * channel_gains[channel].0;
+                    frame.1 += sample * channel_gains[channel].1;
+                    true
+                }
+                None => false,
+            }
+        });
+
+        frames.push(frame);
+    }
+}
@@ -0,0 +1,86 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// Chunk of a RIFF file: a four character code and the chunk data, padded to
+/// an even length
+pub fn riff_chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = id.to_vec();
+    chunk.extend((data.len() as u32).to_le_bytes());
+    chunk.extend(data);
+
+    if data.len() % 2 == 1 {
+        chunk.push(0);
+    }
+
+    chunk
+}
+
+/// RIFF MIDI file: the Standard MIDI File in a "data" chunk, followed by an
+/// INFO list of the given song information ("INAM", "ICOP", ...)
+pub fn rmid(midi_bytes: &[u8], info: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+    let mut form_data = b"RMID".to_vec();
+    form_data.extend(riff_chunk(b"data", midi_bytes));
+
+    if !info.is_empty() {
+        let mut list_data = b"INFO".to_vec();
+
+        for (id, text) in info {
+            // INFO texts are zero-terminated
+            let mut text = text.to_vec();
+            text.push(0);
+
+            list_data.extend(riff_chunk(id, &text));
+        }
+
+        form_data.extend(riff_chunk(b"LIST", &list_data));
+    }
+
+    riff_chunk(b"RIFF", &form_data)
+}
+
+/// Appends a RIFF file (a DLS collection) to a saved RIFF MIDI file
+pub fn embed_riff(rmid_path: &Path, riff_bytes: &[u8]) -> Result<(), Box<dyn Error>> {
+    let mut rmid_bytes = fs::read(rmid_path)?;
+
+    if rmid_bytes.get(..4) != Some(b"RIFF") || rmid_bytes.get(8..12) != Some(b"RMID") {
+        return Err("Not a RIFF MIDI file".into());
+    }
+
+    rmid_bytes.extend(riff_bytes);
+
+    let form_size = (rmid_bytes.len() - 8) as u32;
+    rmid_bytes[4..8].copy_from_slice(&form_size.to_le_bytes());
+
+    fs::write(rmid_path, rmid_bytes)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn midi_data_and_info_are_wrapped_in_a_riff_form() {
+        let rmid_bytes = rmid(b"MThd", &[(b"INAM", b"Song")]);
+
+        assert_eq!(
+            rmid_bytes,
+            [
+                b"RIFF" as &[u8],
+                &42u32.to_le_bytes(),
+                b"RMID",
+                b"data",
+                &4u32.to_le_bytes(),
+                b"MThd",
+                b"LIST",
+                &18u32.to_le_bytes(),
+                b"INFO",
+                b"INAM",
+                &5u32.to_le_bytes(),
+                b"Song\0\0",
+            ]
+            .concat()
+        );
+    }
+}
@@ -0,0 +1,28 @@
+/// Small deterministic pseudo-random generator (SplitMix64), so generated
+/// output is reproducible from its seed without an extra dependency
+#[derive(Debug, Clone)]
+pub struct SplitMix64(u64);
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> Self {
+        SplitMix64(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform number in `min..max`
+    pub fn range(&mut self, min: usize, max: usize) -> usize {
+        min + (self.next_u64() % (max - min) as u64) as usize
+    }
+
+    /// Uniform number in `-amount..=amount`
+    pub fn offset(&mut self, amount: usize) -> isize {
+        self.range(0, 2 * amount + 1) as isize - amount as isize
+    }
+}
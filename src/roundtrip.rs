@@ -0,0 +1,136 @@
+use crate::convert::ChannelAssignment;
+use crate::lmms_model::{LmmsProject, LMMS_TICKS_PER_BAR};
+
+// Layout of the LMMS project structure stored in a Sequencer Specific meta
+// event, numbers are big-endian:
+//
+//     0x7D                        non-commercial manufacturer ID
+//     "lmms2mid" 0x00             tool ID
+//     u8                          layout version (1)
+//     u16                         LMMS ticks per bar of the project
+//     u16                         LMMS ticks per bar of the MIDI positions (192)
+//     u8                          loop enabled (0/1)
+//     u32 u32                     loop start and end in LMMS ticks
+//     u8                          track count, then per track:
+//         u16                     LMMS track index
+//         u8 u8                   MIDI port and first MIDI channel (0-15)
+//         u16 u8                  bank and program
+//         u8 [u8]                 track name length and UTF-8 bytes (up to 255)
+
+const ROUNDTRIP_MANUFACTURER_ID: u8 = 0x7D;
+const ROUNDTRIP_TOOL_ID: &[u8] = b"lmms2mid\0";
+const ROUNDTRIP_VERSION: u8 = 1;
+
+/// Sequencer Specific meta event data describing the LMMS project structure
+pub fn roundtrip_metadata(
+    lmms_project: &LmmsProject,
+    channel_assignments: &[ChannelAssignment],
+) -> Vec<u8> {
+    let mut data = vec![ROUNDTRIP_MANUFACTURER_ID];
+    data.extend(ROUNDTRIP_TOOL_ID);
+    data.push(ROUNDTRIP_VERSION);
+
+    data.extend((lmms_project.ticks_per_bar() as u16).to_be_bytes());
+    data.extend((LMMS_TICKS_PER_BAR as u16).to_be_bytes());
+
+    let loop_points = lmms_project.loop_points();
+    data.push(loop_points.enabled as u8);
+    data.extend((loop_points.start as u32).to_be_bytes());
+    data.extend((loop_points.end as u32).to_be_bytes());
+
+    let channel_assignments = &channel_assignments[..channel_assignments.len().min(255)];
+    data.push(channel_assignments.len() as u8);
+
+    for channel_assignment in channel_assignments {
+        let track_name = &channel_assignment.lmms_track(lmms_project).name;
+
+        // Cut at a character boundary
+        let mut name_length = track_name.len().min(255);
+        while !track_name.is_char_boundary(name_length) {
+            name_length -= 1;
+        }
+
+        data.extend((channel_assignment.track_index as u16).to_be_bytes());
+        data.push(channel_assignment.midi_port as u8);
+        data.push(channel_assignment.midi_channels[0].as_int());
+        data.extend((channel_assignment.midi_preset.bank as u16).to_be_bytes());
+        data.push(channel_assignment.midi_preset.program as u8);
+        data.push(name_length as u8);
+        data.extend(&track_name.as_bytes()[..name_length]);
+    }
+
+    data
+}
+
+/// A track of the round-trip metadata
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoundtripTrack {
+    pub midi_port: usize,
+    pub midi_channel: u8,
+    pub bank: usize,
+    pub program: usize,
+    pub name: String,
+}
+
+/// The LMMS project structure read back from round-trip metadata
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoundtripMetadata {
+    pub loop_enabled: bool,
+
+    /// Loop start and end in LMMS ticks
+    pub loop_start: usize,
+    pub loop_end: usize,
+
+    pub tracks: Vec<RoundtripTrack>,
+}
+
+/// Reads the data of a Sequencer Specific meta event written by
+/// `roundtrip_metadata`, `None` for other data or unknown layout versions
+pub fn parse_roundtrip_metadata(data: &[u8]) -> Option<RoundtripMetadata> {
+    let data = data
+        .strip_prefix(&[ROUNDTRIP_MANUFACTURER_ID])?
+        .strip_prefix(ROUNDTRIP_TOOL_ID)?
+        .strip_prefix(&[ROUNDTRIP_VERSION])?;
+
+    let mut offset = 0;
+    let mut take = |length: usize| {
+        let bytes = data.get(offset..offset + length)?;
+        offset += length;
+        Some(bytes)
+    };
+
+    // The positions are stored in LMMS ticks already
+    take(4)?;
+
+    let loop_enabled = take(1)?[0] != 0;
+    let loop_start = u32::from_be_bytes(take(4)?.try_into().ok()?) as usize;
+    let loop_end = u32::from_be_bytes(take(4)?.try_into().ok()?) as usize;
+
+    let track_count = take(1)?[0] as usize;
+    let mut tracks = Vec::with_capacity(track_count);
+
+    for _ in 0..track_count {
+        take(2)?;
+        let midi_port = take(1)?[0] as usize;
+        let midi_channel = take(1)?[0];
+        let bank = u16::from_be_bytes(take(2)?.try_into().ok()?) as usize;
+        let program = take(1)?[0] as usize;
+        let name_length = take(1)?[0] as usize;
+        let name = String::from_utf8_lossy(take(name_length)?).into_owned();
+
+        tracks.push(RoundtripTrack {
+            midi_port,
+            midi_channel,
+            bank,
+            program,
+            name,
+        });
+    }
+
+    Some(RoundtripMetadata {
+        loop_enabled,
+        loop_start,
+        loop_end,
+        tracks,
+    })
+}
@@ -0,0 +1,104 @@
+use std::collections::{BTreeMap, HashMap};
+
+use midly::num::{u4, u7};
+use midly::{MidiMessage, TrackEventKind};
+
+use crate::lmms_model::LMMS_TICKS_PER_BAR;
+use crate::midi::{AbsoluteTrackEvent, MidiNote, MIDI_CC_SUSTAIN};
+
+// Chords of at least this many notes, held for at least a beat, are played
+// under the sustain pedal
+const SUSTAIN_MIN_CHORD_SIZE: usize = 3;
+const SUSTAIN_MIN_CHORD_LENGTH: usize = LMMS_TICKS_PER_BAR / 4;
+
+// Length of the notes struck under the pedal
+const SUSTAIN_NOTE_LENGTH: usize = LMMS_TICKS_PER_BAR / 16;
+
+/// Shortens sustained chords (notes of a channel starting and ending together)
+/// and holds them with the sustain pedal instead. Chords are skipped when other
+/// notes of the channel end while they sound, as the pedal would hold those
+/// too. Returns the pedal events, two per chord.
+pub fn synthesize_sustain(
+    midi_notes: &mut [MidiNote],
+    is_percussion_channel: impl Fn(u4) -> bool,
+) -> Vec<AbsoluteTrackEvent<'static>> {
+    let mut chords: BTreeMap<_, Vec<_>> = BTreeMap::new();
+
+    for (note_index, midi_note) in midi_notes.iter().enumerate() {
+        if midi_note.ticks_end >= midi_note.ticks_start + SUSTAIN_MIN_CHORD_LENGTH
+            && !is_percussion_channel(midi_note.channel)
+        {
+            chords
+                .entry((
+                    midi_note.ticks_start,
+                    midi_note.ticks_end,
+                    midi_note.channel,
+                ))
+                .or_default()
+                .push(note_index);
+        }
+    }
+
+    let mut pedal_ends = HashMap::new();
+    let mut midi_track_events = Vec::new();
+
+    for ((ticks_start, ticks_end, midi_channel), note_indices) in chords {
+        if note_indices.len() < SUSTAIN_MIN_CHORD_SIZE {
+            continue;
+        }
+
+        // The pedal of an earlier chord is still down
+        if pedal_ends
+            .get(&midi_channel)
+            .is_some_and(|pedal_end| *pedal_end > ticks_start)
+        {
+            continue;
+        }
+
+        let released_notes = midi_notes
+            .iter()
+            .enumerate()
+            .any(|(note_index, midi_note)| {
+                midi_note.channel == midi_channel
+                    && midi_note.ticks_end > ticks_start
+                    && midi_note.ticks_end < ticks_end
+                    && !note_indices.contains(&note_index)
+            });
+
+        if released_notes {
+            continue;
+        }
+
+        for note_index in note_indices {
+            midi_notes[note_index].ticks_end = ticks_start + SUSTAIN_NOTE_LENGTH;
+        }
+
+        pedal_ends.insert(midi_channel, ticks_end);
+
+        // The pedal is released before anything else starting at the chord end
+        midi_track_events.push(pedal_event(ticks_start, ticks_start, midi_channel, 127));
+        midi_track_events.push(pedal_event(ticks_end, ticks_start, midi_channel, 0));
+    }
+
+    midi_track_events
+}
+
+fn pedal_event(
+    ticks: usize,
+    ticks_event_start: usize,
+    midi_channel: u4,
+    value: u8,
+) -> AbsoluteTrackEvent<'static> {
+    AbsoluteTrackEvent {
+        ticks,
+        ticks_event_start,
+        kind: TrackEventKind::Midi {
+            channel: midi_channel,
+            message: MidiMessage::Controller {
+                controller: u7::from(MIDI_CC_SUSTAIN),
+                value: u7::from(value),
+            },
+        },
+        precise_value: None,
+    }
+}
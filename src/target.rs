@@ -0,0 +1,272 @@
+use std::collections::BTreeMap;
+
+use clap::ValueEnum;
+use midly::{MidiMessage, TrackEventKind};
+
+use crate::convert::{MidiPreset, ResetSysEx};
+use crate::lmms_model::LMMS_TICKS_PER_BAR;
+use crate::location::SourceLocator;
+use crate::midi::AbsoluteTrackEvent;
+use crate::warning;
+
+// MIDI cables carry 31250 baud with 10 bits per byte, enough for about a
+// thousand three byte messages per second
+const MIDI_CABLE_EVENTS_PER_SECOND: usize = 1000;
+
+// Melodic channels of most devices, everything except the drum channel 10
+pub const MIDI_MELODIC_CHANNELS: &[u8] = &[0, 1, 2, 3, 4, 5, 6, 7, 8, 10, 11, 12, 13, 14, 15];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum Target {
+    /// Roland SC-55 (GS)
+    #[value(name = "sc55")]
+    Sc55,
+
+    /// Roland MT-32
+    #[value(name = "mt32")]
+    Mt32,
+
+    /// General MIDI Level 1
+    #[value(name = "gm1")]
+    Gm1,
+
+    /// General MIDI Level 2
+    #[value(name = "gm2")]
+    Gm2,
+
+    /// Game Boy Advance sound engine (MusicPlayer2000 via mid2agb)
+    Gba,
+
+    /// Doom engine OPL2 music (DMX, via MUS conversion)
+    DoomOpl,
+}
+
+/// How a device selects sound banks
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum BankSelect {
+    /// Bank select MSB (CC#0) and LSB (CC#32)
+    #[value(name = "full")]
+    MsbLsb,
+
+    /// Bank select MSB (CC#0) only, the LSB is ignored
+    #[value(name = "msb-only")]
+    Msb,
+
+    /// No bank selection, plain Program Changes for GM-only devices
+    None,
+}
+
+/// Constraints of a playback device
+#[derive(Debug, Copy, Clone)]
+pub struct TargetProfile {
+    pub name: &'static str,
+
+    /// Voices available at once
+    pub voices: usize,
+
+    /// Controllers the device responds to
+    pub controllers: &'static [u8],
+
+    pub bank_select: BankSelect,
+
+    /// Channels melodic tracks can be assigned to, channel 10 is always the drum channel
+    pub melodic_channels: &'static [u8],
+
+    /// Whether drum kits can be chosen by Program Change on the drum channel
+    pub drum_kits: bool,
+
+    /// System reset SysEx used unless chosen otherwise
+    pub reset_sysex: ResetSysEx,
+
+    /// Largest file size the device's format or toolchain accepts
+    pub max_file_size: Option<usize>,
+
+    /// Most events per second the device's MIDI input keeps up with
+    pub max_events_per_second: Option<usize>,
+}
+
+impl Target {
+    pub fn profile(&self) -> TargetProfile {
+        match self {
+            Target::Sc55 => TargetProfile {
+                name: "SC-55",
+                voices: 24,
+                controllers: &[
+                    0, 1, 5, 6, 7, 10, 11, 32, 38, 64, 65, 66, 67, 84, 91, 93, 98, 99, 100, 101,
+                    120, 121, 123, 124, 125, 126, 127,
+                ],
+                bank_select: BankSelect::Msb,
+                melodic_channels: MIDI_MELODIC_CHANNELS,
+                drum_kits: true,
+                reset_sysex: ResetSysEx::Gs,
+                max_file_size: None,
+                max_events_per_second: Some(MIDI_CABLE_EVENTS_PER_SECOND),
+            },
+            Target::Mt32 => TargetProfile {
+                name: "MT-32",
+                voices: 32,
+                controllers: &[1, 6, 7, 10, 11, 64, 100, 101, 121, 123],
+                bank_select: BankSelect::None,
+                // Parts 1-8 listen on channels 2-9 by default
+                melodic_channels: &[1, 2, 3, 4, 5, 6, 7, 8],
+                drum_kits: false,
+                reset_sysex: ResetSysEx::None,
+                max_file_size: None,
+                max_events_per_second: Some(MIDI_CABLE_EVENTS_PER_SECOND),
+            },
+            Target::Gm1 => TargetProfile {
+                name: "GM1 synth",
+                voices: 24,
+                controllers: &[
+                    1, 6, 7, 10, 11, 38, 64, 100, 101, 121, 123, 124, 125, 126, 127,
+                ],
+                bank_select: BankSelect::None,
+                melodic_channels: MIDI_MELODIC_CHANNELS,
+                drum_kits: false,
+                reset_sysex: ResetSysEx::Gm,
+                max_file_size: None,
+                max_events_per_second: None,
+            },
+            Target::Gm2 => TargetProfile {
+                name: "GM2 synth",
+                voices: 32,
+                controllers: &[
+                    0, 1, 5, 6, 7, 10, 11, 32, 38, 64, 65, 66, 67, 71, 72, 73, 74, 75, 76, 77, 78,
+                    91, 93, 98, 99, 100, 101, 120, 121, 123, 124, 125, 126, 127,
+                ],
+                bank_select: BankSelect::MsbLsb,
+                melodic_channels: MIDI_MELODIC_CHANNELS,
+                drum_kits: true,
+                reset_sysex: ResetSysEx::Gm2,
+                max_file_size: None,
+                max_events_per_second: None,
+            },
+            Target::Gba => TargetProfile {
+                name: "GBA sound engine",
+                voices: 12,
+                // Modulation, volume, panning and mid2agb's extended controllers
+                controllers: &[1, 7, 10, 20, 21, 22, 24, 26],
+                bank_select: BankSelect::None,
+                melodic_channels: MIDI_MELODIC_CHANNELS,
+                drum_kits: false,
+                reset_sysex: ResetSysEx::None,
+                max_file_size: None,
+                max_events_per_second: None,
+            },
+            Target::DoomOpl => TargetProfile {
+                name: "Doom OPL player",
+                voices: 9,
+                // The controllers MUS files can store
+                controllers: &[0, 1, 7, 10, 11, 64, 67, 91, 93, 120, 121, 123, 126, 127],
+                bank_select: BankSelect::Msb,
+                melodic_channels: MIDI_MELODIC_CHANNELS,
+                drum_kits: false,
+                reset_sysex: ResetSysEx::None,
+                max_file_size: Some(0xFFFF),
+                max_events_per_second: None,
+            },
+        }
+    }
+}
+
+impl TargetProfile {
+    /// Diagnostics for presets the device cannot select
+    pub fn check_preset(&self, track_name: &str, midi_preset: MidiPreset) {
+        if midi_preset.is_percussion() {
+            if !self.drum_kits && midi_preset.program != 0 {
+                warning!(
+                    code: "target-drum-kit", track: track_name;
+                    "the {} has a single drum kit, track '{}' uses drum kit {}",
+                    self.name,
+                    track_name.escape_default(),
+                    midi_preset.program,
+                );
+            }
+        } else if self.bank_select == BankSelect::None && midi_preset.bank != 0 {
+            warning!(
+                code: "target-bank", track: track_name;
+                "the {} cannot select bank {} of track '{}'",
+                self.name,
+                midi_preset.bank,
+                track_name.escape_default(),
+            );
+        } else if self.bank_select == BankSelect::Msb && midi_preset.bank & 0x7F != 0 {
+            warning!(
+                code: "target-bank", track: track_name;
+                "the {} ignores the bank select LSB, track '{}' uses bank {}",
+                self.name,
+                track_name.escape_default(),
+                midi_preset.bank,
+            );
+        }
+    }
+
+    /// Diagnostics for controllers the device ignores, over the music's events
+    pub fn check_controllers(&self, midi_track_events: &[AbsoluteTrackEvent]) {
+        let mut ignored_controllers = BTreeMap::new();
+
+        for event in midi_track_events {
+            if let TrackEventKind::Midi {
+                message: MidiMessage::Controller { controller, .. },
+                ..
+            } = event.kind
+            {
+                if !self.controllers.contains(&controller.as_int()) {
+                    *ignored_controllers.entry(controller.as_int()).or_insert(0) += 1;
+                }
+            }
+        }
+
+        for (controller, count) in ignored_controllers {
+            warning!(
+                code: "target-controller";
+                "the {} ignores CC#{controller}, used by {count} events",
+                self.name
+            );
+        }
+    }
+
+    /// Diagnostics for bursts of events the device's MIDI input cannot keep up
+    /// with, over sorted events
+    pub fn check_event_rate(
+        &self,
+        midi_track_events: &[AbsoluteTrackEvent],
+        bpm: f32,
+        source_locator: &SourceLocator,
+    ) {
+        let Some(max_events_per_second) = self.max_events_per_second else {
+            return;
+        };
+
+        let ticks_per_second = (bpm / 60.0 * (LMMS_TICKS_PER_BAR / 4) as f32) as usize;
+        let mut window_start = 0;
+
+        for (event_index, event) in midi_track_events.iter().enumerate() {
+            while midi_track_events[window_start].ticks + ticks_per_second.max(1) <= event.ticks {
+                window_start += 1;
+            }
+
+            if event_index - window_start >= max_events_per_second {
+                warning!(
+                    code: "target-event-rate",
+                    tick: event.ticks,
+                    position: source_locator.position(event.ticks);
+                    "more than {max_events_per_second} events per second, the {} may fall behind",
+                    self.name
+                );
+                return;
+            }
+        }
+    }
+
+    /// Diagnostics for files too large for the device's format or toolchain
+    pub fn check_file_size(&self, file_size: usize) {
+        if let Some(max_file_size) = self.max_file_size.filter(|max| file_size > *max) {
+            warning!(
+                code: "target-file-size";
+                "the {file_size} byte output exceeds the {max_file_size} bytes the {} can take",
+                self.name
+            );
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use clap::ValueEnum;
+use encoding_rs::{EncoderResult, SHIFT_JIS};
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum TextEncoding {
+    /// UTF-8, understood by most current software
+    #[default]
+    #[value(name = "utf8")]
+    Utf8,
+
+    /// ISO-8859-1 (Latin-1), for western European text on older sequencers
+    #[value(name = "latin1")]
+    Latin1,
+
+    /// Shift-JIS, expected by Japanese hardware and sequencers
+    ShiftJis,
+}
+
+impl TextEncoding {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TextEncoding::Utf8 => "UTF-8",
+            TextEncoding::Latin1 => "Latin-1",
+            TextEncoding::ShiftJis => "Shift-JIS",
+        }
+    }
+
+    /// Meta event text bytes, characters the encoding cannot represent are
+    /// replaced by '?'. Returns whether any were replaced.
+    pub fn encode(&self, text: &str) -> (Vec<u8>, bool) {
+        match self {
+            TextEncoding::Utf8 => (text.as_bytes().to_vec(), false),
+            TextEncoding::Latin1 => {
+                let bytes = text
+                    .chars()
+                    .map(|c| u8::try_from(c).unwrap_or(b'?'))
+                    .collect::<Vec<_>>();
+
+                (bytes, text.chars().any(|c| c as u32 > 0xFF))
+            }
+            TextEncoding::ShiftJis => {
+                let mut encoder = SHIFT_JIS.new_encoder();
+                let mut bytes = Vec::with_capacity(text.len() * 2);
+                let mut rest = text;
+                let mut replaced = false;
+
+                loop {
+                    let capacity = encoder
+                        .max_buffer_length_from_utf8_without_replacement(rest.len())
+                        .unwrap_or(rest.len() * 4);
+                    bytes.reserve(capacity);
+
+                    let (result, read) =
+                        encoder.encode_from_utf8_to_vec_without_replacement(rest, &mut bytes, true);
+                    rest = &rest[read..];
+
+                    match result {
+                        EncoderResult::InputEmpty => break,
+                        EncoderResult::OutputFull => {}
+                        EncoderResult::Unmappable(_) => {
+                            bytes.push(b'?');
+                            replaced = true;
+                        }
+                    }
+                }
+
+                (bytes, replaced)
+            }
+        }
+    }
+}
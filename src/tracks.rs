@@ -0,0 +1,177 @@
+use std::fmt::Write;
+
+use crate::convert::{
+    assign_channels, merged_tracks, skipped_track_reason, ChannelAssignment, ConvertOptions,
+};
+use crate::lmms_model::*;
+
+/// Where the MIDI preset of a track comes from
+fn preset_source(lmms_track: &LmmsTrack, options: &ConvertOptions) -> &'static str {
+    if lmms_track.is_sampler_track() {
+        "soundfont"
+    } else if options.mapped_preset(lmms_track).is_some() {
+        "instrument map"
+    } else if lmms_track.is_vestige_track() {
+        "--vst-program"
+    } else if options.sample_drum_key(lmms_track).is_some() {
+        "sample drum"
+    } else {
+        "--default-program"
+    }
+}
+
+/// MIDI channels of an assignment, e.g. "ch 3", "ch 1-9,11" or "port 2 ch 1"
+fn channel_text(channel_assignment: &ChannelAssignment, options: &ConvertOptions) -> String {
+    let port = if options.midi_port_count() > 1 {
+        format!("port {} ", channel_assignment.midi_port + 1)
+    } else {
+        String::new()
+    };
+
+    // Runs of consecutive channels are written as ranges
+    let mut runs: Vec<(u8, u8)> = Vec::new();
+
+    for midi_channel in &channel_assignment.midi_channels {
+        let channel = midi_channel.as_int() + 1;
+
+        match runs.last_mut() {
+            Some((_, last)) if *last + 1 == channel => *last = channel,
+            _ => runs.push((channel, channel)),
+        }
+    }
+
+    let channels = runs
+        .iter()
+        .map(|(first, last)| {
+            if first == last {
+                first.to_string()
+            } else {
+                format!("{first}-{last}")
+            }
+        })
+        .collect::<Vec<_>>();
+
+    format!("{port}ch {}", channels.join(","))
+}
+
+/// Why a track that has an instrument gets no channel
+fn drop_reason(lmms_track: &LmmsTrack, options: &ConvertOptions) -> String {
+    if options.track_preset(lmms_track).is_some() {
+        return "no MIDI channel left, use `--overflow` or `--midi-ports`".to_owned();
+    }
+
+    if options.sample_drums && lmms_track.audio_file_name().is_some() {
+        return "GM percussion key of the sample is unknown, use `--drum-key`".to_owned();
+    }
+
+    let instrument_name = if lmms_track.is_opulenz_track() {
+        "OpulenZ"
+    } else {
+        lmms_track
+            .instrument()
+            .map_or("", |instrument| &instrument.name)
+    };
+
+    format!("{instrument_name} has no MIDI program, use `--instrument-map` or `--default-program`")
+}
+
+/// Lists every LMMS track with the MIDI channels and the bank and program it
+/// is converted to, or the reason it is dropped, in track order
+pub fn tracks(lmms_project: &LmmsProject, options: &ConvertOptions) -> String {
+    let mut report = String::new();
+
+    let channel_assignments = assign_channels(lmms_project, options);
+    let merged_tracks = merged_tracks(lmms_project, options);
+    let lmms_tracks = &lmms_project.song.track_container.tracks;
+
+    // The kit of the first percussion track is selected on channel 10
+    let percussion_assignment = channel_assignments.iter().find(|channel_assignment| {
+        channel_assignment.midi_preset.is_percussion() && channel_assignment.drum_key.is_none()
+    });
+
+    let mut dropped_track_count = 0;
+
+    for (track_index, lmms_track) in lmms_tracks.iter().enumerate() {
+        let track_name = format!("'{}'", lmms_track.name.escape_default());
+
+        let channel_assignment = channel_assignments
+            .iter()
+            .find(|channel_assignment| channel_assignment.track_index == track_index);
+
+        let Some(channel_assignment) = channel_assignment else {
+            let reason = if lmms_track.instrument_track.is_some() {
+                drop_reason(lmms_track, options)
+            } else {
+                skipped_track_reason(lmms_track).1.to_owned()
+            };
+
+            writeln!(
+                report,
+                "{:>3}  {track_name:<24} dropped: {reason}",
+                track_index + 1
+            )
+            .unwrap();
+
+            dropped_track_count += 1;
+            continue;
+        };
+
+        let midi_preset = channel_assignment.midi_preset;
+
+        let mut preset = format!(
+            "bank {:<3} program {:<3} ({})",
+            options.mapped_bank(midi_preset.bank),
+            midi_preset.program,
+            preset_source(lmms_track, options)
+        );
+
+        if let Some(drum_key) = channel_assignment.drum_key {
+            write!(preset, ", key {drum_key}").unwrap();
+        }
+
+        if let Some((_, shared_track_index)) = merged_tracks
+            .iter()
+            .find(|(merged_track_index, _)| *merged_track_index == track_index)
+        {
+            write!(
+                preset,
+                ", shares the channel of '{}'",
+                lmms_tracks[*shared_track_index].name.escape_default()
+            )
+            .unwrap();
+        }
+
+        if let Some(percussion_assignment) = percussion_assignment
+            .filter(|percussion_assignment| percussion_assignment.track_index != track_index)
+            .filter(|_| midi_preset.is_percussion())
+            .filter(|percussion_assignment| percussion_assignment.midi_preset != midi_preset)
+        {
+            write!(
+                preset,
+                ", plays with the kit of '{}'",
+                lmms_tracks[percussion_assignment.track_index]
+                    .name
+                    .escape_default()
+            )
+            .unwrap();
+        }
+
+        writeln!(
+            report,
+            "{:>3}  {track_name:<24} -> {:<10} {preset}",
+            track_index + 1,
+            channel_text(channel_assignment, options),
+        )
+        .unwrap();
+    }
+
+    writeln!(report).unwrap();
+    writeln!(
+        report,
+        "{} tracks converted, {dropped_track_count} dropped",
+        channel_assignments.len()
+    )
+    .unwrap();
+
+    report
+}
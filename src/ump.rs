@@ -0,0 +1,348 @@
+use std::collections::HashMap;
+
+use midly::num::{u4, u7};
+use midly::{MidiMessage, TrackEventKind};
+
+// MIDI Clip Files start with this identifier, followed by the Universal MIDI Packets
+pub const MIDI_CLIP_FILE_ID: &[u8] = b"SMF2CLIP";
+
+// Delta Clockstamps hold 20-bit tick counts
+const UMP_MAX_DELTA_CLOCKSTAMP: usize = 0xF_FFFF;
+
+// Flex Data statuses: Set Tempo in the setup and performance bank, song
+// information in the metadata text bank
+const UMP_FLEX_SET_TEMPO: (u8, u8) = (0x00, 0x00);
+pub const UMP_FLEX_COMPOSITION_NAME: (u8, u8) = (0x01, 0x02);
+pub const UMP_FLEX_COPYRIGHT_NOTICE: (u8, u8) = (0x01, 0x04);
+
+/// A MIDI 2.0 Clip File assembled from Universal MIDI Packets. Messages are
+/// added in time order, each preceded by the Delta Clockstamp since the last one.
+pub struct MidiClip {
+    words: Vec<u32>,
+    ticks: usize,
+
+    // MIDI 2.0 Program Changes carry the bank, the bank select controllers of
+    // MIDI 1.0 are held back until the next Program Change of their channel
+    bank_selects: HashMap<u4, (Option<u7>, Option<u7>)>,
+}
+
+impl MidiClip {
+    /// Clip header with the time division, followed by the Start of Clip
+    pub fn new(ticks_per_quarter_note: u16) -> MidiClip {
+        let mut midi_clip = MidiClip {
+            words: vec![0x0030_0000 | ticks_per_quarter_note as u32],
+            ticks: 0,
+            bank_selects: HashMap::new(),
+        };
+
+        midi_clip.push_delta_clockstamp(0);
+        midi_clip.words.extend([0xF020_0000, 0, 0, 0]);
+        midi_clip
+    }
+
+    pub fn push_tempo(&mut self, bpm: f32) {
+        // In units of 10 nanoseconds per quarter note
+        let tempo = (6_000_000_000.0 / bpm as f64) as u32;
+
+        self.push_delta_clockstamp(self.ticks);
+        self.words
+            .extend([flex_data_header(0, UMP_FLEX_SET_TEMPO), tempo, 0, 0]);
+    }
+
+    /// Flex Data text message, split into packets of 12 bytes
+    pub fn push_text(&mut self, status: (u8, u8), text: &str) {
+        let chunks = text.as_bytes().chunks(12).collect::<Vec<_>>();
+
+        for (chunk_index, chunk) in chunks.iter().enumerate() {
+            // Complete, start, continue or end
+            let form = match (chunk_index, chunks.len() - chunk_index) {
+                (0, 1) => 0,
+                (0, _) => 1,
+                (_, 1) => 3,
+                _ => 2,
+            };
+
+            let mut words = [0; 3];
+            for (byte_index, byte) in chunk.iter().enumerate() {
+                words[byte_index / 4] |= (*byte as u32) << (24 - byte_index % 4 * 8);
+            }
+
+            self.push_delta_clockstamp(self.ticks);
+            self.words.push(flex_data_header(form, status));
+            self.words.extend(words);
+        }
+    }
+
+    /// Adds a MIDI 1.0 event as its MIDI 2.0 counterpart, using the precise
+    /// value where the event has one. Returns false for meta events, which have
+    /// no counterpart.
+    pub fn push_event(
+        &mut self,
+        ticks: usize,
+        kind: TrackEventKind,
+        precise_value: Option<u32>,
+    ) -> bool {
+        match kind {
+            TrackEventKind::Midi { channel, message } => {
+                self.push_channel_message(ticks, channel, message, precise_value)
+            }
+            TrackEventKind::SysEx(data) => {
+                let data = data.strip_suffix(&[0xF7]).unwrap_or(data);
+                let chunks = data.chunks(6).collect::<Vec<_>>();
+
+                self.push_delta_clockstamp(ticks);
+
+                for (chunk_index, chunk) in chunks.iter().enumerate() {
+                    let status = match (chunk_index, chunks.len() - chunk_index) {
+                        (0, 1) => 0,
+                        (0, _) => 1,
+                        (_, 1) => 3,
+                        _ => 2,
+                    };
+
+                    let mut bytes = [0; 8];
+                    bytes[0] = 0x30;
+                    bytes[1] = status << 4 | chunk.len() as u8;
+                    bytes[2..2 + chunk.len()].copy_from_slice(chunk);
+
+                    self.words.extend([
+                        u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+                        u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+                    ]);
+                }
+                true
+            }
+            TrackEventKind::Escape(_) | TrackEventKind::Meta(_) => false,
+        }
+    }
+
+    fn push_channel_message(
+        &mut self,
+        ticks: usize,
+        channel: u4,
+        message: MidiMessage,
+        precise_value: Option<u32>,
+    ) -> bool {
+        let (opcode, index, flags, data) = match message {
+            MidiMessage::NoteOff { key, vel } => (
+                0x8,
+                key.as_int(),
+                0,
+                precise_velocity(vel, precise_value) << 16,
+            ),
+            MidiMessage::NoteOn { key, vel } => (
+                0x9,
+                key.as_int(),
+                0,
+                precise_velocity(vel, precise_value) << 16,
+            ),
+            MidiMessage::Aftertouch { key, vel } => (
+                0xA,
+                key.as_int(),
+                0,
+                precise_value.unwrap_or(upscale(vel.as_int() as u32, 7, 32)),
+            ),
+            MidiMessage::Controller { controller, value } => match controller.as_int() {
+                0 | 32 => {
+                    let bank_select = self.bank_selects.entry(channel).or_default();
+
+                    if controller.as_int() == 0 {
+                        bank_select.0 = Some(value);
+                    } else {
+                        bank_select.1 = Some(value);
+                    }
+                    return true;
+                }
+                _ => (
+                    0xB,
+                    controller.as_int(),
+                    0,
+                    precise_value.unwrap_or(upscale(value.as_int() as u32, 7, 32)),
+                ),
+            },
+            MidiMessage::ProgramChange { program } => match self.bank_selects.remove(&channel) {
+                Some((bank_msb, bank_lsb)) => (
+                    0xC,
+                    0,
+                    1,
+                    (program.as_int() as u32) << 24
+                        | (bank_msb.map_or(0, u7::as_int) as u32) << 8
+                        | bank_lsb.map_or(0, u7::as_int) as u32,
+                ),
+                None => (0xC, 0, 0, (program.as_int() as u32) << 24),
+            },
+            MidiMessage::ChannelAftertouch { vel } => (
+                0xD,
+                0,
+                0,
+                precise_value.unwrap_or(upscale(vel.as_int() as u32, 7, 32)),
+            ),
+            MidiMessage::PitchBend { bend } => (0xE, 0, 0, upscale(bend.0.as_int() as u32, 14, 32)),
+        };
+
+        self.push_delta_clockstamp(ticks);
+        self.words.extend([
+            0x4000_0000
+                | (opcode as u32) << 20
+                | (channel.as_int() as u32) << 16
+                | (index as u32) << 8
+                | flags,
+            data,
+        ]);
+        true
+    }
+
+    /// Delta Clockstamp up to the given time
+    fn push_delta_clockstamp(&mut self, ticks: usize) {
+        let mut delta = ticks - self.ticks;
+
+        // Consecutive Delta Clockstamps add up
+        while delta > UMP_MAX_DELTA_CLOCKSTAMP {
+            self.words
+                .push(0x0040_0000 | UMP_MAX_DELTA_CLOCKSTAMP as u32);
+            delta -= UMP_MAX_DELTA_CLOCKSTAMP;
+        }
+
+        self.words.push(0x0040_0000 | delta as u32);
+        self.ticks = ticks;
+    }
+
+    /// Ends the clip at the given time and returns the file contents
+    pub fn finish(mut self, ticks: usize) -> Vec<u8> {
+        self.push_delta_clockstamp(ticks.max(self.ticks));
+        self.words.extend([0xF021_0000, 0, 0, 0]);
+
+        let mut bytes = MIDI_CLIP_FILE_ID.to_vec();
+        bytes.extend(self.words.iter().flat_map(|word| word.to_be_bytes()));
+        bytes
+    }
+}
+
+/// First word of a group-wide Flex Data message
+fn flex_data_header(form: u8, (status_bank, status): (u8, u8)) -> u32 {
+    0xD010_0000 | (form as u32) << 22 | (status_bank as u32) << 8 | status as u32
+}
+
+/// 16-bit note velocity
+fn precise_velocity(velocity: u7, precise_value: Option<u32>) -> u32 {
+    precise_value.map_or(upscale(velocity.as_int() as u32, 7, 16), |precise_value| {
+        precise_value >> 16
+    })
+}
+
+/// Min-center-max scaling of the MIDI 2.0 specification: the minimum, center
+/// and maximum values stay the minimum, center and maximum at higher resolution
+fn upscale(value: u32, bits_from: u32, bits_to: u32) -> u32 {
+    let scale_bits = bits_to - bits_from;
+    let shifted_value = value << scale_bits;
+
+    if value <= 1 << (bits_from - 1) {
+        return shifted_value;
+    }
+
+    // Above the center the lower bits are filled with repeats of the value's bits
+    let repeat_bits = bits_from - 1;
+    let mut repeat_value = value & ((1 << repeat_bits) - 1);
+
+    repeat_value = if scale_bits > repeat_bits {
+        repeat_value << (scale_bits - repeat_bits)
+    } else {
+        repeat_value >> (repeat_bits - scale_bits)
+    };
+
+    let mut scaled_value = shifted_value;
+    while repeat_value != 0 {
+        scaled_value |= repeat_value;
+        repeat_value >>= repeat_bits;
+    }
+
+    scaled_value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(bytes: &[u8]) -> Vec<u32> {
+        bytes
+            .chunks(4)
+            .map(|word| u32::from_be_bytes(word.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn clip_is_framed_by_start_and_end_of_clip() {
+        let mut midi_clip = MidiClip::new(96);
+        midi_clip.push_event(
+            0,
+            TrackEventKind::Midi {
+                channel: u4::from(2),
+                message: MidiMessage::NoteOn {
+                    key: u7::from(60),
+                    vel: u7::from(127),
+                },
+            },
+            None,
+        );
+        let bytes = midi_clip.finish(192);
+
+        assert_eq!(&bytes[..8], MIDI_CLIP_FILE_ID);
+        assert_eq!(
+            words(&bytes[8..]),
+            [
+                0x0030_0060,
+                0x0040_0000,
+                0xF020_0000,
+                0,
+                0,
+                0,
+                0x0040_0000,
+                0x4092_3C00,
+                0xFFFF_0000,
+                0x0040_00C0,
+                0xF021_0000,
+                0,
+                0,
+                0,
+            ]
+        );
+    }
+
+    #[test]
+    fn program_change_carries_the_bank() {
+        let mut midi_clip = MidiClip::new(96);
+        for message in [
+            MidiMessage::Controller {
+                controller: u7::from(0),
+                value: u7::from(1),
+            },
+            MidiMessage::Controller {
+                controller: u7::from(32),
+                value: u7::from(2),
+            },
+            MidiMessage::ProgramChange {
+                program: u7::from(3),
+            },
+        ] {
+            midi_clip.push_event(
+                0,
+                TrackEventKind::Midi {
+                    channel: u4::from(0),
+                    message,
+                },
+                None,
+            );
+        }
+        let words = words(&midi_clip.finish(0)[8..]);
+
+        assert_eq!(words[6..9], [0x0040_0000, 0x40C0_0001, 0x0300_0102]);
+    }
+
+    #[test]
+    fn upscale_keeps_minimum_center_and_maximum() {
+        assert_eq!(upscale(0, 7, 16), 0);
+        assert_eq!(upscale(64, 7, 16), 0x8000);
+        assert_eq!(upscale(127, 7, 16), 0xFFFF);
+        assert_eq!(upscale(0x3FFF, 14, 32), u32::MAX);
+    }
+}
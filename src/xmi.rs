@@ -0,0 +1,247 @@
+use std::collections::{HashMap, VecDeque};
+
+use midly::num::u4;
+use midly::{MidiMessage, TrackEventKind};
+
+use crate::midi::{
+    write_vlq, AbsoluteTrackEvent, MIDI_CC_BANK_SELECT_COARSE, MIDI_CC_BANK_SELECT_FINE,
+};
+
+// XMIDI sequences play at a fixed rate of 120 ticks per second
+pub const XMI_TICKS_PER_SECOND: f32 = 120.0;
+
+// Miles Sound System patch bank select, taking the place of MIDI CC#0
+const XMI_CC_PATCH_BANK_SELECT: u8 = 114;
+
+/// IFF chunk with a big-endian size, padded to an even length
+fn iff_chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = id.to_vec();
+    chunk.extend((data.len() as u32).to_be_bytes());
+    chunk.extend(data);
+
+    if data.len() % 2 == 1 {
+        chunk.push(0);
+    }
+
+    chunk
+}
+
+/// XMIDI file of a single sequence. The events are sorted and timed in XMIDI
+/// ticks; Note Ons carry the duration of the note instead of a Note Off, and
+/// the delays between events are runs of bytes below 0x80 adding up. The TIMB
+/// chunk lists the timbres (patch and bank) the sequence selects, for drivers
+/// loading them in advance.
+pub fn xmi(midi_track_events: &[AbsoluteTrackEvent], percussion_channel: Option<u4>) -> Vec<u8> {
+    // Note On event index -> note duration
+    let mut note_durations = HashMap::new();
+    let mut sounding_notes: HashMap<_, VecDeque<usize>> = HashMap::new();
+
+    for (event_index, event) in midi_track_events.iter().enumerate() {
+        if let TrackEventKind::Midi { channel, message } = event.kind {
+            match message {
+                MidiMessage::NoteOn { key, .. } => {
+                    sounding_notes
+                        .entry((channel, key))
+                        .or_default()
+                        .push_back(event_index);
+                }
+                MidiMessage::NoteOff { key, .. } => {
+                    if let Some(note_index) = sounding_notes
+                        .get_mut(&(channel, key))
+                        .and_then(VecDeque::pop_front)
+                    {
+                        note_durations.insert(
+                            note_index,
+                            event.ticks - midi_track_events[note_index].ticks,
+                        );
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut timbres = Vec::new();
+    let mut patch_banks = HashMap::new();
+    let mut event_bytes = Vec::new();
+    let mut ticks_before = 0;
+
+    for (event_index, event) in midi_track_events.iter().enumerate() {
+        let mut bytes = Vec::new();
+
+        match event.kind {
+            TrackEventKind::Midi { channel, message } => {
+                let channel_bits = channel.as_int();
+
+                match message {
+                    MidiMessage::NoteOn { key, vel } => {
+                        // Notes never released are dropped
+                        let Some(duration) = note_durations.get(&event_index) else {
+                            continue;
+                        };
+
+                        bytes.extend([0x90 | channel_bits, key.as_int(), vel.as_int()]);
+                        write_vlq(&mut bytes, *duration);
+                    }
+                    MidiMessage::NoteOff { .. } => continue,
+                    MidiMessage::Aftertouch { key, vel } => {
+                        bytes.extend([0xA0 | channel_bits, key.as_int(), vel.as_int()]);
+                    }
+                    MidiMessage::Controller { controller, value } => {
+                        let controller = match controller.as_int() {
+                            MIDI_CC_BANK_SELECT_COARSE => {
+                                patch_banks.insert(channel, value.as_int());
+                                XMI_CC_PATCH_BANK_SELECT
+                            }
+                            MIDI_CC_BANK_SELECT_FINE => continue,
+                            controller => controller,
+                        };
+
+                        bytes.extend([0xB0 | channel_bits, controller, value.as_int()]);
+                    }
+                    MidiMessage::ProgramChange { program } => {
+                        let timbre = (
+                            program.as_int(),
+                            patch_banks.get(&channel).copied().unwrap_or(0),
+                        );
+
+                        if Some(channel) != percussion_channel && !timbres.contains(&timbre) {
+                            timbres.push(timbre);
+                        }
+
+                        bytes.extend([0xC0 | channel_bits, program.as_int()]);
+                    }
+                    MidiMessage::ChannelAftertouch { vel } => {
+                        bytes.extend([0xD0 | channel_bits, vel.as_int()]);
+                    }
+                    MidiMessage::PitchBend { bend } => {
+                        let bend = bend.0.as_int();
+                        bytes.extend([0xE0 | channel_bits, (bend & 0x7F) as u8, (bend >> 7) as u8]);
+                    }
+                }
+            }
+            TrackEventKind::SysEx(data) => {
+                bytes.push(0xF0);
+                write_vlq(&mut bytes, data.len());
+                bytes.extend(data);
+            }
+            // Drivers ignore the texts, and the timing is in the delays
+            TrackEventKind::Escape(_) | TrackEventKind::Meta(_) => continue,
+        }
+
+        let mut delay = event.ticks - ticks_before;
+        while delay > 0 {
+            event_bytes.push(delay.min(0x7F) as u8);
+            delay -= delay.min(0x7F);
+        }
+
+        event_bytes.extend(bytes);
+        ticks_before = event.ticks;
+    }
+
+    // End of Track
+    event_bytes.extend([0xFF, 0x2F, 0x00]);
+
+    let mut timb = (timbres.len() as u16).to_le_bytes().to_vec();
+    for (patch, bank) in timbres {
+        timb.extend([patch, bank]);
+    }
+
+    let mut xmid_form = b"XMID".to_vec();
+    xmid_form.extend(iff_chunk(b"TIMB", &timb));
+    xmid_form.extend(iff_chunk(b"EVNT", &event_bytes));
+
+    let mut xmid_catalog = b"XMID".to_vec();
+    xmid_catalog.extend(iff_chunk(b"FORM", &xmid_form));
+
+    // Directory of a single sequence
+    let mut xdir_form = b"XDIR".to_vec();
+    xdir_form.extend(iff_chunk(b"INFO", &1u16.to_le_bytes()));
+
+    let mut xmi_bytes = iff_chunk(b"FORM", &xdir_form);
+    xmi_bytes.extend(iff_chunk(b"CAT ", &xmid_catalog));
+    xmi_bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use midly::num::u7;
+
+    use super::*;
+
+    fn event(ticks: usize, message: MidiMessage) -> AbsoluteTrackEvent<'static> {
+        AbsoluteTrackEvent {
+            ticks,
+            ticks_event_start: ticks,
+            kind: TrackEventKind::Midi {
+                channel: u4::from(0),
+                message,
+            },
+            precise_value: None,
+        }
+    }
+
+    #[test]
+    fn sequence_has_timbres_and_notes_with_durations() {
+        let xmi_bytes = xmi(
+            &[
+                event(
+                    0,
+                    MidiMessage::Controller {
+                        controller: u7::from(MIDI_CC_BANK_SELECT_COARSE),
+                        value: u7::from(1),
+                    },
+                ),
+                event(
+                    0,
+                    MidiMessage::ProgramChange {
+                        program: u7::from(5),
+                    },
+                ),
+                event(
+                    0,
+                    MidiMessage::NoteOn {
+                        key: u7::from(60),
+                        vel: u7::from(100),
+                    },
+                ),
+                event(
+                    200,
+                    MidiMessage::NoteOff {
+                        key: u7::from(60),
+                        vel: u7::from(100),
+                    },
+                ),
+            ],
+            None,
+        );
+
+        assert_eq!(
+            xmi_bytes,
+            [
+                b"FORM" as &[u8],
+                &14u32.to_be_bytes(),
+                b"XDIR",
+                b"INFO",
+                &2u32.to_be_bytes(),
+                &[1, 0],
+                b"CAT ",
+                &50u32.to_be_bytes(),
+                b"XMID",
+                b"FORM",
+                &38u32.to_be_bytes(),
+                b"XMID",
+                b"TIMB",
+                &4u32.to_be_bytes(),
+                &[1, 0, 5, 1],
+                b"EVNT",
+                &13u32.to_be_bytes(),
+                &[0xB0, XMI_CC_PATCH_BANK_SELECT, 1],
+                &[0xC0, 5],
+                &[0x90, 60, 100, 0x81, 0x48],
+                &[0xFF, 0x2F, 0x00, 0],
+            ]
+            .concat()
+        );
+    }
+}
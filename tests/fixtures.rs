@@ -0,0 +1,543 @@
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+use clap::Parser;
+use lmms2mid::convert::{Conversion, ConvertOptions};
+use lmms2mid::error::ConvertError;
+use lmms2mid::fixture::{generate_fixture, FixtureOptions};
+use lmms2mid::lmms_model::LmmsProject;
+use midly::{MetaMessage, MidiMessage, Smf, TrackEventKind};
+use serde_json::Value;
+
+/// Conversion options parsed like the command line does
+#[derive(Parser)]
+struct Cli {
+    #[command(flatten)]
+    convert_options: ConvertOptions,
+}
+
+fn convert_options(args: &[&str]) -> ConvertOptions {
+    Cli::parse_from(["lmms2mid"].iter().chain(args)).convert_options
+}
+
+/// Directory of the files of a test, emptied before the test
+fn test_dir(test_name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("lmms2mid-{}-{test_name}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Writes a fixture project into the directory of a test
+fn write_fixture(test_name: &str, fixture_options: &FixtureOptions) -> PathBuf {
+    let project_path = test_dir(test_name).join("fixture.mmp");
+    fs::write(&project_path, generate_fixture(fixture_options)).unwrap();
+    project_path
+}
+
+/// Result of running lmms2mid, with the diagnostics in JSON
+struct Run {
+    exit_code: i32,
+    stdout: String,
+    diagnostics: Vec<Value>,
+    output: Option<Vec<u8>>,
+}
+
+fn run_lmms2mid<S: AsRef<OsStr>>(args: &[S], output_path: Option<&Path>) -> Run {
+    let result = Command::new(env!("CARGO_BIN_EXE_lmms2mid"))
+        .args(["--diagnostics-format", "json"])
+        .args(args)
+        .output()
+        .unwrap();
+
+    let diagnostics = String::from_utf8(result.stderr)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    Run {
+        exit_code: result.status.code().unwrap(),
+        stdout: String::from_utf8(result.stdout).unwrap(),
+        diagnostics,
+        output: output_path.and_then(|output_path| fs::read(output_path).ok()),
+    }
+}
+
+/// Converts a fixture project, `args` preceding the input and output paths
+fn convert_fixture(test_name: &str, fixture_options: &FixtureOptions, args: &[&str]) -> Run {
+    let project_path = write_fixture(test_name, fixture_options);
+    let output_path = project_path.with_file_name("output");
+
+    let mut run_args = args.iter().map(OsStr::new).collect::<Vec<_>>();
+    run_args.push(project_path.as_os_str());
+    run_args.push(output_path.as_os_str());
+
+    let run = run_lmms2mid(&run_args, Some(&output_path));
+    let _ = fs::remove_dir_all(project_path.parent().unwrap());
+    run
+}
+
+impl Run {
+    fn warning_codes(&self) -> Vec<&str> {
+        self.diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic["level"] == "warning")
+            .map(|diagnostic| diagnostic["code"].as_str().unwrap())
+            .collect()
+    }
+
+    fn warning_count(&self, code: &str) -> usize {
+        self.warning_codes()
+            .into_iter()
+            .filter(|warning_code| *warning_code == code)
+            .count()
+    }
+
+    fn notes(&self) -> Vec<Note> {
+        midi_notes(self.output.as_ref().expect("no output file written"))
+    }
+}
+
+/// A note of a MIDI file, in MIDI ticks
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Note {
+    port: u8,
+    channel: u8,
+    key: u8,
+    start: u32,
+    end: u32,
+}
+
+fn midi_notes(midi_bytes: &[u8]) -> Vec<Note> {
+    let midi_document = Smf::parse(midi_bytes).unwrap();
+    let mut notes = Vec::new();
+
+    for midi_track in &midi_document.tracks {
+        let mut ticks = 0;
+        let mut port = 0;
+        let mut sounding_notes: Vec<Note> = Vec::new();
+
+        for event in midi_track {
+            ticks += event.delta.as_int();
+
+            match event.kind {
+                TrackEventKind::Meta(MetaMessage::MidiPort(midi_port)) => {
+                    port = midi_port.as_int();
+                }
+                TrackEventKind::Midi {
+                    channel,
+                    message: MidiMessage::NoteOn { key, vel },
+                } if vel > 0 => sounding_notes.push(Note {
+                    port,
+                    channel: channel.as_int(),
+                    key: key.as_int(),
+                    start: ticks,
+                    end: ticks,
+                }),
+                TrackEventKind::Midi {
+                    channel,
+                    message: MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. },
+                } => {
+                    // Overlapping notes of a key end first in, first out
+                    if let Some(note_index) = sounding_notes.iter().position(|note| {
+                        (note.channel, note.key) == (channel.as_int(), key.as_int())
+                    }) {
+                        let mut note = sounding_notes.remove(note_index);
+                        note.end = ticks;
+                        notes.push(note);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        assert!(
+            sounding_notes.is_empty(),
+            "notes left sounding: {sounding_notes:?}"
+        );
+    }
+
+    notes.sort_by_key(|note| (note.start, note.port, note.channel, note.key));
+    notes
+}
+
+/// Number of same-key notes starting while another one sounds on the channel
+fn overlap_count(notes: &[Note]) -> usize {
+    notes
+        .iter()
+        .enumerate()
+        .filter(|(note_index, note)| {
+            notes[..*note_index].iter().any(|earlier_note| {
+                (earlier_note.port, earlier_note.channel, earlier_note.key)
+                    == (note.port, note.channel, note.key)
+                    && earlier_note.end > note.start
+            })
+        })
+        .count()
+}
+
+/// Most notes sounding at once on a port
+fn max_polyphony(notes: &[Note]) -> usize {
+    notes
+        .iter()
+        .map(|note| {
+            notes
+                .iter()
+                .filter(|other_note| {
+                    other_note.port == note.port
+                        && other_note.start <= note.start
+                        && other_note.end > note.start
+                })
+                .count()
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// Distinct (port, channel) pairs the notes are played on
+fn used_channels(notes: &[Note]) -> Vec<(u8, u8)> {
+    let mut channels = notes
+        .iter()
+        .map(|note| (note.port, note.channel))
+        .collect::<Vec<_>>();
+    channels.sort();
+    channels.dedup();
+    channels
+}
+
+#[test]
+fn fixture_converts_without_warnings() {
+    let run = convert_fixture("plain", &FixtureOptions::default(), &[]);
+
+    assert_eq!(run.exit_code, 0);
+    assert_eq!(run.warning_codes(), Vec::<&str>::new());
+
+    let notes = run.notes();
+    assert_eq!(notes.len(), 100);
+    assert_eq!(used_channels(&notes), [(0, 0), (0, 1), (0, 2), (0, 3)]);
+}
+
+#[test]
+fn fixture_seed_makes_output_reproducible() {
+    let fixture_options = FixtureOptions {
+        seed: 7,
+        ..FixtureOptions::default()
+    };
+
+    let run = convert_fixture("seed-first", &fixture_options, &[]);
+    let rerun = convert_fixture("seed-second", &fixture_options, &[]);
+    let other_seed_run = convert_fixture("seed-other", &FixtureOptions::default(), &[]);
+
+    assert_eq!(run.output, rerun.output);
+    assert_ne!(run.output, other_seed_run.output);
+}
+
+#[test]
+fn overlaps_are_kept_and_reported() {
+    let fixture_options = FixtureOptions {
+        overlaps: 3,
+        ..FixtureOptions::default()
+    };
+
+    let run = convert_fixture("overlaps-keep", &fixture_options, &[]);
+
+    assert_eq!(run.exit_code, 0);
+    assert_eq!(run.warning_codes(), ["note-overlap"; 3]);
+    assert!(run
+        .diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic["level"] == "warning")
+        .all(|diagnostic| diagnostic["track"] == "Track 1"));
+    assert_eq!(overlap_count(&run.notes()), 3);
+}
+
+#[test]
+fn overlaps_are_truncated() {
+    let fixture_options = FixtureOptions {
+        overlaps: 3,
+        ..FixtureOptions::default()
+    };
+
+    let kept_run = convert_fixture("overlaps-kept", &fixture_options, &[]);
+    let run = convert_fixture(
+        "overlaps-truncate",
+        &fixture_options,
+        &["--overlap", "truncate"],
+    );
+
+    assert_eq!(run.exit_code, 0);
+    assert_eq!(run.warning_count("note-overlap"), 0);
+    assert_eq!(overlap_count(&run.notes()), 0);
+    assert_eq!(run.notes().len(), kept_run.notes().len());
+}
+
+#[test]
+fn polyphony_spikes_are_reported() {
+    let fixture_options = FixtureOptions {
+        polyphony_spikes: 2,
+        ..FixtureOptions::default()
+    };
+
+    let run = convert_fixture("spikes", &fixture_options, &[]);
+
+    assert_eq!(run.exit_code, 0);
+    assert_eq!(run.warning_count("polyphony"), 2);
+    assert!(max_polyphony(&run.notes()) >= 32);
+}
+
+#[test]
+fn polyphony_limit_is_enforced() {
+    let fixture_options = FixtureOptions {
+        polyphony_spikes: 2,
+        ..FixtureOptions::default()
+    };
+
+    let run = convert_fixture(
+        "spikes-limited",
+        &fixture_options,
+        &["--max-polyphony", "24"],
+    );
+
+    assert_eq!(run.exit_code, 0);
+    assert_eq!(run.warning_count("polyphony"), 0);
+    assert!(max_polyphony(&run.notes()) <= 24);
+}
+
+#[test]
+fn tracks_over_the_channel_limit_are_dropped() {
+    let fixture_options = FixtureOptions {
+        tracks: 20,
+        ..FixtureOptions::default()
+    };
+
+    let run = convert_fixture("overflow-drop", &fixture_options, &[]);
+
+    assert_eq!(run.exit_code, 0);
+    assert_eq!(run.warning_codes(), ["channel-overflow"]);
+
+    // Channel 10 is kept for percussion
+    let channels = used_channels(&run.notes());
+    assert_eq!(channels.len(), 15);
+    assert!(!channels.contains(&(0, 9)));
+}
+
+#[test]
+fn channel_overflow_fails_with_overflow_error() {
+    let fixture_options = FixtureOptions {
+        tracks: 20,
+        ..FixtureOptions::default()
+    };
+
+    let run = convert_fixture("overflow-error", &fixture_options, &["--overflow", "error"]);
+
+    assert_eq!(run.exit_code, 5);
+    assert!(run.output.is_none());
+}
+
+#[test]
+fn tracks_spill_onto_additional_ports() {
+    let fixture_options = FixtureOptions {
+        tracks: 20,
+        ..FixtureOptions::default()
+    };
+
+    let run = convert_fixture(
+        "overflow-multi-port",
+        &fixture_options,
+        &["--midi-format", "1", "--overflow", "multi-port"],
+    );
+
+    assert_eq!(run.exit_code, 0);
+    assert_eq!(run.warning_codes(), Vec::<&str>::new());
+
+    let channels = used_channels(&run.notes());
+    assert_eq!(channels.len(), 20);
+    assert!(channels.contains(&(1, 0)));
+}
+
+#[test]
+fn spread_track_moves_to_the_next_port_whole() {
+    let fixture_options = FixtureOptions {
+        tracks: 17,
+        ..FixtureOptions::default()
+    };
+
+    // Track 15 finds a single channel left on the first port
+    let run = convert_fixture(
+        "spread-port-boundary",
+        &fixture_options,
+        &[
+            "--midi-format",
+            "1",
+            "--midi-ports",
+            "2",
+            "--spread",
+            "Track 15=3",
+        ],
+    );
+
+    assert_eq!(run.exit_code, 0);
+    assert_eq!(run.warning_codes(), Vec::<&str>::new());
+
+    let channels = used_channels(&run.notes());
+    assert_eq!(channels.len(), 19);
+    assert!(channels.contains(&(0, 15)));
+    assert!(channels.contains(&(1, 2)));
+}
+
+#[test]
+fn percussion_track_is_played_on_channel_10() {
+    let fixture_options = FixtureOptions {
+        percussion: true,
+        ..FixtureOptions::default()
+    };
+
+    let run = convert_fixture("percussion", &fixture_options, &[]);
+
+    assert_eq!(run.exit_code, 0);
+    assert_eq!(
+        used_channels(&run.notes()),
+        [(0, 0), (0, 1), (0, 2), (0, 9)]
+    );
+}
+
+#[test]
+fn loop_points_are_converted_to_the_loop_style() {
+    let fixture_options = FixtureOptions {
+        loop_points: true,
+        ..FixtureOptions::default()
+    };
+
+    let run = convert_fixture(
+        "loop-rpg-maker",
+        &fixture_options,
+        &["--loop-style", "rpg-maker"],
+    );
+    let midi_bytes = run.output.unwrap();
+    let midi_document = Smf::parse(&midi_bytes).unwrap();
+
+    let loop_start = midi_document.tracks[0]
+        .iter()
+        .scan(0, |ticks, event| {
+            *ticks += event.delta.as_int();
+            Some((*ticks, event.kind))
+        })
+        .find_map(|(ticks, kind)| match kind {
+            TrackEventKind::Midi {
+                message: MidiMessage::Controller { controller, .. },
+                ..
+            } if controller == 111 => Some(ticks),
+            _ => None,
+        });
+
+    assert_eq!(loop_start, Some(384));
+}
+
+#[test]
+fn strict_warnings_fail_the_conversion() {
+    let fixture_options = FixtureOptions {
+        overlaps: 1,
+        ..FixtureOptions::default()
+    };
+
+    let run = convert_fixture("strict", &fixture_options, &["--strict"]);
+
+    assert_eq!(run.exit_code, 7);
+    assert!(run.output.is_none());
+}
+
+#[test]
+fn check_counts_the_warnings() {
+    for (overlaps, summary) in [
+        (0, "no problems found"),
+        (1, "1 warning"),
+        (2, "2 warnings"),
+    ] {
+        let fixture_options = FixtureOptions {
+            overlaps,
+            ..FixtureOptions::default()
+        };
+
+        let project_path = write_fixture(&format!("check-{overlaps}"), &fixture_options);
+        let run = run_lmms2mid(&[OsStr::new("--check"), project_path.as_os_str()], None);
+        let _ = fs::remove_dir_all(project_path.parent().unwrap());
+
+        assert_eq!(run.exit_code, 0);
+        assert!(run.stdout.trim_end().ends_with(summary), "{}", run.stdout);
+    }
+}
+
+#[test]
+fn updates_reject_unknown_indexes() {
+    let lmms_project =
+        LmmsProject::from_bytes(generate_fixture(&FixtureOptions::default()).as_bytes()).unwrap();
+    let options = convert_options(&[]);
+    let mut conversion = Conversion::new(&lmms_project, &options).unwrap();
+
+    assert!(matches!(
+        conversion.update_track(&lmms_project, &options, 4),
+        Err(ConvertError::NoSuchTrack(4))
+    ));
+    assert!(matches!(
+        conversion.update_pattern(&lmms_project, &options, 4, 0),
+        Err(ConvertError::NoSuchTrack(4))
+    ));
+    assert!(matches!(
+        conversion.update_pattern(&lmms_project, &options, 0, 1),
+        Err(ConvertError::NoSuchPattern {
+            track_index: 0,
+            pattern_index: 1
+        })
+    ));
+}
+
+#[test]
+fn updates_infer_the_key_signature_again() {
+    let mut lmms_project =
+        LmmsProject::from_bytes(generate_fixture(&FixtureOptions::default()).as_bytes()).unwrap();
+    let options = convert_options(&["--key-signature", "auto"]);
+    let mut conversion = Conversion::new(&lmms_project, &options).unwrap();
+    let original_bytes = conversion.to_bytes(&lmms_project, &options).unwrap();
+
+    // A tritone away is in another key whatever key the notes are in
+    for track_index in 0..lmms_project.song.track_container.tracks.len() {
+        for lmms_pattern in &mut lmms_project.song.track_container.tracks[track_index].patterns {
+            for lmms_note in &mut lmms_pattern.notes {
+                lmms_note.key += 6;
+            }
+        }
+
+        conversion
+            .update_track(&lmms_project, &options, track_index)
+            .unwrap();
+    }
+
+    let updated_bytes = conversion.to_bytes(&lmms_project, &options).unwrap();
+    let converted_bytes = Conversion::new(&lmms_project, &options)
+        .unwrap()
+        .to_bytes(&lmms_project, &options)
+        .unwrap();
+
+    assert_eq!(
+        key_signature(&updated_bytes),
+        key_signature(&converted_bytes)
+    );
+    assert_ne!(
+        key_signature(&updated_bytes),
+        key_signature(&original_bytes)
+    );
+}
+
+/// The key signature of a MIDI file as (sharps, minor)
+fn key_signature(midi_bytes: &[u8]) -> Option<(i8, bool)> {
+    Smf::parse(midi_bytes).unwrap().tracks[0]
+        .iter()
+        .find_map(|event| match event.kind {
+            TrackEventKind::Meta(MetaMessage::KeySignature(sharps, minor)) => Some((sharps, minor)),
+            _ => None,
+        })
+}